@@ -4,9 +4,9 @@
 
 mod utils;
 use crate::{
-    bitcoin::{Block, BlockChainTip},
+    bitcoin::{Block, BlockChainTip, MempoolEntry},
     config,
-    descriptors::LianaDescriptor,
+    descriptors::{LianaDescriptor, SinglePathLianaDesc},
 };
 use utils::{block_before_date, roundup_progress};
 
@@ -199,6 +199,17 @@ impl std::fmt::Display for WalletError {
     }
 }
 
+// Whether all the descriptors were successfully imported, from the result of `importdescriptors`.
+fn all_imports_succeeded(res: &Json) -> bool {
+    res.as_array()
+        .map(|results| {
+            results
+                .iter()
+                .all(|res| res.get("success").and_then(Json::as_bool).unwrap_or(false))
+        })
+        .unwrap_or(false)
+}
+
 pub struct BitcoinD {
     /// Client for generalistic calls.
     node_client: Client,
@@ -553,21 +564,62 @@ impl BitcoinD {
             .collect();
 
         let res = self.make_wallet_request("importdescriptors", &params!(Json::Array(descriptors)));
-        let all_succeeded = res
-            .as_array()
-            .map(|results| {
-                results
-                    .iter()
-                    .all(|res| res.get("success").and_then(Json::as_bool).unwrap_or(false))
-            })
-            .unwrap_or(false);
-        if all_succeeded {
+        if all_imports_succeeded(&res) {
             None
         } else {
             Some(res.to_string())
         }
     }
 
+    /// Make sure the watchonly wallet tracks the addresses of these descriptors up to this
+    /// derivation index (excluded), by extending the range they were imported with if needed.
+    /// Like for a keypool top-up, past transactions paying to the newly tracked addresses are
+    /// only found by a rescan.
+    pub fn extend_descriptors_range(
+        &self,
+        descs: &[SinglePathLianaDesc],
+        end: u32,
+    ) -> Result<(), String> {
+        let current_descs = self.list_descriptors();
+        let descriptors: Vec<Json> = descs
+            .iter()
+            .map(|desc| desc.to_string())
+            .filter(|desc_str| {
+                let tracked_end = current_descs
+                    .iter()
+                    .find(|entry| &entry.desc == desc_str)
+                    .and_then(|entry| entry.range)
+                    .map(|r| r[1] + 1)
+                    .unwrap_or(0);
+                tracked_end < end
+            })
+            .map(|desc_str| {
+                serde_json::json!({
+                    "desc": desc_str,
+                    "timestamp": "now",
+                    "active": false,
+                    "range": [0, end - 1],
+                })
+            })
+            .collect();
+        if descriptors.is_empty() {
+            return Ok(());
+        }
+
+        log::info!(
+            "Extending the range of the descriptors imported in the watchonly wallet up to derivation index {}.",
+            end
+        );
+        let res = self
+            .make_faillible_wallet_request("importdescriptors", &params!(Json::Array(descriptors)))
+            .map_err(|e| e.to_string())?;
+        if all_imports_succeeded(&res) {
+            Ok(())
+        } else {
+            Err(res.to_string())
+        }
+    }
+
     fn list_descriptors(&self) -> Vec<ListDescEntry> {
         self.make_wallet_request("listdescriptors", &[])
             .get("descriptors")
@@ -1113,6 +1165,49 @@ impl BitcoinD {
         }
     }
 
+    /// Get the fee and virtual size of this transaction from the mempool, if it's there.
+    pub fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry> {
+        let res = match self
+            .make_fallible_node_request("getmempoolentry", &params!(Json::String(txid.to_string())))
+        {
+            Ok(res) => res,
+            Err(BitcoindError::Server(jsonrpc::Error::Rpc(jsonrpc::error::RpcError {
+                code: -5,
+                ..
+            }))) => return None,
+            Err(e) => {
+                panic!("Unexpected error returned by bitcoind {}", e);
+            }
+        };
+        let vsize = res
+            .get("vsize")
+            .and_then(Json::as_u64)
+            .expect("Invalid vsize in getmempoolentry response");
+        let fee = res
+            .get("fees")
+            .and_then(|f| f.get("base"))
+            .and_then(Json::as_f64)
+            .and_then(|a| bitcoin::Amount::from_btc(a).ok())
+            .expect("Invalid fees in getmempoolentry response");
+        Some(MempoolEntry { vsize, fee })
+    }
+
+    /// Get bitcoind's estimate of the feerate, in sats/vb, needed for a transaction to confirm
+    /// within this number of blocks. Returns `None` if it doesn't have enough data to estimate it.
+    pub fn estimate_smart_fee(&self, conf_target: u16) -> Option<u64> {
+        let res = self.make_node_request(
+            "estimatesmartfee",
+            &params!(Json::Number(conf_target.into())),
+        );
+        // The feerate is given in BTC/kvB. Round it up to the next sat/vb.
+        let sats_kvb = res
+            .get("feerate")
+            .and_then(Json::as_f64)
+            .and_then(|f| bitcoin::Amount::from_btc(f).ok())?
+            .to_sat();
+        Some(sats_kvb / 1_000 + u64::from(sats_kvb % 1_000 != 0))
+    }
+
     /// Stop bitcoind.
     pub fn stop(&self) {
         self.make_node_request("stop", &[]);
@@ -0,0 +1,190 @@
+//! A minimal client for the Electrum protocol.
+//!
+//! Requests and responses are JSON-RPC messages, separated by newlines, over a plain TCP
+//! connection. The server may push notifications for the subscriptions we made at any time, we
+//! queue them until they are taken by the caller.
+
+use crate::{bitcoin::electrum::ElectrumError, VERSION};
+
+use std::{
+    io::{self, BufRead, Write},
+    net, time,
+};
+
+use serde_json::Value as Json;
+
+// The version of the Electrum protocol we speak.
+const PROTOCOL_VERSION: &str = "1.4";
+
+/// A message pushed by the server for one of our subscriptions.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub method: String,
+    pub params: Vec<Json>,
+}
+
+pub struct Client {
+    reader: io::BufReader<net::TcpStream>,
+    writer: net::TcpStream,
+    next_id: u64,
+    notifications: Vec<Notification>,
+}
+
+impl Client {
+    /// Connect to the Electrum server at this address and negotiate the protocol version.
+    pub fn new(addr: &str, timeout: time::Duration) -> Result<Client, ElectrumError> {
+        let stream = net::TcpStream::connect(addr).map_err(ElectrumError::Io)?;
+        stream
+            .set_read_timeout(Some(timeout))
+            .map_err(ElectrumError::Io)?;
+        stream
+            .set_write_timeout(Some(timeout))
+            .map_err(ElectrumError::Io)?;
+        let writer = stream.try_clone().map_err(ElectrumError::Io)?;
+        let mut client = Client {
+            reader: io::BufReader::new(stream),
+            writer,
+            next_id: 0,
+            notifications: Vec::new(),
+        };
+
+        client.request(
+            "server.version",
+            vec![
+                Json::String(format!("lianad {}", VERSION)),
+                Json::String(PROTOCOL_VERSION.to_string()),
+            ],
+        )?;
+
+        Ok(client)
+    }
+
+    fn request_object(&mut self, method: &str, params: Vec<Json>) -> Json {
+        let id = self.next_id;
+        self.next_id += 1;
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        })
+    }
+
+    fn send(&mut self, msg: &Json) -> Result<(), ElectrumError> {
+        log::trace!("Sending to Electrum server: {}", msg);
+        let mut line = msg.to_string();
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .map_err(ElectrumError::Io)
+    }
+
+    // Read messages until we get a response (or a batch of responses), queuing the notifications
+    // we may receive in the meantime.
+    fn receive(&mut self) -> Result<Json, ElectrumError> {
+        loop {
+            let mut line = String::new();
+            if self
+                .reader
+                .read_line(&mut line)
+                .map_err(ElectrumError::Io)?
+                == 0
+            {
+                return Err(ElectrumError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            log::trace!("Got from Electrum server: {}", line.trim_end());
+            let msg: Json = serde_json::from_str(&line)
+                .map_err(|e| ElectrumError::InvalidResponse(e.to_string()))?;
+
+            if let Some(method) = msg.get("method").and_then(Json::as_str) {
+                let params = msg
+                    .get("params")
+                    .and_then(Json::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+                self.notifications.push(Notification {
+                    method: method.to_string(),
+                    params,
+                });
+                continue;
+            }
+
+            return Ok(msg);
+        }
+    }
+
+    // Get the result out of a response, or the error the server returned.
+    fn result(response: &Json) -> Result<Json, ElectrumError> {
+        if let Some(error) = response.get("error").filter(|e| !e.is_null()) {
+            let message = error
+                .get("message")
+                .and_then(Json::as_str)
+                .map(|m| m.to_string())
+                .unwrap_or_else(|| error.to_string());
+            return Err(ElectrumError::Server(message));
+        }
+        response
+            .get("result")
+            .cloned()
+            .ok_or_else(|| ElectrumError::InvalidResponse(response.to_string()))
+    }
+
+    /// Send a request to the server and wait for its response.
+    pub fn request(&mut self, method: &str, params: Vec<Json>) -> Result<Json, ElectrumError> {
+        let request = self.request_object(method, params);
+        self.send(&request)?;
+        let response = self.receive()?;
+        if response.get("id") != request.get("id") {
+            return Err(ElectrumError::InvalidResponse(response.to_string()));
+        }
+        Client::result(&response)
+    }
+
+    /// Send a batch of requests for the same method to the server. Returns the results in the
+    /// same order as the parameters.
+    pub fn batch_request(
+        &mut self,
+        method: &str,
+        params: Vec<Vec<Json>>,
+    ) -> Result<Vec<Json>, ElectrumError> {
+        if params.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let first_id = self.next_id;
+        let requests: Vec<Json> = params
+            .into_iter()
+            .map(|params| self.request_object(method, params))
+            .collect();
+        self.send(&Json::Array(requests))?;
+
+        let responses = match self.receive()? {
+            Json::Array(responses) => responses,
+            response => return Err(ElectrumError::InvalidResponse(response.to_string())),
+        };
+        let mut results = vec![Json::Null; (self.next_id - first_id) as usize];
+        if responses.len() != results.len() {
+            return Err(ElectrumError::InvalidResponse(format!(
+                "Expected {} responses to our batch request, got {}",
+                results.len(),
+                responses.len()
+            )));
+        }
+        for response in responses {
+            let index = response
+                .get("id")
+                .and_then(Json::as_u64)
+                .and_then(|id| id.checked_sub(first_id))
+                .filter(|i| *i < results.len() as u64)
+                .ok_or_else(|| ElectrumError::InvalidResponse(response.to_string()))?;
+            results[index as usize] = Client::result(&response)?;
+        }
+
+        Ok(results)
+    }
+
+    /// Get all the notifications received from the server since the last call.
+    pub fn take_notifications(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.notifications)
+    }
+}
@@ -0,0 +1,1137 @@
+//! Implementation of the Bitcoin interface using an Electrum server.
+//!
+//! We subscribe to the script hashes derived from the receive and change descriptors, up to a gap
+//! limit past the last one used. At each poll we query the history of the scripts whose status
+//! changed since the previous one, and keep the transactions of the wallet in memory.
+
+mod client;
+
+use crate::{
+    bitcoin::{
+        tracker::{self, HistoryEntry, ScriptTracker},
+        Block, BlockChainTip, MempoolEntry, UTxO,
+    },
+    config, descriptors,
+};
+use client::Client;
+
+use std::{
+    cell::RefCell, cmp, collections::BTreeMap, convert::TryInto, fmt, io, str::FromStr, thread,
+    time,
+};
+
+use miniscript::bitcoin::{
+    self, block,
+    consensus::encode,
+    hashes::{hex::FromHex, sha256, Hash},
+};
+
+use serde_json::Value as Json;
+
+// If the Electrum server takes more than 3 minutes to answer one of our queries, fail.
+const SOCKET_TIMEOUT: u64 = 180;
+
+// Number of times we try to reconnect to the Electrum server, every second, before giving up.
+const ELECTRUM_RETRY_LIMIT: usize = 60;
+
+// How many blocks we rewind when we can't locate where a former tip forked off the best chain.
+const MAX_REORG_DEPTH: i32 = 144;
+
+/// An error in the Electrum interface.
+#[derive(Debug)]
+pub enum ElectrumError {
+    Io(io::Error),
+    /// The server returned an error to our request.
+    Server(String),
+    /// The server sent a response we could not make sense of.
+    InvalidResponse(String),
+    NetworkMismatch(
+        bitcoin::Network,   /*config*/
+        bitcoin::BlockHash, /*server genesis*/
+    ),
+}
+
+impl fmt::Display for ElectrumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Error communicating with the Electrum server: {}", e),
+            Self::Server(e) => write!(f, "Electrum server error: {}", e),
+            Self::InvalidResponse(e) => {
+                write!(f, "Invalid response from the Electrum server: {}", e)
+            }
+            Self::NetworkMismatch(network, genesis) => write!(
+                f,
+                "Network mismatch. We are supposed to run on '{}' but the Electrum server's genesis block is '{}'.",
+                network, genesis
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ElectrumError {}
+
+#[derive(Default)]
+struct State {
+    // None if the connection was lost.
+    client: Option<Client>,
+    // Set when we reconnected to the server and lost our subscriptions.
+    resubscribe: bool,
+    // The tips we've been given by the server, used to locate the fork point upon reorgs.
+    seen_tips: BTreeMap<i32, bitcoin::BlockHash>,
+}
+
+// The script hash the Electrum protocol indexes scripts by: the reversed SHA256 of the script.
+fn script_hash(script: &bitcoin::Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).to_byte_array();
+    hash.reverse();
+    sha256::Hash::from_byte_array(hash).to_string()
+}
+
+fn status_from_json(status: &Json) -> Option<String> {
+    status.as_str().map(|s| s.to_string())
+}
+
+fn header_from_hex(hex: &str) -> Result<block::Header, ElectrumError> {
+    Vec::<u8>::from_hex(hex)
+        .ok()
+        .and_then(|bytes| encode::deserialize(&bytes).ok())
+        .ok_or_else(|| ElectrumError::InvalidResponse(format!("Invalid block header '{}'", hex)))
+}
+
+fn tx_from_json(tx: &Json) -> Result<bitcoin::Transaction, ElectrumError> {
+    tx.as_str()
+        .and_then(|hex| Vec::<u8>::from_hex(hex).ok())
+        .and_then(|bytes| encode::deserialize(&bytes).ok())
+        .ok_or_else(|| ElectrumError::InvalidResponse(format!("Invalid transaction '{}'", tx)))
+}
+
+// Whether this error from the server means it doesn't know about the transaction we asked for.
+// Servers relay the error from bitcoind's `getrawtransaction`, or use their own "not found".
+fn is_unknown_tx_error(error: &ElectrumError) -> bool {
+    match error {
+        ElectrumError::Server(message) => {
+            let message = message.to_lowercase();
+            message.contains("no such mempool or blockchain transaction")
+                || message.contains("not found")
+        }
+        _ => false,
+    }
+}
+
+fn history_from_json(history: &Json) -> Result<Vec<HistoryEntry>, ElectrumError> {
+    let invalid = || ElectrumError::InvalidResponse(format!("Invalid history '{}'", history));
+    history
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|entry| {
+            let txid = entry
+                .get("tx_hash")
+                .and_then(Json::as_str)
+                .and_then(|s| bitcoin::Txid::from_str(s).ok())
+                .ok_or_else(invalid)?;
+            // Positive if confirmed, 0 or negative if in mempool.
+            let height: i32 = entry
+                .get("height")
+                .and_then(Json::as_i64)
+                .and_then(|h| h.try_into().ok())
+                .ok_or_else(invalid)?;
+            let height = if height > 0 { Some(height) } else { None };
+            let fee = entry
+                .get("fee")
+                .and_then(Json::as_u64)
+                .map(bitcoin::Amount::from_sat);
+            Ok(HistoryEntry { txid, height, fee })
+        })
+        .collect()
+}
+
+pub struct Electrum {
+    addr: String,
+    state: RefCell<State>,
+    tracker: RefCell<ScriptTracker>,
+}
+
+impl Electrum {
+    /// Connect to the Electrum server and make sure it is on the expected network.
+    pub fn new(
+        config: &config::ElectrumConfig,
+        network: bitcoin::Network,
+        gap_limit: u32,
+    ) -> Result<Electrum, ElectrumError> {
+        let client = Client::new(&config.addr, time::Duration::from_secs(SOCKET_TIMEOUT))?;
+        let electrum = Electrum {
+            addr: config.addr.clone(),
+            state: RefCell::new(State {
+                client: Some(client),
+                ..State::default()
+            }),
+            tracker: RefCell::new(ScriptTracker::new(network, gap_limit, script_hash)),
+        };
+
+        let genesis_hash = electrum.genesis_block()?.hash;
+        if genesis_hash != bitcoin::blockdata::constants::genesis_block(network).block_hash() {
+            return Err(ElectrumError::NetworkMismatch(network, genesis_hash));
+        }
+
+        Ok(electrum)
+    }
+
+    // Send a request, reconnecting to the server upon I/O error. Errors returned by the server
+    // are passed to the caller.
+    fn try_request(
+        &self,
+        state: &mut State,
+        method: &str,
+        params: Vec<Vec<Json>>,
+    ) -> Result<Vec<Json>, ElectrumError> {
+        let mut error = None;
+        for i in 0..ELECTRUM_RETRY_LIMIT + 1 {
+            if state.client.is_none() {
+                match Client::new(&self.addr, time::Duration::from_secs(SOCKET_TIMEOUT)) {
+                    Ok(client) => {
+                        log::info!("Reconnected to the Electrum server.");
+                        state.client = Some(client);
+                        state.resubscribe = true;
+                    }
+                    Err(e) => {
+                        log::warn!("Error reconnecting to the Electrum server: {}", e);
+                        error = Some(e);
+                        thread::sleep(time::Duration::from_secs(1));
+                        continue;
+                    }
+                }
+            }
+            let client = state.client.as_mut().expect("Just set");
+            match client.batch_request(method, params.clone()) {
+                Err(ElectrumError::Io(e)) => {
+                    log::warn!(
+                        "Error communicating with the Electrum server: {}. Reconnecting (attempt #{}).",
+                        e,
+                        i
+                    );
+                    state.client = None;
+                    error = Some(ElectrumError::Io(e));
+                }
+                res => return res,
+            }
+        }
+
+        Err(error.expect("Always set if we reach this point"))
+    }
+
+    fn batch_request(
+        &self,
+        state: &mut State,
+        method: &str,
+        params: Vec<Vec<Json>>,
+    ) -> Result<Vec<Json>, ElectrumError> {
+        self.try_request(state, method, params)
+    }
+
+    fn request(
+        &self,
+        state: &mut State,
+        method: &str,
+        params: Vec<Json>,
+    ) -> Result<Json, ElectrumError> {
+        Ok(self
+            .batch_request(state, method, vec![params])?
+            .pop()
+            .expect("One request, one response"))
+    }
+
+    fn header_at(&self, state: &mut State, height: i32) -> Result<block::Header, ElectrumError> {
+        let hex = self.request(
+            state,
+            "blockchain.block.header",
+            vec![Json::Number(height.into())],
+        )?;
+        header_from_hex(hex.as_str().unwrap_or_default())
+    }
+
+    fn block_at(&self, state: &mut State, height: i32) -> Result<Block, ElectrumError> {
+        let header = self.header_at(state, height)?;
+        Ok(Block {
+            hash: header.block_hash(),
+            height,
+            time: header.time,
+        })
+    }
+
+    fn tip(&self, state: &mut State) -> Result<(BlockChainTip, block::Header), ElectrumError> {
+        let res = self.request(state, "blockchain.headers.subscribe", vec![])?;
+        let height = res
+            .get("height")
+            .and_then(Json::as_i64)
+            .and_then(|h| h.try_into().ok())
+            .ok_or_else(|| ElectrumError::InvalidResponse(res.to_string()))?;
+        let header = res
+            .get("hex")
+            .and_then(Json::as_str)
+            .ok_or_else(|| ElectrumError::InvalidResponse(res.to_string()))
+            .and_then(header_from_hex)?;
+        let tip = BlockChainTip {
+            hash: header.block_hash(),
+            height,
+        };
+
+        // Remember the tips we've seen within the last blocks, to find the common ancestor with
+        // the best chain should they get reorganized out.
+        state.seen_tips.insert(tip.height, tip.hash);
+        state
+            .seen_tips
+            .retain(|h, _| *h > tip.height - MAX_REORG_DEPTH);
+
+        Ok((tip, header))
+    }
+
+    // Subscribe to these scripts, recording their current status.
+    fn subscribe(
+        &self,
+        state: &mut State,
+        tracker: &mut ScriptTracker,
+        hashes: &[String],
+    ) -> Result<(), ElectrumError> {
+        let params = hashes
+            .iter()
+            .map(|hash| vec![Json::String(hash.clone())])
+            .collect();
+        let statuses = match self.batch_request(state, "blockchain.scripthash.subscribe", params) {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                // We wouldn't be notified about changes to these scripts. Subscribe to all of
+                // them again at the next sync.
+                state.resubscribe = true;
+                return Err(e);
+            }
+        };
+        for (hash, status) in hashes.iter().zip(statuses.iter()) {
+            tracker.set_status(hash, status_from_json(status));
+        }
+
+        Ok(())
+    }
+
+    // Update the histories of our scripts, and fetch the transactions they contain.
+    fn sync(
+        &self,
+        state: &mut State,
+        tracker: &mut ScriptTracker,
+        descs: &[descriptors::SinglePathLianaDesc],
+    ) -> Result<(), ElectrumError> {
+        tracker.set_descriptors(descs);
+
+        // Get the status of the scripts which changed since the last poll. The server notified us
+        // about them, make sure we received all the notifications it sent until now. If we had to
+        // reconnect we lost our subscriptions, subscribe to all the scripts again.
+        self.request(state, "server.ping", vec![])?;
+        while state.resubscribe {
+            state.resubscribe = false;
+            let hashes = tracker.script_hashes();
+            self.subscribe(state, tracker, &hashes)?;
+        }
+        let notifications = state
+            .client
+            .as_mut()
+            .map(Client::take_notifications)
+            .unwrap_or_default();
+        for notif in notifications {
+            if notif.method != "blockchain.scripthash.subscribe" || notif.params.len() != 2 {
+                continue;
+            }
+            if let Some(hash) = notif.params[0].as_str() {
+                tracker.set_status(hash, status_from_json(&notif.params[1]));
+            }
+        }
+
+        let mut new_hashes = tracker.derive_scripts();
+        loop {
+            self.subscribe(state, tracker, &new_hashes)?;
+
+            // Query the history of the scripts whose status changed.
+            let outdated = tracker.outdated_scripts();
+            let params = outdated
+                .iter()
+                .map(|hash| vec![Json::String(hash.clone())])
+                .collect();
+            let histories =
+                self.batch_request(state, "blockchain.scripthash.get_history", params)?;
+            for (hash, history) in outdated.iter().zip(histories.iter()) {
+                tracker.set_history(hash, history_from_json(history)?);
+            }
+
+            // Fetch the transactions we don't have yet.
+            let missing = tracker.missing_txids();
+            let params = missing
+                .iter()
+                .map(|txid| vec![Json::String(txid.to_string())])
+                .collect();
+            let txs = self.batch_request(state, "blockchain.transaction.get", params)?;
+            for tx in txs.iter() {
+                tracker.add_tx(tx_from_json(tx)?);
+            }
+
+            // Newly used scripts may require us to track more of them.
+            new_hashes = tracker.derive_scripts();
+            if new_hashes.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn genesis_block(&self) -> Result<BlockChainTip, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let block = self.block_at(&mut state, 0)?;
+        Ok(BlockChainTip {
+            hash: block.hash,
+            height: block.height,
+        })
+    }
+
+    pub fn chain_tip(&self) -> Result<BlockChainTip, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        Ok(self.tip(&mut state)?.0)
+    }
+
+    pub fn tip_time(&self) -> Result<u32, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        Ok(self.tip(&mut state)?.1.time)
+    }
+
+    pub fn tip_median_time_past(&self) -> Result<u32, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let tip = self.tip(&mut state)?.0;
+        let start = cmp::max(tip.height - 10, 0);
+        let res = self.request(
+            &mut state,
+            "blockchain.block.headers",
+            vec![
+                Json::Number(start.into()),
+                Json::Number((tip.height - start + 1).into()),
+            ],
+        )?;
+        let invalid = || ElectrumError::InvalidResponse(res.to_string());
+        let bytes = res
+            .get("hex")
+            .and_then(Json::as_str)
+            .and_then(|hex| Vec::<u8>::from_hex(hex).ok())
+            .ok_or_else(invalid)?;
+        let mut times = bytes
+            .chunks(80)
+            .map(|header| {
+                encode::deserialize::<block::Header>(header)
+                    .map(|header| header.time)
+                    .map_err(|_| invalid())
+            })
+            .collect::<Result<Vec<u32>, _>>()?;
+        if times.is_empty() {
+            return Err(invalid());
+        }
+        times.sort_unstable();
+        Ok(times[times.len() / 2])
+    }
+
+    pub fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        match self.header_at(&mut state, tip.height) {
+            Ok(header) => Ok(header.block_hash() == tip.hash),
+            // The server errors when asked for a header past its tip.
+            Err(ElectrumError::Server(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn received_coins(
+        &self,
+        tip: &BlockChainTip,
+        descs: &[descriptors::SinglePathLianaDesc],
+    ) -> Result<Vec<UTxO>, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let mut tracker = self.tracker.borrow_mut();
+        self.sync(&mut state, &mut tracker, descs)?;
+        let tip_height = self.tip(&mut state)?.0.height;
+        Ok(tracker.received_coins(tip, tip_height))
+    }
+
+    /// Track the scripts of our descriptors at least up to this derivation index (excluded).
+    pub fn track_addresses(&self, end: u32) {
+        self.tracker.borrow_mut().track_up_to(end);
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn confirmed_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let tip_height = self.tip(&mut state)?.0.height;
+        self.tracker
+            .borrow()
+            .confirmed_coins(outpoints, tip_height, |h| self.block_at(&mut state, h))
+    }
+
+    pub fn spending_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)> {
+        self.tracker.borrow().spending_coins(outpoints)
+    }
+
+    pub fn spent_coins(
+        &self,
+        outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        self.tracker
+            .borrow()
+            .spent_coins(outpoints, |h| self.block_at(&mut state, h))
+    }
+
+    pub fn common_ancestor(
+        &self,
+        tip: &BlockChainTip,
+    ) -> Result<Option<BlockChainTip>, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let seen_tips: Vec<(i32, bitcoin::BlockHash)> = state
+            .seen_tips
+            .range(..=tip.height)
+            .rev()
+            .map(|(h, hash)| (*h, *hash))
+            .collect();
+        for (height, hash) in seen_tips {
+            match self.header_at(&mut state, height) {
+                Ok(header) if header.block_hash() == hash => {
+                    return Ok(Some(BlockChainTip { hash, height }))
+                }
+                Ok(_) | Err(ElectrumError::Server(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        // We don't know where our former chain forked off. Rewind deep enough, we'll go further
+        // if this isn't.
+        let height = cmp::max(tip.height - MAX_REORG_DEPTH, 0);
+        let hash = self.header_at(&mut state, height)?.block_hash();
+        Ok(Some(BlockChainTip { hash, height }))
+    }
+
+    pub fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        self.try_request(
+            &mut state,
+            "blockchain.transaction.broadcast",
+            vec![vec![Json::String(encode::serialize_hex(tx))]],
+        )
+        .map(|_| ())
+    }
+
+    /// All the history of our scripts is always available from the server. Forget about what we
+    /// know so it's all queried again at the next poll.
+    pub fn start_rescan(&self) {
+        self.tracker.borrow_mut().clear();
+    }
+
+    /// Get the height and hash of the last block with a timestamp below the given one.
+    pub fn block_before_date(
+        &self,
+        timestamp: u32,
+    ) -> Result<Option<BlockChainTip>, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let tip = self.tip(&mut state)?.0;
+        tracker::block_before_date(timestamp, tip.height, |h| self.block_at(&mut state, h))
+    }
+
+    pub fn wallet_transaction(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        if let Some((tx, height)) = self.tracker.borrow().transaction(txid) {
+            let block = match height {
+                Some(h) => Some(self.block_at(&mut state, h)?),
+                None => None,
+            };
+            return Ok(Some((tx.clone(), block)));
+        }
+
+        let tx = match self.request(
+            &mut state,
+            "blockchain.transaction.get",
+            vec![Json::String(txid.to_string())],
+        ) {
+            Ok(res) => tx_from_json(&res)?,
+            Err(e) if is_unknown_tx_error(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        // The server doesn't tell us where the transaction was confirmed. Find it in the history
+        // of one of its output scripts.
+        let spk = match tx
+            .output
+            .iter()
+            .find(|txo| !txo.script_pubkey.is_op_return())
+        {
+            Some(txo) => &txo.script_pubkey,
+            None => return Ok(Some((tx, None))),
+        };
+        let history = self.request(
+            &mut state,
+            "blockchain.scripthash.get_history",
+            vec![Json::String(script_hash(spk))],
+        )?;
+        let height = history_from_json(&history)?
+            .into_iter()
+            .find(|entry| entry.txid == *txid)
+            .and_then(|entry| entry.height);
+        let block = match height {
+            Some(h) => Some(self.block_at(&mut state, h)?),
+            None => None,
+        };
+        Ok(Some((tx, block)))
+    }
+
+    pub fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry> {
+        self.tracker.borrow().mempool_entry(txid)
+    }
+
+    /// Get the Electrum server's estimate of the feerate, in sats/vb, needed for a transaction to
+    /// confirm within this number of blocks. Returns `None` if it doesn't have enough data to
+    /// estimate it.
+    pub fn estimate_fee(&self, conf_target: u16) -> Result<Option<u64>, ElectrumError> {
+        let mut state = self.state.borrow_mut();
+        let res = self.request(
+            &mut state,
+            "blockchain.estimatefee",
+            vec![Json::Number(conf_target.into())],
+        )?;
+        // The feerate is given in BTC/kvB, and negative if unavailable. Round it up to the next
+        // sat/vb.
+        Ok(res
+            .as_f64()
+            .filter(|f| *f > 0.0)
+            .and_then(|f| bitcoin::Amount::from_btc(f).ok())
+            .map(|amount| {
+                let sats_kvb = amount.to_sat();
+                sats_kvb / 1_000 + u64::from(sats_kvb % 1_000 != 0)
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_GAP_LIMIT;
+
+    use std::{
+        collections::HashMap,
+        io::{BufRead, Write},
+        net, sync,
+    };
+
+    use miniscript::bitcoin::{bip32, secp256k1};
+
+    // A stand-in for an Electrum server, serving a fake chain.
+    struct FakeChain {
+        headers: Vec<block::Header>,
+        // All the transactions along with their confirmation height, 0 if unconfirmed.
+        txs: HashMap<bitcoin::Txid, (bitcoin::Transaction, i32)>,
+        broadcast: Vec<bitcoin::Transaction>,
+        // Whether to send an invalid response to the requests for the tip.
+        invalid_tip: bool,
+        // Whether to send an empty response to the requests for a range of headers.
+        no_headers: bool,
+        // An error to send in response to the requests for a transaction, if any.
+        tx_error: Option<&'static str>,
+    }
+
+    impl FakeChain {
+        fn new() -> FakeChain {
+            let genesis =
+                bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest).header;
+            FakeChain {
+                headers: vec![genesis],
+                txs: HashMap::new(),
+                broadcast: Vec::new(),
+                invalid_tip: false,
+                no_headers: false,
+                tx_error: None,
+            }
+        }
+
+        // Mine a block confirming these transactions.
+        fn mine(&mut self, txids: &[bitcoin::Txid]) {
+            let prev = self.headers.last().unwrap();
+            let header = block::Header {
+                prev_blockhash: prev.block_hash(),
+                time: prev.time + 600,
+                nonce: self.headers.len() as u32,
+                ..*prev
+            };
+            self.headers.push(header);
+            for txid in txids {
+                self.txs.get_mut(txid).unwrap().1 = self.headers.len() as i32 - 1;
+            }
+        }
+
+        fn history(&self, hash: &str) -> Vec<(bitcoin::Txid, i32)> {
+            let pays_to = |op: &bitcoin::OutPoint| {
+                self.txs
+                    .get(&op.txid)
+                    .and_then(|(tx, _)| tx.output.get(op.vout as usize))
+                    .map(|txo| script_hash(&txo.script_pubkey) == hash)
+                    .unwrap_or(false)
+            };
+            let mut history: Vec<(bitcoin::Txid, i32)> = self
+                .txs
+                .iter()
+                .filter(|(_, (tx, _))| {
+                    tx.output
+                        .iter()
+                        .any(|txo| script_hash(&txo.script_pubkey) == hash)
+                        || tx.input.iter().any(|txin| pays_to(&txin.previous_output))
+                })
+                .map(|(txid, (_, height))| (*txid, *height))
+                .collect();
+            history.sort_by_key(|(txid, height)| (*height, *txid));
+            history
+        }
+
+        fn status(&self, hash: &str) -> Option<String> {
+            let history = self.history(hash);
+            if history.is_empty() {
+                return None;
+            }
+            let preimage: String = history
+                .iter()
+                .map(|(txid, height)| format!("{}:{}:", txid, height))
+                .collect();
+            Some(sha256::Hash::hash(preimage.as_bytes()).to_string())
+        }
+
+        fn respond(
+            &mut self,
+            req: &Json,
+            subscriptions: &mut HashMap<String, Option<String>>,
+        ) -> Json {
+            let params = req["params"].as_array().cloned().unwrap_or_default();
+            let result = match req["method"].as_str().unwrap() {
+                "server.version" => Ok(serde_json::json!(["fake", "1.4"])),
+                "server.ping" => Ok(Json::Null),
+                "blockchain.headers.subscribe" if self.invalid_tip => {
+                    Ok(serde_json::json!({ "height": self.headers.len() - 1, "hex": "00" }))
+                }
+                "blockchain.block.headers" if self.no_headers => {
+                    Ok(serde_json::json!({ "count": 0, "hex": "", "max": 2016 }))
+                }
+                "blockchain.headers.subscribe" => Ok(serde_json::json!({
+                    "height": self.headers.len() - 1,
+                    "hex": encode::serialize_hex(self.headers.last().unwrap()),
+                })),
+                "blockchain.block.header" => self
+                    .headers
+                    .get(params[0].as_u64().unwrap() as usize)
+                    .map(|h| Json::String(encode::serialize_hex(h)))
+                    .ok_or("Unknown block"),
+                "blockchain.block.headers" => {
+                    let start = params[0].as_u64().unwrap() as usize;
+                    let count = params[1].as_u64().unwrap() as usize;
+                    let headers: Vec<&block::Header> =
+                        self.headers.iter().skip(start).take(count).collect();
+                    let hex: String = headers.iter().map(encode::serialize_hex).collect();
+                    Ok(serde_json::json!({ "count": headers.len(), "hex": hex, "max": 2016 }))
+                }
+                "blockchain.scripthash.subscribe" => {
+                    let hash = params[0].as_str().unwrap().to_string();
+                    let status = self.status(&hash);
+                    subscriptions.insert(hash, status.clone());
+                    Ok(serde_json::json!(status))
+                }
+                "blockchain.scripthash.get_history" => Ok(Json::Array(
+                    self.history(params[0].as_str().unwrap())
+                        .into_iter()
+                        .map(|(txid, height)| {
+                            if height > 0 {
+                                serde_json::json!({ "tx_hash": txid.to_string(), "height": height })
+                            } else {
+                                serde_json::json!({ "tx_hash": txid.to_string(), "height": 0, "fee": 1_000 })
+                            }
+                        })
+                        .collect(),
+                )),
+                "blockchain.transaction.get" if self.tx_error.is_some() => {
+                    Err(self.tx_error.unwrap())
+                }
+                "blockchain.transaction.get" => self
+                    .txs
+                    .get(&bitcoin::Txid::from_str(params[0].as_str().unwrap()).unwrap())
+                    .map(|(tx, _)| Json::String(encode::serialize_hex(tx)))
+                    .ok_or("No such mempool or blockchain transaction"),
+                "blockchain.transaction.broadcast" => {
+                    let tx = tx_from_json(&params[0]).unwrap();
+                    if tx.output.is_empty() {
+                        Err("bad-txns-vout-empty")
+                    } else {
+                        self.broadcast.push(tx.clone());
+                        Ok(Json::String(tx.txid().to_string()))
+                    }
+                }
+                "blockchain.estimatefee" => Ok(if params[0].as_u64().unwrap() < 2 {
+                    serde_json::json!(-1)
+                } else {
+                    serde_json::json!(0.00012345)
+                }),
+                method => panic!("Unexpected method '{}'", method),
+            };
+            match result {
+                Ok(result) => {
+                    serde_json::json!({ "jsonrpc": "2.0", "id": req["id"], "result": result })
+                }
+                Err(message) => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": req["id"],
+                    "error": { "code": 1, "message": message },
+                }),
+            }
+        }
+    }
+
+    fn serve(stream: net::TcpStream, chain: sync::Arc<sync::Mutex<FakeChain>>) {
+        let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut subscriptions: HashMap<String, Option<String>> = HashMap::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let mut chain = chain.lock().unwrap();
+
+            // Notify the changes to the scripts we are subscribed to since the last request.
+            for (hash, status) in subscriptions.iter_mut() {
+                let new_status = chain.status(hash);
+                if new_status != *status {
+                    *status = new_status.clone();
+                    let notif = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "method": "blockchain.scripthash.subscribe",
+                        "params": [hash, new_status],
+                    });
+                    writeln!(writer, "{}", notif).unwrap();
+                }
+            }
+
+            let response = match serde_json::from_str(&line).unwrap() {
+                Json::Array(reqs) => Json::Array(
+                    reqs.iter()
+                        .map(|req| chain.respond(req, &mut subscriptions))
+                        .collect(),
+                ),
+                req => chain.respond(&req, &mut subscriptions),
+            };
+            writeln!(writer, "{}", response).unwrap();
+        }
+    }
+
+    // Start a server for this fake chain, returns its address.
+    fn start_server(chain: sync::Arc<sync::Mutex<FakeChain>>) -> String {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let chain = chain.clone();
+                thread::spawn(move || serve(stream.unwrap(), chain));
+            }
+        });
+        addr
+    }
+
+    fn dummy_tx(
+        inputs: &[bitcoin::OutPoint],
+        outputs: &[(&bitcoin::Script, u64)],
+        lock_time: u32,
+    ) -> bitcoin::Transaction {
+        // A transaction without input would be mistaken for a Segwit one when deserialized.
+        let dummy_input = [bitcoin::OutPoint::new(
+            bitcoin::Txid::all_zeros(),
+            lock_time,
+        )];
+        let inputs = if inputs.is_empty() {
+            &dummy_input[..]
+        } else {
+            inputs
+        };
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::from_consensus(lock_time),
+            input: inputs
+                .iter()
+                .map(|op| bitcoin::TxIn {
+                    previous_output: *op,
+                    ..bitcoin::TxIn::default()
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|(spk, value)| bitcoin::TxOut {
+                    script_pubkey: spk.to_owned().into(),
+                    value: *value,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn electrum_interface() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = descriptors::LianaDescriptor::from_str("wsh(or_d(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),and_v(v:pkh([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*),older(10000))))").unwrap();
+        let descs = [
+            desc.receive_descriptor().clone(),
+            desc.change_descriptor().clone(),
+        ];
+        let receive_spk = |index: u32| {
+            descs[0]
+                .derive(bip32::ChildNumber::from(index), &secp)
+                .script_pubkey()
+        };
+        let change_spk = |index: u32| {
+            descs[1]
+                .derive(bip32::ChildNumber::from(index), &secp)
+                .script_pubkey()
+        };
+        let external_spk = bitcoin::ScriptBuf::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros());
+
+        let chain = sync::Arc::new(sync::Mutex::new(FakeChain::new()));
+        let addr = start_server(chain.clone());
+        let config = config::ElectrumConfig { addr: addr.clone() };
+
+        // It must be on the same network as the server.
+        match Electrum::new(&config, bitcoin::Network::Bitcoin, DEFAULT_GAP_LIMIT) {
+            Err(ElectrumError::NetworkMismatch(..)) => {}
+            _ => panic!("Must fail with a network mismatch"),
+        }
+        let electrum =
+            Electrum::new(&config, bitcoin::Network::Regtest, DEFAULT_GAP_LIMIT).unwrap();
+        let genesis = electrum.genesis_block().unwrap();
+        assert_eq!(genesis.height, 0);
+        assert_eq!(electrum.chain_tip().unwrap(), genesis);
+
+        // Receive a coin on the first receive address, one further than the gap limit (but within
+        // the gap limit past the first one), and one on an external address.
+        let tx_a = dummy_tx(
+            &[],
+            &[(&receive_spk(0), 100_000), (&external_spk, 10_000)],
+            1,
+        );
+        let tx_b = dummy_tx(&[], &[(&receive_spk(150), 200_000)], 2);
+        let tx_c = dummy_tx(&[], &[(&receive_spk(300), 300_000)], 3);
+        {
+            let mut chain = chain.lock().unwrap();
+            for tx in &[&tx_a, &tx_b, &tx_c] {
+                chain.txs.insert(tx.txid(), ((*tx).clone(), 0));
+            }
+        }
+        let received = electrum.received_coins(&genesis, &descs).unwrap();
+        assert_eq!(received.len(), 3);
+        for (tx, spk, value) in &[
+            (&tx_a, receive_spk(0), 100_000),
+            (&tx_b, receive_spk(150), 200_000),
+            (&tx_c, receive_spk(300), 300_000),
+        ] {
+            let coin = received
+                .iter()
+                .find(|c| c.outpoint.txid == tx.txid())
+                .unwrap();
+            assert_eq!(coin.outpoint.vout, 0);
+            assert_eq!(coin.amount.to_sat(), *value);
+            assert_eq!(coin.block_height, None);
+            assert!(!coin.is_immature);
+            assert_eq!(
+                coin.address.clone().assume_checked(),
+                bitcoin::Address::from_script(spk, bitcoin::Network::Regtest).unwrap()
+            );
+        }
+        let mempool_entry = electrum.mempool_entry(&tx_a.txid()).unwrap();
+        assert_eq!(mempool_entry.fee.to_sat(), 1_000);
+        assert_eq!(mempool_entry.vsize, tx_a.vsize() as u64);
+        let op_a = bitcoin::OutPoint::new(tx_a.txid(), 0);
+        let op_b = bitcoin::OutPoint::new(tx_b.txid(), 0);
+        let op_c = bitcoin::OutPoint::new(tx_c.txid(), 0);
+        assert_eq!(
+            electrum.confirmed_coins(&[op_a, op_b, op_c]).unwrap(),
+            (vec![], vec![])
+        );
+
+        // Now the first two get confirmed, and the last one is dropped from the mempool.
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.mine(&[tx_a.txid(), tx_b.txid()]);
+            chain.txs.remove(&tx_c.txid());
+        }
+        let tip = electrum.chain_tip().unwrap();
+        assert_eq!(tip.height, 1);
+        assert!(electrum.is_in_chain(&genesis).unwrap());
+        let received = electrum.received_coins(&genesis, &descs).unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received.iter().all(|c| c.block_height == Some(1)));
+        let (confirmed, expired) = electrum.confirmed_coins(&[op_a, op_b, op_c]).unwrap();
+        assert_eq!(expired, vec![op_c]);
+        assert_eq!(confirmed.len(), 2);
+        assert!(confirmed
+            .iter()
+            .all(|(_, height, time)| *height == 1 && *time == electrum.tip_time().unwrap()));
+        // They won't be reported as received since the new tip.
+        assert!(electrum.received_coins(&tip, &descs).unwrap().is_empty());
+        assert!(electrum.mempool_entry(&tx_a.txid()).is_none());
+        assert_eq!(
+            electrum.wallet_transaction(&tx_a.txid()).unwrap().unwrap(),
+            (
+                tx_a.clone(),
+                Some(Block {
+                    hash: tip.hash,
+                    height: 1,
+                    time: electrum.tip_time().unwrap()
+                })
+            )
+        );
+
+        // A transaction we don't track is reported along with its confirmation, if it's known to
+        // the server.
+        let tx_ext = dummy_tx(&[], &[(&external_spk, 50_000)], 8);
+        chain
+            .lock()
+            .unwrap()
+            .txs
+            .insert(tx_ext.txid(), (tx_ext.clone(), 1));
+        assert_eq!(
+            electrum
+                .wallet_transaction(&tx_ext.txid())
+                .unwrap()
+                .unwrap(),
+            (
+                tx_ext.clone(),
+                Some(Block {
+                    hash: tip.hash,
+                    height: 1,
+                    time: electrum.tip_time().unwrap()
+                })
+            )
+        );
+        chain.lock().unwrap().txs.remove(&tx_ext.txid());
+        assert!(electrum
+            .wallet_transaction(&tx_ext.txid())
+            .unwrap()
+            .is_none());
+        // Other errors from the server are reported as such.
+        chain.lock().unwrap().tx_error = Some("Server busy");
+        assert!(matches!(
+            electrum.wallet_transaction(&tx_ext.txid()),
+            Err(ElectrumError::Server(_))
+        ));
+        chain.lock().unwrap().tx_error = None;
+
+        // Spend the first coin to a change address. It gets replaced by another spend, which gets
+        // confirmed.
+        let tx_d = dummy_tx(&[op_a], &[(&change_spk(0), 90_000)], 4);
+        chain
+            .lock()
+            .unwrap()
+            .txs
+            .insert(tx_d.txid(), (tx_d.clone(), 0));
+        let received = electrum.received_coins(&tip, &descs).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].outpoint, bitcoin::OutPoint::new(tx_d.txid(), 0));
+        assert_eq!(
+            electrum.spending_coins(&[op_a, op_b]),
+            vec![(op_a, tx_d.txid())]
+        );
+        assert!(electrum
+            .spent_coins(&[(op_a, tx_d.txid())])
+            .unwrap()
+            .is_empty());
+        let tx_e = dummy_tx(&[op_a], &[(&external_spk, 95_000)], 5);
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.txs.remove(&tx_d.txid());
+            chain.txs.insert(tx_e.txid(), (tx_e.clone(), 0));
+            chain.mine(&[tx_e.txid()]);
+        }
+        assert!(electrum.received_coins(&tip, &descs).unwrap().is_empty());
+        let spent = electrum.spent_coins(&[(op_a, tx_d.txid())]).unwrap();
+        assert_eq!(spent.len(), 1);
+        assert_eq!(
+            (spent[0].0, spent[0].1, spent[0].2.height),
+            (op_a, tx_e.txid(), 2)
+        );
+
+        // Upon reorg we'll find the common ancestor with our former chain.
+        let tip = electrum.chain_tip().unwrap();
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.headers.pop();
+            let mut header = chain.headers[1];
+            header.nonce += 1000;
+            chain.headers[1] = header;
+            chain.mine(&[]);
+            chain.mine(&[]);
+        }
+        assert!(!electrum.is_in_chain(&tip).unwrap());
+        assert_eq!(electrum.common_ancestor(&tip).unwrap(), Some(genesis));
+
+        // We can broadcast transactions, and get the error from the server.
+        electrum.broadcast_tx(&tx_d).unwrap();
+        assert_eq!(chain.lock().unwrap().broadcast, vec![tx_d]);
+        let tx_f = dummy_tx(&[op_b], &[], 6);
+        match electrum.broadcast_tx(&tx_f) {
+            Err(ElectrumError::Server(e)) => assert_eq!(e, "bad-txns-vout-empty"),
+            _ => panic!("Must fail to broadcast"),
+        }
+
+        // Get fee estimates and the block at a given time.
+        assert_eq!(electrum.estimate_fee(1).unwrap(), None);
+        assert_eq!(electrum.estimate_fee(2).unwrap(), Some(13));
+        let genesis_time = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest)
+            .header
+            .time;
+        assert_eq!(electrum.block_before_date(genesis_time - 1).unwrap(), None);
+        assert_eq!(
+            electrum
+                .block_before_date(genesis_time + 601)
+                .unwrap()
+                .map(|b| b.height),
+            Some(1)
+        );
+
+        // Coins further than the gap limit from any used address are found once we track the
+        // addresses up to there.
+        let tx_g = dummy_tx(&[], &[(&receive_spk(600), 10_000)], 7);
+        chain
+            .lock()
+            .unwrap()
+            .txs
+            .insert(tx_g.txid(), (tx_g.clone(), 0));
+        let tip = electrum.chain_tip().unwrap();
+        assert!(electrum.received_coins(&tip, &descs).unwrap().is_empty());
+        electrum.track_addresses(601);
+        let received = electrum.received_coins(&tip, &descs).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].outpoint.txid, tx_g.txid());
+
+        // Invalid responses from the server are reported as errors.
+        electrum.tip_median_time_past().unwrap();
+        chain.lock().unwrap().no_headers = true;
+        assert!(matches!(
+            electrum.tip_median_time_past(),
+            Err(ElectrumError::InvalidResponse(_))
+        ));
+        chain.lock().unwrap().invalid_tip = true;
+        assert!(matches!(
+            electrum.chain_tip(),
+            Err(ElectrumError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            electrum.received_coins(&tip, &descs),
+            Err(ElectrumError::InvalidResponse(_))
+        ));
+    }
+}
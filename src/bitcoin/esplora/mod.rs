@@ -0,0 +1,1212 @@
+//! Implementation of the Bitcoin interface using an Esplora HTTP API.
+//!
+//! Esplora has no subscription mechanism. At each poll we query the statistics of all the scripts
+//! derived from the receive and change descriptors, up to a gap limit past the last one used, and
+//! only query the history of those which changed. The transactions of the wallet are kept in
+//! memory.
+
+use crate::{
+    bitcoin::{
+        tracker::{self, HistoryEntry, ScriptTracker},
+        Block, BlockChainTip, MempoolEntry, UTxO,
+    },
+    config, descriptors,
+};
+
+use std::{
+    cell::RefCell, cmp, collections::HashMap, convert::TryInto, fmt, str::FromStr, thread, time,
+};
+
+use miniscript::bitcoin::{
+    self,
+    consensus::encode,
+    hashes::{hex::FromHex, sha256, Hash},
+};
+
+use serde_json::Value as Json;
+
+// If the Esplora server takes more than 3 minutes to answer one of our queries, fail.
+const HTTP_TIMEOUT: u64 = 180;
+
+// Number of times we retry a request to the Esplora server, every second, before giving up.
+const ESPLORA_RETRY_LIMIT: usize = 60;
+
+// The number of confirmed transactions Esplora returns per page of a script's history.
+const CHAIN_TXS_PER_PAGE: usize = 25;
+
+// How many blocks we rewind when the Esplora server doesn't know about our former tip.
+const MAX_REORG_DEPTH: i32 = 144;
+
+/// An error in the Esplora interface.
+#[derive(Debug)]
+pub enum EsploraError {
+    /// We could not get a response from the server.
+    Transport(minreq::Error),
+    /// The server answered with an error status code, along with the message it returned.
+    Server(i32, String),
+    /// The server sent a response we could not make sense of.
+    InvalidResponse(String),
+    NetworkMismatch(
+        bitcoin::Network,   /*config*/
+        bitcoin::BlockHash, /*server genesis*/
+    ),
+}
+
+impl fmt::Display for EsploraError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Transport(e) => write!(f, "Error communicating with the Esplora server: {}", e),
+            Self::Server(code, e) => write!(f, "Esplora server error ({}): {}", code, e),
+            Self::InvalidResponse(e) => {
+                write!(f, "Invalid response from the Esplora server: {}", e)
+            }
+            Self::NetworkMismatch(network, genesis) => write!(
+                f,
+                "Network mismatch. We are supposed to run on '{}' but the Esplora server's genesis block is '{}'.",
+                network, genesis
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EsploraError {}
+
+struct State {
+    tracker: ScriptTracker,
+    // The blocks our transactions were confirmed in, by height, as reported in the histories.
+    blocks: HashMap<i32, Block>,
+    // The tip as of the last sync. The statistics of our scripts don't change when a transaction
+    // gets confirmed in a different block upon reorg, so we query all their histories again if
+    // it isn't part of the best chain anymore.
+    synced_tip: Option<BlockChainTip>,
+}
+
+// Information about a block returned by the Esplora server.
+struct BlockInfo {
+    height: i32,
+    time: u32,
+    median_time_past: u32,
+    previous_blockhash: Option<bitcoin::BlockHash>,
+}
+
+// The script hash Esplora indexes scripts by: the SHA256 of the script.
+fn script_hash(script: &bitcoin::Script) -> String {
+    sha256::Hash::hash(script.as_bytes()).to_string()
+}
+
+fn invalid_response(body: &str) -> EsploraError {
+    EsploraError::InvalidResponse(body.to_string())
+}
+
+fn json_from_str(body: &str) -> Result<Json, EsploraError> {
+    serde_json::from_str(body).map_err(|_| invalid_response(body))
+}
+
+fn tx_from_hex(hex: &str) -> Result<bitcoin::Transaction, EsploraError> {
+    Vec::<u8>::from_hex(hex.trim())
+        .ok()
+        .and_then(|bytes| encode::deserialize(&bytes).ok())
+        .ok_or_else(|| invalid_response(hex))
+}
+
+fn block_info_from_json(block: &Json) -> Result<BlockInfo, EsploraError> {
+    let invalid = || invalid_response(&block.to_string());
+    let height = block
+        .get("height")
+        .and_then(Json::as_i64)
+        .and_then(|h| h.try_into().ok())
+        .ok_or_else(invalid)?;
+    let time = block
+        .get("timestamp")
+        .and_then(Json::as_u64)
+        .and_then(|t| t.try_into().ok())
+        .ok_or_else(invalid)?;
+    let median_time_past = block
+        .get("mediantime")
+        .and_then(Json::as_u64)
+        .and_then(|t| t.try_into().ok())
+        .ok_or_else(invalid)?;
+    let previous_blockhash = match block.get("previousblockhash") {
+        None | Some(Json::Null) => None,
+        Some(prev) => Some(
+            prev.as_str()
+                .and_then(|s| bitcoin::BlockHash::from_str(s).ok())
+                .ok_or_else(invalid)?,
+        ),
+    };
+    Ok(BlockInfo {
+        height,
+        time,
+        median_time_past,
+        previous_blockhash,
+    })
+}
+
+// The status of a script, from its statistics. None if it has no transaction.
+fn script_status_from_json(stats: &Json) -> Result<Option<String>, EsploraError> {
+    let invalid = || invalid_response(&stats.to_string());
+    let (chain_stats, mempool_stats) = stats
+        .get("chain_stats")
+        .zip(stats.get("mempool_stats"))
+        .ok_or_else(invalid)?;
+    let tx_count = |stats: &Json| stats.get("tx_count").and_then(Json::as_u64);
+    let chain_tx_count = tx_count(chain_stats).ok_or_else(invalid)?;
+    let mempool_tx_count = tx_count(mempool_stats).ok_or_else(invalid)?;
+    if chain_tx_count == 0 && mempool_tx_count == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(format!("{}{}", chain_stats, mempool_stats)))
+    }
+}
+
+// Parse a transaction in the history of a script, along with the block it was confirmed in if
+// any.
+fn history_entry_from_json(tx: &Json) -> Result<(HistoryEntry, Option<Block>), EsploraError> {
+    let invalid = || invalid_response(&tx.to_string());
+    let txid = tx
+        .get("txid")
+        .and_then(Json::as_str)
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(invalid)?;
+    let fee = tx
+        .get("fee")
+        .and_then(Json::as_u64)
+        .map(bitcoin::Amount::from_sat);
+    let status = tx.get("status").ok_or_else(invalid)?;
+    let block = if status.get("confirmed").and_then(Json::as_bool) == Some(true) {
+        Some(Block {
+            hash: status
+                .get("block_hash")
+                .and_then(Json::as_str)
+                .and_then(|s| bitcoin::BlockHash::from_str(s).ok())
+                .ok_or_else(invalid)?,
+            height: status
+                .get("block_height")
+                .and_then(Json::as_i64)
+                .and_then(|h| h.try_into().ok())
+                .ok_or_else(invalid)?,
+            time: status
+                .get("block_time")
+                .and_then(Json::as_u64)
+                .and_then(|t| t.try_into().ok())
+                .ok_or_else(invalid)?,
+        })
+    } else {
+        None
+    };
+    let entry = HistoryEntry {
+        txid,
+        height: block.map(|b| b.height),
+        fee,
+    };
+    Ok((entry, block))
+}
+
+pub struct Esplora {
+    base_url: String,
+    state: RefCell<State>,
+}
+
+impl Esplora {
+    /// Check we can query the Esplora server and that it is on the expected network.
+    pub fn new(
+        config: &config::EsploraConfig,
+        network: bitcoin::Network,
+        gap_limit: u32,
+    ) -> Result<Esplora, EsploraError> {
+        let esplora = Esplora {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            state: RefCell::new(State {
+                tracker: ScriptTracker::new(network, gap_limit, script_hash),
+                blocks: HashMap::new(),
+                synced_tip: None,
+            }),
+        };
+
+        let genesis_hash = esplora.try_block_hash(0)?;
+        if genesis_hash != bitcoin::blockdata::constants::genesis_block(network).block_hash() {
+            return Err(EsploraError::NetworkMismatch(network, genesis_hash));
+        }
+
+        Ok(esplora)
+    }
+
+    // Send a request to this path of the API and get the body of the response. Retry upon
+    // failure to get a response or a server error. Errors for invalid requests are passed to the
+    // caller.
+    fn try_request(
+        &self,
+        method: minreq::Method,
+        path: &str,
+        body: Option<&str>,
+    ) -> Result<String, EsploraError> {
+        let url = format!("{}/{}", self.base_url, path);
+        let mut error = None;
+        for i in 0..ESPLORA_RETRY_LIMIT + 1 {
+            if i > 0 {
+                thread::sleep(time::Duration::from_secs(1));
+            }
+            let mut req =
+                minreq::Request::new(method.clone(), url.as_str()).with_timeout(HTTP_TIMEOUT);
+            if let Some(body) = body {
+                req = req.with_body(body);
+            }
+            log::trace!(
+                "Sending {:?} request to Esplora server at '{}'",
+                method,
+                url
+            );
+            let res = match req.send() {
+                Ok(res) => res,
+                Err(e) => {
+                    log::warn!(
+                        "Error communicating with the Esplora server: {}. Retrying (attempt #{}).",
+                        e,
+                        i
+                    );
+                    error = Some(EsploraError::Transport(e));
+                    continue;
+                }
+            };
+            let res_body = res
+                .as_str()
+                .map_err(|_| invalid_response("Non UTF-8 response body"))?
+                .to_string();
+            log::trace!("Got from Esplora server: {}", res_body);
+            match res.status_code {
+                200..=299 => return Ok(res_body),
+                code @ 500..=599 => {
+                    log::warn!(
+                        "Esplora server error ({}): {}. Retrying (attempt #{}).",
+                        code,
+                        res_body,
+                        i
+                    );
+                    error = Some(EsploraError::Server(code, res_body));
+                }
+                code => return Err(EsploraError::Server(code, res_body)),
+            }
+        }
+
+        Err(error.expect("Always set if we reach this point"))
+    }
+
+    fn try_get(&self, path: &str) -> Result<String, EsploraError> {
+        self.try_request(minreq::Method::Get, path, None)
+    }
+
+    fn get_json(&self, path: &str) -> Result<Json, EsploraError> {
+        json_from_str(&self.try_get(path)?)
+    }
+
+    fn try_block_hash(&self, height: i32) -> Result<bitcoin::BlockHash, EsploraError> {
+        let body = self.try_get(&format!("block-height/{}", height))?;
+        bitcoin::BlockHash::from_str(body.trim()).map_err(|_| invalid_response(&body))
+    }
+
+    fn block_info(&self, hash: &bitcoin::BlockHash) -> Result<BlockInfo, EsploraError> {
+        block_info_from_json(&self.get_json(&format!("block/{}", hash))?)
+    }
+
+    fn block_at(&self, height: i32) -> Result<Block, EsploraError> {
+        let hash = self.try_block_hash(height)?;
+        let info = self.block_info(&hash)?;
+        Ok(Block {
+            hash,
+            height,
+            time: info.time,
+        })
+    }
+
+    fn tip(&self) -> Result<(bitcoin::BlockHash, BlockInfo), EsploraError> {
+        let body = self.try_get("blocks/tip/hash")?;
+        let hash =
+            bitcoin::BlockHash::from_str(body.trim()).map_err(|_| invalid_response(&body))?;
+        Ok((hash, self.block_info(&hash)?))
+    }
+
+    // Get all the transactions of this script, fetching all the pages of its confirmed history.
+    fn script_history(
+        &self,
+        hash: &str,
+    ) -> Result<Vec<(HistoryEntry, Option<Block>)>, EsploraError> {
+        let parse = |txs: Json| -> Result<Vec<(HistoryEntry, Option<Block>)>, EsploraError> {
+            txs.as_array()
+                .ok_or_else(|| invalid_response(&txs.to_string()))?
+                .iter()
+                .map(history_entry_from_json)
+                .collect()
+        };
+
+        // The first page contains the mempool transactions and the most recent confirmed ones.
+        let mut history = parse(self.get_json(&format!("scripthash/{}/txs", hash))?)?;
+        let mut page_len = history.iter().filter(|(_, b)| b.is_some()).count();
+        while page_len >= CHAIN_TXS_PER_PAGE {
+            let last_txid = history.last().expect("Not empty").0.txid;
+            let page =
+                parse(self.get_json(&format!("scripthash/{}/txs/chain/{}", hash, last_txid))?)?;
+            page_len = page.len();
+            history.extend(page);
+        }
+
+        Ok(history)
+    }
+
+    // Query the status of these scripts.
+    fn update_statuses(&self, state: &mut State, hashes: &[String]) -> Result<(), EsploraError> {
+        for hash in hashes {
+            let stats = self.get_json(&format!("scripthash/{}", hash))?;
+            state
+                .tracker
+                .set_status(hash, script_status_from_json(&stats)?);
+            // Unconfirmed transactions may be replaced without changing the statistics of the
+            // script. Always query the history of the scripts with some.
+            if stats
+                .get("mempool_stats")
+                .and_then(|s| s.get("tx_count"))
+                .and_then(Json::as_u64)
+                != Some(0)
+            {
+                state.tracker.invalidate_history(hash);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Update the histories of our scripts, and fetch the transactions they contain. Returns the
+    // current tip.
+    fn sync(
+        &self,
+        state: &mut State,
+        descs: &[descriptors::SinglePathLianaDesc],
+    ) -> Result<BlockChainTip, EsploraError> {
+        state.tracker.set_descriptors(descs);
+
+        let (tip_hash, tip_info) = self.tip()?;
+        if let Some(synced_tip) = state.synced_tip {
+            if !self.is_in_chain(&synced_tip)? {
+                for hash in state.tracker.script_hashes() {
+                    state.tracker.invalidate_history(&hash);
+                }
+                state.blocks.clear();
+            }
+        }
+
+        state.tracker.derive_scripts();
+        let mut to_update = state.tracker.script_hashes();
+        while !to_update.is_empty() {
+            // Query the history of the scripts whose status changed.
+            self.update_statuses(state, &to_update)?;
+            for hash in state.tracker.outdated_scripts() {
+                let mut history = Vec::new();
+                for (entry, block) in self.script_history(&hash)? {
+                    if let Some(block) = block {
+                        state.blocks.insert(block.height, block);
+                    }
+                    history.push(entry);
+                }
+                state.tracker.set_history(&hash, history);
+            }
+
+            // Fetch the transactions we don't have yet.
+            for txid in state.tracker.missing_txids() {
+                let tx = tx_from_hex(&self.try_get(&format!("tx/{}/hex", txid))?)?;
+                state.tracker.add_tx(tx);
+            }
+
+            // Newly used scripts may require us to track more of them.
+            to_update = state.tracker.derive_scripts();
+        }
+
+        let tip = BlockChainTip {
+            hash: tip_hash,
+            height: tip_info.height,
+        };
+        state.synced_tip = Some(tip);
+        Ok(tip)
+    }
+
+    // Get the block at this height, from the blocks our transactions were confirmed in if we
+    // know about it.
+    fn cached_block_at(&self, state: &State, height: i32) -> Result<Block, EsploraError> {
+        match state.blocks.get(&height) {
+            Some(block) => Ok(*block),
+            None => self.block_at(height),
+        }
+    }
+
+    pub fn genesis_block(&self) -> Result<BlockChainTip, EsploraError> {
+        let block = self.block_at(0)?;
+        Ok(BlockChainTip {
+            hash: block.hash,
+            height: block.height,
+        })
+    }
+
+    pub fn chain_tip(&self) -> Result<BlockChainTip, EsploraError> {
+        let body = self.try_get("blocks/tip/height")?;
+        let height = body.trim().parse().map_err(|_| invalid_response(&body))?;
+        let block = self.block_at(height)?;
+        Ok(BlockChainTip {
+            hash: block.hash,
+            height: block.height,
+        })
+    }
+
+    pub fn tip_time(&self) -> Result<u32, EsploraError> {
+        Ok(self.tip()?.1.time)
+    }
+
+    pub fn tip_median_time_past(&self) -> Result<u32, EsploraError> {
+        Ok(self.tip()?.1.median_time_past)
+    }
+
+    pub fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, EsploraError> {
+        match self.try_block_hash(tip.height) {
+            Ok(hash) => Ok(hash == tip.hash),
+            // The server doesn't know about a block at this height.
+            Err(EsploraError::Server(404, _)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn received_coins(
+        &self,
+        tip: &BlockChainTip,
+        descs: &[descriptors::SinglePathLianaDesc],
+    ) -> Result<Vec<UTxO>, EsploraError> {
+        let mut state = self.state.borrow_mut();
+        let tip_height = self.sync(&mut state, descs)?.height;
+        Ok(state.tracker.received_coins(tip, tip_height))
+    }
+
+    /// Track the scripts of our descriptors at least up to this derivation index (excluded).
+    pub fn track_addresses(&self, end: u32) {
+        self.state.borrow_mut().tracker.track_up_to(end);
+    }
+
+    #[allow(clippy::type_complexity)]
+    pub fn confirmed_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), EsploraError> {
+        let state = self.state.borrow();
+        let tip_height = self.tip()?.1.height;
+        state
+            .tracker
+            .confirmed_coins(outpoints, tip_height, |h| self.cached_block_at(&state, h))
+    }
+
+    pub fn spending_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)> {
+        self.state.borrow().tracker.spending_coins(outpoints)
+    }
+
+    pub fn spent_coins(
+        &self,
+        outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, EsploraError> {
+        let state = self.state.borrow();
+        state
+            .tracker
+            .spent_coins(outpoints, |h| self.cached_block_at(&state, h))
+    }
+
+    pub fn common_ancestor(
+        &self,
+        tip: &BlockChainTip,
+    ) -> Result<Option<BlockChainTip>, EsploraError> {
+        let mut ancestor = *tip;
+        loop {
+            let status = match self.get_json(&format!("block/{}/status", ancestor.hash)) {
+                Ok(status) => status,
+                // The server doesn't know about this block. Rewind deep enough, we'll go further
+                // if this isn't.
+                Err(EsploraError::Server(404, _)) => {
+                    let height = cmp::max(tip.height - MAX_REORG_DEPTH, 0);
+                    let hash = self.try_block_hash(height)?;
+                    return Ok(Some(BlockChainTip { hash, height }));
+                }
+                Err(e) => return Err(e),
+            };
+            if status
+                .get("in_best_chain")
+                .and_then(Json::as_bool)
+                .ok_or_else(|| invalid_response(&status.to_string()))?
+            {
+                return Ok(Some(ancestor));
+            }
+            let prev_hash = match self.block_info(&ancestor.hash)?.previous_blockhash {
+                Some(hash) => hash,
+                None => return Ok(None),
+            };
+            ancestor = BlockChainTip {
+                hash: prev_hash,
+                height: ancestor.height - 1,
+            };
+        }
+    }
+
+    pub fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), EsploraError> {
+        self.try_request(minreq::Method::Post, "tx", Some(&encode::serialize_hex(tx)))
+            .map(|_| ())
+    }
+
+    /// All the history of our scripts is always available from the server. Forget about what we
+    /// know so it's all queried again at the next poll.
+    pub fn start_rescan(&self) {
+        let mut state = self.state.borrow_mut();
+        state.tracker.clear();
+        state.blocks.clear();
+        state.synced_tip = None;
+    }
+
+    /// Get the height and hash of the last block with a timestamp below the given one.
+    pub fn block_before_date(&self, timestamp: u32) -> Result<Option<BlockChainTip>, EsploraError> {
+        let tip_height = self.tip()?.1.height;
+        tracker::block_before_date(timestamp, tip_height, |h| self.block_at(h))
+    }
+
+    pub fn wallet_transaction(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, EsploraError> {
+        {
+            let state = self.state.borrow();
+            if let Some((tx, height)) = state.tracker.transaction(txid) {
+                let block = match height {
+                    Some(h) => Some(self.cached_block_at(&state, h)?),
+                    None => None,
+                };
+                return Ok(Some((tx.clone(), block)));
+            }
+        }
+
+        let tx = match self.try_get(&format!("tx/{}/hex", txid)) {
+            Ok(hex) => tx_from_hex(&hex)?,
+            Err(EsploraError::Server(404, _)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let status = self.get_json(&format!("tx/{}/status", txid))?;
+        let (_, block) = history_entry_from_json(&serde_json::json!({
+            "txid": txid.to_string(),
+            "status": status,
+        }))?;
+
+        Ok(Some((tx, block)))
+    }
+
+    pub fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry> {
+        self.state.borrow().tracker.mempool_entry(txid)
+    }
+
+    /// Get the Esplora server's estimate of the feerate, in sats/vb, needed for a transaction to
+    /// confirm within each of these numbers of blocks. Esplora only provides estimates for some
+    /// targets, we use the one for the closest lower target available.
+    pub fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, EsploraError> {
+        let estimates = self.get_json("fee-estimates")?;
+        let estimates: Vec<(u16, f64)> = estimates
+            .as_object()
+            .ok_or_else(|| invalid_response(&estimates.to_string()))?
+            .iter()
+            .filter_map(|(target, feerate)| Some((target.parse().ok()?, feerate.as_f64()?)))
+            .collect();
+
+        Ok(conf_targets
+            .iter()
+            .filter_map(|conf_target| {
+                let feerate = estimates
+                    .iter()
+                    .filter(|(target, _)| target <= conf_target)
+                    .max_by_key(|(target, _)| *target)?
+                    .1;
+                // Round it up to the next sat/vb.
+                Some((*conf_target, feerate.ceil() as u64))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_GAP_LIMIT;
+
+    use std::{
+        io::{self, BufRead, Read, Write},
+        net, sync,
+    };
+
+    use miniscript::bitcoin::{bip32, block, secp256k1};
+
+    // A stand-in for an Esplora server, serving a fake chain.
+    struct FakeChain {
+        headers: Vec<block::Header>,
+        // Blocks which were reorganized out of the chain.
+        stale_headers: Vec<block::Header>,
+        // All the transactions along with their confirmation height, 0 if unconfirmed.
+        txs: HashMap<bitcoin::Txid, (bitcoin::Transaction, i32)>,
+        broadcast: Vec<bitcoin::Transaction>,
+        // The number of requests for the history of a script.
+        history_requests: usize,
+        // Whether to send an invalid response to the requests for the tip.
+        invalid_tip: bool,
+    }
+
+    impl FakeChain {
+        fn new() -> FakeChain {
+            let genesis =
+                bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest).header;
+            FakeChain {
+                headers: vec![genesis],
+                stale_headers: Vec::new(),
+                txs: HashMap::new(),
+                broadcast: Vec::new(),
+                history_requests: 0,
+                invalid_tip: false,
+            }
+        }
+
+        // Mine a block confirming these transactions.
+        fn mine(&mut self, txids: &[bitcoin::Txid]) {
+            let prev = self.headers.last().unwrap();
+            let header = block::Header {
+                prev_blockhash: prev.block_hash(),
+                time: prev.time + 600,
+                nonce: self.headers.len() as u32 + 1_000 * self.stale_headers.len() as u32,
+                ..*prev
+            };
+            self.headers.push(header);
+            for txid in txids {
+                self.txs.get_mut(txid).unwrap().1 = self.headers.len() as i32 - 1;
+            }
+        }
+
+        // Reorganize the last block out of the chain, unconfirming its transactions.
+        fn reorg(&mut self) {
+            let height = self.headers.len() as i32 - 1;
+            let header = self.headers.pop().unwrap();
+            self.stale_headers.push(header);
+            for (_, tx_height) in self.txs.values_mut() {
+                if *tx_height == height {
+                    *tx_height = 0;
+                }
+            }
+        }
+
+        // Get a block by hash, along with its height and whether it's in the best chain.
+        fn block(&self, hash: &str) -> Option<(i32, block::Header, bool)> {
+            if let Some(height) = self
+                .headers
+                .iter()
+                .position(|h| h.block_hash().to_string() == hash)
+            {
+                return Some((height as i32, self.headers[height], true));
+            }
+            let header = self
+                .stale_headers
+                .iter()
+                .find(|h| h.block_hash().to_string() == hash)?;
+            let prev_height = self.block(&header.prev_blockhash.to_string())?.0;
+            Some((prev_height + 1, *header, false))
+        }
+
+        fn tx_json(&self, txid: &bitcoin::Txid) -> Json {
+            let (tx, height) = &self.txs[txid];
+            let status = if *height > 0 {
+                let header = &self.headers[*height as usize];
+                serde_json::json!({
+                    "confirmed": true,
+                    "block_height": height,
+                    "block_hash": header.block_hash().to_string(),
+                    "block_time": header.time,
+                })
+            } else {
+                serde_json::json!({ "confirmed": false })
+            };
+            serde_json::json!({
+                "txid": txid.to_string(),
+                "version": tx.version,
+                "locktime": tx.lock_time.to_consensus_u32(),
+                "fee": 1_000,
+                "status": status,
+            })
+        }
+
+        // The transactions paying to or spending from this script, in the order Esplora returns
+        // them: the unconfirmed first, then the confirmed ones from the most recent.
+        fn history(&self, hash: &str) -> Vec<bitcoin::Txid> {
+            let pays_to = |op: &bitcoin::OutPoint| {
+                self.txs
+                    .get(&op.txid)
+                    .and_then(|(tx, _)| tx.output.get(op.vout as usize))
+                    .map(|txo| script_hash(&txo.script_pubkey) == hash)
+                    .unwrap_or(false)
+            };
+            let mut history: Vec<(bitcoin::Txid, i32)> = self
+                .txs
+                .iter()
+                .filter(|(_, (tx, _))| {
+                    tx.output
+                        .iter()
+                        .any(|txo| script_hash(&txo.script_pubkey) == hash)
+                        || tx.input.iter().any(|txin| pays_to(&txin.previous_output))
+                })
+                .map(|(txid, (_, height))| (*txid, *height))
+                .collect();
+            history.sort_by_key(|(txid, height)| {
+                (if *height > 0 { -*height } else { i32::MIN }, *txid)
+            });
+            history.into_iter().map(|(txid, _)| txid).collect()
+        }
+
+        fn respond(&mut self, method: &str, path: &str, body: &str) -> (u16, String) {
+            let not_found = (404, "Not found".to_string());
+            let parts: Vec<&str> = path.trim_start_matches("/api/").split('/').collect();
+            match (method, parts.as_slice()) {
+                ("GET", ["blocks", "tip", _]) if self.invalid_tip => (200, "garbage".to_string()),
+                ("GET", ["blocks", "tip", "height"]) => (200, (self.headers.len() - 1).to_string()),
+                ("GET", ["blocks", "tip", "hash"]) => {
+                    (200, self.headers.last().unwrap().block_hash().to_string())
+                }
+                ("GET", ["block-height", height]) => {
+                    match self.headers.get(height.parse::<usize>().unwrap()) {
+                        Some(header) => (200, header.block_hash().to_string()),
+                        None => not_found,
+                    }
+                }
+                ("GET", ["block", hash]) => match self.block(hash) {
+                    Some((height, header, _)) => {
+                        let start = cmp::max(height - 10, 0) as usize;
+                        let mut times: Vec<u32> = self.headers[start..height as usize + 1]
+                            .iter()
+                            .map(|h| h.time)
+                            .collect();
+                        times.sort_unstable();
+                        let prev = if height > 0 {
+                            Json::String(header.prev_blockhash.to_string())
+                        } else {
+                            Json::Null
+                        };
+                        let block = serde_json::json!({
+                            "id": hash,
+                            "height": height,
+                            "timestamp": header.time,
+                            "mediantime": times[times.len() / 2],
+                            "previousblockhash": prev,
+                        });
+                        (200, block.to_string())
+                    }
+                    None => not_found,
+                },
+                ("GET", ["block", hash, "status"]) => match self.block(hash) {
+                    Some((height, _, in_best_chain)) => (
+                        200,
+                        serde_json::json!({ "in_best_chain": in_best_chain, "height": height })
+                            .to_string(),
+                    ),
+                    None => not_found,
+                },
+                ("GET", ["scripthash", hash]) => {
+                    let history = self.history(hash);
+                    let unconfirmed = history.iter().filter(|txid| self.txs[*txid].1 == 0);
+                    let stats = serde_json::json!({
+                        "scripthash": hash,
+                        "chain_stats": { "tx_count": history.len() - unconfirmed.clone().count() },
+                        "mempool_stats": { "tx_count": unconfirmed.count() },
+                    });
+                    (200, stats.to_string())
+                }
+                ("GET", ["scripthash", hash, "txs"]) => {
+                    self.history_requests += 1;
+                    let history = self.history(hash);
+                    let unconfirmed = history.iter().filter(|txid| self.txs[*txid].1 == 0);
+                    let confirmed = history
+                        .iter()
+                        .filter(|txid| self.txs[*txid].1 > 0)
+                        .take(CHAIN_TXS_PER_PAGE);
+                    let txs: Vec<Json> = unconfirmed
+                        .chain(confirmed)
+                        .map(|txid| self.tx_json(txid))
+                        .collect();
+                    (200, Json::Array(txs).to_string())
+                }
+                ("GET", ["scripthash", hash, "txs", "chain", last_seen]) => {
+                    self.history_requests += 1;
+                    let txs: Vec<Json> = self
+                        .history(hash)
+                        .iter()
+                        .filter(|txid| self.txs[*txid].1 > 0)
+                        .skip_while(|txid| txid.to_string() != *last_seen)
+                        .skip(1)
+                        .take(CHAIN_TXS_PER_PAGE)
+                        .map(|txid| self.tx_json(txid))
+                        .collect();
+                    (200, Json::Array(txs).to_string())
+                }
+                ("GET", ["tx", txid, "hex"]) => {
+                    match self.txs.get(&bitcoin::Txid::from_str(txid).unwrap()) {
+                        Some((tx, _)) => (200, encode::serialize_hex(tx)),
+                        None => not_found,
+                    }
+                }
+                ("GET", ["tx", txid, "status"]) => {
+                    let txid = bitcoin::Txid::from_str(txid).unwrap();
+                    if self.txs.contains_key(&txid) {
+                        (200, self.tx_json(&txid)["status"].to_string())
+                    } else {
+                        not_found
+                    }
+                }
+                ("POST", ["tx"]) => {
+                    let tx = tx_from_hex(body).unwrap();
+                    if tx.output.is_empty() {
+                        (400, "sendrawtransaction RPC error: {\"code\":-26,\"message\":\"bad-txns-vout-empty\"}".to_string())
+                    } else {
+                        self.broadcast.push(tx.clone());
+                        (200, tx.txid().to_string())
+                    }
+                }
+                ("GET", ["fee-estimates"]) => (
+                    200,
+                    serde_json::json!({ "2": 12.1, "3": 10.0, "6": 5.5, "144": 1.0 }).to_string(),
+                ),
+                _ => panic!("Unexpected request '{} {}'", method, path),
+            }
+        }
+    }
+
+    fn serve(stream: net::TcpStream, chain: sync::Arc<sync::Mutex<FakeChain>>) {
+        let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            let (name, value) = line.split_at(line.find(':').unwrap());
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value[1..].trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+
+        let mut words = request_line.split_whitespace();
+        let (method, path) = (words.next().unwrap(), words.next().unwrap());
+        let (status, response) =
+            chain
+                .lock()
+                .unwrap()
+                .respond(method, path, &String::from_utf8(body).unwrap());
+        write!(
+            writer,
+            "HTTP/1.1 {} Whatever\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            response.len(),
+            response
+        )
+        .unwrap();
+    }
+
+    // Start a server for this fake chain, returns its base URL.
+    fn start_server(chain: sync::Arc<sync::Mutex<FakeChain>>) -> String {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let chain = chain.clone();
+                thread::spawn(move || serve(stream.unwrap(), chain));
+            }
+        });
+        format!("http://{}/api/", addr)
+    }
+
+    fn dummy_tx(
+        inputs: &[bitcoin::OutPoint],
+        outputs: &[(&bitcoin::Script, u64)],
+        lock_time: u32,
+    ) -> bitcoin::Transaction {
+        // A transaction without input would be mistaken for a Segwit one when deserialized.
+        let dummy_input = [bitcoin::OutPoint::new(
+            bitcoin::Txid::all_zeros(),
+            lock_time,
+        )];
+        let inputs = if inputs.is_empty() {
+            &dummy_input[..]
+        } else {
+            inputs
+        };
+        bitcoin::Transaction {
+            version: 2,
+            lock_time: bitcoin::absolute::LockTime::from_consensus(lock_time),
+            input: inputs
+                .iter()
+                .map(|op| bitcoin::TxIn {
+                    previous_output: *op,
+                    ..bitcoin::TxIn::default()
+                })
+                .collect(),
+            output: outputs
+                .iter()
+                .map(|(spk, value)| bitcoin::TxOut {
+                    script_pubkey: spk.to_owned().into(),
+                    value: *value,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn esplora_interface() {
+        let secp = secp256k1::Secp256k1::verification_only();
+        let desc = descriptors::LianaDescriptor::from_str("wsh(or_d(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),and_v(v:pkh([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*),older(10000))))").unwrap();
+        let descs = [
+            desc.receive_descriptor().clone(),
+            desc.change_descriptor().clone(),
+        ];
+        let receive_spk = |index: u32| {
+            descs[0]
+                .derive(bip32::ChildNumber::from(index), &secp)
+                .script_pubkey()
+        };
+        let change_spk = |index: u32| {
+            descs[1]
+                .derive(bip32::ChildNumber::from(index), &secp)
+                .script_pubkey()
+        };
+        let external_spk = bitcoin::ScriptBuf::new_v0_p2wsh(&bitcoin::WScriptHash::all_zeros());
+
+        let chain = sync::Arc::new(sync::Mutex::new(FakeChain::new()));
+        let base_url = start_server(chain.clone());
+        let config = config::EsploraConfig { base_url };
+
+        // It must be on the same network as the server.
+        match Esplora::new(&config, bitcoin::Network::Bitcoin, DEFAULT_GAP_LIMIT) {
+            Err(EsploraError::NetworkMismatch(..)) => {}
+            _ => panic!("Must fail with a network mismatch"),
+        }
+        let esplora = Esplora::new(&config, bitcoin::Network::Regtest, DEFAULT_GAP_LIMIT).unwrap();
+        let genesis = esplora.genesis_block().unwrap();
+        assert_eq!(genesis.height, 0);
+        assert_eq!(esplora.chain_tip().unwrap(), genesis);
+
+        // Receive a coin on the first receive address, one further than the gap limit (but within
+        // the gap limit past the first one), and one on an external address.
+        let tx_a = dummy_tx(
+            &[],
+            &[(&receive_spk(0), 100_000), (&external_spk, 10_000)],
+            1,
+        );
+        let tx_b = dummy_tx(&[], &[(&receive_spk(150), 200_000)], 2);
+        let tx_c = dummy_tx(&[], &[(&receive_spk(300), 300_000)], 3);
+        {
+            let mut chain = chain.lock().unwrap();
+            for tx in &[&tx_a, &tx_b, &tx_c] {
+                chain.txs.insert(tx.txid(), ((*tx).clone(), 0));
+            }
+        }
+        let received = esplora.received_coins(&genesis, &descs).unwrap();
+        assert_eq!(received.len(), 3);
+        for (tx, spk, value) in &[
+            (&tx_a, receive_spk(0), 100_000),
+            (&tx_b, receive_spk(150), 200_000),
+            (&tx_c, receive_spk(300), 300_000),
+        ] {
+            let coin = received
+                .iter()
+                .find(|c| c.outpoint.txid == tx.txid())
+                .unwrap();
+            assert_eq!(coin.outpoint.vout, 0);
+            assert_eq!(coin.amount.to_sat(), *value);
+            assert_eq!(coin.block_height, None);
+            assert!(!coin.is_immature);
+            assert_eq!(
+                coin.address.clone().assume_checked(),
+                bitcoin::Address::from_script(spk, bitcoin::Network::Regtest).unwrap()
+            );
+        }
+        let mempool_entry = esplora.mempool_entry(&tx_a.txid()).unwrap();
+        assert_eq!(mempool_entry.fee.to_sat(), 1_000);
+        assert_eq!(mempool_entry.vsize, tx_a.vsize() as u64);
+        let op_a = bitcoin::OutPoint::new(tx_a.txid(), 0);
+        let op_b = bitcoin::OutPoint::new(tx_b.txid(), 0);
+        let op_c = bitcoin::OutPoint::new(tx_c.txid(), 0);
+        assert_eq!(
+            esplora.confirmed_coins(&[op_a, op_b, op_c]).unwrap(),
+            (vec![], vec![])
+        );
+
+        // Now the first two get confirmed, and the last one is dropped from the mempool.
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.mine(&[tx_a.txid(), tx_b.txid()]);
+            chain.txs.remove(&tx_c.txid());
+        }
+        let tip = esplora.chain_tip().unwrap();
+        assert_eq!(tip.height, 1);
+        assert!(esplora.is_in_chain(&genesis).unwrap());
+        let received = esplora.received_coins(&genesis, &descs).unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received.iter().all(|c| c.block_height == Some(1)));
+        let (confirmed, expired) = esplora.confirmed_coins(&[op_a, op_b, op_c]).unwrap();
+        assert_eq!(expired, vec![op_c]);
+        assert_eq!(confirmed.len(), 2);
+        assert!(confirmed
+            .iter()
+            .all(|(_, height, time)| *height == 1 && *time == esplora.tip_time().unwrap()));
+        // They won't be reported as received since the new tip.
+        assert!(esplora.received_coins(&tip, &descs).unwrap().is_empty());
+        assert!(esplora.mempool_entry(&tx_a.txid()).is_none());
+        let tip_block = Block {
+            hash: tip.hash,
+            height: 1,
+            time: esplora.tip_time().unwrap(),
+        };
+        assert_eq!(
+            esplora.wallet_transaction(&tx_a.txid()).unwrap().unwrap(),
+            (tx_a.clone(), Some(tip_block))
+        );
+
+        // Spend the first coin to a change address. It gets replaced by another spend, which gets
+        // confirmed.
+        let tx_d = dummy_tx(&[op_a], &[(&change_spk(0), 90_000)], 4);
+        chain
+            .lock()
+            .unwrap()
+            .txs
+            .insert(tx_d.txid(), (tx_d.clone(), 0));
+        let received = esplora.received_coins(&tip, &descs).unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].outpoint, bitcoin::OutPoint::new(tx_d.txid(), 0));
+        assert_eq!(
+            esplora.spending_coins(&[op_a, op_b]),
+            vec![(op_a, tx_d.txid())]
+        );
+        assert!(esplora
+            .spent_coins(&[(op_a, tx_d.txid())])
+            .unwrap()
+            .is_empty());
+        let tx_e = dummy_tx(&[op_a], &[(&external_spk, 95_000)], 5);
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.txs.remove(&tx_d.txid());
+            chain.txs.insert(tx_e.txid(), (tx_e.clone(), 0));
+            chain.mine(&[tx_e.txid()]);
+        }
+        assert!(esplora.received_coins(&tip, &descs).unwrap().is_empty());
+        let spent = esplora.spent_coins(&[(op_a, tx_d.txid())]).unwrap();
+        assert_eq!(spent.len(), 1);
+        assert_eq!(
+            (spent[0].0, spent[0].1, spent[0].2.height),
+            (op_a, tx_e.txid(), 2)
+        );
+        // The history of scripts which didn't change isn't queried again.
+        let history_requests = chain.lock().unwrap().history_requests;
+        assert!(esplora.received_coins(&tip, &descs).unwrap().is_empty());
+        assert_eq!(chain.lock().unwrap().history_requests, history_requests);
+
+        // Many confirmed transactions on the same script are fetched across several pages.
+        let page_txs: Vec<bitcoin::Transaction> = (0..CHAIN_TXS_PER_PAGE as u32 + 5)
+            .map(|i| dummy_tx(&[], &[(&receive_spk(1), 1_000)], 100 + i))
+            .collect();
+        {
+            let mut chain = chain.lock().unwrap();
+            for tx in &page_txs {
+                chain.txs.insert(tx.txid(), (tx.clone(), 0));
+            }
+            let txids: Vec<bitcoin::Txid> = page_txs.iter().map(|tx| tx.txid()).collect();
+            chain.mine(&txids[..10]);
+            chain.mine(&txids[10..]);
+        }
+        let tip = esplora.chain_tip().unwrap();
+        assert_eq!(tip.height, 4);
+        let received = esplora.received_coins(&genesis, &descs).unwrap();
+        assert!(page_txs
+            .iter()
+            .all(|tx| received.iter().any(|c| c.outpoint.txid == tx.txid())));
+
+        // Upon reorg we'll find the common ancestor with our former chain, even if the blocks
+        // were reorganized out one by one.
+        {
+            let mut chain = chain.lock().unwrap();
+            chain.reorg();
+            chain.mine(&[]);
+            chain.reorg();
+            chain.reorg();
+            chain.mine(&[]);
+            chain.mine(&[]);
+        }
+        assert!(!esplora.is_in_chain(&tip).unwrap());
+        let ancestor = esplora.common_ancestor(&tip).unwrap().unwrap();
+        assert_eq!(ancestor.height, 2);
+        assert!(esplora.is_in_chain(&ancestor).unwrap());
+        // If the server doesn't know about our tip we rewind deeply.
+        let unknown_tip = BlockChainTip {
+            hash: bitcoin::BlockHash::all_zeros(),
+            height: 4,
+        };
+        assert_eq!(
+            esplora.common_ancestor(&unknown_tip).unwrap(),
+            Some(genesis)
+        );
+        // Transactions confirmed again in a different block are reported at their new height,
+        // even though the statistics of their scripts didn't change.
+        {
+            let mut chain = chain.lock().unwrap();
+            let txids: Vec<bitcoin::Txid> = page_txs.iter().map(|tx| tx.txid()).collect();
+            chain.mine(&txids);
+        }
+        let received = esplora.received_coins(&ancestor, &descs).unwrap();
+        assert!(page_txs.iter().all(|tx| received
+            .iter()
+            .any(|c| c.outpoint.txid == tx.txid() && c.block_height == Some(5))));
+
+        // We can broadcast transactions, and get the error from the server.
+        esplora.broadcast_tx(&tx_d).unwrap();
+        assert_eq!(chain.lock().unwrap().broadcast, vec![tx_d]);
+        let tx_f = dummy_tx(&[op_b], &[], 6);
+        match esplora.broadcast_tx(&tx_f) {
+            Err(EsploraError::Server(400, e)) => assert!(e.contains("bad-txns-vout-empty")),
+            _ => panic!("Must fail to broadcast"),
+        }
+
+        // Get fee estimates and the block at a given time.
+        let estimates = esplora.fee_estimates(&[1, 2, 5, 1008]).unwrap();
+        assert_eq!(estimates.get(&1), None);
+        assert_eq!(estimates.get(&2), Some(&13));
+        assert_eq!(estimates.get(&5), Some(&10));
+        assert_eq!(estimates.get(&1008), Some(&1));
+        let genesis_time = bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Regtest)
+            .header
+            .time;
+        assert_eq!(esplora.block_before_date(genesis_time - 1).unwrap(), None);
+        assert_eq!(
+            esplora
+                .block_before_date(genesis_time + 601)
+                .unwrap()
+                .map(|b| b.height),
+            Some(1)
+        );
+        assert!(esplora.tip_median_time_past().unwrap() <= esplora.tip_time().unwrap());
+
+        // Invalid responses from the server are reported as errors.
+        chain.lock().unwrap().invalid_tip = true;
+        assert!(matches!(
+            esplora.chain_tip(),
+            Err(EsploraError::InvalidResponse(_))
+        ));
+        assert!(matches!(
+            esplora.received_coins(&tip, &descs),
+            Err(EsploraError::InvalidResponse(_))
+        ));
+    }
+}
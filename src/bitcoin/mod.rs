@@ -3,14 +3,21 @@
 //! Broadcast transactions, poll for new unspent coins, gather fee estimates.
 
 pub mod d;
+pub mod electrum;
+pub mod esplora;
 pub mod poller;
+mod tracker;
 
 use crate::{
-    bitcoin::d::{BitcoindError, CachedTxGetter, LSBlockEntry},
+    bitcoin::{
+        d::{BitcoindError, CachedTxGetter, LSBlockEntry},
+        electrum::ElectrumError,
+        esplora::EsploraError,
+    },
     descriptors,
 };
 
-use std::{fmt, sync};
+use std::{collections::HashMap, fmt, sync};
 
 use miniscript::bitcoin::{self, address};
 
@@ -24,6 +31,15 @@ pub struct Block {
     pub time: u32,
 }
 
+/// Information about an unconfirmed transaction in the mempool
+#[derive(Debug, Clone, Eq, PartialEq, Copy)]
+pub struct MempoolEntry {
+    /// The virtual size of the transaction.
+    pub vsize: u64,
+    /// The fee paid by this transaction, not accounting for its ancestors nor descendants.
+    pub fee: bitcoin::Amount,
+}
+
 /// Information about the best block in the chain
 #[derive(Debug, Clone, Eq, PartialEq, Copy)]
 pub struct BlockChainTip {
@@ -38,50 +54,66 @@ impl fmt::Display for BlockChainTip {
 }
 
 /// Our Bitcoin backend.
+///
+/// A remote backend may be unreachable or misbehave. Queries to it return an error in this case,
+/// and the caller gives up on the poll or command it was processing.
 pub trait BitcoinInterface: Send {
-    fn genesis_block(&self) -> BlockChainTip;
+    fn genesis_block(&self) -> Result<BlockChainTip, String>;
 
     /// Get the progress of the block chain synchronization.
     /// Returns a percentage between 0 and 1.
     fn sync_progress(&self) -> f64;
 
     /// Get the best block info.
-    fn chain_tip(&self) -> BlockChainTip;
+    fn chain_tip(&self) -> Result<BlockChainTip, String>;
 
     /// Get the timestamp set in the best block's header.
-    fn tip_time(&self) -> u32;
+    fn tip_time(&self) -> Result<u32, String>;
+
+    /// Get the median time past of the best block, against which time-based nLockTimes are
+    /// checked for inclusion in the next block.
+    fn tip_median_time_past(&self) -> Result<u32, String>;
 
     /// Check whether this former tip is part of the current best chain.
-    fn is_in_chain(&self, tip: &BlockChainTip) -> bool;
+    fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, String>;
 
     /// Get coins received since the specified tip.
     fn received_coins(
         &self,
         tip: &BlockChainTip,
         descs: &[descriptors::SinglePathLianaDesc],
-    ) -> Vec<UTxO>;
+    ) -> Result<Vec<UTxO>, String>;
+
+    /// Make sure we look for coins on the addresses of these descriptors up to this derivation
+    /// index (excluded).
+    fn track_addresses(
+        &self,
+        descs: &[descriptors::SinglePathLianaDesc],
+        end: u32,
+    ) -> Result<(), String>;
 
     /// Get all coins that were confirmed, and at what height and time. Along with "expired"
     /// unconfirmed coins (for instance whose creating transaction may have been replaced).
+    #[allow(clippy::type_complexity)]
     fn confirmed_coins(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> (Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>);
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), String>;
 
     /// Get all coins that are being spent, and the spending txid.
     fn spending_coins(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)>;
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid)>, String>;
 
     /// Get all coins that are spent with the final spend tx txid and blocktime.
     fn spent_coins(
         &self,
         outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>;
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, String>;
 
     /// Get the common ancestor between the Bitcoin backend's tip and the given tip.
-    fn common_ancestor(&self, tip: &BlockChainTip) -> Option<BlockChainTip>;
+    fn common_ancestor(&self, tip: &BlockChainTip) -> Result<Option<BlockChainTip>, String>;
 
     /// Broadcast this transaction to the Bitcoin P2P network
     fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), String>;
@@ -99,46 +131,55 @@ pub trait BitcoinInterface: Send {
 
     /// Get the last block chain tip with a timestamp below this. Timestamp must be a valid block
     /// timestamp.
-    fn block_before_date(&self, timestamp: u32) -> Option<BlockChainTip>;
+    fn block_before_date(&self, timestamp: u32) -> Result<Option<BlockChainTip>, String>;
 
     /// Get a transaction related to the wallet along with potential confirmation info.
+    #[allow(clippy::type_complexity)]
     fn wallet_transaction(
         &self,
         txid: &bitcoin::Txid,
-    ) -> Option<(bitcoin::Transaction, Option<Block>)>;
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, String>;
+
+    /// Get the fee and size of a transaction from the mempool, if it's there.
+    fn mempool_entry(&self, txid: &bitcoin::Txid) -> Result<Option<MempoolEntry>, String>;
+
+    /// Get an estimate of the feerate, in sats/vb, needed for a transaction to confirm within
+    /// each of these numbers of blocks. Targets for which no estimate is available are omitted.
+    fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, String>;
 }
 
 impl BitcoinInterface for d::BitcoinD {
-    fn genesis_block(&self) -> BlockChainTip {
+    fn genesis_block(&self) -> Result<BlockChainTip, String> {
         let height = 0;
         let hash = self
             .get_block_hash(height)
             .expect("Genesis block hash must always be there");
-        BlockChainTip { hash, height }
+        Ok(BlockChainTip { hash, height })
     }
 
     fn sync_progress(&self) -> f64 {
         self.sync_progress()
     }
 
-    fn chain_tip(&self) -> BlockChainTip {
-        self.chain_tip()
+    fn chain_tip(&self) -> Result<BlockChainTip, String> {
+        Ok(self.chain_tip())
     }
 
-    fn is_in_chain(&self, tip: &BlockChainTip) -> bool {
-        self.get_block_hash(tip.height)
+    fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, String> {
+        Ok(self
+            .get_block_hash(tip.height)
             .map(|bh| bh == tip.hash)
-            .unwrap_or(false)
+            .unwrap_or(false))
     }
 
     fn received_coins(
         &self,
         tip: &BlockChainTip,
         descs: &[descriptors::SinglePathLianaDesc],
-    ) -> Vec<UTxO> {
+    ) -> Result<Vec<UTxO>, String> {
         let lsb_res = self.list_since_block(&tip.hash);
 
-        lsb_res
+        Ok(lsb_res
             .received_coins
             .into_iter()
             .filter_map(|entry| {
@@ -165,13 +206,21 @@ impl BitcoinInterface for d::BitcoinD {
                     None
                 }
             })
-            .collect()
+            .collect())
+    }
+
+    fn track_addresses(
+        &self,
+        descs: &[descriptors::SinglePathLianaDesc],
+        end: u32,
+    ) -> Result<(), String> {
+        self.extend_descriptors_range(descs, end)
     }
 
     fn confirmed_coins(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> (Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>) {
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), String> {
         // The confirmed and expired coins to be returned.
         let mut confirmed = Vec::with_capacity(outpoints.len());
         let mut expired = Vec::new();
@@ -203,13 +252,13 @@ impl BitcoinInterface for d::BitcoinD {
             }
         }
 
-        (confirmed, expired)
+        Ok((confirmed, expired))
     }
 
     fn spending_coins(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)> {
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid)>, String> {
         let mut spent = Vec::with_capacity(outpoints.len());
 
         for op in outpoints {
@@ -229,13 +278,13 @@ impl BitcoinInterface for d::BitcoinD {
             }
         }
 
-        spent
+        Ok(spent)
     }
 
     fn spent_coins(
         &self,
         outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)> {
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, String> {
         // Spend coins to be returned.
         let mut spent = Vec::with_capacity(outpoints.len());
         // Cached calls to `gettransaction`.
@@ -266,22 +315,26 @@ impl BitcoinInterface for d::BitcoinD {
             }
         }
 
-        spent
+        Ok(spent)
     }
 
-    fn common_ancestor(&self, tip: &BlockChainTip) -> Option<BlockChainTip> {
+    fn common_ancestor(&self, tip: &BlockChainTip) -> Result<Option<BlockChainTip>, String> {
         let mut stats = self.get_block_stats(tip.hash);
         let mut ancestor = *tip;
 
         while stats.confirmations == -1 {
-            stats = self.get_block_stats(stats.previous_blockhash?);
+            let prev_hash = match stats.previous_blockhash {
+                Some(hash) => hash,
+                None => return Ok(None),
+            };
+            stats = self.get_block_stats(prev_hash);
             ancestor = BlockChainTip {
                 hash: stats.blockhash,
                 height: stats.height,
             };
         }
 
-        Some(ancestor)
+        Ok(Some(ancestor))
     }
 
     fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), String> {
@@ -310,26 +363,270 @@ impl BitcoinInterface for d::BitcoinD {
         self.rescan_progress()
     }
 
-    fn block_before_date(&self, timestamp: u32) -> Option<BlockChainTip> {
-        self.tip_before_timestamp(timestamp)
+    fn block_before_date(&self, timestamp: u32) -> Result<Option<BlockChainTip>, String> {
+        Ok(self.tip_before_timestamp(timestamp))
     }
 
-    fn tip_time(&self) -> u32 {
+    fn tip_time(&self) -> Result<u32, String> {
         let tip = self.chain_tip();
-        self.get_block_stats(tip.hash).time
+        Ok(self.get_block_stats(tip.hash).time)
+    }
+
+    fn tip_median_time_past(&self) -> Result<u32, String> {
+        let tip = self.chain_tip();
+        Ok(self.get_block_stats(tip.hash).median_time_past)
     }
 
     fn wallet_transaction(
         &self,
         txid: &bitcoin::Txid,
-    ) -> Option<(bitcoin::Transaction, Option<Block>)> {
-        self.get_transaction(txid).map(|res| (res.tx, res.block))
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, String> {
+        Ok(self.get_transaction(txid).map(|res| (res.tx, res.block)))
+    }
+
+    fn mempool_entry(&self, txid: &bitcoin::Txid) -> Result<Option<MempoolEntry>, String> {
+        Ok(self.mempool_entry(txid))
+    }
+
+    fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, String> {
+        Ok(conf_targets
+            .iter()
+            .filter_map(|target| {
+                self.estimate_smart_fee(*target)
+                    .map(|feerate| (*target, feerate))
+            })
+            .collect())
+    }
+}
+
+impl BitcoinInterface for electrum::Electrum {
+    fn genesis_block(&self) -> Result<BlockChainTip, String> {
+        self.genesis_block().map_err(|e| e.to_string())
+    }
+
+    fn sync_progress(&self) -> f64 {
+        // The Electrum server only serves requests once it's synced.
+        1.0
+    }
+
+    fn chain_tip(&self) -> Result<BlockChainTip, String> {
+        self.chain_tip().map_err(|e| e.to_string())
+    }
+
+    fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, String> {
+        self.is_in_chain(tip).map_err(|e| e.to_string())
+    }
+
+    fn received_coins(
+        &self,
+        tip: &BlockChainTip,
+        descs: &[descriptors::SinglePathLianaDesc],
+    ) -> Result<Vec<UTxO>, String> {
+        self.received_coins(tip, descs).map_err(|e| e.to_string())
+    }
+
+    fn track_addresses(
+        &self,
+        _descs: &[descriptors::SinglePathLianaDesc],
+        end: u32,
+    ) -> Result<(), String> {
+        self.track_addresses(end);
+        Ok(())
+    }
+
+    fn confirmed_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), String> {
+        self.confirmed_coins(outpoints).map_err(|e| e.to_string())
+    }
+
+    fn spending_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid)>, String> {
+        Ok(self.spending_coins(outpoints))
+    }
+
+    fn spent_coins(
+        &self,
+        outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, String> {
+        self.spent_coins(outpoints).map_err(|e| e.to_string())
+    }
+
+    fn common_ancestor(&self, tip: &BlockChainTip) -> Result<Option<BlockChainTip>, String> {
+        self.common_ancestor(tip).map_err(|e| e.to_string())
+    }
+
+    fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), String> {
+        self.broadcast_tx(tx).map_err(|e| match e {
+            ElectrumError::Server(e) => e,
+            e => e.to_string(),
+        })
+    }
+
+    fn start_rescan(
+        &self,
+        _desc: &descriptors::LianaDescriptor,
+        _timestamp: u32,
+    ) -> Result<(), String> {
+        self.start_rescan();
+        Ok(())
+    }
+
+    fn rescan_progress(&self) -> Option<f64> {
+        // There is no rescan to wait for, the whole history is available from the server.
+        None
+    }
+
+    fn block_before_date(&self, timestamp: u32) -> Result<Option<BlockChainTip>, String> {
+        self.block_before_date(timestamp).map_err(|e| e.to_string())
+    }
+
+    fn tip_time(&self) -> Result<u32, String> {
+        self.tip_time().map_err(|e| e.to_string())
+    }
+
+    fn tip_median_time_past(&self) -> Result<u32, String> {
+        self.tip_median_time_past().map_err(|e| e.to_string())
+    }
+
+    fn wallet_transaction(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, String> {
+        self.wallet_transaction(txid).map_err(|e| e.to_string())
+    }
+
+    fn mempool_entry(&self, txid: &bitcoin::Txid) -> Result<Option<MempoolEntry>, String> {
+        Ok(self.mempool_entry(txid))
+    }
+
+    fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, String> {
+        let mut estimates = HashMap::with_capacity(conf_targets.len());
+        for target in conf_targets {
+            if let Some(feerate) = self.estimate_fee(*target).map_err(|e| e.to_string())? {
+                estimates.insert(*target, feerate);
+            }
+        }
+        Ok(estimates)
+    }
+}
+
+impl BitcoinInterface for esplora::Esplora {
+    fn genesis_block(&self) -> Result<BlockChainTip, String> {
+        self.genesis_block().map_err(|e| e.to_string())
+    }
+
+    fn sync_progress(&self) -> f64 {
+        // The Esplora server only serves requests once it's synced.
+        1.0
+    }
+
+    fn chain_tip(&self) -> Result<BlockChainTip, String> {
+        self.chain_tip().map_err(|e| e.to_string())
+    }
+
+    fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, String> {
+        self.is_in_chain(tip).map_err(|e| e.to_string())
+    }
+
+    fn received_coins(
+        &self,
+        tip: &BlockChainTip,
+        descs: &[descriptors::SinglePathLianaDesc],
+    ) -> Result<Vec<UTxO>, String> {
+        self.received_coins(tip, descs).map_err(|e| e.to_string())
+    }
+
+    fn track_addresses(
+        &self,
+        _descs: &[descriptors::SinglePathLianaDesc],
+        end: u32,
+    ) -> Result<(), String> {
+        self.track_addresses(end);
+        Ok(())
+    }
+
+    fn confirmed_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), String> {
+        self.confirmed_coins(outpoints).map_err(|e| e.to_string())
+    }
+
+    fn spending_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid)>, String> {
+        Ok(self.spending_coins(outpoints))
+    }
+
+    fn spent_coins(
+        &self,
+        outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, String> {
+        self.spent_coins(outpoints).map_err(|e| e.to_string())
+    }
+
+    fn common_ancestor(&self, tip: &BlockChainTip) -> Result<Option<BlockChainTip>, String> {
+        self.common_ancestor(tip).map_err(|e| e.to_string())
+    }
+
+    fn broadcast_tx(&self, tx: &bitcoin::Transaction) -> Result<(), String> {
+        self.broadcast_tx(tx).map_err(|e| match e {
+            // The server rejected the transaction.
+            EsploraError::Server(code, e) if (400..500).contains(&code) => e,
+            e => e.to_string(),
+        })
+    }
+
+    fn start_rescan(
+        &self,
+        _desc: &descriptors::LianaDescriptor,
+        _timestamp: u32,
+    ) -> Result<(), String> {
+        self.start_rescan();
+        Ok(())
+    }
+
+    fn rescan_progress(&self) -> Option<f64> {
+        // There is no rescan to wait for, the whole history is available from the server.
+        None
+    }
+
+    fn block_before_date(&self, timestamp: u32) -> Result<Option<BlockChainTip>, String> {
+        self.block_before_date(timestamp).map_err(|e| e.to_string())
+    }
+
+    fn tip_time(&self) -> Result<u32, String> {
+        self.tip_time().map_err(|e| e.to_string())
+    }
+
+    fn tip_median_time_past(&self) -> Result<u32, String> {
+        self.tip_median_time_past().map_err(|e| e.to_string())
+    }
+
+    fn wallet_transaction(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, String> {
+        self.wallet_transaction(txid).map_err(|e| e.to_string())
+    }
+
+    fn mempool_entry(&self, txid: &bitcoin::Txid) -> Result<Option<MempoolEntry>, String> {
+        Ok(self.mempool_entry(txid))
+    }
+
+    fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, String> {
+        self.fee_estimates(conf_targets).map_err(|e| e.to_string())
     }
 }
 
 // FIXME: do we need to repeat the entire trait implemenation? Isn't there a nicer way?
 impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>> {
-    fn genesis_block(&self) -> BlockChainTip {
+    fn genesis_block(&self) -> Result<BlockChainTip, String> {
         self.lock().unwrap().genesis_block()
     }
 
@@ -337,11 +634,11 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         self.lock().unwrap().sync_progress()
     }
 
-    fn chain_tip(&self) -> BlockChainTip {
+    fn chain_tip(&self) -> Result<BlockChainTip, String> {
         self.lock().unwrap().chain_tip()
     }
 
-    fn is_in_chain(&self, tip: &BlockChainTip) -> bool {
+    fn is_in_chain(&self, tip: &BlockChainTip) -> Result<bool, String> {
         self.lock().unwrap().is_in_chain(tip)
     }
 
@@ -349,32 +646,40 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         &self,
         tip: &BlockChainTip,
         descs: &[descriptors::SinglePathLianaDesc],
-    ) -> Vec<UTxO> {
+    ) -> Result<Vec<UTxO>, String> {
         self.lock().unwrap().received_coins(tip, descs)
     }
 
+    fn track_addresses(
+        &self,
+        descs: &[descriptors::SinglePathLianaDesc],
+        end: u32,
+    ) -> Result<(), String> {
+        self.lock().unwrap().track_addresses(descs, end)
+    }
+
     fn confirmed_coins(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> (Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>) {
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), String> {
         self.lock().unwrap().confirmed_coins(outpoints)
     }
 
     fn spending_coins(
         &self,
         outpoints: &[bitcoin::OutPoint],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)> {
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid)>, String> {
         self.lock().unwrap().spending_coins(outpoints)
     }
 
     fn spent_coins(
         &self,
         outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)> {
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, String> {
         self.lock().unwrap().spent_coins(outpoints)
     }
 
-    fn common_ancestor(&self, tip: &BlockChainTip) -> Option<BlockChainTip> {
+    fn common_ancestor(&self, tip: &BlockChainTip) -> Result<Option<BlockChainTip>, String> {
         self.lock().unwrap().common_ancestor(tip)
     }
 
@@ -394,20 +699,32 @@ impl BitcoinInterface for sync::Arc<sync::Mutex<dyn BitcoinInterface + 'static>>
         self.lock().unwrap().rescan_progress()
     }
 
-    fn block_before_date(&self, timestamp: u32) -> Option<BlockChainTip> {
+    fn block_before_date(&self, timestamp: u32) -> Result<Option<BlockChainTip>, String> {
         self.lock().unwrap().block_before_date(timestamp)
     }
 
-    fn tip_time(&self) -> u32 {
+    fn tip_time(&self) -> Result<u32, String> {
         self.lock().unwrap().tip_time()
     }
 
+    fn tip_median_time_past(&self) -> Result<u32, String> {
+        self.lock().unwrap().tip_median_time_past()
+    }
+
     fn wallet_transaction(
         &self,
         txid: &bitcoin::Txid,
-    ) -> Option<(bitcoin::Transaction, Option<Block>)> {
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, String> {
         self.lock().unwrap().wallet_transaction(txid)
     }
+
+    fn mempool_entry(&self, txid: &bitcoin::Txid) -> Result<Option<MempoolEntry>, String> {
+        self.lock().unwrap().mempool_entry(txid)
+    }
+
+    fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, String> {
+        self.lock().unwrap().fee_estimates(conf_targets)
+    }
 }
 
 // FIXME: We could avoid this type (and all the conversions entailing allocations) if bitcoind
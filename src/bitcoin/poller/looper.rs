@@ -5,6 +5,8 @@ use crate::{
 };
 
 use std::{
+    cmp,
+    collections::HashSet,
     sync::{self, atomic},
     thread, time,
 };
@@ -32,62 +34,77 @@ fn update_coins(
     previous_tip: &BlockChainTip,
     descs: &[descriptors::SinglePathLianaDesc],
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
-) -> UpdatedCoins {
+) -> Result<UpdatedCoins, String> {
     let network = db_conn.network();
     let curr_coins = db_conn.coins(CoinType::All);
     log::debug!("Current coins: {:?}", curr_coins);
 
     // Start by fetching newly received coins.
     let mut received = Vec::new();
-    for utxo in bit.received_coins(previous_tip, descs) {
-        let UTxO {
-            outpoint,
-            amount,
-            address,
-            is_immature,
-            ..
-        } = utxo;
+    let mut unknown = Vec::new();
+    for utxo in bit.received_coins(previous_tip, descs)? {
         // We can only really treat them if we know the derivation index that was used.
-        let address = match address.require_network(network) {
-            Ok(addr) => addr,
-            Err(e) => {
-                log::error!("Invalid network for address: {}", e);
-                continue;
-            }
-        };
-        if let Some((derivation_index, is_change)) = db_conn.derivation_index_by_address(&address) {
-            // First of if we are receiving coins that are beyond our next derivation index,
-            // adjust it.
-            if derivation_index > db_conn.receive_index() {
-                db_conn.set_receive_index(derivation_index, secp);
-            }
-            if derivation_index > db_conn.change_index() {
-                db_conn.set_change_index(derivation_index, secp);
-            }
-
-            // Now record this coin as a newly received one.
-            if !curr_coins.contains_key(&utxo.outpoint) {
-                let coin = Coin {
-                    outpoint,
-                    is_immature,
-                    amount,
-                    derivation_index,
-                    is_change,
-                    block_info: None,
-                    spend_txid: None,
-                    spend_block: None,
-                };
-                received.push(coin);
+        match utxo.address.clone().require_network(network) {
+            Ok(addr) => unknown.push((utxo, addr)),
+            Err(e) => log::error!("Invalid network for address: {}", e),
+        }
+    }
+    // Bumping the derivation indexes extends the addresses cache, so coins paying to an address
+    // past the gap may become known after treating the others. Keep going until we stop making
+    // progress.
+    loop {
+        let mut progress = false;
+        for (utxo, address) in std::mem::take(&mut unknown) {
+            if let Some((derivation_index, is_change)) =
+                db_conn.derivation_index_by_address(&address)
+            {
+                progress = true;
+
+                // First of if we are receiving coins that are beyond our next derivation index,
+                // adjust it.
+                if derivation_index > db_conn.receive_index() {
+                    db_conn.set_receive_index(derivation_index, secp);
+                }
+                if derivation_index > db_conn.change_index() {
+                    db_conn.set_change_index(derivation_index, secp);
+                }
+
+                // Now record this coin as a newly received one.
+                if !curr_coins.contains_key(&utxo.outpoint) {
+                    let UTxO {
+                        outpoint,
+                        amount,
+                        is_immature,
+                        ..
+                    } = utxo;
+                    let coin = Coin {
+                        outpoint,
+                        is_immature,
+                        is_frozen: false,
+                        amount,
+                        derivation_index,
+                        is_change,
+                        block_info: None,
+                        spend_txid: None,
+                        spend_block: None,
+                    };
+                    received.push(coin);
+                }
+            } else {
+                unknown.push((utxo, address));
             }
-        } else {
-            // TODO: maybe we could try out something here? Like bruteforcing the next 200 indexes?
-            log::error!(
-                "Could not get derivation index for coin '{}' (address: '{}')",
-                &utxo.outpoint,
-                &address
-            );
+        }
+        if !progress || unknown.is_empty() {
+            break;
         }
     }
+    for (utxo, address) in unknown {
+        log::error!(
+            "Could not get derivation index for coin '{}' (address: '{}')",
+            &utxo.outpoint,
+            &address
+        );
+    }
     log::debug!("Newly received coins: {:?}", received);
 
     // We need to take the newly received ones into account as well, as they may have been
@@ -104,7 +121,7 @@ fn update_coins(
             }
         })
         .collect();
-    let (confirmed, expired) = bit.confirmed_coins(&to_be_confirmed);
+    let (confirmed, expired) = bit.confirmed_coins(&to_be_confirmed)?;
     log::debug!("Newly confirmed coins: {:?}", confirmed);
     log::debug!("Expired coins: {:?}", expired);
 
@@ -123,7 +140,7 @@ fn update_coins(
             }
         })
         .collect();
-    let spending = bit.spending_coins(&to_be_spent);
+    let spending = bit.spending_coins(&to_be_spent)?;
     log::debug!("Newly spending coins: {:?}", spending);
 
     // Mark coins in a spending state whose Spend transaction was confirmed as such. Note we
@@ -137,18 +154,92 @@ fn update_coins(
         .chain(spending.iter().cloned())
         .collect();
     let spent = bit
-        .spent_coins(spending_coins.as_slice())
+        .spent_coins(spending_coins.as_slice())?
         .into_iter()
         .map(|(oupoint, txid, block)| (oupoint, txid, block.height, block.time))
         .collect();
     log::debug!("Newly spent coins: {:?}", spent);
 
-    UpdatedCoins {
+    Ok(UpdatedCoins {
         received,
         confirmed,
         expired,
         spending,
         spent,
+    })
+}
+
+// Store the transactions which deposited to or spent our coins that we don't have yet. This
+// includes those of the coins about to be updated, so their transaction is always stored by the
+// time they are. This also takes care of the coins recorded before transactions were stored.
+// Each transaction is stored as soon as it's fetched, and failing to fetch one doesn't prevent
+// fetching the others: those we couldn't get are queried again at the next poll. The txids the
+// backend doesn't know about are remembered in `unknown_txids` to only report them once.
+fn update_txs(
+    bit: &impl BitcoinInterface,
+    db_conn: &mut Box<dyn DatabaseConnection>,
+    updated_coins: &UpdatedCoins,
+    unknown_txids: &mut HashSet<bitcoin::Txid>,
+) {
+    let updated_txids: Vec<bitcoin::Txid> = updated_coins
+        .received
+        .iter()
+        .map(|coin| coin.outpoint.txid)
+        .chain(updated_coins.spending.iter().map(|(_, txid)| *txid))
+        .chain(updated_coins.spent.iter().map(|(_, txid, _, _)| *txid))
+        .collect();
+    let stored_txids: HashSet<bitcoin::Txid> = db_conn
+        .list_wallet_transactions(&updated_txids)
+        .into_iter()
+        .map(|(tx, _)| tx.txid())
+        .collect();
+    let missing_txids: HashSet<bitcoin::Txid> = updated_txids
+        .into_iter()
+        .filter(|txid| !stored_txids.contains(txid))
+        .chain(db_conn.list_missing_txids())
+        .collect();
+
+    // Forget about the unknown transactions we aren't looking for anymore.
+    unknown_txids.retain(|txid| missing_txids.contains(txid));
+
+    let (mut stored, mut failed) = (0, 0);
+    let mut last_error = None;
+    for txid in missing_txids {
+        match bit.wallet_transaction(&txid) {
+            Ok(Some((tx, _))) => {
+                log::debug!("New transaction: '{}'", txid);
+                db_conn.new_txs(&[tx]);
+                unknown_txids.remove(&txid);
+                stored += 1;
+            }
+            Ok(None) => {
+                if unknown_txids.insert(txid) {
+                    log::error!(
+                        "Could not get transaction '{}' from the Bitcoin backend.",
+                        txid
+                    );
+                } else {
+                    log::debug!(
+                        "Transaction '{}' still unknown to the Bitcoin backend.",
+                        txid
+                    );
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                last_error = Some(e);
+            }
+        }
+    }
+    if stored > 0 {
+        log::debug!("Stored {} new transaction(s).", stored);
+    }
+    if let Some(e) = last_error {
+        log::error!(
+            "Error fetching {} transaction(s) from the Bitcoin backend. Last error: {}",
+            failed,
+            e
+        );
     }
 }
 
@@ -163,19 +254,19 @@ enum TipUpdate {
 }
 
 // Returns the new block chain tip, if it changed.
-fn new_tip(bit: &impl BitcoinInterface, current_tip: &BlockChainTip) -> TipUpdate {
-    let bitcoin_tip = bit.chain_tip();
+fn new_tip(bit: &impl BitcoinInterface, current_tip: &BlockChainTip) -> Result<TipUpdate, String> {
+    let bitcoin_tip = bit.chain_tip()?;
 
     // If the tip didn't change, there is nothing to update.
     if current_tip == &bitcoin_tip {
-        return TipUpdate::Same;
+        return Ok(TipUpdate::Same);
     }
 
     if bitcoin_tip.height > current_tip.height {
         // Make sure we are on the same chain.
-        if bit.is_in_chain(current_tip) {
+        if bit.is_in_chain(current_tip)? {
             // All good, we just moved forward.
-            return TipUpdate::Progress(bitcoin_tip);
+            return Ok(TipUpdate::Progress(bitcoin_tip));
         }
     }
 
@@ -183,13 +274,13 @@ fn new_tip(bit: &impl BitcoinInterface, current_tip: &BlockChainTip) -> TipUpdat
     // block chain re-organisation. Find the common ancestor between our current chain and
     // the new chain and return that. The caller will take care of rewinding our state.
     log::info!("Block chain reorganization detected. Looking for common ancestor.");
-    if let Some(common_ancestor) = bit.common_ancestor(current_tip) {
+    if let Some(common_ancestor) = bit.common_ancestor(current_tip)? {
         log::info!(
             "Common ancestor found: '{}'. Starting rescan from there. Old tip was '{}'.",
             common_ancestor,
             current_tip
         );
-        TipUpdate::Reorged(common_ancestor)
+        Ok(TipUpdate::Reorged(common_ancestor))
     } else {
         log::error!(
             "Failed to get common ancestor for tip '{}'. Starting over.",
@@ -204,12 +295,13 @@ fn updates(
     db: &impl DatabaseInterface,
     descs: &[descriptors::SinglePathLianaDesc],
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
-) {
+    unknown_txids: &mut HashSet<bitcoin::Txid>,
+) -> Result<(), String> {
     let mut db_conn = db.connection();
 
     // Check if there was a new block before updating ourselves.
     let current_tip = db_conn.chain_tip().expect("Always set at first startup");
-    let latest_tip = match new_tip(bit, &current_tip) {
+    let latest_tip = match new_tip(bit, &current_tip)? {
         TipUpdate::Same => current_tip,
         TipUpdate::Progress(new_tip) => new_tip,
         TipUpdate::Reorged(new_tip) => {
@@ -217,18 +309,19 @@ fn updates(
             // between our former chain and the new one, then restart fresh.
             db_conn.rollback_tip(&new_tip);
             log::info!("Tip was rolled back to '{}'.", new_tip);
-            return updates(bit, db, descs, secp);
+            return updates(bit, db, descs, secp, unknown_txids);
         }
     };
 
     // Then check the state of our coins. Do it even if the tip did not change since last poll, as
     // we may have unconfirmed transactions.
-    let updated_coins = update_coins(bit, &mut db_conn, &current_tip, descs, secp);
+    let updated_coins = update_coins(bit, &mut db_conn, &current_tip, descs, secp)?;
+    update_txs(bit, &mut db_conn, &updated_coins, unknown_txids);
 
     // If the tip changed while we were polling our Bitcoin interface, start over.
-    if bit.chain_tip() != latest_tip {
+    if bit.chain_tip()? != latest_tip {
         log::info!("Chain tip changed while we were updating our state. Starting over.");
-        return updates(bit, db, descs, secp);
+        return updates(bit, db, descs, secp, unknown_txids);
     }
 
     // The chain tip did not change since we started our updates. Record them and the latest tip.
@@ -245,6 +338,8 @@ fn updates(
     }
 
     log::debug!("Updates done.");
+
+    Ok(())
 }
 
 // Check if there is any rescan of the backend ongoing or one that just finished.
@@ -253,7 +348,8 @@ fn rescan_check(
     db: &impl DatabaseInterface,
     descs: &[descriptors::SinglePathLianaDesc],
     secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
-) {
+    unknown_txids: &mut HashSet<bitcoin::Txid>,
+) -> Result<(), String> {
     log::debug!("Checking the state of an ongoing rescan if there is any");
     let mut db_conn = db.connection();
 
@@ -267,6 +363,7 @@ fn rescan_check(
         if rescan_timestamp.is_none() {
             log::warn!("Backend is rescanning but we didn't ask for it.");
         }
+        Ok(())
     } else if let Some(timestamp) = rescan_timestamp {
         log::info!("Rescan completed on the backend.");
         // TODO: we could check if the timestamp of the descriptors in the Bitcoin backend are
@@ -274,14 +371,14 @@ fn rescan_check(
         // no use for the bitcoind implementation of the backend, since bitcoind will always set
         // the timestamp of the descriptors in the wallet first (and therefore consider it as
         // rescanned from this height even if it aborts the rescan by being stopped).
-        let rescan_tip = match bit.block_before_date(timestamp) {
+        let rescan_tip = match bit.block_before_date(timestamp)? {
             Some(block) => block,
             None => {
                 log::error!(
                     "Could not retrieve block height for timestamp '{}'",
                     timestamp
                 );
-                return;
+                return Ok(());
             }
         };
         db_conn.rollback_tip(&rescan_tip);
@@ -290,20 +387,41 @@ fn rescan_check(
             "Rolling back our internal tip to '{}' to update our internal state with past transactions.",
             rescan_tip
         );
-        updates(bit, db, descs, secp)
+        updates(bit, db, descs, secp, unknown_txids)
     } else {
         log::debug!("No ongoing rescan.");
+        Ok(())
     }
 }
 
+// Make sure the Bitcoin backend looks for coins on all the addresses of our cache, that is up to
+// the gap limit past the highest derivation index in use.
+fn track_addresses(
+    bit: &impl BitcoinInterface,
+    db: &impl DatabaseInterface,
+    descs: &[descriptors::SinglePathLianaDesc],
+    gap_limit: u32,
+) -> Result<(), String> {
+    let mut db_conn = db.connection();
+    let highest_index: u32 = cmp::max(db_conn.receive_index(), db_conn.change_index()).into();
+    // Derivation indexes are unhardened, therefore strictly below 2**31.
+    let end = cmp::min(highest_index.saturating_add(gap_limit), 1 << 31);
+    bit.track_addresses(descs, end)
+}
+
 // If the database chain tip is NULL (first startup), initialize it.
-fn maybe_initialize_tip(bit: &impl BitcoinInterface, db: &impl DatabaseInterface) {
+fn maybe_initialize_tip(
+    bit: &impl BitcoinInterface,
+    db: &impl DatabaseInterface,
+) -> Result<(), String> {
     let mut db_conn = db.connection();
 
     if db_conn.chain_tip().is_none() {
         // TODO: be smarter. We can use the timestamp of the descriptor to get a newer block hash.
-        db_conn.update_tip(&bit.genesis_block());
+        db_conn.update_tip(&bit.genesis_block()?);
     }
+
+    Ok(())
 }
 
 fn sync_poll_interval() -> time::Duration {
@@ -324,6 +442,7 @@ pub fn looper(
     shutdown: sync::Arc<atomic::AtomicBool>,
     poll_interval: time::Duration,
     desc: descriptors::LianaDescriptor,
+    gap_limit: u32,
 ) {
     let mut last_poll = None;
     let mut synced = false;
@@ -332,8 +451,13 @@ pub fn looper(
         desc.change_descriptor().clone(),
     ];
     let secp = secp256k1::Secp256k1::verification_only();
+    // The transactions the backend couldn't find, to not report them at every poll.
+    let mut unknown_txids = HashSet::new();
 
-    maybe_initialize_tip(&bit, &db);
+    // We'll try again at the first poll if the Bitcoin backend can't be reached.
+    if let Err(e) = maybe_initialize_tip(&bit, &db) {
+        log::error!("Error initializing our chain tip: {}", e);
+    }
 
     while !shutdown.load(atomic::Ordering::Relaxed) || last_poll.is_none() {
         let now = time::Instant::now();
@@ -367,7 +491,14 @@ pub fn looper(
             }
         }
 
-        updates(&bit, &db, &descs, &secp);
-        rescan_check(&bit, &db, &descs, &secp);
+        // A remote Bitcoin backend may not be reachable. Don't update our state in this case,
+        // we'll try again at the next poll.
+        if let Err(e) = maybe_initialize_tip(&bit, &db)
+            .and_then(|_| track_addresses(&bit, &db, &descs, gap_limit))
+            .and_then(|_| updates(&bit, &db, &descs, &secp, &mut unknown_txids))
+            .and_then(|_| rescan_check(&bit, &db, &descs, &secp, &mut unknown_txids))
+        {
+            log::error!("Error while polling the Bitcoin backend: {}", e);
+        }
     }
 }
@@ -23,13 +23,14 @@ impl Poller {
         db: sync::Arc<sync::Mutex<dyn DatabaseInterface>>,
         poll_interval: time::Duration,
         desc: descriptors::LianaDescriptor,
+        gap_limit: u32,
     ) -> Poller {
         let shutdown = sync::Arc::from(atomic::AtomicBool::from(false));
         let handle = thread::Builder::new()
             .name("Bitcoin poller".to_string())
             .spawn({
                 let shutdown = shutdown.clone();
-                move || looper(bit, db, shutdown, poll_interval, desc)
+                move || looper(bit, db, shutdown, poll_interval, desc, gap_limit)
             })
             .expect("Must not fail");
 
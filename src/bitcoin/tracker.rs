@@ -0,0 +1,414 @@
+//! Tracking of the scripts derived from our descriptors, for the backends which query the history
+//! of each of our scripts from a server that indexes the chain by script (Electrum and Esplora).
+//!
+//! We track the scripts derived from the receive and change descriptors up to a gap limit past
+//! the last one used, along with their history and the transactions it contains. The state of our
+//! coins is inferred from it.
+
+use crate::{
+    bitcoin::{Block, BlockChainTip, MempoolEntry, UTxO, COINBASE_MATURITY},
+    descriptors,
+};
+
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+};
+
+use miniscript::bitcoin::{self, address, bip32, secp256k1};
+
+/// A transaction in the history of a script.
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub txid: bitcoin::Txid,
+    /// The height of the block it was confirmed in, if any.
+    pub height: Option<i32>,
+    /// The fee it pays. Only available for unconfirmed transactions.
+    pub fee: Option<bitcoin::Amount>,
+}
+
+// A script derived from one of our descriptors.
+#[derive(Debug, Clone)]
+struct TrackedScript {
+    script_pubkey: bitcoin::ScriptBuf,
+    // The index in the list of descriptors, and the derivation index.
+    desc_index: usize,
+    der_index: u32,
+    // The latest status of the script according to the server, and the status as of our last
+    // query of its history. None if it has no history.
+    status: Option<String>,
+    history_status: Option<String>,
+    history: Vec<HistoryEntry>,
+}
+
+pub struct ScriptTracker {
+    network: bitcoin::Network,
+    // How many unused scripts we keep track of past the last used one, for each descriptor. It
+    // matches the number of addresses past the highest derivation index cached in database, as
+    // we wouldn't be able to know the derivation index of coins received further anyways.
+    gap_limit: u32,
+    // How the server indexes scripts.
+    script_hash: fn(&bitcoin::Script) -> String,
+    secp: secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+    // The descriptors we derive the scripts from, along with the next index to derive.
+    descs: Vec<(descriptors::SinglePathLianaDesc, u32)>,
+    // We track the scripts of each descriptor at least up to this derivation index (excluded),
+    // whether they were used or not.
+    min_end: u32,
+    // The scripts we track, by script hash.
+    scripts: HashMap<String, TrackedScript>,
+    // The transactions of the wallet.
+    txs: HashMap<bitcoin::Txid, bitcoin::Transaction>,
+}
+
+impl ScriptTracker {
+    pub fn new(
+        network: bitcoin::Network,
+        gap_limit: u32,
+        script_hash: fn(&bitcoin::Script) -> String,
+    ) -> ScriptTracker {
+        ScriptTracker {
+            network,
+            gap_limit,
+            script_hash,
+            secp: secp256k1::Secp256k1::verification_only(),
+            descs: Vec::new(),
+            min_end: 0,
+            scripts: HashMap::new(),
+            txs: HashMap::new(),
+        }
+    }
+
+    /// Start over if we are given different descriptors.
+    pub fn set_descriptors(&mut self, descs: &[descriptors::SinglePathLianaDesc]) {
+        if self.descs.len() != descs.len()
+            || self
+                .descs
+                .iter()
+                .zip(descs.iter())
+                .any(|((a, _), b)| a != b)
+        {
+            self.descs = descs.iter().map(|desc| (desc.clone(), 0)).collect();
+            self.scripts.clear();
+        }
+    }
+
+    /// Track the scripts of each descriptor at least up to this derivation index (excluded).
+    pub fn track_up_to(&mut self, end: u32) {
+        self.min_end = cmp::max(self.min_end, end);
+    }
+
+    /// Forget about everything we know, so it's all queried again.
+    pub fn clear(&mut self) {
+        self.descs.clear();
+        self.scripts.clear();
+        self.txs.clear();
+    }
+
+    /// Derive new scripts for each descriptor so we always track gap_limit unused ones past the
+    /// last used one. Returns the script hashes of the new scripts.
+    pub fn derive_scripts(&mut self) -> Vec<String> {
+        let mut new_hashes = Vec::new();
+
+        for desc_index in 0..self.descs.len() {
+            let last_used = self
+                .scripts
+                .values()
+                .filter(|s| s.desc_index == desc_index && !s.history.is_empty())
+                .map(|s| s.der_index)
+                .max();
+            let target = cmp::max(
+                last_used.map(|i| i + 1).unwrap_or(0) + self.gap_limit,
+                self.min_end,
+            );
+            let (desc, next_index) = self.descs[desc_index].clone();
+            for der_index in next_index..target {
+                let script_pubkey = desc
+                    .derive(bip32::ChildNumber::from(der_index), &self.secp)
+                    .script_pubkey();
+                let hash = (self.script_hash)(&script_pubkey);
+                self.scripts.insert(
+                    hash.clone(),
+                    TrackedScript {
+                        script_pubkey,
+                        desc_index,
+                        der_index,
+                        status: None,
+                        history_status: None,
+                        history: Vec::new(),
+                    },
+                );
+                new_hashes.push(hash);
+            }
+            self.descs[desc_index].1 = cmp::max(next_index, target);
+        }
+
+        new_hashes
+    }
+
+    /// The script hashes of all the scripts we track.
+    pub fn script_hashes(&self) -> Vec<String> {
+        self.scripts.keys().cloned().collect()
+    }
+
+    /// Record the latest status of this script according to the server.
+    pub fn set_status(&mut self, hash: &str, status: Option<String>) {
+        if let Some(script) = self.scripts.get_mut(hash) {
+            script.status = status;
+        }
+    }
+
+    /// Make sure the history of this script is queried again, even if its status didn't change.
+    pub fn invalidate_history(&mut self, hash: &str) {
+        if let Some(script) = self.scripts.get_mut(hash) {
+            script.history_status = None;
+        }
+    }
+
+    /// The script hashes of the scripts whose history must be queried, as their status changed
+    /// since the last query.
+    pub fn outdated_scripts(&self) -> Vec<String> {
+        self.scripts
+            .iter()
+            .filter(|(_, s)| s.status != s.history_status)
+            .map(|(hash, _)| hash.clone())
+            .collect()
+    }
+
+    /// Record the history of this script as of its current status.
+    pub fn set_history(&mut self, hash: &str, history: Vec<HistoryEntry>) {
+        if let Some(script) = self.scripts.get_mut(hash) {
+            script.history = history;
+            script.history_status = script.status.clone();
+        }
+    }
+
+    /// The transactions in the history of our scripts which we don't have yet.
+    pub fn missing_txids(&self) -> Vec<bitcoin::Txid> {
+        self.scripts
+            .values()
+            .flat_map(|s| s.history.iter().map(|entry| entry.txid))
+            .filter(|txid| !self.txs.contains_key(txid))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+
+    pub fn add_tx(&mut self, tx: bitcoin::Transaction) {
+        self.txs.insert(tx.txid(), tx);
+    }
+
+    // The history entry of all the transactions of our scripts.
+    fn history_entries(&self) -> HashMap<bitcoin::Txid, &HistoryEntry> {
+        self.scripts
+            .values()
+            .flat_map(|s| s.history.iter())
+            .map(|entry| (entry.txid, entry))
+            .collect()
+    }
+
+    // The transactions of the wallet spending each outpoint.
+    fn spenders(&self) -> HashMap<bitcoin::OutPoint, bitcoin::Txid> {
+        self.history_entries()
+            .keys()
+            .filter_map(|txid| self.txs.get(txid))
+            .flat_map(|tx| {
+                let txid = tx.txid();
+                tx.input
+                    .iter()
+                    .map(move |txin| (txin.previous_output, txid))
+            })
+            .collect()
+    }
+
+    /// Get the coins received since the given tip.
+    pub fn received_coins(&self, tip: &BlockChainTip, tip_height: i32) -> Vec<UTxO> {
+        let mut coins = Vec::new();
+        for script in self.scripts.values() {
+            let address: bitcoin::Address<address::NetworkUnchecked> = bitcoin::Address::new(
+                self.network,
+                address::Payload::from_script(&script.script_pubkey)
+                    .expect("Our descriptors always have an address form"),
+            );
+            for entry in &script.history {
+                if entry.height.map(|h| h <= tip.height).unwrap_or(false) {
+                    continue;
+                }
+                let tx = match self.txs.get(&entry.txid) {
+                    Some(tx) => tx,
+                    None => continue,
+                };
+                let is_immature = tx.is_coin_base()
+                    && entry
+                        .height
+                        .map(|h| tip_height - h + 1 < COINBASE_MATURITY)
+                        .unwrap_or(true);
+                for (vout, txout) in tx.output.iter().enumerate() {
+                    if txout.script_pubkey != script.script_pubkey {
+                        continue;
+                    }
+                    coins.push(UTxO {
+                        outpoint: bitcoin::OutPoint {
+                            txid: entry.txid,
+                            vout: vout.try_into().expect("Can't have more than 2^32 outputs"),
+                        },
+                        amount: bitcoin::Amount::from_sat(txout.value),
+                        block_height: entry.height,
+                        address: address.clone(),
+                        is_immature,
+                    });
+                }
+            }
+        }
+
+        coins
+    }
+
+    /// Get the coins which were confirmed, and the unconfirmed ones whose transaction isn't part
+    /// of the history of our scripts anymore. The blocks are queried through `block_at`.
+    #[allow(clippy::type_complexity)]
+    pub fn confirmed_coins<E>(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+        tip_height: i32,
+        mut block_at: impl FnMut(i32) -> Result<Block, E>,
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), E> {
+        let entries = self.history_entries();
+
+        let mut confirmed = Vec::with_capacity(outpoints.len());
+        let mut expired = Vec::new();
+        let mut blocks: HashMap<i32, Block> = HashMap::new();
+        for op in outpoints {
+            match entries.get(&op.txid).map(|entry| entry.height) {
+                Some(Some(height)) => {
+                    let is_coinbase = self
+                        .txs
+                        .get(&op.txid)
+                        .map(|tx| tx.is_coin_base())
+                        .unwrap_or(false);
+                    if is_coinbase && tip_height - height + 1 < COINBASE_MATURITY {
+                        log::debug!("Coin at '{}' comes from an immature coinbase transaction. Not marking it as confirmed for now.", op);
+                        continue;
+                    }
+                    let block = match blocks.get(&height) {
+                        Some(block) => *block,
+                        None => block_at(height)?,
+                    };
+                    blocks.insert(height, block);
+                    confirmed.push((*op, block.height, block.time));
+                }
+                // Still unconfirmed.
+                Some(None) => {}
+                // The transaction isn't part of the history of our scripts anymore, it must have
+                // been dropped from the mempool.
+                None => expired.push(*op),
+            }
+        }
+
+        Ok((confirmed, expired))
+    }
+
+    /// Get the coins which are spent by a transaction of the wallet, and the spending txid.
+    pub fn spending_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)> {
+        let spenders = self.spenders();
+        outpoints
+            .iter()
+            .filter_map(|op| spenders.get(op).map(|txid| (*op, *txid)))
+            .collect()
+    }
+
+    /// Get the coins whose spending transaction was confirmed, along with its txid and the block
+    /// it was confirmed in. The blocks are queried through `block_at`.
+    pub fn spent_coins<E>(
+        &self,
+        outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)],
+        mut block_at: impl FnMut(i32) -> Result<Block, E>,
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, E> {
+        let heights: HashMap<bitcoin::Txid, i32> = self
+            .history_entries()
+            .into_iter()
+            .filter_map(|(txid, entry)| entry.height.map(|h| (txid, h)))
+            .collect();
+        let spenders = self.spenders();
+
+        let mut spent = Vec::with_capacity(outpoints.len());
+        for (op, txid) in outpoints {
+            // If the spending transaction was replaced by a conflicting one which got confirmed,
+            // report the latter instead.
+            let txid = if heights.contains_key(txid) {
+                *txid
+            } else {
+                match spenders.get(op) {
+                    Some(spender) => *spender,
+                    None => continue,
+                }
+            };
+            if let Some(height) = heights.get(&txid) {
+                spent.push((*op, txid, block_at(*height)?));
+            }
+        }
+
+        Ok(spent)
+    }
+
+    /// Get a transaction of the wallet, along with the height of the block it was confirmed in
+    /// if any.
+    pub fn transaction(
+        &self,
+        txid: &bitcoin::Txid,
+    ) -> Option<(&bitcoin::Transaction, Option<i32>)> {
+        let tx = self.txs.get(txid)?;
+        let height = self
+            .history_entries()
+            .get(txid)
+            .and_then(|entry| entry.height);
+        Some((tx, height))
+    }
+
+    /// Get the fee and size of an unconfirmed transaction of the wallet.
+    pub fn mempool_entry(&self, txid: &bitcoin::Txid) -> Option<MempoolEntry> {
+        let fee = self
+            .history_entries()
+            .get(txid)
+            .filter(|entry| entry.height.is_none())
+            .and_then(|entry| entry.fee)?;
+        let vsize = self.txs.get(txid)?.vsize() as u64;
+        Some(MempoolEntry { vsize, fee })
+    }
+}
+
+/// Get the last block with a timestamp below the given one, if it's between the timestamps of
+/// the genesis block and the tip. The blocks are queried through `block_at`.
+pub fn block_before_date<E>(
+    timestamp: u32,
+    tip_height: i32,
+    mut block_at: impl FnMut(i32) -> Result<Block, E>,
+) -> Result<Option<BlockChainTip>, E> {
+    let genesis_time = block_at(0)?.time;
+    let tip_time = block_at(tip_height)?.time;
+    if !(genesis_time..tip_time).contains(&timestamp) {
+        return Ok(None);
+    }
+
+    let (mut start_height, mut end_height) = (0, tip_height);
+    while start_height < end_height {
+        let current_height = start_height + (end_height - start_height) / 2;
+        // We want the last block with a timestamp below, not the first with a higher one.
+        let next_height = current_height + 1;
+        if timestamp > block_at(next_height)?.time {
+            start_height = next_height;
+        } else {
+            end_height = current_height;
+        }
+    }
+
+    let block = block_at(start_height)?;
+    Ok(Some(BlockChainTip {
+        hash: block.hash,
+        height: block.height,
+    }))
+}
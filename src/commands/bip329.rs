@@ -0,0 +1,141 @@
+//! BIP329 labels.
+//!
+//! Import and export our labels in the JSON Lines format specified in BIP329, so they can be
+//! shared with other wallets. See https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki.
+
+use crate::database::LabelItem;
+
+use std::fmt;
+
+use miniscript::bitcoin;
+use serde::{Deserialize, Serialize};
+
+// A single line of a BIP329 export. Records of types we don't know about as well as fields
+// we don't use ("origin", "spendable") are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "ref")]
+    reference: String,
+    label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bip329Error {
+    /// The line at which the invalid record was found, starting from 1.
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for Bip329Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+/// Serialize the given labels as BIP329 JSON Lines.
+pub fn to_jsonl<'a>(labels: impl IntoIterator<Item = &'a (LabelItem, String)>) -> String {
+    labels
+        .into_iter()
+        .map(|(item, label)| {
+            let kind = match item {
+                LabelItem::Address(..) => "addr",
+                LabelItem::Txid(..) => "tx",
+                LabelItem::OutPoint(..) => "output",
+            };
+            let record = Record {
+                kind: kind.to_string(),
+                reference: item.to_string(),
+                label: Some(label.clone()),
+            };
+            serde_json::to_string(&record).expect("Serialization can't fail") + "\n"
+        })
+        .collect()
+}
+
+/// Parse the labels from BIP329 JSON Lines. Addresses must be valid for the given network.
+pub fn from_jsonl(
+    jsonl: &str,
+    network: bitcoin::Network,
+) -> Result<Vec<(LabelItem, String)>, Bip329Error> {
+    let mut labels = Vec::new();
+
+    for (i, line) in jsonl.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let error = |reason: String| Bip329Error {
+            line: i + 1,
+            reason,
+        };
+
+        let record: Record = serde_json::from_str(line).map_err(|e| error(e.to_string()))?;
+        let label = match record.label.clone() {
+            Some(label) => label,
+            None => continue,
+        };
+        let item = match record.kind.as_str() {
+            "addr" => LabelItem::parse(&record.reference, network)
+                .filter(|item| matches!(item, LabelItem::Address(..))),
+            "tx" => LabelItem::parse(&record.reference, network)
+                .filter(|item| matches!(item, LabelItem::Txid(..))),
+            "output" => LabelItem::parse(&record.reference, network)
+                .filter(|item| matches!(item, LabelItem::OutPoint(..))),
+            _ => continue,
+        }
+        .ok_or_else(|| {
+            error(format!(
+                "invalid reference '{}' for type '{}'",
+                record.reference, record.kind
+            ))
+        })?;
+
+        labels.push((item, label));
+    }
+
+    Ok(labels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bip329_roundtrip() {
+        // The example export from BIP329, with a few records we don't support.
+        let jsonl = r#"{ "type": "tx", "ref": "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd", "label": "Transaction", "origin": "wpkh([d34db33f/84'/0'/0'])" }
+{ "type": "addr", "ref": "bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c", "label": "Address" }
+{ "type": "pubkey", "ref": "0283409659355b6d1cc3c32decd5d561abaac86c37a353b52895a5e6c196d6f448", "label": "Public Key" }
+{ "type": "input", "ref": "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0", "label": "Input" }
+{ "type": "output", "ref": "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1", "label": "Output" , "spendable" : false }
+
+{ "type": "tx", "ref": "f546156d9044844e02b181026a1a407abfca62e7ea1159f87bbeaa77b4286c74" }
+"#;
+        let labels = from_jsonl(jsonl, bitcoin::Network::Bitcoin).unwrap();
+        assert_eq!(labels.len(), 3);
+        assert!(matches!(labels[0], (LabelItem::Txid(_), ref l) if l == "Transaction"));
+        assert!(matches!(labels[1], (LabelItem::Address(_), ref l) if l == "Address"));
+        assert!(matches!(labels[2], (LabelItem::OutPoint(_), ref l) if l == "Output"));
+
+        // Exporting and importing back the labels is a no-op.
+        let exported = to_jsonl(&labels);
+        assert_eq!(exported.lines().count(), 3);
+        assert_eq!(
+            from_jsonl(&exported, bitcoin::Network::Bitcoin).unwrap(),
+            labels
+        );
+
+        // Addresses must be for our network, and references valid for their type.
+        let err = from_jsonl(jsonl, bitcoin::Network::Testnet).unwrap_err();
+        assert_eq!(err.line, 2);
+        let err = from_jsonl(
+            "{ \"type\": \"output\", \"ref\": \"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd\", \"label\": \"Output\" }",
+            bitcoin::Network::Bitcoin,
+        )
+        .unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(from_jsonl("{ \"type\": \"tx\" }", bitcoin::Network::Bitcoin).is_err());
+        assert!(from_jsonl("not json", bitcoin::Network::Bitcoin).is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Coin selection.
+//!
+//! Select coins to fund a transaction with given outputs. We first try to find a changeless
+//! solution minimizing the waste using a Branch and Bound search, as in Bitcoin Core. If there is
+//! none we fallback to adding the largest coins first until we can create a change output.
+
+use std::{cmp, convert::TryInto};
+
+use miniscript::bitcoin;
+
+// Maximum number of iterations of the Branch and Bound search.
+const BNB_MAX_TRIES: usize = 100_000;
+
+// The feerate we assume we'll be able to spend the coins at in the long run. Used to compute the
+// waste metric: if the current feerate is higher than this one we'll try to spend less coins, if
+// it's lower we'll be more inclined to consolidate.
+const LONG_TERM_FEERATE_VB: u64 = 10;
+
+/// A coin that may be selected to fund a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub outpoint: bitcoin::OutPoint,
+    pub amount: bitcoin::Amount,
+}
+
+/// The parameters of the transaction to be funded.
+#[derive(Debug, Clone, Copy)]
+pub struct SelectionParams {
+    /// The total value of the outputs of the transaction, excluding change.
+    pub out_value: u64,
+    /// Target feerate in sats/vb.
+    pub feerate_vb: u64,
+    /// The size of the transaction without any input nor change output, in virtual bytes.
+    pub base_vb: u64,
+    /// The size of a single input, including its maximum satisfaction, in virtual bytes.
+    pub input_vb: u64,
+    /// The size of the change output, in virtual bytes.
+    pub change_vb: u64,
+    /// The minimum value of a change output. Any excess below this value is left as fees.
+    pub min_change: u64,
+}
+
+impl SelectionParams {
+    // What the outputs and the fixed part of the transaction cost.
+    fn target(&self) -> u64 {
+        self.out_value
+            .checked_add(self.base_vb.checked_mul(self.feerate_vb).unwrap())
+            .unwrap()
+    }
+
+    // The value of a coin net of the fee needed to spend it at the target feerate.
+    fn effective_value(&self, amount: bitcoin::Amount) -> Option<u64> {
+        amount
+            .to_sat()
+            .checked_sub(self.input_vb.checked_mul(self.feerate_vb).unwrap())
+            .filter(|v| *v > 0)
+    }
+
+    // The cost of spending a coin now instead of at the long term feerate.
+    fn input_waste(&self) -> i64 {
+        let input_vb: i64 = self.input_vb.try_into().expect("Input size fits in an i64");
+        let feerate: i64 = self.feerate_vb.try_into().expect("Checked by caller");
+        let lt_feerate: i64 = LONG_TERM_FEERATE_VB.try_into().unwrap();
+        input_vb * (feerate - lt_feerate)
+    }
+}
+
+/// The result of a successful coin selection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub coins: Vec<Candidate>,
+    /// Whether the selected coins are expected to fund a change output.
+    pub has_change: bool,
+}
+
+// Search for a set of coins whose effective value is within the [target; upper_bound] range,
+// minimizing the waste. The effective values must be sorted in descending order. Returns the
+// indexes of the selected coins.
+fn branch_and_bound(
+    eff_values: &[u64],
+    target: u64,
+    upper_bound: u64,
+    input_waste: i64,
+) -> Option<Vec<usize>> {
+    let mut remaining: u64 = eff_values.iter().sum();
+    if remaining < target {
+        return None;
+    }
+
+    let mut curr_value = 0;
+    let mut curr_waste = 0;
+    let mut selection: Vec<usize> = Vec::with_capacity(eff_values.len());
+    let mut best: Option<(Vec<usize>, i64)> = None;
+
+    let mut index = 0;
+    for _ in 0..BNB_MAX_TRIES {
+        let best_waste = best.as_ref().map(|(_, w)| *w).unwrap_or(i64::MAX);
+        let mut backtrack = false;
+        if curr_value + remaining < target
+            || curr_value > upper_bound
+            || (curr_waste > best_waste && input_waste > 0)
+        {
+            // This branch can't lead to a (better) solution.
+            backtrack = true;
+        } else if curr_value >= target {
+            let excess: i64 = (curr_value - target)
+                .try_into()
+                .expect("Bounded by MAX_MONEY");
+            let waste = curr_waste + excess;
+            if waste <= best_waste {
+                best = Some((selection.clone(), waste));
+            }
+            backtrack = true;
+        }
+
+        if backtrack {
+            let last = match selection.last() {
+                Some(last) => *last,
+                // We explored the whole tree.
+                None => break,
+            };
+            // Add the coins we omitted back to the available value before exploring the
+            // omission branch of the last included coin.
+            index -= 1;
+            while index > last {
+                remaining += eff_values[index];
+                index -= 1;
+            }
+            curr_value -= eff_values[index];
+            curr_waste -= input_waste;
+            selection.pop();
+        } else {
+            remaining -= eff_values[index];
+            // Skip this coin if we previously omitted one with the same value, it would only
+            // lead to an equivalent solution.
+            if selection.last().map(|l| l + 1 == index).unwrap_or(true)
+                || index == 0
+                || eff_values[index] != eff_values[index - 1]
+            {
+                selection.push(index);
+                curr_value += eff_values[index];
+                curr_waste += input_waste;
+            }
+        }
+        index += 1;
+    }
+
+    best.map(|(sel, _)| sel)
+}
+
+/// Select coins among the given candidates to fund a transaction with the given parameters, in
+/// addition to the mandatory coins. Only the additionally selected coins are returned.
+///
+/// Returns `None` if the candidates aren't sufficient to fund the transaction at the target
+/// feerate.
+pub fn select_coins(
+    mandatory: &[Candidate],
+    candidates: &[Candidate],
+    params: &SelectionParams,
+) -> Option<Selection> {
+    // Only consider the coins which are worth spending at this feerate, largest first.
+    let mut candidates: Vec<(Candidate, u64)> = candidates
+        .iter()
+        .filter_map(|c| params.effective_value(c.amount).map(|v| (*c, v)))
+        .collect();
+    candidates.sort_by_key(|(_, v)| cmp::Reverse(*v));
+    let eff_values: Vec<u64> = candidates.iter().map(|(_, v)| *v).collect();
+
+    // Any excess below the cost of creating a change output isn't worth a change output and would
+    // be left as fees anyways.
+    let cost_of_change = params
+        .change_vb
+        .checked_mul(params.feerate_vb)
+        .unwrap()
+        .checked_add(params.min_change)
+        .unwrap();
+
+    // The mandatory coins count toward the target. Their effective value may be negative.
+    let input_fee: i64 = (params.input_vb * params.feerate_vb)
+        .try_into()
+        .expect("Fits in an i64");
+    let mandatory_value: i64 = mandatory
+        .iter()
+        .map(|c| {
+            let amount: i64 = c.amount.to_sat().try_into().expect("Bounded by MAX_MONEY");
+            amount - input_fee
+        })
+        .sum();
+    let target: i64 = params.target().try_into().expect("Fits in an i64");
+    let target = target - mandatory_value;
+    if target <= 0 {
+        let excess: u64 = (-target).try_into().expect("Positive");
+        return Some(Selection {
+            coins: Vec::new(),
+            has_change: excess >= cost_of_change,
+        });
+    }
+    let target: u64 = target.try_into().expect("Positive");
+
+    // First try to find a changeless solution.
+    let upper_bound = target.checked_add(cost_of_change).unwrap() - 1;
+    if let Some(indexes) = branch_and_bound(&eff_values, target, upper_bound, params.input_waste())
+    {
+        return Some(Selection {
+            coins: indexes.into_iter().map(|i| candidates[i].0).collect(),
+            has_change: false,
+        });
+    }
+
+    // Otherwise add coins, largest first, until we can afford a change output.
+    let mut coins = Vec::new();
+    let mut value = 0;
+    for (coin, eff_value) in candidates {
+        coins.push(coin);
+        value += eff_value;
+        if value >= target + cost_of_change {
+            return Some(Selection {
+                coins,
+                has_change: true,
+            });
+        }
+    }
+
+    // The search may have given up before finding a changeless solution.
+    if value >= target {
+        return Some(Selection {
+            coins,
+            has_change: false,
+        });
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn candidates(amounts: &[u64]) -> Vec<Candidate> {
+        amounts
+            .iter()
+            .enumerate()
+            .map(|(i, a)| Candidate {
+                outpoint: bitcoin::OutPoint::from_str(&format!(
+                    "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                    i
+                ))
+                .unwrap(),
+                amount: bitcoin::Amount::from_sat(*a),
+            })
+            .collect()
+    }
+
+    fn amounts(selection: &Selection) -> Vec<u64> {
+        let mut amounts: Vec<u64> = selection.coins.iter().map(|c| c.amount.to_sat()).collect();
+        amounts.sort_unstable();
+        amounts
+    }
+
+    #[test]
+    fn bnb_exact_match() {
+        // With a 0 feerate the effective values are the amounts.
+        let params = SelectionParams {
+            out_value: 0,
+            feerate_vb: 0,
+            base_vb: 50,
+            input_vb: 100,
+            change_vb: 43,
+            min_change: 1,
+        };
+        let coins = candidates(&[1, 2, 3, 4]);
+        for target in 1..=10 {
+            let params = SelectionParams {
+                out_value: target,
+                ..params
+            };
+            let sel = select_coins(&[], &coins, &params).unwrap();
+            assert!(!sel.has_change);
+            assert_eq!(amounts(&sel).iter().sum::<u64>(), target);
+        }
+        let params = SelectionParams {
+            out_value: 11,
+            ..params
+        };
+        assert!(select_coins(&[], &coins, &params).is_none());
+    }
+
+    #[test]
+    fn selection() {
+        let params = SelectionParams {
+            out_value: 100_000,
+            feerate_vb: 2,
+            base_vb: 50,
+            input_vb: 100,
+            change_vb: 43,
+            min_change: 5_000,
+        };
+
+        // There is a changeless solution using the two coins exactly matching the target (the
+        // outputs and 50 + 2 * 100 vbytes at 2sat/vb), we'll use it.
+        let coins = candidates(&[1_000_000, 60_200, 50_000, 40_300, 30_000]);
+        let sel = select_coins(&[], &coins, &params).unwrap();
+        assert!(!sel.has_change);
+        assert_eq!(amounts(&sel), vec![40_300, 60_200]);
+
+        // If there is none, we'll use the largest coins and get change.
+        let coins = candidates(&[1_000_000, 60_000, 50_000, 30_000]);
+        let sel = select_coins(&[], &coins, &params).unwrap();
+        assert!(sel.has_change);
+        assert_eq!(amounts(&sel), vec![1_000_000]);
+        let coins = candidates(&[70_000, 60_000, 30_000]);
+        let sel = select_coins(&[], &coins, &params).unwrap();
+        assert!(sel.has_change);
+        assert_eq!(amounts(&sel), vec![60_000, 70_000]);
+
+        // Coins that cost more to spend than they are worth are ignored.
+        let coins = candidates(&[60_200, 40_300, 200]);
+        let sel = select_coins(&[], &coins, &params).unwrap();
+        assert_eq!(amounts(&sel), vec![40_300, 60_200]);
+
+        // Not enough funds.
+        let coins = candidates(&[50_000, 50_000]);
+        assert!(select_coins(&[], &coins, &params).is_none());
+        assert!(select_coins(&[], &[], &params).is_none());
+    }
+
+    #[test]
+    fn selection_mandatory() {
+        let params = SelectionParams {
+            out_value: 100_000,
+            feerate_vb: 2,
+            base_vb: 50,
+            input_vb: 100,
+            change_vb: 43,
+            min_change: 5_000,
+        };
+        let coins = candidates(&[1_000_000, 60_200, 50_000, 40_300]);
+
+        // The mandatory coins are sufficient, with or without change.
+        let sel = select_coins(&coins[..1], &coins[1..], &params).unwrap();
+        assert!(sel.coins.is_empty());
+        assert!(sel.has_change);
+        let sel = select_coins(
+            &coins[3..],
+            &[],
+            &SelectionParams {
+                out_value: 40_000,
+                ..params
+            },
+        )
+        .unwrap();
+        assert!(sel.coins.is_empty());
+        assert!(!sel.has_change);
+
+        // They are not, but complemented by the coin exactly matching the target.
+        let sel = select_coins(&coins[3..], &coins[..3], &params).unwrap();
+        assert!(!sel.has_change);
+        assert_eq!(amounts(&sel), vec![60_200]);
+
+        // Not enough funds even with the mandatory coins.
+        assert!(select_coins(&coins[3..], &coins[2..3], &params).is_none());
+    }
+}
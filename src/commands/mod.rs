@@ -2,30 +2,39 @@
 //!
 //! External interface to the Liana daemon.
 
+mod bip329;
+mod coin_selection;
 mod utils;
 
 use crate::{
     bitcoin::BitcoinInterface,
-    database::{Coin, CoinType, DatabaseInterface},
-    descriptors, DaemonControl, VERSION,
+    database::{Coin, CoinType, DatabaseConnection, DatabaseInterface, LabelItem},
+    descriptors::{self, RecoveryTimelock},
+    random,
+    signer::{HotSigner, SignerError},
+    DaemonControl, VERSION,
 };
 
+pub use bip329::Bip329Error;
+use coin_selection::{select_coins, Candidate, SelectionParams};
 use utils::{
-    deser_addr_assume_checked, deser_amount_from_sats, deser_fromstr, deser_hex, ser_amount,
-    ser_hex, ser_to_string,
+    deser_addr_assume_checked, deser_amount_from_sats, deser_fromstr, deser_hex,
+    deser_optional_amount_from_sats, ser_amount, ser_hex, ser_optional_amount, ser_to_string,
 };
 
 use std::{
-    collections::{hash_map, BTreeMap, HashMap},
+    cmp,
+    collections::{hash_map, BTreeMap, HashMap, HashSet},
     convert::TryInto,
-    fmt,
+    fmt, io,
 };
 
 use miniscript::{
     bitcoin::{
-        self, address,
+        self, address, bip32,
         locktime::absolute,
         psbt::{Input as PsbtIn, Output as PsbtOut, PartiallySignedTransaction as Psbt},
+        secp256k1,
     },
     psbt::PsbtExt,
 };
@@ -41,6 +50,21 @@ const MAX_FEE: u64 = bitcoin::blockdata::constants::COIN_VALUE;
 // Assume that paying more than 1000sat/vb in feerate is a bug.
 const MAX_FEERATE: u64 = 1_000;
 
+// The minimum feerate increase, in sats/vb, for a replacement transaction to be relayed. This is
+// Bitcoin Core's default incremental relay feerate.
+const INCREMENTAL_RELAY_FEERATE: u64 = 1;
+
+// The largest confirmation target, in blocks, fee estimates can be asked for. That's Bitcoin
+// Core's limit.
+const MAX_CONF_TARGET: u16 = 1_008;
+
+// The confirmation targets, in blocks, we give fee estimates for.
+const FEE_ESTIMATES_TARGETS: [u16; 9] = [1, 2, 3, 6, 12, 24, 144, 504, MAX_CONF_TARGET];
+
+// The number of blocks before an absolute recovery path becomes available within which coins are
+// considered at risk, when the descriptor has no relative one. That's about a month.
+const ABS_TIMELOCK_EXPIRY_THRESHOLD: u32 = 144 * 30;
+
 // Timestamp in the header of the genesis block. Used for sanity checks.
 const MAINNET_GENESIS_TIME: u32 = 1231006505;
 
@@ -48,9 +72,13 @@ const MAINNET_GENESIS_TIME: u32 = 1231006505;
 pub enum CommandError {
     NoOutpoint,
     InvalidFeerate(/* sats/vb */ u64),
+    InvalidConfTarget(/* blocks */ u16),
+    NoFeeEstimate(/* blocks */ u16),
     UnknownOutpoint(bitcoin::OutPoint),
     AlreadySpent(bitcoin::OutPoint),
     ImmatureCoinbase(bitcoin::OutPoint),
+    FrozenCoin(bitcoin::OutPoint),
+    ReservedCoin(bitcoin::OutPoint, /* draft spend txid */ bitcoin::Txid),
     Address(bitcoin::address::Error),
     InvalidOutputValue(bitcoin::Amount),
     InsufficientFunds(
@@ -70,6 +98,24 @@ pub enum CommandError {
     /// An error that might occur in the racy rescan triggering logic.
     RescanTrigger(String),
     RecoveryNotAvailable,
+    CoinNotRecoverable(bitcoin::OutPoint),
+    NoCoinToRefresh(/* threshold in blocks */ u32),
+    /// None of our recovery paths is behind this relative timelock.
+    UnknownRecoveryTimelock(u16),
+    /// Our descriptor has no recovery path behind a relative timelock, refreshing coins doesn't
+    /// delay the others.
+    NoRelativeRecoveryPath,
+    RbfError(RbfErrorInfo),
+    CpfpError(CpfpErrorInfo),
+    InvalidBip329(Bip329Error),
+    /// Failed to gather randomness for creating a transaction.
+    Randomness(String),
+    /// No hot signer is stored in our data directory.
+    NoHotSigner,
+    /// Error when loading the hot signers or signing with them.
+    HotSigner(String),
+    /// Error when querying the Bitcoin backend, for instance if it can't be reached.
+    BitcoinBackend(String),
 }
 
 impl fmt::Display for CommandError {
@@ -77,8 +123,24 @@ impl fmt::Display for CommandError {
         match self {
             Self::NoOutpoint => write!(f, "No provided outpoint. Need at least one."),
             Self::InvalidFeerate(sats_vb) => write!(f, "Invalid feerate: {} sats/vb.", sats_vb),
+            Self::InvalidConfTarget(blocks) => write!(
+                f,
+                "Invalid confirmation target: {} blocks. Must be between 1 and {}.",
+                blocks, MAX_CONF_TARGET
+            ),
+            Self::NoFeeEstimate(blocks) => write!(
+                f,
+                "No fee estimate available for a confirmation within {} blocks.",
+                blocks
+            ),
             Self::AlreadySpent(op) => write!(f, "Coin at '{}' is already spent.", op),
             Self::ImmatureCoinbase(op) => write!(f, "Coin at '{}' is from an immature coinbase transaction.", op),
+            Self::FrozenCoin(op) => write!(f, "Coin at '{}' is frozen.", op),
+            Self::ReservedCoin(op, txid) => write!(
+                f,
+                "Coin at '{}' is already spent by the stored Spend transaction '{}'.",
+                op, txid
+            ),
             Self::UnknownOutpoint(op) => write!(f, "Unknown outpoint '{}'.", op),
             Self::Address(e) => write!(
                 f,
@@ -134,12 +196,70 @@ impl fmt::Display for CommandError {
                 f,
                 "No coin currently spendable through this timelocked recovery path."
            ),
+            Self::CoinNotRecoverable(op) => write!(
+                f,
+                "Coin at '{}' is not spendable through this timelocked recovery path yet.",
+                op
+            ),
+            Self::NoCoinToRefresh(threshold) => write!(
+                f,
+                "No coin whose recovery path becomes available within {} blocks.",
+                threshold
+            ),
+            Self::UnknownRecoveryTimelock(tl) => {
+                write!(f, "No recovery path behind a relative timelock of {} blocks.", tl)
+            }
+            Self::NoRelativeRecoveryPath => write!(
+                f,
+                "No recovery path behind a relative timelock to refresh coins against."
+            ),
+            Self::RbfError(info) => write!(
+                f,
+                "Cannot create a replacement transaction: {}.",
+                match info {
+                    RbfErrorInfo::AlreadyConfirmed => "the transaction is already confirmed".to_string(),
+                    RbfErrorInfo::NotSignaling => "the transaction does not signal replaceability".to_string(),
+                    RbfErrorInfo::TooLowFeerate(r) => format!("the feerate must be at least {} sats/vb", r),
+                    RbfErrorInfo::TooLowFee(f) => format!("the fee must be at least {} sats", f),
+                    RbfErrorInfo::NotOurs(op) => format!("the transaction spends coin '{}' which isn't ours", op),
+                },
+            ),
+            Self::CpfpError(info) => write!(
+                f,
+                "Cannot create a CPFP transaction: {}.",
+                match info {
+                    CpfpErrorInfo::NoTransaction => "no transaction to bump was given".to_string(),
+                    CpfpErrorInfo::UnknownTransaction(txid) => format!("unknown transaction '{}'", txid),
+                    CpfpErrorInfo::AlreadyConfirmed(txid) => format!("transaction '{}' is already confirmed", txid),
+                    CpfpErrorInfo::NotInMempool(txid) => format!("transaction '{}' is not in mempool", txid),
+                    CpfpErrorInfo::NoSpendableCoin(txid) => format!("transaction '{}' has no spendable output of ours", txid),
+                    CpfpErrorInfo::TooHighFeerate(r) => format!(
+                        "the child would need a feerate of {} sats/vb to lift the package, above the maximum of {} sats/vb",
+                        r, MAX_FEERATE
+                    ),
+                },
+            ),
+            Self::InvalidBip329(e) => write!(f, "Invalid BIP329 labels at {}.", e),
+            Self::Randomness(e) => write!(f, "Error when generating randomness: {}.", e),
+            Self::NoHotSigner => write!(f, "No hot signer in the data directory."),
+            Self::HotSigner(e) => write!(f, "Hot signer error: {}.", e),
+            Self::BitcoinBackend(e) => write!(f, "Error querying the Bitcoin backend: {}.", e),
         }
     }
 }
 
 impl std::error::Error for CommandError {}
 
+/// The fee to create a transaction with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeTarget {
+    /// A feerate in sats/vb.
+    Feerate(u64),
+    /// The number of blocks within which the transaction should confirm. The feerate is
+    /// estimated by our Bitcoin backend.
+    Blocks(u16),
+}
+
 // Sanity check the value of a transaction output.
 fn check_output_value(value: bitcoin::Amount) -> Result<(), CommandError> {
     // NOTE: the network parameter isn't used upstream
@@ -160,6 +280,27 @@ pub enum InsaneFeeInfo {
     TooHighFeerate(u64),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RbfErrorInfo {
+    AlreadyConfirmed,
+    NotSignaling,
+    TooLowFeerate(u64),
+    TooLowFee(u64),
+    /// The transaction spends this coin which isn't ours.
+    NotOurs(bitcoin::OutPoint),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpfpErrorInfo {
+    NoTransaction,
+    UnknownTransaction(bitcoin::Txid),
+    AlreadyConfirmed(bitcoin::Txid),
+    NotInMempool(bitcoin::Txid),
+    NoSpendableCoin(bitcoin::Txid),
+    /// The feerate the child needs to reach the target for the package exceeds our maximum.
+    TooHighFeerate(u64),
+}
+
 // Apply some sanity checks on a created transaction's PSBT.
 // TODO: add more sanity checks from revault_tx
 fn sanity_check_psbt(
@@ -180,7 +321,7 @@ fn sanity_check_psbt(
     // index set for signing devices to recognize them as ours.
     let mut value_in = 0;
     for psbtin in psbt.inputs.iter() {
-        if psbtin.bip32_derivation.is_empty() {
+        if psbtin.bip32_derivation.is_empty() && psbtin.tap_key_origins.is_empty() {
             return Err(CommandError::SanityCheckFailure(psbt.clone()));
         }
         value_in += psbtin
@@ -225,6 +366,22 @@ fn serializable_size<T: bitcoin::consensus::Encodable + ?Sized>(t: &T) -> u64 {
     bitcoin::consensus::serialize(t).len().try_into().unwrap()
 }
 
+// Shuffle the outputs of the transaction, and the PSBT outputs along with them, so the change
+// output can't be told apart by its position.
+fn shuffle_outputs(
+    tx: &mut bitcoin::Transaction,
+    psbt_outs: &mut [PsbtOut],
+) -> Result<(), CommandError> {
+    assert_eq!(tx.output.len(), psbt_outs.len());
+    for i in (1..tx.output.len()).rev() {
+        let j = random::random_below(i as u64 + 1)
+            .map_err(|e| CommandError::Randomness(e.to_string()))? as usize;
+        tx.output.swap(i, j);
+        psbt_outs.swap(i, j);
+    }
+    Ok(())
+}
+
 impl DaemonControl {
     // Get the derived descriptor for this coin
     fn derived_desc(&self, coin: &Coin) -> descriptors::DerivedSinglePathLianaDesc {
@@ -236,6 +393,212 @@ impl DaemonControl {
         desc.derive(coin.derivation_index, &self.secp)
     }
 
+    // Get the txids of the stored Spend transactions which were not broadcast yet, for each of the
+    // coins they spend.
+    fn draft_spends(
+        &self,
+        db_conn: &mut dyn DatabaseConnection,
+    ) -> HashMap<bitcoin::OutPoint, Vec<bitcoin::Txid>> {
+        let spends = db_conn.list_spend();
+        let outpoints: Vec<bitcoin::OutPoint> = spends
+            .iter()
+            .flat_map(|(psbt, _)| {
+                psbt.unsigned_tx
+                    .input
+                    .iter()
+                    .map(|txin| txin.previous_output)
+            })
+            .collect();
+        let coins = db_conn.coins_by_outpoints(&outpoints);
+
+        let mut drafts: HashMap<bitcoin::OutPoint, Vec<bitcoin::Txid>> = HashMap::new();
+        for (psbt, _) in spends {
+            let txid = psbt.unsigned_tx.txid();
+            let inputs = psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output);
+            // Once broadcast, the coins are marked as spent by this transaction.
+            let is_broadcast = inputs
+                .clone()
+                .any(|op| coins.get(&op).and_then(|c| c.spend_txid) == Some(txid));
+            if !is_broadcast {
+                for op in inputs {
+                    drafts.entry(op).or_default().push(txid);
+                }
+            }
+        }
+        for txids in drafts.values_mut() {
+            txids.sort_unstable();
+        }
+
+        drafts
+    }
+
+    // The feerate in sats/vb to create a transaction at to reach this fee target.
+    fn target_feerate(&self, target: FeeTarget) -> Result<u64, CommandError> {
+        match target {
+            FeeTarget::Feerate(feerate_vb) => Ok(feerate_vb),
+            FeeTarget::Blocks(blocks) => {
+                if !(1..=MAX_CONF_TARGET).contains(&blocks) {
+                    return Err(CommandError::InvalidConfTarget(blocks));
+                }
+                self.bitcoin
+                    .fee_estimates(&[blocks])
+                    .map_err(CommandError::BitcoinBackend)?
+                    .get(&blocks)
+                    .map(|feerate_vb| cmp::max(*feerate_vb, 1))
+                    .ok_or(CommandError::NoFeeEstimate(blocks))
+            }
+        }
+    }
+
+    // The nLockTime to set on the transactions we create to discourage fee sniping. As Bitcoin Core
+    // we use the current height, and sometimes a height up to 100 blocks before it so transactions
+    // that were delayed for privacy or latency reasons don't stand out. While syncing our tip may
+    // be far behind and would reveal it, so we don't set any.
+    fn anti_fee_sniping_locktime(&self) -> Result<absolute::LockTime, CommandError> {
+        if self.bitcoin.sync_progress() < 1.0 {
+            return Ok(absolute::LockTime::ZERO);
+        }
+        let randomness_err = |e: random::RandomnessError| CommandError::Randomness(e.to_string());
+        let mut height: u32 = self
+            .bitcoin
+            .chain_tip()
+            .map_err(CommandError::BitcoinBackend)?
+            .height
+            .try_into()
+            .expect("Must not be negative");
+        if random::random_below(10).map_err(randomness_err)? == 0 {
+            let backdate = random::random_below(100).map_err(randomness_err)? as u32;
+            height = height.saturating_sub(backdate);
+        }
+        Ok(absolute::LockTime::Blocks(
+            absolute::Height::from_consensus(height).expect("Must be a valid block height"),
+        ))
+    }
+
+    // Our unspent coins which may be added to a transaction spending the coins at the given
+    // outpoints: those which are mature, not frozen and not already used by a stored Spend
+    // transaction. Never returns a coin created by a transaction which is being replaced, nor an
+    // unconfirmed one when replacing: the replacement may not spend unconfirmed coins other than
+    // the ones spent by the transactions it replaces (BIP125 rule 2).
+    fn selectable_coins(
+        &self,
+        db_conn: &mut dyn DatabaseConnection,
+        mandatory_outpoints: &[bitcoin::OutPoint],
+    ) -> Vec<Coin> {
+        let replaced: HashSet<bitcoin::Txid> = db_conn
+            .coins_by_outpoints(mandatory_outpoints)
+            .values()
+            .filter_map(|c| c.spend_txid)
+            .collect();
+        let is_replacement = !replaced.is_empty();
+        let reserved = self.draft_spends(db_conn);
+        db_conn
+            .coins(CoinType::Unspent)
+            .into_values()
+            .filter(|c| {
+                !c.is_immature
+                    && !c.is_frozen
+                    && !reserved.contains_key(&c.outpoint)
+                    && !replaced.contains(&c.outpoint.txid)
+                    && !mandatory_outpoints.contains(&c.outpoint)
+                    && (!is_replacement || c.block_info.is_some())
+            })
+            .collect()
+    }
+
+    // Select coins to fund a transaction with these outputs at this feerate, among our selectable
+    // coins. The coins at the given outpoints are always spent, the selected coins are returned in
+    // addition to them.
+    fn select_coins(
+        &self,
+        db_conn: &mut dyn DatabaseConnection,
+        mandatory_outpoints: &[bitcoin::OutPoint],
+        txouts: &[bitcoin::TxOut],
+        out_value: bitcoin::Amount,
+        feerate_vb: u64,
+    ) -> Result<Vec<bitcoin::OutPoint>, CommandError> {
+        let mandatory: Vec<Candidate> = db_conn
+            .coins_by_outpoints(mandatory_outpoints)
+            .values()
+            .map(|c| Candidate {
+                outpoint: c.outpoint,
+                amount: c.amount,
+            })
+            .collect();
+        let candidates: Vec<Candidate> = self
+            .selectable_coins(db_conn, mandatory_outpoints)
+            .into_iter()
+            .map(|c| Candidate {
+                outpoint: c.outpoint,
+                amount: c.amount,
+            })
+            .collect();
+
+        // The size of the transaction without inputs, deduced from its size with a single one.
+        let txin = bitcoin::TxIn::default();
+        let txin_vb = serializable_size(&txin);
+        let base_vb = (bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![txin],
+            output: txouts.to_vec(),
+        }
+        .vsize() as u64)
+            .checked_sub(txin_vb)
+            .unwrap();
+        let change_txo = bitcoin::TxOut {
+            value: u64::MAX,
+            script_pubkey: self
+                .config
+                .main_descriptor
+                .change_descriptor()
+                .derive(db_conn.change_index(), &self.secp)
+                .script_pubkey(),
+        };
+        let params = SelectionParams {
+            out_value: out_value.to_sat(),
+            feerate_vb,
+            base_vb,
+            input_vb: txin_vb + self.config.main_descriptor.max_sat_vbytes() as u64,
+            change_vb: serializable_size(&change_txo),
+            min_change: DUST_OUTPUT_SATS,
+        };
+
+        let selection = select_coins(&mandatory, &candidates, &params).ok_or_else(|| {
+            let available = mandatory
+                .iter()
+                .chain(candidates.iter())
+                .map(|c| c.amount)
+                .sum();
+            CommandError::InsufficientFunds(available, Some(out_value), feerate_vb)
+        })?;
+        Ok(selection.coins.into_iter().map(|c| c.outpoint).collect())
+    }
+
+    // Freeze or unfreeze the coins at the given outpoints. They must all exist, and not be spent
+    // if we are to freeze them.
+    fn set_frozen_coins(
+        &self,
+        outpoints: &[bitcoin::OutPoint],
+        frozen: bool,
+    ) -> Result<(), CommandError> {
+        let mut db_conn = self.db.connection();
+        let coins = db_conn.coins_by_outpoints(outpoints);
+        for op in outpoints {
+            let coin = coins.get(op).ok_or(CommandError::UnknownOutpoint(*op))?;
+            if frozen && coin.is_spent() {
+                return Err(CommandError::AlreadySpent(*op));
+            }
+        }
+        db_conn.set_frozen_coins(outpoints, frozen);
+
+        Ok(())
+    }
+
     // Check whether this address is valid for the network we are operating on.
     fn validate_address(
         &self,
@@ -249,14 +612,49 @@ impl DaemonControl {
 }
 
 impl DaemonControl {
-    /// Get information about the current state of the daemon
-    pub fn get_info(&self) -> GetInfoResult {
+    /// Get information about the current state of the daemon.
+    ///
+    /// Coins whose first recovery path becomes available within `threshold` blocks after the
+    /// next one are reported at risk. By default, within 10% of the timelock of the first
+    /// recovery path.
+    pub fn get_info(&self, threshold: Option<u32>) -> GetInfoResult {
         let mut db_conn = self.db.connection();
 
         let block_height = db_conn.chain_tip().map(|tip| tip.height).unwrap_or(0);
         let rescan_progress = db_conn
             .rescan_timestamp()
             .map(|_| self.bitcoin.rescan_progress().unwrap_or(1.0));
+
+        // Unless told otherwise, warn about the coins whose first recovery path becomes available
+        // within 10% of its timelock. If we only have recovery paths behind an absolute timelock,
+        // within a fixed number of blocks of the first one.
+        let policy = self.config.main_descriptor.policy();
+        let threshold = threshold.unwrap_or_else(|| match policy.recovery_paths().keys().next() {
+            Some(tl) => u32::from(*tl) / 10,
+            None if !policy.abs_recovery_paths().is_empty() => ABS_TIMELOCK_EXPIRY_THRESHOLD,
+            None => 0,
+        });
+        let mut expiry = ExpirySummary {
+            threshold,
+            earliest_expiry: None,
+            value_at_risk: bitcoin::Amount::from_sat(0),
+        };
+        let abs_expiries = self.abs_expiries(block_height + 1);
+        for coin in db_conn.coins(CoinType::Unspent).into_values() {
+            let first_expiry = match self.coin_expiries(&coin, &abs_expiries).first() {
+                Some(exp) => exp.height,
+                None => continue,
+            };
+            expiry.earliest_expiry = Some(
+                expiry
+                    .earliest_expiry
+                    .map_or(first_expiry, |h| cmp::min(h, first_expiry)),
+            );
+            if i64::from(first_expiry) - i64::from(block_height + 1) < i64::from(threshold) {
+                expiry.value_at_risk += coin.amount;
+            }
+        }
+
         GetInfoResult {
             version: VERSION.to_string(),
             network: self.config.bitcoin_config.network,
@@ -266,9 +664,90 @@ impl DaemonControl {
                 main: self.config.main_descriptor.clone(),
             },
             rescan_progress,
+            expiry,
+        }
+    }
+
+    /// Describe the spending paths of our descriptor, naming the keys after the given aliases for
+    /// their master fingerprint.
+    pub fn get_policy(&self, aliases: &HashMap<bip32::Fingerprint, String>) -> GetPolicyResult {
+        let policy = self.config.main_descriptor.policy().description(aliases);
+        GetPolicyResult {
+            text: policy.to_string(),
+            policy,
         }
     }
 
+    // For each of our recovery paths behind an absolute timelock, the height of the first block
+    // at which it is available. It's the same for all coins. For those behind a date it is
+    // estimated from the median time past of the tip, assuming a block every 10 minutes, and
+    // they are omitted if our Bitcoin backend can't tell us the latter. Those already available
+    // are available at the next block.
+    fn abs_expiries(&self, next_height: i32) -> Vec<RecoveryPathExpiry> {
+        let policy = self.config.main_descriptor.policy();
+        let timelocks = policy.abs_recovery_paths().keys();
+        let mtp = if timelocks.clone().any(|tl| *tl >= 500_000_000) {
+            self.bitcoin.tip_median_time_past().ok()
+        } else {
+            None
+        };
+        timelocks
+            .filter_map(|timelock| {
+                let height = match absolute::LockTime::from_consensus(*timelock) {
+                    absolute::LockTime::Blocks(h) => {
+                        h.to_consensus_u32().try_into().expect("Below 500M")
+                    }
+                    absolute::LockTime::Seconds(t) => {
+                        let mtp = mtp?;
+                        match t.to_consensus_u32().checked_sub(mtp) {
+                            Some(secs_left) => {
+                                let blocks_left: i32 =
+                                    (secs_left / 600 + 1).try_into().expect("Fits");
+                                next_height + blocks_left
+                            }
+                            None => next_height,
+                        }
+                    }
+                };
+                Some(RecoveryPathExpiry {
+                    timelock: None,
+                    abs_timelock: Some(*timelock),
+                    height,
+                })
+            })
+            .collect()
+    }
+
+    // For each of our recovery paths, the height of the first block at which it is available for
+    // this coin, by increasing height. Empty for an unconfirmed coin, as its timelocks haven't
+    // started yet. The expiries of the recovery paths behind an absolute timelock are the same for
+    // all coins and must be given.
+    fn coin_expiries(
+        &self,
+        coin: &Coin,
+        abs_expiries: &[RecoveryPathExpiry],
+    ) -> Vec<RecoveryPathExpiry> {
+        let block_height = match coin.block_info {
+            Some(info) => info.height,
+            None => return Vec::new(),
+        };
+        let mut expiries: Vec<_> = self
+            .config
+            .main_descriptor
+            .policy()
+            .recovery_paths()
+            .keys()
+            .map(|timelock| RecoveryPathExpiry {
+                timelock: Some(*timelock),
+                abs_timelock: None,
+                height: block_height + i32::from(*timelock),
+            })
+            .chain(abs_expiries.iter().copied())
+            .collect();
+        expiries.sort_by_key(|exp| exp.height);
+        expiries
+    }
+
     /// Get a new deposit address. This will always generate a new deposit address, regardless of
     /// whether it was actually used.
     pub fn get_new_address(&self) -> GetAddressResult {
@@ -290,6 +769,7 @@ impl DaemonControl {
     /// Get a list of all known coins.
     pub fn list_coins(&self) -> ListCoinsResult {
         let mut db_conn = self.db.connection();
+        let mut draft_spends = self.draft_spends(db_conn.as_mut());
         let coins: Vec<ListCoinsEntry> = db_conn
             .coins(CoinType::All)
             .into_values()
@@ -301,6 +781,7 @@ impl DaemonControl {
                     spend_txid,
                     spend_block,
                     is_immature,
+                    is_frozen,
                     ..
                 } = coin;
                 let spend_info = spend_txid.map(|txid| LCSpendInfo {
@@ -308,32 +789,212 @@ impl DaemonControl {
                     height: spend_block.map(|b| b.height),
                 });
                 let block_height = block_info.map(|b| b.height);
+                let draft_spends = draft_spends.remove(&outpoint).unwrap_or_default();
                 ListCoinsEntry {
                     amount,
                     outpoint,
                     block_height,
                     spend_info,
                     is_immature,
+                    is_frozen,
+                    draft_spends,
                 }
             })
             .collect();
         ListCoinsResult { coins }
     }
 
+    /// List our confirmed unspent coins along with the height at which each of our recovery paths
+    /// becomes available for them, by increasing height of the first one.
+    ///
+    /// If `threshold` is set, only list the coins whose first recovery path becomes available
+    /// within this number of blocks after the next one.
+    pub fn list_expiring_coins(&self, threshold: Option<u32>) -> ListExpiringCoinsResult {
+        let mut db_conn = self.db.connection();
+        let next_height = db_conn.chain_tip().map(|tip| tip.height).unwrap_or(0) + 1;
+        let abs_expiries = self.abs_expiries(next_height);
+
+        let mut coins: Vec<ListExpiringCoinsEntry> = db_conn
+            .coins(CoinType::Unspent)
+            .into_values()
+            .filter_map(|coin| {
+                let block_height = coin.block_info?.height;
+                let expiries = self.coin_expiries(&coin, &abs_expiries);
+                let first_expiry = expiries.first()?.height;
+                if let Some(threshold) = threshold {
+                    if i64::from(first_expiry) - i64::from(next_height) >= i64::from(threshold) {
+                        return None;
+                    }
+                }
+                Some(ListExpiringCoinsEntry {
+                    amount: coin.amount,
+                    outpoint: coin.outpoint,
+                    block_height,
+                    expiries,
+                })
+            })
+            .collect();
+        coins.sort_by_key(|c| (c.block_height, c.outpoint));
+
+        ListExpiringCoinsResult { coins }
+    }
+
+    /// Create a transaction paying to these destinations, spending these coins or coins we
+    /// select if none is given.
+    ///
+    /// If `send_max` is set, all the value left after paying the destinations and the fees is
+    /// sent to this address instead of a change output. If no coin is given, all our available
+    /// coins are spent.
     pub fn create_spend(
         &self,
         destinations: &HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>,
         coins_outpoints: &[bitcoin::OutPoint],
-        feerate_vb: u64,
+        fee_target: FeeTarget,
+        allow_frozen: bool,
+        send_max: Option<bitcoin::Address<bitcoin::address::NetworkUnchecked>>,
     ) -> Result<CreateSpendResult, CommandError> {
-        let is_self_send = destinations.is_empty();
-        if coins_outpoints.is_empty() {
+        // We can't select coins for a send-to-self, we wouldn't know which ones to refresh.
+        if destinations.is_empty() && send_max.is_none() && coins_outpoints.is_empty() {
             return Err(CommandError::NoOutpoint);
         }
+        let destinations = destinations
+            .iter()
+            .map(|(addr, value)| {
+                let address = self.validate_address(addr.clone())?;
+                let amount = bitcoin::Amount::from_sat(*value);
+                check_output_value(amount)?;
+                Ok(bitcoin::TxOut {
+                    value: amount.to_sat(),
+                    script_pubkey: address.script_pubkey(),
+                })
+            })
+            .collect::<Result<Vec<_>, CommandError>>()?;
+        let send_max = send_max
+            .map(|addr| self.validate_address(addr))
+            .transpose()?;
+        let feerate_vb = self.target_feerate(fee_target)?;
+        let mut db_conn = self.db.connection();
+
+        // Frozen coins may only be spent if explicitly requested.
+        if !allow_frozen {
+            let coins = db_conn.coins_by_outpoints(coins_outpoints);
+            if let Some(op) = coins_outpoints
+                .iter()
+                .find(|op| coins.get(op).map(|c| c.is_frozen).unwrap_or(false))
+            {
+                return Err(CommandError::FrozenCoin(*op));
+            }
+        }
+
+        // Don't create conflicting drafts. The stored Spend must be deleted first to release its
+        // coins.
+        let draft_spends = self.draft_spends(db_conn.as_mut());
+        for op in coins_outpoints {
+            if let Some(txid) = draft_spends.get(op).and_then(|txids| txids.first()) {
+                return Err(CommandError::ReservedCoin(*op, *txid));
+            }
+        }
+
+        // If no coin was provided, select them ourselves among our available coins.
+        self.create_spend_internal(
+            db_conn.as_mut(),
+            &destinations,
+            coins_outpoints,
+            coins_outpoints.is_empty(),
+            feerate_vb,
+            None,
+            send_max.as_ref(),
+        )
+    }
+
+    // The PSBT output for a payment to this address. If it's an address of ours, signal it as
+    // change to signing devices by adding the BIP32 derivation path.
+    fn destination_psbt_out(
+        &self,
+        db_conn: &mut dyn DatabaseConnection,
+        address: &bitcoin::Address,
+    ) -> PsbtOut {
+        let mut psbt_out = PsbtOut::default();
+        if let Some((index, is_change)) = db_conn.derivation_index_by_address(address) {
+            let desc = if is_change {
+                self.config.main_descriptor.change_descriptor()
+            } else {
+                self.config.main_descriptor.receive_descriptor()
+            };
+            desc.derive(index, &self.secp)
+                .update_psbt_out(&mut psbt_out);
+        }
+        psbt_out
+    }
+
+    // Create a transaction with these destination outputs and spending these coins. If
+    // `auto_select` is set, additional coins will be selected among our available ones if the
+    // given ones are not sufficient to fund the transaction. If `replaced_txid` is set, the coins
+    // spent by this (unconfirmed) transaction may be spent again.
+    //
+    // If `send_max` is set, the value left after paying the destinations and the fees is sent to
+    // this address instead of a change output. Coin selection then spends all our available coins.
+    #[allow(clippy::too_many_arguments)]
+    fn create_spend_internal(
+        &self,
+        db_conn: &mut dyn DatabaseConnection,
+        destinations: &[bitcoin::TxOut],
+        coins_outpoints: &[bitcoin::OutPoint],
+        auto_select: bool,
+        feerate_vb: u64,
+        replaced_txid: Option<bitcoin::Txid>,
+        send_max: Option<&bitcoin::Address>,
+    ) -> Result<CreateSpendResult, CommandError> {
+        let is_self_send = destinations.is_empty() && send_max.is_none();
+        // Whether we must add an output taking the value left, be it change or the send-max one.
+        let has_remainder_output = is_self_send || send_max.is_some();
         if feerate_vb < 1 {
             return Err(CommandError::InvalidFeerate(feerate_vb));
         }
-        let mut db_conn = self.db.connection();
+
+        // Add the destinations outputs to the transaction and PSBT. At the same time record the
+        // total output value to later compute fees. The value of each output must have been
+        // checked by the caller.
+        let network = self.config.bitcoin_config.network;
+        let mut out_value = bitcoin::Amount::from_sat(0);
+        let mut txouts = Vec::with_capacity(destinations.len());
+        let mut psbt_outs = Vec::with_capacity(destinations.len());
+        for txo in destinations {
+            out_value = out_value
+                .checked_add(bitcoin::Amount::from_sat(txo.value))
+                .unwrap();
+            // Outputs without an address (such as OP_RETURN) are never ours.
+            let psbt_out = bitcoin::Address::from_script(&txo.script_pubkey, network)
+                .map(|address| self.destination_psbt_out(db_conn, &address))
+                .unwrap_or_default();
+            txouts.push(txo.clone());
+            psbt_outs.push(psbt_out);
+        }
+        assert_eq!(txouts.is_empty(), destinations.is_empty());
+        let insufficient_funds = |in_value| {
+            let out_value = if destinations.is_empty() {
+                None
+            } else {
+                Some(out_value)
+            };
+            CommandError::InsufficientFunds(in_value, out_value, feerate_vb)
+        };
+
+        let mut coins_outpoints = coins_outpoints.to_vec();
+        if auto_select {
+            if send_max.is_some() {
+                // There is no target to select coins for, sweep all of them.
+                let selectable = self.selectable_coins(db_conn, &coins_outpoints);
+                coins_outpoints.extend(selectable.into_iter().map(|c| c.outpoint));
+                if coins_outpoints.is_empty() {
+                    return Err(insufficient_funds(bitcoin::Amount::from_sat(0)));
+                }
+            } else {
+                let selected =
+                    self.select_coins(db_conn, &coins_outpoints, &txouts, out_value, feerate_vb)?;
+                coins_outpoints.extend(selected);
+            }
+        }
 
         // Iterate through given outpoints to fetch the coins (hence checking their existence
         // at the same time). We checked there is at least one, therefore after this loop the
@@ -346,11 +1007,12 @@ impl DaemonControl {
         let mut txins = Vec::with_capacity(coins_outpoints.len());
         let mut psbt_ins = Vec::with_capacity(coins_outpoints.len());
         let mut spent_txs = HashMap::with_capacity(coins_outpoints.len());
-        let coins = db_conn.coins_by_outpoints(coins_outpoints);
-        for op in coins_outpoints {
-            // Get the coin from our in-DB unspent txos
+        let coins = db_conn.coins_by_outpoints(&coins_outpoints);
+        for op in &coins_outpoints {
+            // Get the coin from our in-DB unspent txos. The coins spent by the transaction we are
+            // replacing may be spent again.
             let coin = coins.get(op).ok_or(CommandError::UnknownOutpoint(*op))?;
-            if coin.is_spent() {
+            if coin.is_spent() && (coin.spend_txid != replaced_txid || coin.spend_block.is_some()) {
                 return Err(CommandError::AlreadySpent(*op));
             }
             if coin.is_immature {
@@ -362,6 +1024,7 @@ impl DaemonControl {
                 let tx = self
                     .bitcoin
                     .wallet_transaction(&op.txid)
+                    .map_err(CommandError::BitcoinBackend)?
                     .ok_or(CommandError::FetchingTransaction(*op))?;
                 spent_txs.insert(*op, tx.0);
             }
@@ -377,63 +1040,25 @@ impl DaemonControl {
             // Populate the PSBT input with the information needed by signers.
             let coin_desc = self.derived_desc(coin);
             sat_vb += txin_sat_vb;
-            let witness_script = Some(coin_desc.witness_script());
             let witness_utxo = Some(bitcoin::TxOut {
                 value: coin.amount.to_sat(),
                 script_pubkey: coin_desc.script_pubkey(),
             });
             let non_witness_utxo = spent_txs.get(op).cloned();
-            let bip32_derivation = coin_desc.bip32_derivations();
-            psbt_ins.push(PsbtIn {
-                witness_script,
+            let mut psbt_in = PsbtIn {
                 witness_utxo,
-                bip32_derivation,
                 non_witness_utxo,
                 ..PsbtIn::default()
-            });
-        }
-
-        // Add the destinations outputs to the transaction and PSBT. At the same time record the
-        // total output value to later compute fees, and sanity check each output's value.
-        let mut out_value = bitcoin::Amount::from_sat(0);
-        let mut txouts = Vec::with_capacity(destinations.len());
-        let mut psbt_outs = Vec::with_capacity(destinations.len());
-        for (address, value_sat) in destinations {
-            let address = self.validate_address(address.clone())?;
-
-            let amount = bitcoin::Amount::from_sat(*value_sat);
-            check_output_value(amount)?;
-            out_value = out_value.checked_add(amount).unwrap();
-
-            txouts.push(bitcoin::TxOut {
-                value: amount.to_sat(),
-                script_pubkey: address.script_pubkey(),
-            });
-            // If it's an address of ours, signal it as change to signing devices by adding the
-            // BIP32 derivation path to the PSBT output.
-            let bip32_derivation =
-                if let Some((index, is_change)) = db_conn.derivation_index_by_address(&address) {
-                    let desc = if is_change {
-                        self.config.main_descriptor.change_descriptor()
-                    } else {
-                        self.config.main_descriptor.receive_descriptor()
-                    };
-                    desc.derive(index, &self.secp).bip32_derivations()
-                } else {
-                    Default::default()
-                };
-            psbt_outs.push(PsbtOut {
-                bip32_derivation,
-                ..PsbtOut::default()
-            });
+            };
+            coin_desc.update_psbt_in(&mut psbt_in);
+            psbt_ins.push(psbt_in);
         }
-        assert_eq!(txouts.is_empty(), is_self_send);
 
         // Now create the transaction, compute its fees and already sanity check if its feerate
         // isn't much less than what was asked (and obviously that fees aren't negative).
         let mut tx = bitcoin::Transaction {
             version: 2,
-            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO), // TODO: randomized anti fee sniping
+            lock_time: self.anti_fee_sniping_locktime()?,
             input: txins,
             output: txouts,
         };
@@ -455,25 +1080,38 @@ impl DaemonControl {
             ));
         }
 
-        // If necessary, add a change output. The computation here is a bit convoluted: we infer
-        // the needed change value from the target feerate and the size of the transaction *with
-        // an added output* (for the change).
-        if is_self_send || nochange_feerate_vb > feerate_vb {
-            // Get the change address to create a dummy change txo.
-            let change_index = db_conn.change_index();
-            let change_desc = self
-                .config
-                .main_descriptor
-                .change_descriptor()
-                .derive(change_index, &self.secp);
-            // Don't forget to update our next change index!
-            let next_index = change_index
-                .increment()
-                .expect("Must not get into hardened territory");
-            db_conn.set_change_index(next_index, &self.secp);
-            let mut change_txo = bitcoin::TxOut {
-                value: std::u64::MAX,
-                script_pubkey: change_desc.script_pubkey(),
+        // If necessary, add a change output (or the send-max output, which takes the place of the
+        // change). The computation here is a bit convoluted: we infer the needed change value from
+        // the target feerate and the size of the transaction *with an added output* (for the
+        // change).
+        let mut change = None;
+        if has_remainder_output || nochange_feerate_vb > feerate_vb {
+            let (mut change_txo, change_psbt_out) = if let Some(address) = send_max {
+                let txo = bitcoin::TxOut {
+                    value: u64::MAX,
+                    script_pubkey: address.script_pubkey(),
+                };
+                (txo, self.destination_psbt_out(db_conn, address))
+            } else {
+                // Get the change address to create a dummy change txo.
+                let change_index = db_conn.change_index();
+                let change_desc = self
+                    .config
+                    .main_descriptor
+                    .change_descriptor()
+                    .derive(change_index, &self.secp);
+                // Don't forget to update our next change index!
+                let next_index = change_index
+                    .increment()
+                    .expect("Must not get into hardened territory");
+                db_conn.set_change_index(next_index, &self.secp);
+                let txo = bitcoin::TxOut {
+                    value: u64::MAX,
+                    script_pubkey: change_desc.script_pubkey(),
+                };
+                let mut psbt_out = PsbtOut::default();
+                change_desc.update_psbt_out(&mut psbt_out);
+                (txo, psbt_out)
             };
             // Serialized size is equal to the virtual size for an output.
             let change_vb: u64 = serializable_size(&change_txo);
@@ -493,21 +1131,22 @@ impl DaemonControl {
                 if change_amount.to_sat() >= DUST_OUTPUT_SATS {
                     check_output_value(change_amount)?;
 
-                    // TODO: shuffle once we have Taproot
                     change_txo.value = change_amount.to_sat();
+                    if send_max.is_none() {
+                        change = Some(change_amount);
+                    }
                     tx.output.push(change_txo);
-                    psbt_outs.push(PsbtOut {
-                        bip32_derivation: change_desc.bip32_derivations(),
-                        ..PsbtOut::default()
-                    });
-                } else if is_self_send {
-                    return Err(CommandError::InsufficientFunds(in_value, None, feerate_vb));
+                    psbt_outs.push(change_psbt_out);
+                } else if has_remainder_output {
+                    return Err(insufficient_funds(in_value));
                 }
-            } else if is_self_send {
-                return Err(CommandError::InsufficientFunds(in_value, None, feerate_vb));
+            } else if has_remainder_output {
+                return Err(insufficient_funds(in_value));
             }
         }
 
+        shuffle_outputs(&mut tx, &mut psbt_outs)?;
+
         let psbt = Psbt {
             unsigned_tx: tx,
             version: 0,
@@ -520,7 +1159,11 @@ impl DaemonControl {
         sanity_check_psbt(&self.config.main_descriptor, &psbt)?;
         // TODO: maybe check for common standardness rules (max size, ..)?
 
-        Ok(CreateSpendResult { psbt })
+        Ok(CreateSpendResult {
+            psbt,
+            coins: coins_outpoints,
+            change_amount: change,
+        })
     }
 
     pub fn update_spend(&self, mut psbt: Psbt) -> Result<(), CommandError> {
@@ -553,6 +1196,12 @@ impl DaemonControl {
                 psbtin
                     .partial_sigs
                     .extend(db_psbtin.partial_sigs.clone().into_iter());
+                psbtin
+                    .tap_script_sigs
+                    .extend(db_psbtin.tap_script_sigs.clone());
+                if psbtin.tap_key_sig.is_none() {
+                    psbtin.tap_key_sig = db_psbtin.tap_key_sig;
+                }
             }
         } else {
             // If the transaction doesn't exist in DB already, sanity check its inputs.
@@ -585,6 +1234,50 @@ impl DaemonControl {
         ListSpendResult { spend_txs }
     }
 
+    /// Sign a stored Spend transaction with all the hot signers present in our data directory.
+    /// Encrypted mnemonics are unlocked using the given passphrase. The signatures are merged with
+    /// the stored PSBT as with [DaemonControl::update_spend].
+    pub fn sign_spend(
+        &self,
+        txid: &bitcoin::Txid,
+        passphrase: Option<&str>,
+    ) -> Result<SignSpendResult, CommandError> {
+        let mut psbt = self
+            .db
+            .connection()
+            .spend_tx(txid)
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+
+        let data_dir = self.config.data_dir().ok_or(CommandError::NoHotSigner)?;
+        let signers = match HotSigner::from_datadir(
+            &data_dir,
+            self.config.bitcoin_config.network,
+            passphrase,
+        ) {
+            Ok(signers) => signers,
+            Err(SignerError::MnemonicStorage(e)) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(CommandError::NoHotSigner)
+            }
+            Err(e) => return Err(CommandError::HotSigner(e.to_string())),
+        };
+        if signers.is_empty() {
+            return Err(CommandError::NoHotSigner);
+        }
+
+        let secp = secp256k1::Secp256k1::new();
+        for signer in signers {
+            // Anyone with access to the RPC interface can store a Spend. Never sign it blindly.
+            psbt = signer
+                .sign_psbt_checked(psbt, &self.config.main_descriptor, &secp)
+                .map(|(psbt, _)| psbt)
+                .map_err(|e| CommandError::HotSigner(e.to_string()))?;
+        }
+        self.update_spend(psbt.clone())?;
+
+        Ok(SignSpendResult { psbt })
+    }
+
+    /// Delete a stored Spend transaction, releasing the coins it spends for other drafts.
     pub fn delete_spend(&self, txid: &bitcoin::Txid) {
         let mut db_conn = self.db.connection();
         db_conn.delete_spend(txid);
@@ -616,137 +1309,510 @@ impl DaemonControl {
             .map_err(CommandError::TxBroadcast)
     }
 
-    /// Trigger a rescan of the block chain for transactions involving our main descriptor between
-    /// the given date and the current tip.
-    /// The date must be after the genesis block time and before the current tip blocktime.
-    pub fn start_rescan(&self, timestamp: u32) -> Result<(), CommandError> {
+    /// Create a transaction replacing an unconfirmed Spend transaction of ours at a higher
+    /// feerate, following the BIP125 rules. The replacement pays to the same destinations and
+    /// spends the same coins. The change output is shrunk first, and more coins are added if it
+    /// is not sufficient to pay for the increased fee.
+    ///
+    /// The replacement transaction is stored in database alongside the original one.
+    pub fn rbf_psbt(
+        &self,
+        txid: &bitcoin::Txid,
+        feerate_vb: u64,
+    ) -> Result<CreateSpendResult, CommandError> {
         let mut db_conn = self.db.connection();
 
-        if timestamp < MAINNET_GENESIS_TIME || timestamp >= self.bitcoin.tip_time() {
-            return Err(CommandError::InsaneRescanTimestamp(timestamp));
+        let (prev_tx, prev_block) = self
+            .bitcoin
+            .wallet_transaction(txid)
+            .map_err(CommandError::BitcoinBackend)?
+            .ok_or(CommandError::UnknownSpend(*txid))?;
+        if prev_block.is_some() {
+            return Err(CommandError::RbfError(RbfErrorInfo::AlreadyConfirmed));
         }
-        if db_conn.rescan_timestamp().is_some() || self.bitcoin.rescan_progress().is_some() {
-            return Err(CommandError::AlreadyRescanning);
+        if !prev_tx.is_explicitly_rbf() {
+            return Err(CommandError::RbfError(RbfErrorInfo::NotSignaling));
         }
 
-        // TODO: there is a race with the above check for whether the backend is already
-        // rescanning. This could make us crash with the bitcoind backend if someone triggered a
-        // rescan of the wallet just after we checked above and did now.
-        self.bitcoin
-            .start_rescan(&self.config.main_descriptor, timestamp)
-            .map_err(CommandError::RescanTrigger)?;
-        db_conn.set_rescan(timestamp);
+        // All the coins spent by the transaction to be replaced must be ours.
+        let prev_outpoints: Vec<bitcoin::OutPoint> = prev_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        let prev_coins = db_conn.coins_by_outpoints(&prev_outpoints);
+        let mut prev_in_value = bitcoin::Amount::from_sat(0);
+        for op in &prev_outpoints {
+            let coin = prev_coins
+                .get(op)
+                .ok_or(CommandError::RbfError(RbfErrorInfo::NotOurs(*op)))?;
+            prev_in_value += coin.amount;
+        }
 
-        Ok(())
-    }
+        // Keep all the outputs of the transaction to be replaced as they are, except the change.
+        let network = self.config.bitcoin_config.network;
+        let mut destinations = Vec::with_capacity(prev_tx.output.len());
+        let mut prev_out_value = bitcoin::Amount::from_sat(0);
+        for txo in &prev_tx.output {
+            prev_out_value += bitcoin::Amount::from_sat(txo.value);
+            let is_change = bitcoin::Address::from_script(&txo.script_pubkey, network)
+                .ok()
+                .and_then(|address| db_conn.derivation_index_by_address(&address))
+                .map(|(_, is_change)| is_change)
+                .unwrap_or(false);
+            if !is_change {
+                destinations.push(txo.clone());
+            }
+        }
 
-    /// list_confirmed_transactions retrieves a limited list of transactions which occured between two given dates.
-    pub fn list_confirmed_transactions(
-        &self,
+        // The replacement must have a higher feerate than the replaced transaction and pay for
+        // its own relay. The replaced transaction is the one our backend knows about, with its
+        // witnesses, so this is its actual feerate.
+        let prev_fee = prev_in_value
+            .checked_sub(prev_out_value)
+            .ok_or(CommandError::InsaneFees(InsaneFeeInfo::NegativeFee))?
+            .to_sat();
+        let prev_vb: u64 = prev_tx
+            .vsize()
+            .try_into()
+            .expect("Transaction size must fit in a u64");
+        let prev_feerate = prev_fee / prev_vb + u64::from(prev_fee % prev_vb != 0);
+        let min_feerate = prev_feerate + INCREMENTAL_RELAY_FEERATE;
+        if feerate_vb < min_feerate {
+            return Err(CommandError::RbfError(RbfErrorInfo::TooLowFeerate(
+                min_feerate,
+            )));
+        }
+
+        let res = self.create_spend_internal(
+            db_conn.as_mut(),
+            &destinations,
+            &prev_outpoints,
+            true,
+            feerate_vb,
+            Some(*txid),
+            None,
+        )?;
+
+        // Make sure the absolute fee increase pays for the relay of the replacement.
+        let psbt = &res.psbt;
+        let in_value: u64 = psbt
+            .inputs
+            .iter()
+            .map(|psbtin| {
+                psbtin
+                    .witness_utxo
+                    .as_ref()
+                    .map(|txo| txo.value)
+                    .unwrap_or(0)
+            })
+            .sum();
+        let out_value: u64 = psbt.unsigned_tx.output.iter().map(|txo| txo.value).sum();
+        let fee = in_value - out_value;
+        let vb = (psbt.unsigned_tx.vsize()
+            + self.config.main_descriptor.max_sat_vbytes() * psbt.unsigned_tx.input.len())
+            as u64;
+        let min_fee = prev_fee + vb * INCREMENTAL_RELAY_FEERATE;
+        if fee < min_fee {
+            return Err(CommandError::RbfError(RbfErrorInfo::TooLowFee(min_fee)));
+        }
+
+        db_conn.store_spend(&res.psbt);
+
+        Ok(res)
+    }
+
+    /// Get our Bitcoin backend's estimates of the feerate needed for a transaction to confirm
+    /// within various numbers of blocks.
+    pub fn get_fee_estimates(&self) -> Result<GetFeeEstimatesResult, CommandError> {
+        let estimates = self
+            .bitcoin
+            .fee_estimates(&FEE_ESTIMATES_TARGETS)
+            .map_err(CommandError::BitcoinBackend)?;
+        let estimates = FEE_ESTIMATES_TARGETS
+            .iter()
+            .filter_map(|conf_target| {
+                estimates.get(conf_target).map(|feerate| FeeEstimate {
+                    conf_target: *conf_target,
+                    feerate: cmp::max(*feerate, 1),
+                })
+            })
+            .collect();
+        Ok(GetFeeEstimatesResult { estimates })
+    }
+
+    /// Create a transaction spending all our coins created by these unconfirmed transactions to
+    /// a single change output, such as the package formed by the parents and this child has the
+    /// given target feerate.
+    ///
+    /// The fee and size of each parent is taken from the mempool of our Bitcoin backend. Note
+    /// their own unconfirmed ancestors aren't taken into account.
+    pub fn create_cpfp(
+        &self,
+        txids: &[bitcoin::Txid],
+        feerate_vb: u64,
+    ) -> Result<CreateSpendResult, CommandError> {
+        if txids.is_empty() {
+            return Err(CommandError::CpfpError(CpfpErrorInfo::NoTransaction));
+        }
+        if !(1..=MAX_FEERATE).contains(&feerate_vb) {
+            return Err(CommandError::InvalidFeerate(feerate_vb));
+        }
+        let mut db_conn = self.db.connection();
+
+        // Gather the coins to spend along with the fees already paid by the parents. Coins used by
+        // a stored Spend are left alone, as we must not create conflicting drafts. A parent given
+        // twice is only accounted for once.
+        let unspent_coins = db_conn.coins(CoinType::Unspent);
+        let draft_spends = self.draft_spends(db_conn.as_mut());
+        let mut seen = HashSet::with_capacity(txids.len());
+        let mut outpoints = Vec::new();
+        let (mut parents_fee, mut parents_vb) = (0, 0);
+        for txid in txids.iter().filter(|txid| seen.insert(**txid)) {
+            let (_, block) = self
+                .bitcoin
+                .wallet_transaction(txid)
+                .map_err(CommandError::BitcoinBackend)?
+                .ok_or(CommandError::CpfpError(CpfpErrorInfo::UnknownTransaction(
+                    *txid,
+                )))?;
+            if block.is_some() {
+                return Err(CommandError::CpfpError(CpfpErrorInfo::AlreadyConfirmed(
+                    *txid,
+                )));
+            }
+            let entry = self
+                .bitcoin
+                .mempool_entry(txid)
+                .map_err(CommandError::BitcoinBackend)?
+                .ok_or(CommandError::CpfpError(CpfpErrorInfo::NotInMempool(*txid)))?;
+            parents_fee += entry.fee.to_sat();
+            parents_vb += entry.vsize;
+
+            let n_outpoints = outpoints.len();
+            outpoints.extend(
+                unspent_coins
+                    .values()
+                    .filter(|c| {
+                        c.outpoint.txid == *txid
+                            && !c.is_immature
+                            && !c.is_frozen
+                            && !draft_spends.contains_key(&c.outpoint)
+                    })
+                    .map(|c| c.outpoint),
+            );
+            if outpoints.len() == n_outpoints {
+                return Err(CommandError::CpfpError(CpfpErrorInfo::NoSpendableCoin(
+                    *txid,
+                )));
+            }
+        }
+        // Have a deterministic ordering of the child's inputs.
+        outpoints.sort();
+
+        // Compute the size of the child, as a transaction with a maximum satisfaction for each
+        // input and a single change output.
+        let change_spk = self
+            .config
+            .main_descriptor
+            .change_descriptor()
+            .derive(db_conn.change_index(), &self.secp)
+            .script_pubkey();
+        let child_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: outpoints
+                .iter()
+                .map(|op| bitcoin::TxIn {
+                    previous_output: *op,
+                    ..bitcoin::TxIn::default()
+                })
+                .collect(),
+            output: vec![bitcoin::TxOut {
+                value: u64::MAX,
+                script_pubkey: change_spk,
+            }],
+        };
+        let child_vb = (child_tx.vsize()
+            + self.config.main_descriptor.max_sat_vbytes() * outpoints.len())
+            as u64;
+
+        // The child must pay for the whole package to reach the target feerate, and at least for
+        // itself at this feerate. The feerate is bounded, so this can't overflow.
+        let package_fee = (feerate_vb * (parents_vb + child_vb)).saturating_sub(parents_fee);
+        let child_fee = cmp::max(package_fee, feerate_vb * child_vb);
+        let child_feerate = child_fee / child_vb + u64::from(child_fee % child_vb != 0);
+        if child_feerate > MAX_FEERATE {
+            return Err(CommandError::CpfpError(CpfpErrorInfo::TooHighFeerate(
+                child_feerate,
+            )));
+        }
+
+        self.create_spend_internal(
+            db_conn.as_mut(),
+            &[],
+            &outpoints,
+            false,
+            child_feerate,
+            None,
+            None,
+        )
+    }
+
+    /// Freeze the coins at the given outpoints. They won't be spent unless explicitly requested.
+    pub fn freeze_coins(&self, outpoints: &[bitcoin::OutPoint]) -> Result<(), CommandError> {
+        self.set_frozen_coins(outpoints, true)
+    }
+
+    /// Unfreeze the coins at the given outpoints.
+    pub fn unfreeze_coins(&self, outpoints: &[bitcoin::OutPoint]) -> Result<(), CommandError> {
+        self.set_frozen_coins(outpoints, false)
+    }
+
+    /// Set the label of the given items, or remove it if `None`.
+    pub fn update_labels(&self, items: &HashMap<LabelItem, Option<String>>) {
+        let mut db_conn = self.db.connection();
+        db_conn.update_labels(items);
+    }
+
+    /// Get the labels of the given items, for those which have one.
+    pub fn get_labels(&self, items: &HashSet<LabelItem>) -> GetLabelsResult {
+        let mut db_conn = self.db.connection();
+        let labels = db_conn
+            .labels(items)
+            .into_iter()
+            .map(|(item, label)| (item.to_string(), label))
+            .collect();
+        GetLabelsResult { labels }
+    }
+
+    /// Import labels from BIP329 JSON Lines, overwriting the existing labels for the same items.
+    /// Records of types we don't support are ignored.
+    pub fn import_labels(&self, jsonl: &str) -> Result<(), CommandError> {
+        let labels = bip329::from_jsonl(jsonl, self.config.bitcoin_config.network)
+            .map_err(CommandError::InvalidBip329)?;
+        let mut db_conn = self.db.connection();
+        db_conn.update_labels(
+            &labels
+                .into_iter()
+                .map(|(item, label)| (item, Some(label)))
+                .collect(),
+        );
+        Ok(())
+    }
+
+    /// Export all our labels as BIP329 JSON Lines.
+    pub fn export_labels(&self) -> ExportLabelsResult {
+        let mut db_conn = self.db.connection();
+        ExportLabelsResult {
+            labels: bip329::to_jsonl(&db_conn.list_labels()),
+        }
+    }
+
+    /// Trigger a rescan of the block chain for transactions involving our main descriptor between
+    /// the given date and the current tip.
+    /// The date must be after the genesis block time and before the current tip blocktime.
+    pub fn start_rescan(&self, timestamp: u32) -> Result<(), CommandError> {
+        let mut db_conn = self.db.connection();
+
+        let tip_time = self
+            .bitcoin
+            .tip_time()
+            .map_err(CommandError::BitcoinBackend)?;
+        if timestamp < MAINNET_GENESIS_TIME || timestamp >= tip_time {
+            return Err(CommandError::InsaneRescanTimestamp(timestamp));
+        }
+        if db_conn.rescan_timestamp().is_some() || self.bitcoin.rescan_progress().is_some() {
+            return Err(CommandError::AlreadyRescanning);
+        }
+
+        // TODO: there is a race with the above check for whether the backend is already
+        // rescanning. This could make us crash with the bitcoind backend if someone triggered a
+        // rescan of the wallet just after we checked above and did now.
+        self.bitcoin
+            .start_rescan(&self.config.main_descriptor, timestamp)
+            .map_err(CommandError::RescanTrigger)?;
+        db_conn.set_rescan(timestamp);
+
+        Ok(())
+    }
+
+    /// list_confirmed_transactions retrieves a limited list of transactions which occured between two given dates.
+    pub fn list_confirmed_transactions(
+        &self,
         start: u32,
         end: u32,
         limit: u64,
     ) -> ListTransactionsResult {
         let mut db_conn = self.db.connection();
         let txids = db_conn.list_txids(start, end, limit);
-        let transactions = txids
-            .iter()
-            .filter_map(|txid| {
-                // TODO: batch those calls to the Bitcoin backend
-                // so it can in turn optimize its queries.
-                self.bitcoin
-                    .wallet_transaction(txid)
-                    .map(|(tx, block)| TransactionInfo {
-                        tx,
-                        height: block.map(|b| b.height),
-                        time: block.map(|b| b.time),
-                    })
-            })
-            .collect();
-        ListTransactionsResult { transactions }
+        self.list_transactions(&txids)
     }
 
     /// list_transactions retrieves the transactions with the given txids.
     pub fn list_transactions(&self, txids: &[bitcoin::Txid]) -> ListTransactionsResult {
-        let transactions = txids
-            .iter()
-            .filter_map(|txid| {
-                // TODO: batch those calls to the Bitcoin backend
-                // so it can in turn optimize its queries.
-                self.bitcoin
-                    .wallet_transaction(txid)
-                    .map(|(tx, block)| TransactionInfo {
-                        tx,
-                        height: block.map(|b| b.height),
-                        time: block.map(|b| b.time),
-                    })
+        let transactions = self
+            .db
+            .connection()
+            .list_wallet_transactions(txids)
+            .into_iter()
+            .map(|(tx, block)| TransactionInfo {
+                tx,
+                height: block.map(|b| b.height),
+                time: block.map(|b| b.time),
             })
             .collect();
         ListTransactionsResult { transactions }
     }
 
-    /// Create a transaction that sweeps all coins for which a timelocked recovery path is
-    /// currently available to a provided address with the provided feerate.
+    /// Create a transaction spending coins for which a timelocked recovery path is currently
+    /// available, with the provided fee target.
+    ///
+    /// If no coin is given, all the coins spendable through this recovery path are spent. Frozen
+    /// coins are only spent if explicitly given.
+    ///
+    /// The given destinations are paid the given amounts. The value left is sent to `address` if
+    /// provided, otherwise to a change output back to the wallet if it is worth it. Without any
+    /// destination, this sweeps the coins to `address`. It is an error for the value left to be
+    /// sent to `address` to be dust.
     ///
     /// The `timelock` parameter can be used to specify which recovery path to use. By default,
-    /// we'll use the first recovery path available.
+    /// we'll use the first recovery path available. A recovery path behind a relative timelock
+    /// is used by setting the nSequence of the inputs, one behind an absolute timelock by
+    /// setting the nLockTime of the transaction.
     ///
-    /// Note that not all coins may be spendable through a single recovery path at the same time.
+    /// Note that not all coins may be spendable through a single relative recovery path at the
+    /// same time.
     pub fn create_recovery(
         &self,
-        address: bitcoin::Address<address::NetworkUnchecked>,
-        feerate_vb: u64,
-        timelock: Option<u16>,
+        address: Option<bitcoin::Address<address::NetworkUnchecked>>,
+        destinations: &HashMap<bitcoin::Address<address::NetworkUnchecked>, u64>,
+        coins_outpoints: &[bitcoin::OutPoint],
+        fee_target: FeeTarget,
+        timelock: Option<RecoveryTimelock>,
     ) -> Result<CreateRecoveryResult, CommandError> {
+        let feerate_vb = self.target_feerate(fee_target)?;
         if feerate_vb < 1 {
             return Err(CommandError::InvalidFeerate(feerate_vb));
         }
-        let address = self.validate_address(address)?;
+        let address = address
+            .map(|addr| self.validate_address(addr))
+            .transpose()?;
+        let destinations = destinations
+            .iter()
+            .map(|(addr, value)| Ok((self.validate_address(addr.clone())?, *value)))
+            .collect::<Result<Vec<_>, CommandError>>()?;
         let mut db_conn = self.db.connection();
 
-        // The transaction template. We'll fill-in the inputs afterward.
-        let mut psbt = Psbt {
-            unsigned_tx: bitcoin::Transaction {
-                version: 2,
-                lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO), // TODO: anti-fee sniping
-                input: Vec::new(),
-                output: vec![bitcoin::TxOut {
-                    script_pubkey: address.script_pubkey(),
-                    value: 0xFF_FF_FF_FF,
-                }],
-            },
-            version: 0,
-            xpub: BTreeMap::new(),
-            proprietary: BTreeMap::new(),
-            unknown: BTreeMap::new(),
-            inputs: Vec::new(),
-            outputs: vec![PsbtOut::default()],
+        // Get the coins to spend through the specified recovery path (if no recovery path
+        // specified, use the first available one). We are interested in coins available at the
+        // *next* block.
+        let current_height = self
+            .bitcoin
+            .chain_tip()
+            .map_err(CommandError::BitcoinBackend)?
+            .height;
+        let timelock =
+            timelock.unwrap_or_else(|| self.config.main_descriptor.first_recovery_timelock());
+        let (sequence, lock_time) = match timelock {
+            RecoveryTimelock::Relative(tl) => (
+                bitcoin::Sequence::from_height(tl),
+                self.anti_fee_sniping_locktime()?,
+            ),
+            RecoveryTimelock::Absolute(tl) => {
+                // The transaction can be included in the next block if its nLockTime is below
+                // its height, or below the median time past of the current tip. All coins are
+                // recoverable at once.
+                let lock_time = absolute::LockTime::from_consensus(tl);
+                let lock_time = match lock_time {
+                    absolute::LockTime::Blocks(h)
+                        if h.to_consensus_u32() <= current_height as u32 =>
+                    {
+                        // Don't give up on anti fee sniping if we can set a higher nLockTime.
+                        cmp::max(self.anti_fee_sniping_locktime()?.to_consensus_u32(), tl)
+                    }
+                    absolute::LockTime::Seconds(t)
+                        if t.to_consensus_u32()
+                            < self
+                                .bitcoin
+                                .tip_median_time_past()
+                                .map_err(CommandError::BitcoinBackend)? =>
+                    {
+                        tl
+                    }
+                    _ => return Err(CommandError::RecoveryNotAvailable),
+                };
+                (
+                    bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    absolute::LockTime::from_consensus(lock_time),
+                )
+            }
+        };
+        let is_recoverable = |c: &Coin| match timelock {
+            RecoveryTimelock::Relative(tl) => c
+                .block_info
+                .map(|b| current_height + 1 >= b.height + i32::from(tl))
+                .unwrap_or(false),
+            RecoveryTimelock::Absolute(_) => !c.is_immature,
+        };
+        let coins: Vec<Coin> = if coins_outpoints.is_empty() {
+            // Frozen coins are never swept.
+            db_conn
+                .coins(CoinType::Unspent)
+                .into_values()
+                .filter(|c| !c.is_frozen && is_recoverable(c))
+                .collect()
+        } else {
+            // Don't count a coin twice if its outpoint was given more than once.
+            let coins = db_conn.coins_by_outpoints(coins_outpoints);
+            let mut seen = HashSet::with_capacity(coins_outpoints.len());
+            coins_outpoints
+                .iter()
+                .filter(|op| seen.insert(**op))
+                .map(|op| {
+                    let coin = coins.get(op).ok_or(CommandError::UnknownOutpoint(*op))?;
+                    if coin.is_spent() {
+                        return Err(CommandError::AlreadySpent(*op));
+                    }
+                    if !is_recoverable(coin) {
+                        return Err(CommandError::CoinNotRecoverable(*op));
+                    }
+                    Ok(*coin)
+                })
+                .collect::<Result<_, _>>()?
         };
+        if coins.is_empty() {
+            return Err(CommandError::RecoveryNotAvailable);
+        }
 
-        // Query the coins that we can spend through the specified recovery path (if no recovery
-        // path specified, use the first available one) from the database.
-        let current_height = self.bitcoin.chain_tip().height;
-        let timelock =
-            timelock.unwrap_or_else(|| self.config.main_descriptor.first_timelock_value());
-        let height_delta: i32 = timelock.try_into().expect("Must fit, it's a u16");
-        let sweepable_coins = db_conn.coins(CoinType::Unspent).into_values().filter(|c| {
-            // We are interested in coins available at the *next* block
-            c.block_info
-                .map(|b| current_height + 1 >= b.height + height_delta)
-                .unwrap_or(false)
-        });
+        // Add the destinations outputs, recording the total output value to later compute fees.
+        let mut out_value = bitcoin::Amount::from_sat(0);
+        let mut txouts = Vec::with_capacity(destinations.len() + 1);
+        let mut psbt_outs = Vec::with_capacity(destinations.len() + 1);
+        for (address, value_sat) in &destinations {
+            let amount = bitcoin::Amount::from_sat(*value_sat);
+            check_output_value(amount)?;
+            out_value = out_value.checked_add(amount).unwrap();
+            txouts.push(bitcoin::TxOut {
+                value: amount.to_sat(),
+                script_pubkey: address.script_pubkey(),
+            });
+            psbt_outs.push(self.destination_psbt_out(db_conn.as_mut(), address));
+        }
 
         // Fill-in the transaction inputs and PSBT inputs information. Record the value
         // that is fed to the transaction while doing so, to compute the fees afterward.
         let mut in_value = bitcoin::Amount::from_sat(0);
         let txin_sat_vb = self.config.main_descriptor.max_sat_vbytes();
         let mut sat_vb = 1; // Start at 1 for the segwit marker size, rounded up.
+        let mut txins = Vec::with_capacity(coins.len());
+        let mut psbt_ins = Vec::with_capacity(coins.len());
         let mut spent_txs = HashMap::new();
-        for coin in sweepable_coins {
+        for coin in coins {
             in_value += coin.amount;
-            psbt.unsigned_tx.input.push(bitcoin::TxIn {
+            txins.push(bitcoin::TxIn {
                 previous_output: coin.outpoint,
-                sequence: bitcoin::Sequence::from_height(timelock),
+                sequence,
                 // TODO: once we move to Taproot, anti-fee-sniping using nSequence
                 ..bitcoin::TxIn::default()
             });
@@ -756,45 +1822,185 @@ impl DaemonControl {
                 let tx = self
                     .bitcoin
                     .wallet_transaction(&coin.outpoint.txid)
+                    .map_err(CommandError::BitcoinBackend)?
                     .ok_or(CommandError::FetchingTransaction(coin.outpoint))?;
                 e.insert(tx.0);
             }
 
             let coin_desc = self.derived_desc(&coin);
             sat_vb += txin_sat_vb;
-            let witness_script = Some(coin_desc.witness_script());
             let witness_utxo = Some(bitcoin::TxOut {
                 value: coin.amount.to_sat(),
                 script_pubkey: coin_desc.script_pubkey(),
             });
             let non_witness_utxo = spent_txs.get(&coin.outpoint).cloned();
-            let bip32_derivation = coin_desc.bip32_derivations();
-            psbt.inputs.push(PsbtIn {
-                witness_script,
+            let mut psbt_in = PsbtIn {
                 witness_utxo,
                 non_witness_utxo,
-                bip32_derivation,
                 ..PsbtIn::default()
-            });
+            };
+            coin_desc.update_psbt_in(&mut psbt_in);
+            psbt_ins.push(psbt_in);
         }
+        let mut tx = bitcoin::Transaction {
+            version: 2,
+            lock_time,
+            input: txins,
+            output: txouts,
+        };
+        let insufficient_funds = || {
+            let out_value = if destinations.is_empty() {
+                None
+            } else {
+                Some(out_value)
+            };
+            CommandError::InsufficientFunds(in_value, out_value, feerate_vb)
+        };
 
-        // The sweepable_coins iterator may have been empty.
-        if psbt.unsigned_tx.input.is_empty() {
-            return Err(CommandError::RecoveryNotAvailable);
+        // Compute the value of the output receiving what's left based on the requested feerate.
+        // It's the given address, or otherwise a change output which we only add if it's worth it.
+        // In both cases it must not be dust, or the transaction would not relay.
+        let nochange_vb = (tx.vsize() + sat_vb) as u64;
+        let change_index = db_conn.change_index();
+        let change_desc = self
+            .config
+            .main_descriptor
+            .change_descriptor()
+            .derive(change_index, &self.secp);
+        let (mut remainder_txo, remainder_psbt_out) = if let Some(address) = &address {
+            let txo = bitcoin::TxOut {
+                script_pubkey: address.script_pubkey(),
+                value: u64::MAX,
+            };
+            (txo, self.destination_psbt_out(db_conn.as_mut(), address))
+        } else {
+            let txo = bitcoin::TxOut {
+                script_pubkey: change_desc.script_pubkey(),
+                value: u64::MAX,
+            };
+            let mut psbt_out = PsbtOut::default();
+            change_desc.update_psbt_out(&mut psbt_out);
+            (txo, psbt_out)
+        };
+        let with_remainder_vb = nochange_vb
+            .checked_add(serializable_size(&remainder_txo))
+            .unwrap();
+        let remainder_value = in_value.checked_sub(out_value).and_then(|v| {
+            v.checked_sub(bitcoin::Amount::from_sat(
+                with_remainder_vb.checked_mul(feerate_vb).unwrap(),
+            ))
+        });
+        match remainder_value {
+            Some(value) if value.to_sat() >= DUST_OUTPUT_SATS => {
+                if address.is_none() {
+                    // Don't forget to update our next change index!
+                    let next_index = change_index
+                        .increment()
+                        .expect("Must not get into hardened territory");
+                    db_conn.set_change_index(next_index, &self.secp);
+                }
+                remainder_txo.value = value.to_sat();
+                tx.output.push(remainder_txo);
+                psbt_outs.push(remainder_psbt_out);
+            }
+            _ => {
+                // Without a remainder output, the value left must pay for the transaction. We
+                // don't silently drop what was meant to be sent to the given address though.
+                let fee = in_value.checked_sub(out_value);
+                let min_fee =
+                    bitcoin::Amount::from_sat(nochange_vb.checked_mul(feerate_vb).unwrap());
+                if address.is_some() || fee.map(|f| f < min_fee).unwrap_or(true) {
+                    return Err(insufficient_funds());
+                }
+            }
         }
 
-        // Compute the value of the single output based on the requested feerate.
-        let tx_vbytes = (psbt.unsigned_tx.vsize() + sat_vb) as u64;
-        let absolute_fee = bitcoin::Amount::from_sat(tx_vbytes.checked_mul(feerate_vb).unwrap());
-        let output_value = in_value
-            .checked_sub(absolute_fee)
-            .ok_or(CommandError::InsufficientFunds(in_value, None, feerate_vb))?;
-        psbt.unsigned_tx.output[0].value = output_value.to_sat();
-
+        shuffle_outputs(&mut tx, &mut psbt_outs)?;
+        let psbt = Psbt {
+            unsigned_tx: tx,
+            version: 0,
+            xpub: BTreeMap::new(),
+            proprietary: BTreeMap::new(),
+            unknown: BTreeMap::new(),
+            inputs: psbt_ins,
+            outputs: psbt_outs,
+        };
         sanity_check_psbt(&self.config.main_descriptor, &psbt)?;
 
         Ok(CreateRecoveryResult { psbt })
     }
+
+    /// Create a send-to-self transaction refreshing all the coins for which the timelocked
+    /// recovery path will be available in less than `threshold` blocks, so they stay out of reach
+    /// of the recovery keys.
+    ///
+    /// The `timelock` parameter can be used to specify which recovery path to consider, it must
+    /// be the timelock of one of them. By default, we'll use the first recovery path available.
+    /// Only recovery paths behind a relative timelock are considered, as refreshing coins doesn't
+    /// delay an absolute one.
+    ///
+    /// Unconfirmed, immature and frozen coins as well as coins already spent by a stored Spend
+    /// transaction are never refreshed.
+    pub fn create_refresh(
+        &self,
+        threshold: u32,
+        fee_target: FeeTarget,
+        timelock: Option<u16>,
+    ) -> Result<CreateSpendResult, CommandError> {
+        let feerate_vb = self.target_feerate(fee_target)?;
+        let mut db_conn = self.db.connection();
+
+        // The recovery path of a coin is available at the block its timelock expires. Refresh
+        // those for which it is fewer than `threshold` blocks after the next one.
+        let next_height = self
+            .bitcoin
+            .chain_tip()
+            .map_err(CommandError::BitcoinBackend)?
+            .height
+            + 1;
+        let policy = self.config.main_descriptor.policy();
+        let timelock = match timelock {
+            Some(tl) if policy.recovery_paths().contains_key(&tl) => tl,
+            Some(tl) => return Err(CommandError::UnknownRecoveryTimelock(tl)),
+            None => *policy
+                .recovery_paths()
+                .keys()
+                .next()
+                .ok_or(CommandError::NoRelativeRecoveryPath)?,
+        };
+        let draft_spends = self.draft_spends(db_conn.as_mut());
+        let mut outpoints: Vec<bitcoin::OutPoint> = db_conn
+            .coins(CoinType::Unspent)
+            .into_values()
+            .filter(|c| {
+                !c.is_immature
+                    && !c.is_frozen
+                    && !draft_spends.contains_key(&c.outpoint)
+                    && c.block_info
+                        .map(|b| {
+                            let expiry = i64::from(b.height) + i64::from(timelock);
+                            expiry - i64::from(next_height) < i64::from(threshold)
+                        })
+                        .unwrap_or(false)
+            })
+            .map(|c| c.outpoint)
+            .collect();
+        if outpoints.is_empty() {
+            return Err(CommandError::NoCoinToRefresh(threshold));
+        }
+        // Have a deterministic ordering of the inputs.
+        outpoints.sort();
+
+        self.create_spend_internal(
+            db_conn.as_mut(),
+            &[],
+            &outpoints,
+            false,
+            feerate_vb,
+            None,
+            None,
+        )
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -802,6 +2008,21 @@ pub struct GetInfoDescriptors {
     pub main: descriptors::LianaDescriptor,
 }
 
+/// Summary of when the first recovery path of our coins becomes available.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExpirySummary {
+    /// The number of blocks after the next one within which a coin is considered at risk.
+    pub threshold: u32,
+    /// The height at which the first recovery path of one of our coins becomes available, if any.
+    pub earliest_expiry: Option<i32>,
+    /// The total value of our coins whose first recovery path is available within the threshold.
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub value_at_risk: bitcoin::Amount,
+}
+
 /// Information about the daemon
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GetInfoResult {
@@ -812,6 +2033,14 @@ pub struct GetInfoResult {
     pub descriptors: GetInfoDescriptors,
     /// The progress as a percentage (between 0 and 1) of an ongoing rescan if there is any
     pub rescan_progress: Option<f64>,
+    pub expiry: ExpirySummary,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetPolicyResult {
+    pub policy: descriptors::PolicyDescription,
+    /// The same description as plain text, for instance to be printed along with a backup.
+    pub text: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -837,7 +2066,7 @@ pub struct LCSpendInfo {
     pub height: Option<i32>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListCoinsEntry {
     #[serde(
         serialize_with = "ser_amount",
@@ -850,6 +2079,10 @@ pub struct ListCoinsEntry {
     pub spend_info: Option<LCSpendInfo>,
     /// Whether this coin was created by a coinbase transaction that is still immature.
     pub is_immature: bool,
+    /// Whether this coin was frozen, in which case it is never spent unless explicitly requested.
+    pub is_frozen: bool,
+    /// The stored Spend transactions spending this coin which were not broadcast yet.
+    pub draft_spends: Vec<bitcoin::Txid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -857,10 +2090,49 @@ pub struct ListCoinsResult {
     pub coins: Vec<ListCoinsEntry>,
 }
 
+/// When a recovery path becomes available for a coin. For a recovery path behind a date, the
+/// height is an estimate.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RecoveryPathExpiry {
+    /// The recovery path, identified by its relative timelock in blocks if it's behind one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub timelock: Option<u16>,
+    /// The recovery path, identified by its absolute timelock if it's behind one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub abs_timelock: Option<u32>,
+    /// The height of the first block at which it is available.
+    pub height: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ListExpiringCoinsEntry {
+    #[serde(
+        serialize_with = "ser_amount",
+        deserialize_with = "deser_amount_from_sats"
+    )]
+    pub amount: bitcoin::Amount,
+    pub outpoint: bitcoin::OutPoint,
+    pub block_height: i32,
+    pub expiries: Vec<RecoveryPathExpiry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListExpiringCoinsResult {
+    pub coins: Vec<ListExpiringCoinsEntry>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CreateSpendResult {
     #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
     pub psbt: Psbt,
+    /// The coins spent by this transaction, either provided by the caller or selected by us.
+    pub coins: Vec<bitcoin::OutPoint>,
+    /// The value of the change output, if any.
+    #[serde(
+        serialize_with = "ser_optional_amount",
+        deserialize_with = "deser_optional_amount_from_sats"
+    )]
+    pub change_amount: Option<bitcoin::Amount>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -875,6 +2147,13 @@ pub struct ListSpendResult {
     pub spend_txs: Vec<ListSpendEntry>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SignSpendResult {
+    /// The stored Spend transaction PSBT, along with the signatures of the hot signers.
+    #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
+    pub psbt: Psbt,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListTransactionsResult {
     pub transactions: Vec<TransactionInfo>,
@@ -888,16 +2167,46 @@ pub struct TransactionInfo {
     pub time: Option<u32>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetLabelsResult {
+    /// Map from the string representation of an item to its label.
+    pub labels: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ExportLabelsResult {
+    /// The labels in the BIP329 JSON Lines format.
+    pub labels: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct CreateRecoveryResult {
     #[serde(serialize_with = "ser_to_string", deserialize_with = "deser_fromstr")]
     pub psbt: Psbt,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeeEstimate {
+    /// The number of blocks within which a transaction should confirm.
+    pub conf_target: u16,
+    /// The estimated feerate, in sats/vb.
+    pub feerate: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GetFeeEstimatesResult {
+    pub estimates: Vec<FeeEstimate>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{bitcoin::Block, database::BlockInfo, testutils::*};
+    use crate::{
+        bitcoin::{Block, MempoolEntry},
+        database::BlockInfo,
+        testutils::*,
+    };
+    use miniscript::descriptor;
 
     use bitcoin::{
         bip32::{self, ChildNumber},
@@ -911,39 +2220,218 @@ mod tests {
     fn getinfo() {
         let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
         // We can query getinfo
-        ms.handle.control.get_info();
+        ms.handle.control.get_info(None);
         ms.shutdown();
     }
 
     #[test]
-    fn getnewaddress() {
+    fn list_expiring_coins() {
         let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
-
         let control = &ms.handle.control;
-        // We can get an address
-        let addr = control.get_new_address().address;
-        assert_eq!(
-            addr,
-            bitcoin::Address::from_str(
-                "bc1q9ksrc647hx8zp2cewl8p5f487dgux3777yees8rjcx46t4daqzzqt7yga8"
-            )
+        let mut db_conn = control.db().lock().unwrap().connection();
+        assert!(control.list_expiring_coins(None).coins.is_empty());
+        assert_eq!(control.get_info(None).expiry.earliest_expiry, None);
+
+        // Two confirmed coins and an unconfirmed one, whose timelock didn't start yet. The
+        // recovery path is available after 10_000 blocks.
+        let op = |vout| {
+            bitcoin::OutPoint::from_str(&format!(
+                "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:{}",
+                vout
+            ))
             .unwrap()
-            .assume_checked()
-        );
-        // We won't get the same twice.
-        let addr2 = control.get_new_address().address;
-        assert_ne!(addr, addr2);
-
-        ms.shutdown();
-    }
-
-    #[test]
+        };
+        let coins: Vec<Coin> = [(0, Some(500)), (1, Some(1)), (2, None)]
+            .iter()
+            .map(|(vout, height)| Coin {
+                outpoint: op(*vout),
+                is_immature: false,
+                is_frozen: false,
+                block_info: height.map(|height| BlockInfo { height, time: 1 }),
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(*vout),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+
+        let expiring = control.list_expiring_coins(None).coins;
+        assert_eq!(
+            expiring.iter().map(|c| c.outpoint).collect::<Vec<_>>(),
+            vec![op(1), op(0)]
+        );
+        assert_eq!(
+            expiring[0].expiries,
+            vec![RecoveryPathExpiry {
+                timelock: Some(10_000),
+                abs_timelock: None,
+                height: 10_001
+            }]
+        );
+        assert_eq!(expiring[1].expiries[0].height, 10_500);
+
+        // Only the first one expires within 10_100 blocks (the tip is at most at height 100).
+        let expiring = control.list_expiring_coins(Some(10_100)).coins;
+        assert_eq!(expiring.len(), 1);
+        assert_eq!(expiring[0].outpoint, op(1));
+
+        // None of them is at risk yet.
+        let expiry = control.get_info(None).expiry;
+        assert_eq!(expiry.threshold, 1_000);
+        assert_eq!(expiry.earliest_expiry, Some(10_001));
+        assert_eq!(expiry.value_at_risk, bitcoin::Amount::from_sat(0));
+
+        // Unless we look further ahead.
+        let expiry = control.get_info(Some(10_100)).expiry;
+        assert_eq!(expiry.threshold, 10_100);
+        assert_eq!(expiry.earliest_expiry, Some(10_001));
+        assert_eq!(expiry.value_at_risk, bitcoin::Amount::from_sat(100_000));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn list_expiring_coins_abs_timelocks() {
+        let secp = secp256k1::Secp256k1::new();
+        let network = bitcoin::Network::Bitcoin;
+        let origin_der = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+        let desc_key = || {
+            let signer = HotSigner::generate(network).unwrap();
+            descriptors::PathInfo::Single(
+                descriptor::DescriptorPublicKey::from_str(&format!(
+                    "[{}/48'/0'/0'/2']{}/<0;1>/*",
+                    signer.fingerprint(&secp),
+                    signer.xpub_at(&origin_der, &secp)
+                ))
+                .unwrap(),
+            )
+        };
+        // Recovery paths available at height 2_000 and at a date, but none behind a relative
+        // timelock.
+        let policy = descriptors::LianaPolicy::new_with_abs_timelocks(
+            desc_key(),
+            BTreeMap::new(),
+            [(2_000, desc_key()), (1_700_000_000, desc_key())]
+                .iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+        let desc = descriptors::LianaDescriptor::new(policy);
+        let ms = DummyLiana::new_with_descriptor(DummyBitcoind::new(), DummyDatabase::new(), desc);
+        let control = &ms.handle.control;
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let outpoint = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint,
+            is_immature: false,
+            is_frozen: false,
+            block_info: Some(BlockInfo {
+                height: 50,
+                time: 1,
+            }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(0),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+
+        // The recovery path behind a block height is available at this height regardless of
+        // when the coin confirmed. The height of the one behind a date is estimated from the
+        // median time past of the tip (at most at height 100), assuming 10 minutes blocks.
+        let expiring = control.list_expiring_coins(None).coins;
+        assert_eq!(expiring.len(), 1);
+        let expiries = &expiring[0].expiries;
+        assert_eq!(expiries.len(), 2);
+        assert_eq!(
+            expiries[0],
+            RecoveryPathExpiry {
+                timelock: None,
+                abs_timelock: Some(2_000),
+                height: 2_000
+            }
+        );
+        assert_eq!(expiries[1].abs_timelock, Some(1_700_000_000));
+        let blocks_left = (1_700_000_000 - 1_231_660_825) / 600 + 1;
+        assert!((blocks_left + 1..=blocks_left + 101).contains(&expiries[1].height));
+
+        // The coin is at risk, as the tip is less than a month of blocks away from this height.
+        let expiry = control.get_info(None).expiry;
+        assert_eq!(expiry.threshold, ABS_TIMELOCK_EXPIRY_THRESHOLD);
+        assert_eq!(expiry.earliest_expiry, Some(2_000));
+        assert_eq!(expiry.value_at_risk, bitcoin::Amount::from_sat(100_000));
+
+        // Refreshing the coin wouldn't delay the recovery paths.
+        assert_eq!(
+            control.create_refresh(10_000, FeeTarget::Feerate(1), None),
+            Err(CommandError::NoRelativeRecoveryPath)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn get_fee_estimates() {
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.fee_estimates.insert(1, 20);
+        dummy_bitcoind.fee_estimates.insert(6, 5);
+        dummy_bitcoind.fee_estimates.insert(7, 4);
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+
+        // Only the estimates for the targets we query are returned, in order.
+        assert_eq!(
+            ms.handle.control.get_fee_estimates().unwrap().estimates,
+            vec![
+                FeeEstimate {
+                    conf_target: 1,
+                    feerate: 20
+                },
+                FeeEstimate {
+                    conf_target: 6,
+                    feerate: 5
+                }
+            ]
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn getnewaddress() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+
+        let control = &ms.handle.control;
+        // We can get an address
+        let addr = control.get_new_address().address;
+        assert_eq!(
+            addr,
+            bitcoin::Address::from_str(
+                "bc1q9ksrc647hx8zp2cewl8p5f487dgux3777yees8rjcx46t4daqzzqt7yga8"
+            )
+            .unwrap()
+            .assume_checked()
+        );
+        // We won't get the same twice.
+        let addr2 = control.get_new_address().address;
+        assert_ne!(addr, addr2);
+
+        ms.shutdown();
+    }
+
+    #[test]
     fn create_spend() {
         let dummy_op = bitcoin::OutPoint::from_str(
             "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
         )
         .unwrap();
         let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.fee_estimates.insert(6, 2);
         dummy_bitcoind.txs.insert(
             dummy_op.txid,
             (
@@ -969,24 +2457,45 @@ mod tests {
                 .cloned()
                 .collect();
         assert_eq!(
-            control.create_spend(&destinations, &[], 1),
+            control.create_spend(&HashMap::new(), &[], FeeTarget::Feerate(1), false, None),
             Err(CommandError::NoOutpoint)
         );
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 0),
+            control.create_spend(&destinations, &[], FeeTarget::Feerate(1), false, None),
+            Err(CommandError::InsufficientFunds(
+                bitcoin::Amount::from_sat(0),
+                Some(bitcoin::Amount::from_sat(dummy_value)),
+                1
+            ))
+        );
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(0),
+                false,
+                None
+            ),
             Err(CommandError::InvalidFeerate(0))
         );
 
         // The coin doesn't exist. If we create a new unspent one at this outpoint with a much
         // higher value, we'll get a Spend transaction with a change output.
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1),
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
             Err(CommandError::UnknownOutpoint(dummy_op))
         );
         let mut db_conn = control.db().lock().unwrap().connection();
         db_conn.new_unspent_coins(&[Coin {
             outpoint: dummy_op,
             is_immature: false,
+            is_frozen: false,
             block_info: None,
             amount: bitcoin::Amount::from_sat(100_000),
             derivation_index: bip32::ChildNumber::from(13),
@@ -994,137 +2503,1086 @@ mod tests {
             spend_txid: None,
             spend_block: None,
         }]);
-        let res = control.create_spend(&destinations, &[dummy_op], 1).unwrap();
+        let res = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None,
+            )
+            .unwrap();
         assert!(res.psbt.inputs[0].non_witness_utxo.is_some());
         let tx = res.psbt.unsigned_tx;
         assert_eq!(tx.input.len(), 1);
         assert_eq!(tx.input[0].previous_output, dummy_op);
         assert_eq!(tx.output.len(), 2);
+        // The outputs are shuffled, find the destination among them.
+        let dest_spk = dummy_addr.payload.script_pubkey();
+        let (dest_i, change_i) = if tx.output[0].script_pubkey == dest_spk {
+            (0, 1)
+        } else {
+            (1, 0)
+        };
+        let (dest_txo, change_txo) = (&tx.output[dest_i], &tx.output[change_i]);
+        assert_eq!(dest_txo.script_pubkey, dest_spk);
+        // The PSBT outputs were permuted along with the transaction outputs.
+        assert!(res.psbt.outputs[dest_i].bip32_derivation.is_empty());
+        assert!(!res.psbt.outputs[change_i].bip32_derivation.is_empty());
+        assert_eq!(dest_txo.value, dummy_value);
+        // The locktime is set to the current tip (height 100), possibly backdated.
+        match tx.lock_time {
+            absolute::LockTime::Blocks(h) => assert!((1..=100).contains(&h.to_consensus_u32())),
+            _ => panic!("Locktime must be a block height"),
+        }
+
+        // Transaction is 1 in (P2WSH satisfaction), 2 outs. At 1sat/vb, it's 170 sats fees.
+        // At 2sats/vb, it's twice that.
+        assert_eq!(change_txo.value, 89_830);
+        assert_eq!(res.coins, vec![dummy_op]);
+        assert_eq!(res.change_amount, Some(bitcoin::Amount::from_sat(89_830)));
+
+        // If we don't provide any outpoint, the coin will be selected automatically.
+        let res_auto = control
+            .create_spend(&destinations, &[], FeeTarget::Feerate(1), false, None)
+            .unwrap();
+        assert_eq!(res_auto.coins, vec![dummy_op]);
+        assert_eq!(res_auto.change_amount, res.change_amount);
+        assert_eq!(res_auto.psbt.unsigned_tx.input[0].previous_output, dummy_op);
+
+        // But not if it's already used by a stored Spend transaction.
+        let spend_txid = res_auto.psbt.unsigned_tx.txid();
+        db_conn.store_spend(&res_auto.psbt);
+        assert_eq!(
+            control.create_spend(&destinations, &[], FeeTarget::Feerate(1), false, None),
+            Err(CommandError::InsufficientFunds(
+                bitcoin::Amount::from_sat(0),
+                Some(bitcoin::Amount::from_sat(dummy_value)),
+                1
+            ))
+        );
+        // It's reserved by the stored Spend, which is reported when listing coins and prevents
+        // creating a conflicting draft until it's deleted.
+        let draft_spends = |op: bitcoin::OutPoint| {
+            control
+                .list_coins()
+                .coins
+                .into_iter()
+                .find(|c| c.outpoint == op)
+                .unwrap()
+                .draft_spends
+        };
+        assert_eq!(draft_spends(dummy_op), vec![spend_txid]);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
+            Err(CommandError::ReservedCoin(dummy_op, spend_txid))
+        );
+        control.delete_spend(&spend_txid);
+        assert!(draft_spends(dummy_op).is_empty());
+
+        // Nor if it's frozen, in which case it can't be spent unless explicitly requested.
+        let is_frozen = |op: bitcoin::OutPoint| {
+            control
+                .list_coins()
+                .coins
+                .iter()
+                .find(|c| c.outpoint == op)
+                .unwrap()
+                .is_frozen
+        };
+        assert!(!is_frozen(dummy_op));
+        control.freeze_coins(&[dummy_op]).unwrap();
+        assert!(is_frozen(dummy_op));
+        assert_eq!(
+            control.create_spend(&destinations, &[], FeeTarget::Feerate(1), false, None),
+            Err(CommandError::InsufficientFunds(
+                bitcoin::Amount::from_sat(0),
+                Some(bitcoin::Amount::from_sat(dummy_value)),
+                1
+            ))
+        );
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
+            Err(CommandError::FrozenCoin(dummy_op))
+        );
+        let res_frozen = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                true,
+                None,
+            )
+            .unwrap();
+        assert_eq!(res_frozen.coins, vec![dummy_op]);
+        control.unfreeze_coins(&[dummy_op]).unwrap();
+        assert!(!is_frozen(dummy_op));
+        let unknown_op = bitcoin::OutPoint {
+            txid: dummy_op.txid,
+            vout: dummy_op.vout + 100,
+        };
+        assert_eq!(
+            control.freeze_coins(&[unknown_op]),
+            Err(CommandError::UnknownOutpoint(unknown_op))
+        );
+
+        let res = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(2),
+                false,
+                None,
+            )
+            .unwrap();
+        assert_eq!(res.change_amount, Some(bitcoin::Amount::from_sat(89_660)));
+
+        // We can also give a confirmation target, the feerate is then estimated by our backend.
+        let res_target = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Blocks(6),
+                false,
+                None,
+            )
+            .unwrap();
+        assert_eq!(res_target.change_amount, res.change_amount);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Blocks(12),
+                false,
+                None
+            ),
+            Err(CommandError::NoFeeEstimate(12))
+        );
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Blocks(0),
+                false,
+                None
+            ),
+            Err(CommandError::InvalidConfTarget(0))
+        );
+
+        // A feerate of 555 won't trigger the sanity checks (they were previously not taking the
+        // satisfaction size into account and overestimating the feerate).
+        control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(555),
+                false,
+                None,
+            )
+            .unwrap();
+
+        // If we ask for a too high feerate, or a too large/too small output, it'll fail.
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(10_000),
+                false,
+                None
+            ),
+            Err(CommandError::InsufficientFunds(
+                bitcoin::Amount::from_sat(100_000),
+                Some(bitcoin::Amount::from_sat(10_000)),
+                10_000
+            ))
+        );
+        *destinations.get_mut(&dummy_addr).unwrap() = 100_001;
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
+            Err(CommandError::InsufficientFunds(
+                bitcoin::Amount::from_sat(100_000),
+                Some(bitcoin::Amount::from_sat(100_001)),
+                1
+            ))
+        );
+        *destinations.get_mut(&dummy_addr).unwrap() = 4_500;
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
+            Err(CommandError::InvalidOutputValue(bitcoin::Amount::from_sat(
+                4_500
+            )))
+        );
+
+        // If we ask to create an output for an address from another network, it will fail.
+        let invalid_addr =
+            bitcoin::Address::new(bitcoin::Network::Testnet, dummy_addr.payload.clone());
+        let invalid_destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(invalid_addr, dummy_value)].iter().cloned().collect();
+        assert!(matches!(
+            control.create_spend(
+                &invalid_destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
+            Err(CommandError::Address(
+                address::Error::NetworkValidation { .. }
+            ))
+        ));
+
+        // If we ask for a large, but valid, output we won't get a change output. 95_000 because we
+        // won't create an output lower than 5k sats.
+        *destinations.get_mut(&dummy_addr).unwrap() = 95_000;
+        let res = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None,
+            )
+            .unwrap();
+        let tx = res.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output, dummy_op);
+        assert_eq!(tx.output.len(), 1);
         assert_eq!(
             tx.output[0].script_pubkey,
             dummy_addr.payload.script_pubkey()
         );
-        assert_eq!(tx.output[0].value, dummy_value);
-
-        // Transaction is 1 in (P2WSH satisfaction), 2 outs. At 1sat/vb, it's 170 sats fees.
-        // At 2sats/vb, it's twice that.
-        assert_eq!(tx.output[1].value, 89_830);
-        let res = control.create_spend(&destinations, &[dummy_op], 2).unwrap();
-        let tx = res.psbt.unsigned_tx;
-        assert_eq!(tx.output[1].value, 89_660);
-
-        // A feerate of 555 won't trigger the sanity checks (they were previously not taking the
-        // satisfaction size into account and overestimating the feerate).
-        control
-            .create_spend(&destinations, &[dummy_op], 555)
-            .unwrap();
+        assert_eq!(tx.output[0].value, 95_000);
+
+        // We can send all the value left to another destination instead of getting change.
+        let max_addr = control.get_new_address().address().clone();
+        let max_addr_unchecked = bitcoin::Address::from_str(&max_addr.to_string()).unwrap();
+        *destinations.get_mut(&dummy_addr).unwrap() = dummy_value;
+        let res = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                Some(max_addr_unchecked.clone()),
+            )
+            .unwrap();
+        assert_eq!(res.change_amount, None);
+        let tx = &res.psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        let max_i = tx
+            .output
+            .iter()
+            .position(|txo| txo.script_pubkey == max_addr.script_pubkey())
+            .unwrap();
+        let vb = (tx.vsize() + control.config.main_descriptor.max_sat_vbytes()) as u64;
+        assert_eq!(tx.output[max_i].value, 100_000 - dummy_value - vb);
+        assert_eq!(tx.output[1 - max_i].value, dummy_value);
+
+        // Without destination nor coins, all our available coins are swept to the address.
+        let res = control
+            .create_spend(
+                &HashMap::new(),
+                &[],
+                FeeTarget::Feerate(1),
+                false,
+                Some(max_addr_unchecked.clone()),
+            )
+            .unwrap();
+        assert_eq!(res.coins, vec![dummy_op]);
+        assert_eq!(res.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            res.psbt.unsigned_tx.output[0].script_pubkey,
+            max_addr.script_pubkey()
+        );
+
+        // The value left must be worth creating an output.
+        *destinations.get_mut(&dummy_addr).unwrap() = 95_000;
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                Some(max_addr_unchecked)
+            ),
+            Err(CommandError::InsufficientFunds(
+                bitcoin::Amount::from_sat(100_000),
+                Some(bitcoin::Amount::from_sat(95_000)),
+                1
+            ))
+        );
+
+        // Now if we mark the coin as spent, we won't create another Spend transaction containing
+        // it.
+        db_conn.spend_coins(&[(
+            dummy_op,
+            bitcoin::Txid::from_str(
+                "ef78f79ba747813887747cf8582897a48f1a09f1ca04d2cd3d6fcfdcbb5e0797",
+            )
+            .unwrap(),
+        )]);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None
+            ),
+            Err(CommandError::AlreadySpent(dummy_op))
+        );
+
+        // We'd bail out if they tried to create a transaction with a too high feerate.
+        let dummy_op_dup = bitcoin::OutPoint {
+            txid: dummy_op.txid,
+            vout: dummy_op.vout + 10,
+        };
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op_dup,
+            is_immature: false,
+            is_frozen: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(400_000),
+            derivation_index: bip32::ChildNumber::from(42),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[dummy_op_dup],
+                FeeTarget::Feerate(1_001),
+                false,
+                None
+            ),
+            Err(CommandError::InsaneFees(InsaneFeeInfo::TooHighFeerate(
+                1001
+            )))
+        );
+
+        // Can't create a transaction that spends an immature coinbase deposit.
+        let imma_op = bitcoin::OutPoint::from_str(
+            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: imma_op,
+            is_immature: true,
+            is_frozen: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        assert_eq!(
+            control.create_spend(
+                &destinations,
+                &[imma_op],
+                FeeTarget::Feerate(1_001),
+                false,
+                None
+            ),
+            Err(CommandError::ImmatureCoinbase(imma_op))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn rbf_psbt() {
+        let dummy_op_a = bitcoin::OutPoint::from_str(
+            "3753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
+        )
+        .unwrap();
+        let dummy_op_b = bitcoin::OutPoint::from_str(
+            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:1",
+        )
+        .unwrap();
+        let dummy_addr = bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv")
+            .unwrap()
+            .assume_checked();
+        let dummy_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![],
+            output: vec![],
+        };
+        // The transaction to be replaced spends the first coin and pays 5k sats in fees. It also
+        // has an output without an address.
+        let op_return_spk = bitcoin::ScriptBuf::new_op_return(&[0x42; 20]);
+        let spend_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn {
+                previous_output: dummy_op_a,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..TxIn::default()
+            }],
+            output: vec![
+                TxOut {
+                    value: 95_000,
+                    script_pubkey: dummy_addr.script_pubkey(),
+                },
+                TxOut {
+                    value: 0,
+                    script_pubkey: op_return_spk.clone(),
+                },
+            ],
+        };
+        let spend_txid = spend_tx.txid();
+        let spend_tx_vsize = spend_tx.vsize();
+        // The same, but not signaling for RBF.
+        let mut final_tx = spend_tx.clone();
+        final_tx.input[0].sequence = Sequence::MAX;
+        final_tx.output[0].value = 94_999;
+        let final_txid = final_tx.txid();
+        // The same, but confirmed.
+        let mut conf_tx = spend_tx.clone();
+        conf_tx.output[0].value = 94_998;
+        let conf_txid = conf_tx.txid();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind
+            .txs
+            .insert(dummy_op_a.txid, (dummy_tx.clone(), None));
+        dummy_bitcoind.txs.insert(dummy_op_b.txid, (dummy_tx, None));
+        dummy_bitcoind.txs.insert(spend_txid, (spend_tx, None));
+        dummy_bitcoind.txs.insert(final_txid, (final_tx, None));
+        dummy_bitcoind.txs.insert(
+            conf_txid,
+            (
+                conf_tx,
+                Some(Block {
+                    hash: bitcoin::BlockHash::from_str(
+                        "000000000000000000046feeb6e4c55dd3e7ef8e3a3bc5ec09a6d62e1b62c8a6",
+                    )
+                    .unwrap(),
+                    height: 99,
+                    time: 1_000_000,
+                }),
+            ),
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.handle.control;
+        let mut db_conn = control.db().lock().unwrap().connection();
+
+        // We can't replace a transaction we don't know about.
+        assert_eq!(
+            control.rbf_psbt(&spend_txid, 100),
+            Err(CommandError::RbfError(RbfErrorInfo::NotOurs(dummy_op_a)))
+        );
+        let unknown_txid = bitcoin::Txid::from_str(
+            "ef78f79ba747813887747cf8582897a48f1a09f1ca04d2cd3d6fcfdcbb5e0797",
+        )
+        .unwrap();
+        assert_eq!(
+            control.rbf_psbt(&unknown_txid, 100),
+            Err(CommandError::UnknownSpend(unknown_txid))
+        );
+        // Nor one which was already confirmed, or which does not signal for RBF.
+        assert_eq!(
+            control.rbf_psbt(&conf_txid, 100),
+            Err(CommandError::RbfError(RbfErrorInfo::AlreadyConfirmed))
+        );
+        assert_eq!(
+            control.rbf_psbt(&final_txid, 100),
+            Err(CommandError::RbfError(RbfErrorInfo::NotSignaling))
+        );
+
+        // Add the two coins, the first one being spent by the transaction to be replaced.
+        db_conn.new_unspent_coins(&[
+            Coin {
+                outpoint: dummy_op_a,
+                is_immature: false,
+                is_frozen: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(100_000),
+                derivation_index: bip32::ChildNumber::from(13),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+            Coin {
+                outpoint: dummy_op_b,
+                is_immature: false,
+                is_frozen: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(50_000),
+                derivation_index: bip32::ChildNumber::from(34),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            },
+        ]);
+        db_conn.spend_coins(&[(dummy_op_a, spend_txid)]);
+
+        // The replacement must have a higher feerate than the original (5k sats for the
+        // transaction as known by our backend), and pay for its own relay.
+        let min_feerate = match control.rbf_psbt(&spend_txid, 1) {
+            Err(CommandError::RbfError(RbfErrorInfo::TooLowFeerate(r))) => r,
+            e => panic!("Unexpected result: {:?}", e),
+        };
+        let spend_tx_vb = spend_tx_vsize as u64;
+        assert_eq!(min_feerate, (5_000 + spend_tx_vb - 1) / spend_tx_vb + 1);
+
+        // The second coin is unconfirmed, it can't be used to fund the replacement.
+        assert!(matches!(
+            control.rbf_psbt(&spend_txid, min_feerate),
+            Err(CommandError::InsufficientFunds(_, Some(_), _))
+        ));
+        db_conn.confirm_coins(&[(dummy_op_b, 99, 1_000_000)]);
+
+        // The first coin alone can't pay for the increased fee, the second one is added and we
+        // get a change output. The destination and the output without an address are unchanged.
+        let res = control.rbf_psbt(&spend_txid, min_feerate).unwrap();
+        let tx = &res.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.input[0].previous_output, dummy_op_a);
+        assert_eq!(tx.input[1].previous_output, dummy_op_b);
+        assert_eq!(tx.output.len(), 3);
+        assert!(tx
+            .output
+            .iter()
+            .any(|txo| txo.script_pubkey == op_return_spk && txo.value == 0));
+        let dest_txo = tx
+            .output
+            .iter()
+            .find(|txo| txo.script_pubkey == dummy_addr.script_pubkey())
+            .unwrap();
+        assert_eq!(dest_txo.value, 95_000);
+        assert!(res.change_amount.is_some());
+
+        // It was stored in database.
+        assert_eq!(db_conn.spend_tx(&tx.txid()), Some(res.psbt.clone()));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_refresh() {
+        let dummy_addr = bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv")
+            .unwrap()
+            .assume_checked();
+        let deposit_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
+            output: (0..4)
+                .map(|_| TxOut {
+                    value: 100_000,
+                    script_pubkey: dummy_addr.script_pubkey(),
+                })
+                .collect(),
+        };
+        let deposit_txid = deposit_tx.txid();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(deposit_txid, (deposit_tx, None));
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.handle.control;
+        let mut db_conn = control.db().lock().unwrap().connection();
+
+        assert_eq!(
+            control.create_refresh(10_000, FeeTarget::Feerate(1), None),
+            Err(CommandError::NoCoinToRefresh(10_000))
+        );
+
+        // The tip is at height 100 and the recovery path is available after 10_000 blocks. Two
+        // coins confirmed at heights 1 and 90, an unconfirmed one and a frozen one.
+        let op = |vout| bitcoin::OutPoint {
+            txid: deposit_txid,
+            vout,
+        };
+        let coins: Vec<Coin> = [
+            (0, Some(1), false),
+            (1, Some(90), false),
+            (2, None, false),
+            (3, Some(1), true),
+        ]
+        .iter()
+        .map(|(vout, height, is_frozen)| Coin {
+            outpoint: op(*vout),
+            is_immature: false,
+            is_frozen: *is_frozen,
+            block_info: height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(*vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        })
+        .collect();
+        db_conn.new_unspent_coins(&coins);
+
+        // The first coin expires in 9_900 blocks, the second in 9_989.
+        assert_eq!(
+            control.create_refresh(9_900, FeeTarget::Feerate(1), None),
+            Err(CommandError::NoCoinToRefresh(9_900))
+        );
+        let res = control
+            .create_refresh(9_901, FeeTarget::Feerate(1), None)
+            .unwrap();
+        assert_eq!(res.coins, vec![op(0)]);
+        let res = control
+            .create_refresh(10_000, FeeTarget::Feerate(1), None)
+            .unwrap();
+        assert_eq!(res.coins, vec![op(0), op(1)]);
+        // It's a send-to-self, with a single change output.
+        let tx = &res.psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(res.change_amount.unwrap().to_sat(), tx.output[0].value);
+
+        // The recovery path to consider may be given explicitly, but it must be one of ours.
+        assert_eq!(
+            control.create_refresh(10_000, FeeTarget::Feerate(1), Some(1_000)),
+            Err(CommandError::UnknownRecoveryTimelock(1_000))
+        );
+        let res = control
+            .create_refresh(10_000, FeeTarget::Feerate(1), Some(10_000))
+            .unwrap();
+        assert_eq!(res.coins, vec![op(0), op(1)]);
+
+        // Coins already being refreshed by a stored Spend won't be refreshed again.
+        db_conn.store_spend(&res.psbt);
+        assert_eq!(
+            control.create_refresh(10_000, FeeTarget::Feerate(1), None),
+            Err(CommandError::NoCoinToRefresh(10_000))
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_recovery() {
+        let dummy_addr = bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv")
+            .unwrap()
+            .assume_checked();
+        let deposit_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
+            output: (0..4)
+                .map(|_| TxOut {
+                    value: 100_000,
+                    script_pubkey: dummy_addr.script_pubkey(),
+                })
+                .collect(),
+        };
+        let deposit_txid = deposit_tx.txid();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(deposit_txid, (deposit_tx, None));
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.handle.control;
+        let mut db_conn = control.db().lock().unwrap().connection();
+        let sweep_addr =
+            bitcoin::Address::from_str("bc1q39srgatmkp6k2ne3l52yhkjprdvunvspqydmkx").unwrap();
+        let dest_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let no_dest = HashMap::new();
+
+        // The tip is at height 100. Coins confirmed at heights 1 and 95, an unconfirmed one and a
+        // frozen one. Use a recovery path available after 10 blocks.
+        let op = |vout| bitcoin::OutPoint {
+            txid: deposit_txid,
+            vout,
+        };
+        let coins: Vec<Coin> = [
+            (0, Some(1), false),
+            (1, Some(95), false),
+            (2, None, false),
+            (3, Some(1), true),
+        ]
+        .iter()
+        .map(|(vout, height, is_frozen)| Coin {
+            outpoint: op(*vout),
+            is_immature: false,
+            is_frozen: *is_frozen,
+            block_info: height.map(|height| BlockInfo { height, time: 1 }),
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(*vout),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        })
+        .collect();
+        db_conn.new_unspent_coins(&coins);
+        let input_ops = |psbt: &Psbt| {
+            let mut ops: Vec<_> = psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|txin| txin.previous_output)
+                .collect();
+            ops.sort();
+            ops
+        };
+
+        // No coin is available through the default recovery path.
+        assert_eq!(
+            control.create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[],
+                FeeTarget::Feerate(1),
+                None
+            ),
+            Err(CommandError::RecoveryNotAvailable)
+        );
+
+        // By default we sweep all the recoverable coins, except the frozen one.
+        let psbt = control
+            .create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            )
+            .unwrap()
+            .psbt;
+        assert_eq!(input_ops(&psbt), vec![op(0)]);
+        assert!(psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .all(|txin| txin.sequence == bitcoin::Sequence::from_height(10)));
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+
+        // Coins may be selected explicitly, including frozen ones. But they must be recoverable.
+        // A coin given twice is only spent once.
+        let psbt = control
+            .create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[op(0), op(3), op(0)],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            )
+            .unwrap()
+            .psbt;
+        assert_eq!(input_ops(&psbt), vec![op(0), op(3)]);
+        assert_eq!(
+            control.create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[op(0), op(1)],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            ),
+            Err(CommandError::CoinNotRecoverable(op(1)))
+        );
+        let unknown_op = bitcoin::OutPoint {
+            txid: deposit_txid,
+            vout: 42,
+        };
+        assert_eq!(
+            control.create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[unknown_op],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            ),
+            Err(CommandError::UnknownOutpoint(unknown_op))
+        );
+
+        // Pay a destination and send the rest back to the wallet.
+        let destinations: HashMap<_, _> = [(dest_addr.clone(), 60_000)].iter().cloned().collect();
+        let psbt = control
+            .create_recovery(
+                None,
+                &destinations,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            )
+            .unwrap()
+            .psbt;
+        assert_eq!(input_ops(&psbt), vec![op(0)]);
+        let tx = &psbt.unsigned_tx;
+        assert_eq!(tx.output.len(), 2);
+        let dest_spk = dest_addr.clone().assume_checked().script_pubkey();
+        let dest_index = tx
+            .output
+            .iter()
+            .position(|o| o.script_pubkey == dest_spk)
+            .unwrap();
+        assert_eq!(tx.output[dest_index].value, 60_000);
+        let change_txo = &tx.output[1 - dest_index];
+        assert!(change_txo.value < 40_000 && change_txo.value > 39_000);
+        assert!(!psbt.outputs[1 - dest_index].bip32_derivation.is_empty());
+
+        // Or to a given address.
+        let psbt = control
+            .create_recovery(
+                Some(sweep_addr.clone()),
+                &destinations,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            )
+            .unwrap()
+            .psbt;
+        let sweep_spk = sweep_addr.clone().assume_checked().script_pubkey();
+        assert!(psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|o| o.script_pubkey == sweep_spk && o.value < 40_000));
+
+        // A change output is only created if it's worth it.
+        let destinations: HashMap<_, _> = [(dest_addr.clone(), 99_700)].iter().cloned().collect();
+        let psbt = control
+            .create_recovery(
+                None,
+                &destinations,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10)),
+            )
+            .unwrap()
+            .psbt;
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(psbt.unsigned_tx.output[0].value, 99_700);
+
+        // But we never create a dust output to the given address.
+        assert!(matches!(
+            control.create_recovery(
+                Some(sweep_addr.clone()),
+                &destinations,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10))
+            ),
+            Err(CommandError::InsufficientFunds(_, Some(_), 1))
+        ));
+
+        // We can't pay more than what the recoverable coins are worth.
+        let destinations: HashMap<_, _> = [(dest_addr, 99_990)].iter().cloned().collect();
+        assert!(matches!(
+            control.create_recovery(
+                None,
+                &destinations,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Relative(10))
+            ),
+            Err(CommandError::InsufficientFunds(_, Some(_), 1))
+        ));
+
+        // A recovery path behind an absolute timelock is available once the tip reached its
+        // height, and spends all coins at once (but the frozen ones).
+        assert_eq!(
+            control.create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Absolute(101)),
+            ),
+            Err(CommandError::RecoveryNotAvailable)
+        );
+        let psbt = control
+            .create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Absolute(90)),
+            )
+            .unwrap()
+            .psbt;
+        assert_eq!(input_ops(&psbt), vec![op(0), op(1), op(2)]);
+        let tx = &psbt.unsigned_tx;
+        assert!(tx
+            .input
+            .iter()
+            .all(|txin| txin.sequence == bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME));
+        assert!(tx.lock_time.is_block_height());
+        assert!((90..=100).contains(&tx.lock_time.to_consensus_u32()));
+
+        // Or once the median time past of the tip is past its date.
+        assert_eq!(
+            control.create_recovery(
+                Some(sweep_addr.clone()),
+                &no_dest,
+                &[],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Absolute(1_231_660_825)),
+            ),
+            Err(CommandError::RecoveryNotAvailable)
+        );
+        let psbt = control
+            .create_recovery(
+                Some(sweep_addr),
+                &no_dest,
+                &[op(3)],
+                FeeTarget::Feerate(1),
+                Some(RecoveryTimelock::Absolute(1_231_660_824)),
+            )
+            .unwrap()
+            .psbt;
+        assert_eq!(input_ops(&psbt), vec![op(3)]);
+        assert_eq!(
+            psbt.unsigned_tx.lock_time,
+            absolute::LockTime::from_consensus(1_231_660_824)
+        );
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn create_cpfp() {
+        let dummy_addr = bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv")
+            .unwrap()
+            .assume_checked();
+        let parent_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut {
+                    value: 50_000,
+                    script_pubkey: dummy_addr.script_pubkey(),
+                },
+                TxOut {
+                    value: 60_000,
+                    script_pubkey: dummy_addr.script_pubkey(),
+                },
+            ],
+        };
+        let parent_txid = parent_tx.txid();
+        // Another unconfirmed transaction, but not in mempool.
+        let mut evicted_tx = parent_tx.clone();
+        evicted_tx.output[0].value = 49_999;
+        let evicted_txid = evicted_tx.txid();
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        dummy_bitcoind.txs.insert(parent_txid, (parent_tx, None));
+        dummy_bitcoind.txs.insert(evicted_txid, (evicted_tx, None));
+        dummy_bitcoind.mempool_entries.insert(
+            parent_txid,
+            MempoolEntry {
+                vsize: 200,
+                fee: bitcoin::Amount::from_sat(200),
+            },
+        );
+        let ms = DummyLiana::new(dummy_bitcoind, DummyDatabase::new());
+        let control = &ms.handle.control;
+        let mut db_conn = control.db().lock().unwrap().connection();
 
-        // If we ask for a too high feerate, or a too large/too small output, it'll fail.
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 10_000),
-            Err(CommandError::InsufficientFunds(
-                bitcoin::Amount::from_sat(100_000),
-                Some(bitcoin::Amount::from_sat(10_000)),
-                10_000
-            ))
+            control.create_cpfp(&[], 10),
+            Err(CommandError::CpfpError(CpfpErrorInfo::NoTransaction))
         );
-        *destinations.get_mut(&dummy_addr).unwrap() = 100_001;
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1),
-            Err(CommandError::InsufficientFunds(
-                bitcoin::Amount::from_sat(100_000),
-                Some(bitcoin::Amount::from_sat(100_001)),
-                1
-            ))
+            control.create_cpfp(&[parent_txid], 0),
+            Err(CommandError::InvalidFeerate(0))
         );
-        *destinations.get_mut(&dummy_addr).unwrap() = 4_500;
+        let unknown_txid = bitcoin::Txid::from_str(
+            "ef78f79ba747813887747cf8582897a48f1a09f1ca04d2cd3d6fcfdcbb5e0797",
+        )
+        .unwrap();
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1),
-            Err(CommandError::InvalidOutputValue(bitcoin::Amount::from_sat(
-                4_500
+            control.create_cpfp(&[unknown_txid], 10),
+            Err(CommandError::CpfpError(CpfpErrorInfo::UnknownTransaction(
+                unknown_txid
             )))
         );
-
-        // If we ask to create an output for an address from another network, it will fail.
-        let invalid_addr =
-            bitcoin::Address::new(bitcoin::Network::Testnet, dummy_addr.payload.clone());
-        let invalid_destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
-            [(invalid_addr, dummy_value)].iter().cloned().collect();
-        assert!(matches!(
-            control.create_spend(&invalid_destinations, &[dummy_op], 1),
-            Err(CommandError::Address(
-                address::Error::NetworkValidation { .. }
-            ))
-        ));
-
-        // If we ask for a large, but valid, output we won't get a change output. 95_000 because we
-        // won't create an output lower than 5k sats.
-        *destinations.get_mut(&dummy_addr).unwrap() = 95_000;
-        let res = control.create_spend(&destinations, &[dummy_op], 1).unwrap();
-        let tx = res.psbt.unsigned_tx;
-        assert_eq!(tx.input.len(), 1);
-        assert_eq!(tx.input[0].previous_output, dummy_op);
-        assert_eq!(tx.output.len(), 1);
         assert_eq!(
-            tx.output[0].script_pubkey,
-            dummy_addr.payload.script_pubkey()
+            control.create_cpfp(&[evicted_txid], 10),
+            Err(CommandError::CpfpError(CpfpErrorInfo::NotInMempool(
+                evicted_txid
+            )))
         );
-        assert_eq!(tx.output[0].value, 95_000);
-
-        // Now if we mark the coin as spent, we won't create another Spend transaction containing
-        // it.
-        db_conn.spend_coins(&[(
-            dummy_op,
-            bitcoin::Txid::from_str(
-                "ef78f79ba747813887747cf8582897a48f1a09f1ca04d2cd3d6fcfdcbb5e0797",
-            )
-            .unwrap(),
-        )]);
+        // The parent doesn't pay to us yet.
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op], 1),
-            Err(CommandError::AlreadySpent(dummy_op))
+            control.create_cpfp(&[parent_txid], 10),
+            Err(CommandError::CpfpError(CpfpErrorInfo::NoSpendableCoin(
+                parent_txid
+            )))
         );
 
-        // We'd bail out if they tried to create a transaction with a too high feerate.
-        let dummy_op_dup = bitcoin::OutPoint {
-            txid: dummy_op.txid,
-            vout: dummy_op.vout + 10,
-        };
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: dummy_op_dup,
-            is_immature: false,
-            block_info: None,
-            amount: bitcoin::Amount::from_sat(400_000),
-            derivation_index: bip32::ChildNumber::from(42),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
+        // Now add the two coins it creates.
+        let coins: Vec<Coin> = (0..2)
+            .map(|vout| Coin {
+                outpoint: bitcoin::OutPoint {
+                    txid: parent_txid,
+                    vout,
+                },
+                is_immature: false,
+                is_frozen: false,
+                block_info: None,
+                amount: bitcoin::Amount::from_sat(50_000 + 10_000 * vout as u64),
+                derivation_index: bip32::ChildNumber::from(13 + vout),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            })
+            .collect();
+        db_conn.new_unspent_coins(&coins);
+
+        // The child spends both to a change output and pays for the whole package to reach the
+        // target feerate.
+        let res = control.create_cpfp(&[parent_txid], 10).unwrap();
+        let tx = &res.psbt.unsigned_tx;
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(res.change_amount.unwrap().to_sat(), tx.output[0].value);
+        let child_vb = (tx.vsize() + 2 * control.config.main_descriptor.max_sat_vbytes()) as u64;
+        let child_fee = 110_000 - tx.output[0].value;
+        assert!(child_fee >= 10 * (200 + child_vb) - 200);
+        assert!(child_fee < 10 * (200 + child_vb) - 200 + child_vb);
+
+        // The target feerate can't exceed our maximum. And as the parent pays 1 sat/vb, the
+        // child can't lift the package to the maximum feerate without exceeding it.
         assert_eq!(
-            control.create_spend(&destinations, &[dummy_op_dup], 1_001),
-            Err(CommandError::InsaneFees(InsaneFeeInfo::TooHighFeerate(
-                1001
-            )))
+            control.create_cpfp(&[parent_txid], MAX_FEERATE + 1),
+            Err(CommandError::InvalidFeerate(MAX_FEERATE + 1))
         );
+        assert!(matches!(
+            control.create_cpfp(&[parent_txid], MAX_FEERATE),
+            Err(CommandError::CpfpError(CpfpErrorInfo::TooHighFeerate(r))) if r > MAX_FEERATE
+        ));
 
-        // Can't create a transaction that spends an immature coinbase deposit.
-        let imma_op = bitcoin::OutPoint::from_str(
-            "4753a1d74c0af8dd0a0f3b763c14faf3bd9ed03cbdf33337a074fb0e9f6c7810:0",
-        )
-        .unwrap();
-        db_conn.new_unspent_coins(&[Coin {
-            outpoint: imma_op,
-            is_immature: true,
-            block_info: None,
-            amount: bitcoin::Amount::from_sat(100_000),
-            derivation_index: bip32::ChildNumber::from(13),
-            is_change: false,
-            spend_txid: None,
-            spend_block: None,
-        }]);
+        // A parent given twice is only bumped once.
+        let dup_res = control
+            .create_cpfp(&[parent_txid, parent_txid], 10)
+            .unwrap();
+        assert_eq!(dup_res.psbt.unsigned_tx.input, tx.input);
+        assert_eq!(dup_res.change_amount, res.change_amount);
+
+        // Once the child is stored as a draft, its coins can't be used by another one.
+        db_conn.store_spend(&res.psbt);
         assert_eq!(
-            control.create_spend(&destinations, &[imma_op], 1_001),
-            Err(CommandError::ImmatureCoinbase(imma_op))
+            control.create_cpfp(&[parent_txid], 10),
+            Err(CommandError::CpfpError(CpfpErrorInfo::NoSpendableCoin(
+                parent_txid
+            )))
         );
 
         ms.shutdown();
@@ -1160,6 +3618,7 @@ mod tests {
             Coin {
                 outpoint: dummy_op_a,
                 is_immature: false,
+                is_frozen: false,
                 block_info: None,
                 amount: bitcoin::Amount::from_sat(100_000),
                 derivation_index: bip32::ChildNumber::from(13),
@@ -1170,6 +3629,7 @@ mod tests {
             Coin {
                 outpoint: dummy_op_b,
                 is_immature: false,
+                is_frozen: false,
                 block_info: None,
                 amount: bitcoin::Amount::from_sat(115_680),
                 derivation_index: bip32::ChildNumber::from(34),
@@ -1202,17 +3662,35 @@ mod tests {
                 .cloned()
                 .collect();
         let mut psbt_a = control
-            .create_spend(&destinations_a, &[dummy_op_a], 1)
+            .create_spend(
+                &destinations_a,
+                &[dummy_op_a],
+                FeeTarget::Feerate(1),
+                false,
+                None,
+            )
             .unwrap()
             .psbt;
         let txid_a = psbt_a.unsigned_tx.txid();
         let psbt_b = control
-            .create_spend(&destinations_b, &[dummy_op_b], 10)
+            .create_spend(
+                &destinations_b,
+                &[dummy_op_b],
+                FeeTarget::Feerate(10),
+                false,
+                None,
+            )
             .unwrap()
             .psbt;
         let txid_b = psbt_b.unsigned_tx.txid();
         let psbt_c = control
-            .create_spend(&destinations_c, &[dummy_op_a, dummy_op_b], 100)
+            .create_spend(
+                &destinations_c,
+                &[dummy_op_a, dummy_op_b],
+                FeeTarget::Feerate(100),
+                false,
+                None,
+            )
             .unwrap()
             .psbt;
         let txid_c = psbt_c.unsigned_tx.txid();
@@ -1255,6 +3733,126 @@ mod tests {
         ms.shutdown();
     }
 
+    #[test]
+    fn sign_spend() {
+        let secp = secp256k1::Secp256k1::new();
+        let network = bitcoin::Network::Bitcoin;
+        let (signer, other_signer) = (
+            HotSigner::generate(network).unwrap(),
+            HotSigner::generate(network).unwrap(),
+        );
+        let origin_der = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+        let desc_key = |signer: &HotSigner| {
+            descriptor::DescriptorPublicKey::from_str(&format!(
+                "[{}/48'/0'/0'/2']{}/<0;1>/*",
+                signer.fingerprint(&secp),
+                signer.xpub_at(&origin_der, &secp)
+            ))
+            .unwrap()
+        };
+        let heir_key = descriptor::DescriptorPublicKey::from_str("[aabbccdd]xpub68JJTXc1MWK8PEQozKsRatrUHXKFNkD1Cb1BuQU9Xr5moCv87anqGyXLyUd4KpnDyZgo3gz4aN1r3NiaoweFW8UutBsBbgKHzaD5HkTkifK/<0;1>/*").unwrap();
+        let policy = descriptors::LianaPolicy::new(
+            descriptors::PathInfo::Single(desc_key(&signer)),
+            [(10_000, descriptors::PathInfo::Single(heir_key))]
+                .iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+        let desc = descriptors::LianaDescriptor::new(policy);
+
+        let mut dummy_bitcoind = DummyBitcoind::new();
+        let dummy_tx = bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+            input: vec![],
+            output: vec![bitcoin::TxOut {
+                value: 100_000,
+                script_pubkey: desc
+                    .receive_descriptor()
+                    .derive(13.into(), &secp)
+                    .script_pubkey(),
+            }],
+        };
+        let dummy_op = bitcoin::OutPoint::new(dummy_tx.txid(), 0);
+        dummy_bitcoind.txs.insert(dummy_op.txid, (dummy_tx, None));
+        let ms = DummyLiana::new_with_descriptor(dummy_bitcoind, DummyDatabase::new(), desc);
+        let control = &ms.handle.control;
+        let mut db_conn = control.db().lock().unwrap().connection();
+        db_conn.new_unspent_coins(&[Coin {
+            outpoint: dummy_op,
+            is_immature: false,
+            is_frozen: false,
+            block_info: None,
+            amount: bitcoin::Amount::from_sat(100_000),
+            derivation_index: bip32::ChildNumber::from(13),
+            is_change: false,
+            spend_txid: None,
+            spend_block: None,
+        }]);
+        let dummy_addr =
+            bitcoin::Address::from_str("bc1qnsexk3gnuyayu92fc3tczvc7k62u22a22ua2kv").unwrap();
+        let destinations: HashMap<bitcoin::Address<address::NetworkUnchecked>, u64> =
+            [(dummy_addr, 50_000)].iter().cloned().collect();
+        let psbt = control
+            .create_spend(
+                &destinations,
+                &[dummy_op],
+                FeeTarget::Feerate(1),
+                false,
+                None,
+            )
+            .unwrap()
+            .psbt;
+        let txid = psbt.unsigned_tx.txid();
+
+        // The Spend transaction must be stored first.
+        assert_eq!(
+            control.sign_spend(&txid, None),
+            Err(CommandError::UnknownSpend(txid))
+        );
+        control.update_spend(psbt.clone()).unwrap();
+
+        // There must be hot signers in the data directory.
+        assert_eq!(
+            control.sign_spend(&txid, None),
+            Err(CommandError::NoHotSigner)
+        );
+        let data_dir = control.config.data_dir().unwrap();
+        signer
+            .store(&data_dir, network, &secp, Some("passphrase"))
+            .unwrap();
+        other_signer.store(&data_dir, network, &secp, None).unwrap();
+
+        // The encrypted mnemonic can't be loaded without the passphrase.
+        assert!(matches!(
+            control.sign_spend(&txid, None),
+            Err(CommandError::HotSigner(_))
+        ));
+        assert!(matches!(
+            control.sign_spend(&txid, Some("wrong passphrase")),
+            Err(CommandError::HotSigner(_))
+        ));
+
+        // With the passphrase only the signer from our descriptor signs, and the result is
+        // stored.
+        let signed_psbt = control.sign_spend(&txid, Some("passphrase")).unwrap().psbt;
+        assert_eq!(signed_psbt.inputs[0].partial_sigs.len(), 1);
+        assert_eq!(db_conn.spend_tx(&txid).unwrap(), signed_psbt);
+
+        // A stored Spend lying about the value of the coins it spends is not signed.
+        control.delete_spend(&txid);
+        let mut forged_psbt = psbt;
+        forged_psbt.inputs[0].witness_utxo.as_mut().unwrap().value = 1_000_000;
+        control.update_spend(forged_psbt).unwrap();
+        assert!(matches!(
+            control.sign_spend(&txid, Some("passphrase")),
+            Err(CommandError::HotSigner(_))
+        ));
+
+        ms.shutdown();
+    }
+
     #[test]
     fn list_confirmed_transactions() {
         let outpoint = OutPoint::new(
@@ -1338,6 +3936,7 @@ mod tests {
             Coin {
                 is_change: false,
                 is_immature: false,
+                is_frozen: false,
                 outpoint: OutPoint {
                     txid: deposit1.txid(),
                     vout: 0,
@@ -1352,6 +3951,7 @@ mod tests {
             Coin {
                 is_change: false,
                 is_immature: false,
+                is_frozen: false,
                 outpoint: OutPoint {
                     txid: deposit2.txid(),
                     vout: 0,
@@ -1366,6 +3966,7 @@ mod tests {
             Coin {
                 is_change: true,
                 is_immature: false,
+                is_frozen: false,
                 outpoint: OutPoint::new(spend_tx.txid(), 1),
                 block_info: Some(BlockInfo { height: 3, time: 3 }),
                 spend_block: None,
@@ -1377,6 +3978,7 @@ mod tests {
             Coin {
                 is_change: false,
                 is_immature: false,
+                is_frozen: false,
                 outpoint: OutPoint {
                     txid: deposit3.txid(),
                     vout: 0,
@@ -1389,65 +3991,14 @@ mod tests {
             },
         ]);
 
-        let mut btc = DummyBitcoind::new();
-        btc.txs.insert(
-            deposit1.txid(),
-            (
-                deposit1.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 1,
-                    height: 1,
-                }),
-            ),
-        );
-        btc.txs.insert(
-            deposit2.txid(),
-            (
-                deposit2.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 2,
-                    height: 2,
-                }),
-            ),
-        );
-        btc.txs.insert(
-            spend_tx.txid(),
-            (
-                spend_tx.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 3,
-                    height: 3,
-                }),
-            ),
-        );
-        btc.txs.insert(
-            deposit3.txid(),
-            (
-                deposit3.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 4,
-                    height: 4,
-                }),
-            ),
-        );
+        db.new_txs(&[
+            deposit1.clone(),
+            deposit2.clone(),
+            spend_tx.clone(),
+            deposit3.clone(),
+        ]);
 
-        let ms = DummyLiana::new(btc, db);
+        let ms = DummyLiana::new(DummyBitcoind::new(), db);
 
         let control = &ms.handle.control;
 
@@ -1535,51 +4086,13 @@ mod tests {
             }],
         };
 
+        // The transactions are served from the database, not the Bitcoin backend.
+        let mut db = DummyDatabase::new();
+        db.new_txs(&[tx1.clone(), tx2.clone()]);
         let mut btc = DummyBitcoind::new();
-        btc.txs.insert(
-            tx1.txid(),
-            (
-                tx1.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 1,
-                    height: 1,
-                }),
-            ),
-        );
-        btc.txs.insert(
-            tx2.txid(),
-            (
-                tx2.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 2,
-                    height: 2,
-                }),
-            ),
-        );
-        btc.txs.insert(
-            tx3.txid(),
-            (
-                tx3.clone(),
-                Some(Block {
-                    hash: bitcoin::BlockHash::from_str(
-                        "0000000000000000000326b8fca8d3f820647c97ea33ef722096b3c7b2c8ee94",
-                    )
-                    .unwrap(),
-                    time: 4,
-                    height: 4,
-                }),
-            ),
-        );
+        btc.txs.insert(tx3.txid(), (tx3.clone(), None));
 
-        let ms = DummyLiana::new(btc, DummyDatabase::new());
+        let ms = DummyLiana::new(btc, db);
 
         let control = &ms.handle.control;
 
@@ -1587,10 +4100,13 @@ mod tests {
         assert_eq!(transactions.len(), 1);
         assert_eq!(transactions[0].tx, tx1);
 
+        assert_eq!(transactions[0].height, None);
+        assert_eq!(transactions[0].time, None);
+
         let transactions = control
             .list_transactions(&[tx1.txid(), tx2.txid(), tx3.txid()])
             .transactions;
-        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions.len(), 2);
 
         let txs: Vec<Transaction> = transactions
             .iter()
@@ -1599,7 +4115,74 @@ mod tests {
 
         assert!(txs.contains(&tx1));
         assert!(txs.contains(&tx2));
-        assert!(txs.contains(&tx3));
+        assert!(!txs.contains(&tx3));
+
+        ms.shutdown();
+    }
+
+    #[test]
+    fn labels() {
+        let ms = DummyLiana::new(DummyBitcoind::new(), DummyDatabase::new());
+        let control = &ms.handle.control;
+
+        let addr = LabelItem::Address(control.get_new_address().address);
+        let txid = LabelItem::Txid(
+            Txid::from_str("f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd")
+                .unwrap(),
+        );
+        let outpoint = LabelItem::OutPoint(
+            OutPoint::from_str(
+                "f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1",
+            )
+            .unwrap(),
+        );
+        let items: HashSet<LabelItem> = [addr.clone(), txid.clone(), outpoint.clone()]
+            .iter()
+            .cloned()
+            .collect();
+        assert!(control.get_labels(&items).labels.is_empty());
+
+        // Set labels, and get them back keyed by the string representation of the items.
+        let mut labels = HashMap::new();
+        labels.insert(addr.clone(), Some("deposit".to_string()));
+        labels.insert(txid.clone(), Some("payment".to_string()));
+        control.update_labels(&labels);
+        let res = control.get_labels(&items).labels;
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[&addr.to_string()], "deposit");
+        assert_eq!(res[&txid.to_string()], "payment");
+
+        // Export them, then import them back along with a label for the outpoint and an
+        // overwritten label for the transaction.
+        let exported = control.export_labels().labels;
+        assert_eq!(exported.lines().count(), 2);
+        let imported = exported.replace("payment", "rent")
+            + &format!(
+                "{{\"type\":\"output\",\"ref\":\"{}\",\"label\":\"change\"}}\n",
+                outpoint
+            );
+        control.import_labels(&imported).unwrap();
+        let res = control.get_labels(&items).labels;
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[&addr.to_string()], "deposit");
+        assert_eq!(res[&txid.to_string()], "rent");
+        assert_eq!(res[&outpoint.to_string()], "change");
+
+        // Removing a label.
+        let mut labels = HashMap::new();
+        labels.insert(txid.clone(), None);
+        control.update_labels(&labels);
+        assert!(!control
+            .get_labels(&items)
+            .labels
+            .contains_key(&txid.to_string()));
+
+        // An invalid import doesn't change anything.
+        assert!(matches!(
+            control.import_labels(&format!("{}invalid", imported)),
+            Err(CommandError::InvalidBip329(Bip329Error { line: 4, .. }))
+        ));
+        assert_eq!(control.get_labels(&items).labels.len(), 2);
 
         ms.shutdown();
     }
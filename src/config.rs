@@ -43,6 +43,13 @@ fn default_poll_interval() -> Duration {
     Duration::from_secs(30)
 }
 
+/// The number of unused addresses we look for coins on by default, past the last used one.
+pub const DEFAULT_GAP_LIMIT: u32 = 200;
+
+fn default_gap_limit() -> u32 {
+    DEFAULT_GAP_LIMIT
+}
+
 #[cfg(unix)]
 fn default_daemon() -> bool {
     false
@@ -57,6 +64,21 @@ pub struct BitcoindConfig {
     pub addr: SocketAddr,
 }
 
+/// Everything we need to know for talking to an Electrum server
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ElectrumConfig {
+    /// The host:port the Electrum server is listening on, over plain TCP
+    pub addr: String,
+}
+
+/// Everything we need to know for querying an Esplora HTTP API
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EsploraConfig {
+    /// The URL the Esplora API is served at, such as 'http://127.0.0.1:3000/api'. Only plain HTTP
+    /// is supported
+    pub base_url: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BitcoinConfig {
     /// The network we are operating on, one of "bitcoin", "testnet", "regtest", "signet"
@@ -68,6 +90,10 @@ pub struct BitcoinConfig {
         default = "default_poll_interval"
     )]
     pub poll_interval_secs: Duration,
+    /// How many consecutive unused addresses we look for coins on past the last used one, for
+    /// both receive and change addresses
+    #[serde(default = "default_gap_limit")]
+    pub gap_limit: u32,
 }
 
 /// Static informations we require to operate
@@ -96,6 +122,10 @@ pub struct Config {
     pub bitcoin_config: BitcoinConfig,
     /// Settings specific to bitcoind as the Bitcoin interface
     pub bitcoind_config: Option<BitcoindConfig>,
+    /// Settings specific to an Electrum server as the Bitcoin interface, in place of bitcoind
+    pub electrum_config: Option<ElectrumConfig>,
+    /// Settings specific to an Esplora server as the Bitcoin interface, in place of bitcoind
+    pub esplora_config: Option<EsploraConfig>,
 }
 
 impl Config {
@@ -124,7 +154,7 @@ impl std::fmt::Display for ConfigError {
             Self::ReadingFile(e) => write!(f, "Failed to read configuration file: {}", e),
             Self::UnexpectedDescriptor(desc) => write!(
                 f,
-                "Unexpected descriptor '{}'. We only support wsh() descriptors for now.",
+                "Unexpected descriptor '{}'. We only support wsh() and tr() descriptors.",
                 desc
             ),
             Self::Unexpected(e) => write!(f, "Configuration error: {}", e),
@@ -209,6 +239,27 @@ impl Config {
             )));
         }
 
+        if self.bitcoin_config.gap_limit == 0 {
+            return Err(ConfigError::Unexpected(
+                "The gap limit must be at least 1.".to_string(),
+            ));
+        }
+
+        let backends_count = [
+            self.bitcoind_config.is_some(),
+            self.electrum_config.is_some(),
+            self.esplora_config.is_some(),
+        ]
+        .iter()
+        .filter(|is_set| **is_set)
+        .count();
+        if backends_count > 1 {
+            return Err(ConfigError::Unexpected(
+                "Only one of 'bitcoind_config', 'electrum_config' and 'esplora_config' may be set."
+                    .to_string(),
+            ));
+        }
+
         // TODO: check the semantics of the main descriptor
 
         Ok(())
@@ -217,7 +268,7 @@ impl Config {
 
 #[cfg(test)]
 mod tests {
-    use super::{config_file_path, Config};
+    use super::{config_file_path, Config, DEFAULT_GAP_LIMIT};
 
     // Test the format of the configuration file
     #[test]
@@ -237,7 +288,8 @@ mod tests {
             cookie_path = "/home/user/.bitcoin/.cookie"
             addr = "127.0.0.1:8332"
             "#.trim_start().replace("            ", "");
-        toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        assert_eq!(config.bitcoin_config.gap_limit, DEFAULT_GAP_LIMIT);
 
         // A valid, round-tripping, config
         let toml_str = r#"
@@ -249,6 +301,7 @@ mod tests {
             [bitcoin_config]
             network = 'bitcoin'
             poll_interval_secs = 18
+            gap_limit = 1000
 
             [bitcoind_config]
             cookie_path = '/home/user/.bitcoin/.cookie'
@@ -259,6 +312,83 @@ mod tests {
         #[cfg(unix)] // On non-UNIX there is no 'daemon' member.
         assert_eq!(toml_str, serialized);
 
+        // The gap limit can't be 0
+        let toml_str = r#"
+            main_descriptor = "wsh(andor(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#dw4ulnrs"
+
+            [bitcoin_config]
+            network = "testnet"
+            gap_limit = 0
+            "#.trim_start().replace("            ", "");
+        let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        config.check().expect_err("Null gap limit");
+
+        // A valid config using an Electrum server
+        let toml_str = r#"
+            data_dir = "/home/wizardsardine/custom/folder/"
+            daemon = false
+            log_level = "debug"
+            main_descriptor = "wsh(andor(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#dw4ulnrs"
+
+            [bitcoin_config]
+            network = "testnet"
+
+            [electrum_config]
+            addr = "127.0.0.1:50001"
+            "#.trim_start().replace("            ", "");
+        let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        assert_eq!(config.electrum_config.unwrap().addr, "127.0.0.1:50001");
+
+        // But it can't use both bitcoind and an Electrum server
+        let toml_str = r#"
+            main_descriptor = "wsh(andor(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#dw4ulnrs"
+
+            [bitcoin_config]
+            network = "testnet"
+
+            [bitcoind_config]
+            cookie_path = "/home/user/.bitcoin/.cookie"
+            addr = "127.0.0.1:8332"
+
+            [electrum_config]
+            addr = "127.0.0.1:50001"
+            "#.trim_start().replace("            ", "");
+        let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        config.check().expect_err("Both bitcoind and Electrum");
+
+        // A valid config using an Esplora server
+        let toml_str = r#"
+            main_descriptor = "wsh(andor(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#dw4ulnrs"
+
+            [bitcoin_config]
+            network = "testnet"
+
+            [esplora_config]
+            base_url = "http://127.0.0.1:3000/api"
+            "#.trim_start().replace("            ", "");
+        let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        config.check().expect("Only Esplora");
+        assert_eq!(
+            config.esplora_config.unwrap().base_url,
+            "http://127.0.0.1:3000/api"
+        );
+
+        // But it can't use both an Electrum and an Esplora server
+        let toml_str = r#"
+            main_descriptor = "wsh(andor(pk([aabbccdd]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))#dw4ulnrs"
+
+            [bitcoin_config]
+            network = "testnet"
+
+            [electrum_config]
+            addr = "127.0.0.1:50001"
+
+            [esplora_config]
+            base_url = "http://127.0.0.1:3000/api"
+            "#.trim_start().replace("            ", "");
+        let config = toml::from_str::<Config>(&toml_str).expect("Deserializing toml_str");
+        config.check().expect_err("Both Electrum and Esplora");
+
         // Invalid desc checksum
         let toml_str = r#"
             daemon = false
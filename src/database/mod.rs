@@ -12,7 +12,12 @@ use crate::{
     },
 };
 
-use std::{collections::HashMap, sync};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    str::FromStr,
+    sync,
+};
 
 use miniscript::bitcoin::{self, bip32, psbt::PartiallySignedTransaction as Psbt, secp256k1};
 
@@ -96,6 +101,9 @@ pub trait DatabaseConnection {
     /// chain.
     fn confirm_coins(&mut self, outpoints: &[(bitcoin::OutPoint, i32, u32)]);
 
+    /// Mark a set of coins as frozen, or unfreeze them.
+    fn set_frozen_coins(&mut self, outpoints: &[bitcoin::OutPoint], frozen: bool);
+
     /// Mark a set of coins as being spent by a specified txid of a pending transaction.
     fn spend_coins(&mut self, outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)]);
 
@@ -124,6 +132,29 @@ pub trait DatabaseConnection {
 
     /// Retrieve a limited list of txids that where deposited or spent between the start and end timestamps (inclusive bounds)
     fn list_txids(&mut self, start: u32, end: u32, limit: u64) -> Vec<bitcoin::Txid>;
+
+    /// Store transactions which deposited to or spent our coins. Already stored ones are ignored.
+    fn new_txs(&mut self, txs: &[bitcoin::Transaction]);
+
+    /// List the txids of the transactions which deposited to or spent our coins but that aren't
+    /// stored yet.
+    fn list_missing_txids(&mut self) -> Vec<bitcoin::Txid>;
+
+    /// Get the stored transactions with the given txids, in the same order, along with their
+    /// confirmation info. Unknown txids are ignored.
+    fn list_wallet_transactions(
+        &mut self,
+        txids: &[bitcoin::Txid],
+    ) -> Vec<(bitcoin::Transaction, Option<BlockInfo>)>;
+
+    /// Set the label of the given items, or remove it if `None`.
+    fn update_labels(&mut self, items: &HashMap<LabelItem, Option<String>>);
+
+    /// Get the labels of the given items, for those which have one.
+    fn labels(&mut self, items: &HashSet<LabelItem>) -> HashMap<LabelItem, String>;
+
+    /// List all the labels in database, in the order they were first set.
+    fn list_labels(&mut self) -> Vec<(LabelItem, String)>;
 }
 
 impl DatabaseConnection for SqliteConn {
@@ -208,6 +239,10 @@ impl DatabaseConnection for SqliteConn {
         self.confirm_coins(outpoints)
     }
 
+    fn set_frozen_coins(&mut self, outpoints: &[bitcoin::OutPoint], frozen: bool) {
+        self.set_frozen_coins(outpoints, frozen)
+    }
+
     fn spend_coins<'a>(&mut self, outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)]) {
         self.spend_coins(outpoints)
     }
@@ -264,6 +299,48 @@ impl DatabaseConnection for SqliteConn {
     fn list_txids(&mut self, start: u32, end: u32, limit: u64) -> Vec<bitcoin::Txid> {
         self.db_list_txids(start, end, limit)
     }
+
+    fn new_txs(&mut self, txs: &[bitcoin::Transaction]) {
+        self.new_txs(txs)
+    }
+
+    fn list_missing_txids(&mut self) -> Vec<bitcoin::Txid> {
+        self.db_list_missing_txids()
+    }
+
+    fn list_wallet_transactions(
+        &mut self,
+        txids: &[bitcoin::Txid],
+    ) -> Vec<(bitcoin::Transaction, Option<BlockInfo>)> {
+        let mut db_txs: HashMap<bitcoin::Txid, _> = self
+            .db_wallet_transactions(txids)
+            .into_iter()
+            .map(|db_tx| (db_tx.transaction.txid(), db_tx))
+            .collect();
+        txids
+            .iter()
+            .filter_map(|txid| db_txs.remove(txid))
+            .map(|db_tx| (db_tx.transaction, db_tx.block_info.map(BlockInfo::from)))
+            .collect()
+    }
+
+    fn update_labels(&mut self, items: &HashMap<LabelItem, Option<String>>) {
+        self.update_labels(items)
+    }
+
+    fn labels(&mut self, items: &HashSet<LabelItem>) -> HashMap<LabelItem, String> {
+        self.db_labels(items)
+            .into_iter()
+            .map(|db_label| (db_label.item, db_label.value))
+            .collect()
+    }
+
+    fn list_labels(&mut self) -> Vec<(LabelItem, String)> {
+        self.list_labels()
+            .into_iter()
+            .map(|db_label| (db_label.item, db_label.value))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -285,6 +362,7 @@ impl From<DbBlockInfo> for BlockInfo {
 pub struct Coin {
     pub outpoint: bitcoin::OutPoint,
     pub is_immature: bool,
+    pub is_frozen: bool,
     pub block_info: Option<BlockInfo>,
     pub amount: bitcoin::Amount,
     pub derivation_index: bip32::ChildNumber,
@@ -298,6 +376,7 @@ impl std::convert::From<DbCoin> for Coin {
         let DbCoin {
             outpoint,
             is_immature,
+            is_frozen,
             block_info,
             amount,
             derivation_index,
@@ -309,6 +388,7 @@ impl std::convert::From<DbCoin> for Coin {
         Coin {
             outpoint,
             is_immature,
+            is_frozen,
             block_info: block_info.map(BlockInfo::from),
             amount,
             derivation_index,
@@ -335,3 +415,39 @@ pub enum CoinType {
     Unspent,
     Spent,
 }
+
+/// An item of the wallet that can be given a label.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LabelItem {
+    Address(bitcoin::Address),
+    Txid(bitcoin::Txid),
+    OutPoint(bitcoin::OutPoint),
+}
+
+impl LabelItem {
+    /// Parse a label item from its string representation. Addresses must be valid for the given
+    /// network.
+    pub fn parse(s: &str, network: bitcoin::Network) -> Option<LabelItem> {
+        if let Ok(outpoint) = bitcoin::OutPoint::from_str(s) {
+            Some(LabelItem::OutPoint(outpoint))
+        } else if let Ok(txid) = bitcoin::Txid::from_str(s) {
+            Some(LabelItem::Txid(txid))
+        } else {
+            bitcoin::Address::from_str(s)
+                .ok()?
+                .require_network(network)
+                .ok()
+                .map(LabelItem::Address)
+        }
+    }
+}
+
+impl fmt::Display for LabelItem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LabelItem::Address(addr) => write!(f, "{}", addr),
+            LabelItem::Txid(txid) => write!(f, "{}", txid),
+            LabelItem::OutPoint(outpoint) => write!(f, "{}", outpoint),
+        }
+    }
+}
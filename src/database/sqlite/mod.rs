@@ -14,18 +14,26 @@ use crate::{
     bitcoin::BlockChainTip,
     database::{
         sqlite::{
-            schema::{DbAddress, DbCoin, DbSpendTransaction, DbTip, DbWallet, SCHEMA},
+            schema::{
+                DbAddress, DbCoin, DbLabel, DbLabelledKind, DbSpendTransaction, DbTip, DbWallet,
+                DbWalletTransaction, SCHEMA,
+            },
             utils::{
                 create_fresh_db, curr_timestamp, db_exec, db_query, db_tx_query, db_version,
-                maybe_apply_migration, LOOK_AHEAD_LIMIT,
+                maybe_apply_migration,
             },
         },
-        Coin, CoinType,
+        Coin, CoinType, LabelItem,
     },
     descriptors::LianaDescriptor,
 };
 
-use std::{cmp, convert::TryInto, fmt, io, path};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fmt, io, path,
+};
 
 use miniscript::bitcoin::{
     self, bip32,
@@ -35,7 +43,7 @@ use miniscript::bitcoin::{
     secp256k1,
 };
 
-const DB_VERSION: i64 = 2;
+const DB_VERSION: i64 = 5;
 
 #[derive(Debug)]
 pub enum SqliteDbError {
@@ -127,19 +135,24 @@ impl FreshDbOptions {
 #[derive(Debug, Clone)]
 pub struct SqliteDb {
     db_path: path::PathBuf,
+    gap_limit: u32,
 }
 
 impl SqliteDb {
     /// Instanciate an SQLite database either from an existing database file or by creating a fresh
     /// one.
+    ///
+    /// The addresses cache is populated up to `gap_limit` addresses past the highest derivation
+    /// index in use.
     pub fn new(
         db_path: path::PathBuf,
         fresh_options: Option<FreshDbOptions>,
+        gap_limit: u32,
         secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     ) -> Result<SqliteDb, SqliteDbError> {
         // Create the database if needed, and make sure the db file exists.
         if let Some(options) = fresh_options {
-            create_fresh_db(&db_path, options, secp)?;
+            create_fresh_db(&db_path, options)?;
             log::info!("Created a fresh database at {}.", db_path.display());
         }
         if !db_path.exists() {
@@ -149,14 +162,24 @@ impl SqliteDb {
         log::info!("Checking if the database needs upgrading.");
         maybe_apply_migration(&db_path)?;
 
-        Ok(SqliteDb { db_path })
+        let db = SqliteDb { db_path, gap_limit };
+        // The gap limit may have been increased since the last time we opened the database.
+        let mut conn = db.connection()?;
+        db_exec(&mut conn.conn, |db_tx| {
+            populate_address_cache(db_tx, gap_limit, secp)
+        })?;
+
+        Ok(db)
     }
 
     /// Get a new connection to the database.
     pub fn connection(&self) -> Result<SqliteConn, SqliteDbError> {
         let conn = rusqlite::Connection::open(&self.db_path)?;
         conn.busy_timeout(std::time::Duration::from_secs(60))?;
-        Ok(SqliteConn { conn })
+        Ok(SqliteConn {
+            conn,
+            gap_limit: self.gap_limit,
+        })
     }
 
     /// Perform startup sanity checks.
@@ -194,8 +217,61 @@ impl SqliteDb {
 // We only support single wallet. The id of the wallet row is always 1.
 const WALLET_ID: i64 = 1;
 
+// Make sure the address->deriv_index mapping contains an entry for every derivation index up to
+// `gap_limit` indexes past the highest of the deposit and change derivation indexes.
+fn populate_address_cache(
+    db_tx: &rusqlite::Transaction,
+    gap_limit: u32,
+    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
+) -> rusqlite::Result<()> {
+    let db_tip: DbTip = db_tx_query(db_tx, "SELECT * FROM tip", rusqlite::params![], |row| {
+        row.try_into()
+    })?
+    .pop()
+    .expect("There is always a row in the tip table");
+    let db_wallet: DbWallet =
+        db_tx_query(db_tx, "SELECT * FROM wallets", rusqlite::params![], |row| {
+            row.try_into()
+        })?
+        .pop()
+        .expect("There is always a row in the wallet table");
+    let cached_max: Option<u32> = db_tx.query_row(
+        "SELECT MAX(derivation_index) FROM addresses",
+        rusqlite::params![],
+        |row| row.get(0),
+    )?;
+
+    // The first index not in the cache and the first index past the gap. Derivation indexes are
+    // unhardened, therefore strictly below 2**31.
+    let start = cached_max.map(|i| i + 1).unwrap_or(0);
+    let highest_index: u32 = cmp::max(
+        db_wallet.deposit_derivation_index,
+        db_wallet.change_derivation_index,
+    )
+    .into();
+    let end = cmp::min(highest_index.saturating_add(gap_limit), 1 << 31);
+
+    let receive_desc = db_wallet.main_descriptor.receive_descriptor();
+    let change_desc = db_wallet.main_descriptor.change_descriptor();
+    for index in start..end {
+        let receive_addr = receive_desc
+            .derive(index.into(), secp)
+            .address(db_tip.network);
+        let change_addr = change_desc
+            .derive(index.into(), secp)
+            .address(db_tip.network);
+        db_tx.execute(
+            "INSERT INTO addresses (receive_address, change_address, derivation_index) VALUES (?1, ?2, ?3)",
+            rusqlite::params![receive_addr.to_string(), change_addr.to_string(), index],
+        )?;
+    }
+
+    Ok(())
+}
+
 pub struct SqliteConn {
     conn: rusqlite::Connection,
+    gap_limit: u32,
 }
 
 impl SqliteConn {
@@ -252,7 +328,7 @@ impl SqliteConn {
         change: bool,
         secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
     ) {
-        let network = self.db_tip().network;
+        let gap_limit = self.gap_limit;
 
         db_exec(&mut self.conn, |db_tx| {
             let db_wallet: DbWallet =
@@ -265,7 +341,9 @@ impl SqliteConn {
             // Make sure we don't set a lower derivation index. This can happen since the
             // derivation is set outside the atomic transaction. So there may be a race between say
             // the Bitcoin poller thread and the JSONRPC commands thread.
-            if (change && index <= db_wallet.change_derivation_index) || (!change && index <= db_wallet.deposit_derivation_index) {
+            if (change && index <= db_wallet.change_derivation_index)
+                || (!change && index <= db_wallet.deposit_derivation_index)
+            {
                 // It was already set at a higher index.
                 return Ok(());
             }
@@ -287,27 +365,7 @@ impl SqliteConn {
             // Now if this new index is higher than the highest of our current derivation indexes,
             // populate the addresses mapping for derivation indexes between our previous "gap
             // limit index" and the new one.
-            let curr_highest_index = cmp::max(
-                db_wallet.deposit_derivation_index,
-                db_wallet.change_derivation_index,
-            ).into();
-            if index_u32 > curr_highest_index {
-                let receive_desc = db_wallet.main_descriptor.receive_descriptor();
-                let change_desc = db_wallet.main_descriptor.change_descriptor();
-
-                for index in curr_highest_index + 1..=index_u32 {
-                    let la_index = index + LOOK_AHEAD_LIMIT - 1;
-                    let receive_addr = receive_desc.derive(la_index.into(), secp).address(network);
-                    let change_addr = change_desc.derive(la_index.into(), secp).address(network);
-                    db_tx.execute(
-                        "INSERT INTO addresses (receive_address, change_address, derivation_index) VALUES (?1, ?2, ?3)",
-                        rusqlite::params![receive_addr.to_string(), change_addr.to_string(), la_index],
-                    )?;
-                }
-
-            }
-
-            Ok(())
+            populate_address_cache(db_tx, gap_limit, secp)
         })
         .expect("Database must be available")
     }
@@ -383,8 +441,8 @@ impl SqliteConn {
             for coin in coins {
                 let deriv_index: u32 = coin.derivation_index.into();
                 db_tx.execute(
-                    "INSERT INTO coins (wallet_id, txid, vout, amount_sat, derivation_index, is_change, is_immature) \
-                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    "INSERT INTO coins (wallet_id, txid, vout, amount_sat, derivation_index, is_change, is_immature, is_frozen) \
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
                     rusqlite::params![
                         WALLET_ID,
                         coin.outpoint.txid[..].to_vec(),
@@ -393,6 +451,7 @@ impl SqliteConn {
                         deriv_index,
                         coin.is_change,
                         coin.is_immature,
+                        coin.is_frozen,
                     ],
                 )?;
             }
@@ -437,6 +496,21 @@ impl SqliteConn {
         .expect("Database must be available")
     }
 
+    /// Mark a set of coins as frozen, or unfreeze them.
+    pub fn set_frozen_coins(&mut self, outpoints: &[bitcoin::OutPoint], frozen: bool) {
+        db_exec(&mut self.conn, |db_tx| {
+            for outpoint in outpoints {
+                db_tx.execute(
+                    "UPDATE coins SET is_frozen = ?1 WHERE txid = ?2 AND vout = ?3",
+                    rusqlite::params![frozen, outpoint.txid[..].to_vec(), outpoint.vout,],
+                )?;
+            }
+
+            Ok(())
+        })
+        .expect("Database must be available")
+    }
+
     /// Mark a set of coins as spent.
     pub fn spend_coins<'a>(
         &mut self,
@@ -586,6 +660,75 @@ impl SqliteConn {
         .expect("Db must not fail")
     }
 
+    /// Store the given transactions. Those already in database are ignored.
+    pub fn new_txs<'a>(&mut self, txs: impl IntoIterator<Item = &'a bitcoin::Transaction>) {
+        db_exec(&mut self.conn, |db_tx| {
+            for tx in txs {
+                db_tx.execute(
+                    "INSERT INTO transactions (txid, tx) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+                    rusqlite::params![tx.txid()[..].to_vec(), encode::serialize(tx)],
+                )?;
+            }
+
+            Ok(())
+        })
+        .expect("Database must be available")
+    }
+
+    /// List the txids of the transactions which deposited to or spent our coins and which aren't
+    /// stored in database.
+    pub fn db_list_missing_txids(&mut self) -> Vec<bitcoin::Txid> {
+        db_query(
+            &mut self.conn,
+            "SELECT txid FROM ( \
+                SELECT txid FROM coins \
+                UNION \
+                SELECT spend_txid AS txid FROM coins WHERE spend_txid IS NOT NULL \
+            ) WHERE txid NOT IN (SELECT txid FROM transactions)",
+            rusqlite::params![],
+            |row| {
+                let txid: Vec<u8> = row.get(0)?;
+                let txid: bitcoin::Txid =
+                    encode::deserialize(&txid).expect("We only store valid txids");
+                Ok(txid)
+            },
+        )
+        .expect("Db must not fail")
+    }
+
+    /// Get the stored transactions with the given txids, along with their confirmation info.
+    pub fn db_wallet_transactions(&mut self, txids: &[bitcoin::Txid]) -> Vec<DbWalletTransaction> {
+        if txids.is_empty() {
+            return Vec::new();
+        }
+
+        // The confirmation info of a transaction is the one of the coins it created or spent.
+        let mut query = "SELECT t.tx, b.height, b.time FROM transactions AS t \
+            LEFT JOIN ( \
+                SELECT txid, blockheight AS height, blocktime AS time FROM coins \
+                WHERE blockheight IS NOT NULL \
+                UNION \
+                SELECT spend_txid AS txid, spend_block_height AS height, spend_block_time AS time \
+                FROM coins WHERE spend_block_height IS NOT NULL \
+            ) AS b ON b.txid = t.txid \
+            WHERE t.txid IN (VALUES "
+            .to_string();
+        for (i, txid) in txids.iter().enumerate() {
+            // NOTE: SQLite doesn't know Satoshi decided txids would be displayed as little-endian
+            // hex.
+            query += &format!("(x'{}')", FrontwardHexTxid(*txid));
+            if i != txids.len() - 1 {
+                query += ", ";
+            }
+        }
+        query += ") GROUP BY t.txid";
+
+        db_query(&mut self.conn, &query, rusqlite::params![], |row| {
+            row.try_into()
+        })
+        .expect("Db must not fail")
+    }
+
     pub fn delete_spend(&mut self, txid: &bitcoin::Txid) {
         db_exec(&mut self.conn, |db_tx| {
             db_tx.execute(
@@ -597,6 +740,64 @@ impl SqliteConn {
         .expect("Db must not fail");
     }
 
+    /// Set the label of the given items, or remove it if `None`.
+    pub fn update_labels(&mut self, items: &HashMap<LabelItem, Option<String>>) {
+        db_exec(&mut self.conn, |db_tx| {
+            for (item, value) in items {
+                if let Some(value) = value {
+                    db_tx.execute(
+                        "INSERT INTO labels (wallet_id, item_kind, item, value) VALUES (?1, ?2, ?3, ?4) \
+                         ON CONFLICT DO UPDATE SET value=excluded.value",
+                        rusqlite::params![
+                            WALLET_ID,
+                            DbLabelledKind::from(item) as i64,
+                            item.to_string(),
+                            value
+                        ],
+                    )?;
+                } else {
+                    db_tx.execute(
+                        "DELETE FROM labels WHERE item = ?1",
+                        rusqlite::params![item.to_string()],
+                    )?;
+                }
+            }
+            Ok(())
+        })
+        .expect("Db must not fail");
+    }
+
+    pub fn db_labels(&mut self, items: &HashSet<LabelItem>) -> Vec<DbLabel> {
+        // SELECT * FROM labels WHERE item IN (?1, ?2);
+        let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+        let query = format!(
+            "SELECT * FROM labels WHERE item IN ({})",
+            (1..=items.len())
+                .map(|i| format!("?{}", i))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let params: Vec<&dyn rusqlite::ToSql> = items
+            .iter()
+            .map(|item| item as &dyn rusqlite::ToSql)
+            .collect();
+
+        db_query(&mut self.conn, &query, params.as_slice(), |row| {
+            row.try_into()
+        })
+        .expect("Db must not fail")
+    }
+
+    pub fn list_labels(&mut self) -> Vec<DbLabel> {
+        db_query(
+            &mut self.conn,
+            "SELECT * FROM labels ORDER BY id",
+            rusqlite::params![],
+            |row| row.try_into(),
+        )
+        .expect("Db must not fail")
+    }
+
     // TODO: mark coinbase deposits that were mature and became immature as such.
     /// Unconfirm all data that was marked as being confirmed *after* the given chain
     /// tip, and set it as our new best block seen.
@@ -631,7 +832,8 @@ impl SqliteConn {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::database::{BlockInfo, DbBlockInfo};
+    use crate::config::DEFAULT_GAP_LIMIT;
+    use crate::database::{BlockInfo, DatabaseConnection, DbBlockInfo};
     use crate::testutils::*;
     use std::{
         collections::{HashMap, HashSet},
@@ -737,7 +939,7 @@ CREATE TABLE spend_transactions (
             .iter()
             .collect();
         let options = dummy_options();
-        let db = SqliteDb::new(db_path, Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(db_path, Some(options.clone()), DEFAULT_GAP_LIMIT, &secp).unwrap();
 
         (tmp_dir, options, secp, db)
     }
@@ -751,14 +953,22 @@ CREATE TABLE spend_transactions (
         let db_path: path::PathBuf = [tmp_dir.as_path(), path::Path::new("lianad.sqlite3")]
             .iter()
             .collect();
-        assert!(SqliteDb::new(db_path.clone(), None, &secp)
-            .unwrap_err()
-            .to_string()
-            .contains("database file not found"));
+        assert!(
+            SqliteDb::new(db_path.clone(), None, DEFAULT_GAP_LIMIT, &secp)
+                .unwrap_err()
+                .to_string()
+                .contains("database file not found")
+        );
 
         let options = dummy_options();
 
-        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(
+            db_path.clone(),
+            Some(options.clone()),
+            DEFAULT_GAP_LIMIT,
+            &secp,
+        )
+        .unwrap();
         db.sanity_check(bitcoin::Network::Testnet, &options.main_descriptor)
             .unwrap_err()
             .to_string()
@@ -766,7 +976,13 @@ CREATE TABLE spend_transactions (
         fs::remove_file(&db_path).unwrap();
         let other_desc_str = "wsh(andor(pk([aabbccdd]tpubDExU4YLJkyQ9RRbVScQq2brFxWWha7WmAUByPWyaWYwmcTv3Shx8aHp6mVwuE5n4TeM4z5DTWGf2YhNPmXtfvyr8cUDVvA3txdrFnFgNdF7/<0;1>/*),older(10000),pk([aabbccdd]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))";
         let other_desc = LianaDescriptor::from_str(other_desc_str).unwrap();
-        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(
+            db_path.clone(),
+            Some(options.clone()),
+            DEFAULT_GAP_LIMIT,
+            &secp,
+        )
+        .unwrap();
         db.sanity_check(bitcoin::Network::Bitcoin, &other_desc)
             .unwrap_err()
             .to_string()
@@ -774,10 +990,16 @@ CREATE TABLE spend_transactions (
         fs::remove_file(&db_path).unwrap();
         // TODO: version check
 
-        let db = SqliteDb::new(db_path.clone(), Some(options.clone()), &secp).unwrap();
+        let db = SqliteDb::new(
+            db_path.clone(),
+            Some(options.clone()),
+            DEFAULT_GAP_LIMIT,
+            &secp,
+        )
+        .unwrap();
         db.sanity_check(bitcoin::Network::Bitcoin, &options.main_descriptor)
             .unwrap();
-        let db = SqliteDb::new(db_path, None, &secp).unwrap();
+        let db = SqliteDb::new(db_path, None, DEFAULT_GAP_LIMIT, &secp).unwrap();
         db.sanity_check(bitcoin::Network::Bitcoin, &options.main_descriptor)
             .unwrap();
 
@@ -829,6 +1051,7 @@ CREATE TABLE spend_transactions (
                 )
                 .unwrap(),
                 is_immature: false,
+                is_frozen: false,
                 block_info: None,
                 amount: bitcoin::Amount::from_sat(98765),
                 derivation_index: bip32::ChildNumber::from_normal_idx(10).unwrap(),
@@ -862,6 +1085,7 @@ CREATE TABLE spend_transactions (
                 )
                 .unwrap(),
                 is_immature: false,
+                is_frozen: false,
                 block_info: None,
                 amount: bitcoin::Amount::from_sat(1111),
                 derivation_index: bip32::ChildNumber::from_normal_idx(103).unwrap(),
@@ -894,6 +1118,16 @@ CREATE TABLE spend_transactions (
             assert_eq!(conn.coins(CoinType::Unspent).len(), 2);
             assert!(conn.coins(CoinType::Spent).is_empty());
 
+            // We can freeze one, and unfreeze it.
+            assert!(conn.coins(CoinType::All).iter().all(|c| !c.is_frozen));
+            conn.set_frozen_coins(&[coin_b.outpoint], true);
+            let coins = conn.db_coins(&[coin_a.outpoint, coin_b.outpoint]);
+            assert!(coins
+                .iter()
+                .all(|c| c.is_frozen == (c.outpoint == coin_b.outpoint)));
+            conn.set_frozen_coins(&[coin_b.outpoint], false);
+            assert!(conn.coins(CoinType::All).iter().all(|c| !c.is_frozen));
+
             // Now if we confirm one, it'll be marked as such.
             let height = 174500;
             let time = 174500;
@@ -965,6 +1199,7 @@ CREATE TABLE spend_transactions (
                 )
                 .unwrap(),
                 is_immature: true,
+                is_frozen: false,
                 block_info: None,
                 amount: bitcoin::Amount::from_sat(424242),
                 derivation_index: bip32::ChildNumber::from_normal_idx(4103).unwrap(),
@@ -1097,6 +1332,40 @@ CREATE TABLE spend_transactions (
             conn.set_derivation_index(8.into(), true, &secp);
         }
 
+        // If the database is opened with a larger gap limit, the cache gets extended accordingly.
+        let db_path: path::PathBuf = [tmp_dir.as_path(), path::Path::new("lianad.sqlite3")]
+            .iter()
+            .collect();
+        let receive_addr = |index: u32| {
+            options
+                .main_descriptor
+                .receive_descriptor()
+                .derive(index.into(), &secp)
+                .address(options.bitcoind_network)
+        };
+        {
+            let db = SqliteDb::new(db_path.clone(), None, 300, &secp).unwrap();
+            let mut conn = db.connection().unwrap();
+            let db_addr = conn.db_address(&receive_addr(351)).unwrap();
+            assert_eq!(db_addr.derivation_index, 351.into());
+            assert!(conn.db_address(&receive_addr(352)).is_none());
+
+            // And the gap is maintained when the derivation index increases.
+            conn.set_derivation_index(60.into(), false, &secp);
+            let db_addr = conn.db_address(&receive_addr(359)).unwrap();
+            assert_eq!(db_addr.derivation_index, 359.into());
+            assert!(conn.db_address(&receive_addr(360)).is_none());
+        }
+
+        // Opening it with a smaller gap limit afterward is fine. The cache isn't pruned.
+        {
+            let db = SqliteDb::new(db_path, None, 10, &secp).unwrap();
+            let mut conn = db.connection().unwrap();
+            assert!(conn.db_address(&receive_addr(359)).is_some());
+            conn.set_derivation_index(61.into(), false, &secp);
+            assert!(conn.db_address(&receive_addr(360)).is_none());
+        }
+
         fs::remove_dir_all(tmp_dir).unwrap();
     }
 
@@ -1130,6 +1399,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: None,
                     amount: bitcoin::Amount::from_sat(98765),
                     derivation_index: bip32::ChildNumber::from_normal_idx(10).unwrap(),
@@ -1143,6 +1413,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_095,
                         time: 1_111_899,
@@ -1159,6 +1430,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_099,
                         time: 1_121_899,
@@ -1183,6 +1455,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_100,
                         time: 1_131_899,
@@ -1199,6 +1472,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_102,
                         time: 1_134_899,
@@ -1348,6 +1622,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: None,
                     amount: bitcoin::Amount::from_sat(98765),
                     derivation_index: bip32::ChildNumber::from_normal_idx(10).unwrap(),
@@ -1361,6 +1636,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_095,
                         time: 1_121_000,
@@ -1377,6 +1653,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_099,
                         time: 1_122_000,
@@ -1401,6 +1678,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: true,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_100,
                         time: 1_124_000,
@@ -1417,6 +1695,7 @@ CREATE TABLE spend_transactions (
                     )
                     .unwrap(),
                     is_immature: false,
+                    is_frozen: false,
                     block_info: Some(BlockInfo {
                         height: 101_102,
                         time: 1_125_000,
@@ -1497,7 +1776,194 @@ CREATE TABLE spend_transactions (
     }
 
     #[test]
-    fn v0_to_v2_migration() {
+    fn sqlite_transactions() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+
+            // A dummy transaction paying to a single output of the given value.
+            let dummy_tx = |value: u64| bitcoin::Transaction {
+                version: 2,
+                lock_time: bitcoin::absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn::default()],
+                output: vec![bitcoin::TxOut {
+                    script_pubkey: bitcoin::ScriptBuf::new(),
+                    value,
+                }],
+            };
+            let deposit_a = dummy_tx(10_000);
+            let deposit_b = dummy_tx(20_000);
+            let spend = dummy_tx(9_000);
+            let coin = |txid, block_info| Coin {
+                outpoint: bitcoin::OutPoint::new(txid, 0),
+                is_immature: false,
+                is_frozen: false,
+                block_info,
+                amount: bitcoin::Amount::from_sat(10_000),
+                derivation_index: bip32::ChildNumber::from_normal_idx(10).unwrap(),
+                is_change: false,
+                spend_txid: None,
+                spend_block: None,
+            };
+
+            // No coin, no transaction.
+            assert!(conn.db_list_missing_txids().is_empty());
+            assert!(conn.db_wallet_transactions(&[]).is_empty());
+
+            // Receive two coins, confirm one of them and spend it.
+            let coin_a = coin(
+                deposit_a.txid(),
+                Some(BlockInfo {
+                    height: 100,
+                    time: 1_100,
+                }),
+            );
+            let coin_b = coin(deposit_b.txid(), None);
+            conn.new_unspent_coins(&[coin_a, coin_b]);
+            conn.confirm_coins(&[(coin_a.outpoint, 100, 1_100)]);
+            conn.spend_coins(&[(coin_a.outpoint, spend.txid())]);
+            let missing: HashSet<_> = conn.db_list_missing_txids().into_iter().collect();
+            assert_eq!(
+                missing,
+                [deposit_a.txid(), deposit_b.txid(), spend.txid()]
+                    .iter()
+                    .cloned()
+                    .collect()
+            );
+            assert!(conn
+                .db_wallet_transactions(&[deposit_a.txid(), spend.txid()])
+                .is_empty());
+
+            // Once stored, they aren't missing anymore. Storing them twice is fine.
+            conn.new_txs(&[deposit_a.clone(), spend.clone()]);
+            conn.new_txs(std::slice::from_ref(&deposit_a));
+            assert_eq!(conn.db_list_missing_txids(), vec![deposit_b.txid()]);
+            conn.new_txs(std::slice::from_ref(&deposit_b));
+            assert!(conn.db_list_missing_txids().is_empty());
+
+            // They are returned along with the confirmation info of the coins. Unknown txids are
+            // ignored.
+            let db_txs = conn.db_wallet_transactions(&[
+                deposit_a.txid(),
+                deposit_b.txid(),
+                spend.txid(),
+                dummy_tx(1).txid(),
+            ]);
+            assert_eq!(db_txs.len(), 3);
+            let block_info = |tx: &bitcoin::Transaction| {
+                db_txs
+                    .iter()
+                    .find(|db_tx| &db_tx.transaction == tx)
+                    .unwrap()
+                    .block_info
+            };
+            assert_eq!(
+                block_info(&deposit_a),
+                Some(DbBlockInfo {
+                    height: 100,
+                    time: 1_100
+                })
+            );
+            assert_eq!(block_info(&deposit_b), None);
+            assert_eq!(block_info(&spend), None);
+
+            // Once the spend is confirmed, so is its transaction.
+            conn.confirm_spend(&[(coin_a.outpoint, spend.txid(), 101, 1_101)]);
+            let db_txs = conn.db_wallet_transactions(&[spend.txid()]);
+            assert_eq!(
+                db_txs[0].block_info,
+                Some(DbBlockInfo {
+                    height: 101,
+                    time: 1_101
+                })
+            );
+
+            // The transactions are returned in the requested order through the database
+            // interface.
+            let txs: Vec<_> = DatabaseConnection::list_wallet_transactions(
+                &mut conn,
+                &[spend.txid(), deposit_b.txid(), deposit_a.txid()],
+            )
+            .into_iter()
+            .map(|(tx, _)| tx)
+            .collect();
+            assert_eq!(txs, vec![spend, deposit_b, deposit_a]);
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_labels() {
+        let (tmp_dir, _, _, db) = dummy_db();
+
+        {
+            let mut conn = db.connection().unwrap();
+            let address = LabelItem::Address(
+                bitcoin::Address::from_str("bc1qvklensptw5lk7d470ds60pcpsr0psdpgyvwepv")
+                    .unwrap()
+                    .assume_checked(),
+            );
+            let txid = LabelItem::Txid(
+                bitcoin::Txid::from_str(
+                    "0c62a990d20d54429e70859292e82374ba6b1b951a3ab60f26bb65fee5724ff7",
+                )
+                .unwrap(),
+            );
+            let outpoint = LabelItem::OutPoint(
+                bitcoin::OutPoint::from_str(
+                    "0c62a990d20d54429e70859292e82374ba6b1b951a3ab60f26bb65fee5724ff7:1",
+                )
+                .unwrap(),
+            );
+            let all_items: HashSet<LabelItem> = [address.clone(), txid.clone(), outpoint.clone()]
+                .iter()
+                .cloned()
+                .collect();
+            assert!(conn.db_labels(&all_items).is_empty());
+            assert!(conn.list_labels().is_empty());
+
+            // Set a label on each item.
+            let mut items = HashMap::new();
+            items.insert(address.clone(), Some("address label".to_string()));
+            items.insert(txid.clone(), Some("txid label".to_string()));
+            items.insert(outpoint.clone(), Some("outpoint label".to_string()));
+            conn.update_labels(&items);
+            let db_labels = conn.db_labels(&all_items);
+            assert_eq!(db_labels.len(), 3);
+            let mut label = |item: &LabelItem| {
+                conn.db_labels(&[item.clone()].iter().cloned().collect())
+                    .pop()
+                    .map(|db_label| db_label.value)
+            };
+            assert_eq!(label(&address), Some("address label".to_string()));
+            assert_eq!(label(&txid), Some("txid label".to_string()));
+            assert_eq!(label(&outpoint), Some("outpoint label".to_string()));
+
+            // We can update a label, and remove another one.
+            let mut items = HashMap::new();
+            items.insert(txid.clone(), Some("new txid label".to_string()));
+            items.insert(outpoint.clone(), None);
+            conn.update_labels(&items);
+            let db_labels: HashMap<LabelItem, String> = conn
+                .list_labels()
+                .into_iter()
+                .map(|db_label| (db_label.item, db_label.value))
+                .collect();
+            assert_eq!(db_labels.len(), 2);
+            assert_eq!(db_labels[&address], "address label");
+            assert_eq!(db_labels[&txid], "new txid label");
+            assert!(conn
+                .db_labels(&[outpoint].iter().cloned().collect())
+                .is_empty());
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn v0_to_v5_migration() {
         let secp = secp256k1::Secp256k1::verification_only();
 
         // Create a database with version 0, using the old schema.
@@ -1510,7 +1976,7 @@ CREATE TABLE spend_transactions (
         let mut options = dummy_options();
         options.schema = V0_SCHEMA;
         options.version = 0;
-        create_fresh_db(&db_path, options, &secp).unwrap();
+        create_fresh_db(&db_path, options).unwrap();
 
         // Two PSBTs we'll insert in the DB before and after the migration. Note they are random
         // PSBTs taken from the descriptor unit tests, it doesn't matter.
@@ -1595,7 +2061,7 @@ CREATE TABLE spend_transactions (
         // Migrate the DB.
         maybe_apply_migration(&db_path).unwrap();
         maybe_apply_migration(&db_path).unwrap(); // Migrating twice will be a no-op.
-        let db = SqliteDb::new(db_path, None, &secp).unwrap();
+        let db = SqliteDb::new(db_path, None, DEFAULT_GAP_LIMIT, &secp).unwrap();
 
         // We should now be able to insert another PSBT, to query both, and the first PSBT must
         // have no associated timestamp.
@@ -1625,6 +2091,7 @@ CREATE TABLE spend_transactions (
                 )
                 .unwrap(),
                 is_immature: true,
+                is_frozen: false,
                 block_info: None,
                 amount: bitcoin::Amount::from_sat(98765),
                 derivation_index: bip32::ChildNumber::from_normal_idx(10).unwrap(),
@@ -1635,6 +2102,35 @@ CREATE TABLE spend_transactions (
             let coins = conn.coins(CoinType::All);
             assert_eq!(coins.len(), 3);
             assert_eq!(coins.iter().filter(|c| !c.is_immature).count(), 2);
+
+            // None of them is frozen, but they can be frozen now.
+            assert!(coins.iter().all(|c| !c.is_frozen));
+            conn.set_frozen_coins(&[coins[0].outpoint], true);
+            assert!(conn.db_coins(&[coins[0].outpoint])[0].is_frozen);
+        }
+
+        // We should now be able to set and query labels.
+        {
+            let mut conn = db.connection().unwrap();
+            let txid = LabelItem::Txid(first_psbt.unsigned_tx.txid());
+            let mut items = HashMap::new();
+            items.insert(txid.clone(), Some("first spend".to_string()));
+            conn.update_labels(&items);
+            let db_labels = conn.list_labels();
+            assert_eq!(db_labels.len(), 1);
+            assert_eq!(db_labels[0].item, txid);
+        }
+
+        // The transactions of the coins stored before the migration are missing, and we can
+        // store transactions.
+        {
+            let mut conn = db.connection().unwrap();
+            assert_eq!(conn.db_list_missing_txids().len(), 3);
+            let tx = first_psbt.unsigned_tx.clone();
+            conn.new_txs(std::slice::from_ref(&tx));
+            let db_txs = conn.db_wallet_transactions(&[tx.txid()]);
+            assert_eq!(db_txs.len(), 1);
+            assert_eq!(db_txs[0].transaction, tx);
         }
 
         fs::remove_dir_all(tmp_dir).unwrap();
@@ -1,4 +1,4 @@
-use crate::descriptors::LianaDescriptor;
+use crate::{database::LabelItem, descriptors::LianaDescriptor};
 
 use std::{convert::TryFrom, str::FromStr};
 
@@ -43,6 +43,9 @@ CREATE TABLE wallets (
  * The 'is_immature' field is for coinbase deposits that are not yet buried under 100
  * blocks. Note coinbase deposits can't be change. They also technically can't be
  * unconfirmed but we keep them as such until they become mature.
+ *
+ * The 'is_frozen' field is set by the user for coins that must never be spent
+ * unless explicitly requested.
  */
 CREATE TABLE coins (
     id INTEGER PRIMARY KEY NOT NULL,
@@ -58,6 +61,7 @@ CREATE TABLE coins (
     spend_block_height INTEGER,
     spend_block_time INTEGER,
     is_immature BOOLEAN NOT NULL CHECK (is_immature IN (0,1)),
+    is_frozen BOOLEAN NOT NULL CHECK (is_frozen IN (0,1)),
     CHECK (is_change IS 0 OR is_immature IS 0),
     UNIQUE (txid, vout),
     FOREIGN KEY (wallet_id) REFERENCES wallets (id)
@@ -81,6 +85,33 @@ CREATE TABLE spend_transactions (
     txid BLOB UNIQUE NOT NULL,
     updated_at INTEGER
 );
+
+/* The transactions which deposited to or spent our coins.
+ *
+ * The 'tx' field is the consensus-serialized transaction. Its confirmation
+ * info is the one recorded for the coins it deposited to or spent.
+ */
+CREATE TABLE transactions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    txid BLOB UNIQUE NOT NULL,
+    tx BLOB UNIQUE NOT NULL
+);
+
+/* Labels applied on addresses (0), txids (1) and outpoints (2).
+ *
+ * The 'item' is the string representation of the labelled item. It is unique,
+ * there can only be a single label per item.
+ */
+CREATE TABLE labels (
+    id INTEGER PRIMARY KEY NOT NULL,
+    wallet_id INTEGER NOT NULL,
+    item_kind INTEGER NOT NULL CHECK (item_kind IN (0,1,2)),
+    item TEXT UNIQUE NOT NULL,
+    value TEXT NOT NULL,
+    FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+        ON UPDATE RESTRICT
+        ON DELETE RESTRICT
+);
 ";
 
 /// A row in the "tip" table.
@@ -164,6 +195,8 @@ pub struct DbCoin {
     pub wallet_id: i64,
     /// Whether this coin was created by a yet-to-be-mature coinbase transaction.
     pub is_immature: bool,
+    /// Whether this coin was frozen by the user.
+    pub is_frozen: bool,
     pub outpoint: bitcoin::OutPoint,
     pub block_info: Option<DbBlockInfo>,
     pub amount: bitcoin::Amount,
@@ -214,11 +247,13 @@ impl TryFrom<&rusqlite::Row<'_>> for DbCoin {
             !is_immature || !is_change,
             "A coin cannot be both created in a coinbase and be change"
         );
+        let is_frozen: bool = row.get(13)?;
 
         Ok(DbCoin {
             id,
             wallet_id,
             is_immature,
+            is_frozen,
             outpoint,
             block_info,
             amount,
@@ -293,3 +328,94 @@ impl TryFrom<&rusqlite::Row<'_>> for DbSpendTransaction {
         })
     }
 }
+
+/// A row in the "transactions" table, along with the confirmation info recorded for the coins
+/// this transaction deposited to or spent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbWalletTransaction {
+    pub transaction: bitcoin::Transaction,
+    pub block_info: Option<DbBlockInfo>,
+}
+
+impl TryFrom<&rusqlite::Row<'_>> for DbWalletTransaction {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &rusqlite::Row) -> Result<Self, Self::Error> {
+        let transaction: Vec<u8> = row.get(0)?;
+        let transaction: bitcoin::Transaction =
+            encode::deserialize(&transaction).expect("We only store valid transactions");
+
+        let block_height: Option<i32> = row.get(1)?;
+        let block_time: Option<u32> = row.get(2)?;
+        assert_eq!(block_height.is_none(), block_time.is_none());
+        let block_info = match (block_height, block_time) {
+            (Some(height), Some(time)) => Some(DbBlockInfo { height, time }),
+            _ => None,
+        };
+
+        Ok(DbWalletTransaction {
+            transaction,
+            block_info,
+        })
+    }
+}
+
+/// The kind of an item in the "labels" table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbLabelledKind {
+    Address = 0,
+    Txid = 1,
+    OutPoint = 2,
+}
+
+impl From<&LabelItem> for DbLabelledKind {
+    fn from(item: &LabelItem) -> DbLabelledKind {
+        match item {
+            LabelItem::Address(..) => DbLabelledKind::Address,
+            LabelItem::Txid(..) => DbLabelledKind::Txid,
+            LabelItem::OutPoint(..) => DbLabelledKind::OutPoint,
+        }
+    }
+}
+
+/// A row in the "labels" table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbLabel {
+    pub id: i64,
+    pub wallet_id: i64,
+    pub item: LabelItem,
+    pub value: String,
+}
+
+impl TryFrom<&rusqlite::Row<'_>> for DbLabel {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &rusqlite::Row) -> Result<Self, Self::Error> {
+        let id: i64 = row.get(0)?;
+        let wallet_id: i64 = row.get(1)?;
+
+        let item_kind: i64 = row.get(2)?;
+        let item: String = row.get(3)?;
+        let item = if item_kind == DbLabelledKind::Address as i64 {
+            let address = bitcoin::Address::from_str(&item).expect("We only store valid addresses");
+            LabelItem::Address(address.assume_checked())
+        } else if item_kind == DbLabelledKind::Txid as i64 {
+            LabelItem::Txid(bitcoin::Txid::from_str(&item).expect("We only store valid txids"))
+        } else if item_kind == DbLabelledKind::OutPoint as i64 {
+            LabelItem::OutPoint(
+                bitcoin::OutPoint::from_str(&item).expect("We only store valid outpoints"),
+            )
+        } else {
+            unreachable!("Insane database: invalid label item kind")
+        };
+
+        let value: String = row.get(4)?;
+
+        Ok(DbLabel {
+            id,
+            wallet_id,
+            item,
+            value,
+        })
+    }
+}
@@ -2,10 +2,6 @@ use crate::database::sqlite::{FreshDbOptions, SqliteDbError, DB_VERSION};
 
 use std::{convert::TryInto, fs, path, time};
 
-use miniscript::bitcoin::secp256k1;
-
-pub const LOOK_AHEAD_LIMIT: u32 = 200;
-
 /// Perform a set of modifications to the database inside a single transaction
 pub fn db_exec<F>(conn: &mut rusqlite::Connection, modifications: F) -> Result<(), rusqlite::Error>
 where
@@ -83,35 +79,13 @@ pub fn create_db_file(db_path: &path::Path) -> Result<(), std::io::Error> {
 }
 
 /// Create a fresh Liana database with the given schema.
-pub fn create_fresh_db(
-    db_path: &path::Path,
-    options: FreshDbOptions,
-    secp: &secp256k1::Secp256k1<secp256k1::VerifyOnly>,
-) -> Result<(), SqliteDbError> {
+///
+/// The addresses cache is left empty, it's populated when opening the database.
+pub fn create_fresh_db(db_path: &path::Path, options: FreshDbOptions) -> Result<(), SqliteDbError> {
     create_db_file(db_path)?;
 
     let timestamp = curr_timestamp();
 
-    // Fill the initial addresses. On a fresh database, the deposit_derivation_index is
-    // necessarily 0.
-    let mut query = String::with_capacity(100 * LOOK_AHEAD_LIMIT as usize);
-    for index in 0..LOOK_AHEAD_LIMIT {
-        let receive_address = options
-            .main_descriptor
-            .receive_descriptor()
-            .derive(index.into(), secp)
-            .address(options.bitcoind_network);
-        let change_address = options
-            .main_descriptor
-            .change_descriptor()
-            .derive(index.into(), secp)
-            .address(options.bitcoind_network);
-        query += &format!(
-            "INSERT INTO addresses (receive_address, change_address, derivation_index) VALUES (\"{}\", \"{}\", {});\n",
-            receive_address, change_address, index
-        );
-    }
-
     let mut conn = rusqlite::Connection::open(db_path)?;
     db_exec(&mut conn, |tx| {
         tx.execute_batch(options.schema)?;
@@ -128,7 +102,6 @@ pub fn create_fresh_db(
                      VALUES (?1, ?2, ?3, ?4)",
             rusqlite::params![timestamp, options.main_descriptor.to_string(), 0, 0],
         )?;
-        tx.execute_batch(&query)?;
 
         Ok(())
     })?;
@@ -180,6 +153,62 @@ fn migrate_v1_to_v2(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError
     Ok(())
 }
 
+// After Liana 1.0 we upgraded the schema to store labels for addresses, txids and outpoints.
+fn migrate_v2_to_v3(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE labels (
+                id INTEGER PRIMARY KEY NOT NULL,
+                wallet_id INTEGER NOT NULL,
+                item_kind INTEGER NOT NULL CHECK (item_kind IN (0,1,2)),
+                item TEXT UNIQUE NOT NULL,
+                value TEXT NOT NULL,
+                FOREIGN KEY (wallet_id) REFERENCES wallets (id)
+                    ON UPDATE RESTRICT
+                    ON DELETE RESTRICT
+            )",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 3", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// After Liana 1.0 we upgraded the schema to record whether a coin was frozen by the user.
+fn migrate_v3_to_v4(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "ALTER TABLE coins ADD COLUMN is_frozen BOOLEAN NOT NULL DEFAULT 0 CHECK (is_frozen IN (0,1))",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 4", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+// After Liana 1.0 we upgraded the schema to store the transactions which deposited to or spent
+// our coins. They are fetched from the Bitcoin backend by the poller.
+fn migrate_v4_to_v5(conn: &mut rusqlite::Connection) -> Result<(), SqliteDbError> {
+    db_exec(conn, |tx| {
+        tx.execute(
+            "CREATE TABLE transactions (
+                id INTEGER PRIMARY KEY NOT NULL,
+                txid BLOB UNIQUE NOT NULL,
+                tx BLOB UNIQUE NOT NULL
+            )",
+            rusqlite::params![],
+        )?;
+        tx.execute("UPDATE version SET version = 5", rusqlite::params![])?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
 /// Check the database version and if necessary apply the migrations to upgrade it to the current
 /// one.
 pub fn maybe_apply_migration(db_path: &path::Path) -> Result<(), SqliteDbError> {
@@ -203,6 +232,21 @@ pub fn maybe_apply_migration(db_path: &path::Path) -> Result<(), SqliteDbError>
                 migrate_v1_to_v2(&mut conn)?;
                 log::warn!("Migration from database version 1 to version 2 successful.");
             }
+            2 => {
+                log::warn!("Upgrading database from version 2 to version 3.");
+                migrate_v2_to_v3(&mut conn)?;
+                log::warn!("Migration from database version 2 to version 3 successful.");
+            }
+            3 => {
+                log::warn!("Upgrading database from version 3 to version 4.");
+                migrate_v3_to_v4(&mut conn)?;
+                log::warn!("Migration from database version 3 to version 4 successful.");
+            }
+            4 => {
+                log::warn!("Upgrading database from version 4 to version 5.");
+                migrate_v4_to_v5(&mut conn)?;
+                log::warn!("Migration from database version 4 to version 5 successful.");
+            }
             _ => return Err(SqliteDbError::UnsupportedVersion(version)),
         }
     }
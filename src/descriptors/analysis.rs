@@ -1,16 +1,28 @@
 use miniscript::{
-    bitcoin::{bip32, Sequence},
+    bitcoin::{
+        bip32,
+        hashes::{sha256, Hash, HashEngine},
+        secp256k1, Sequence,
+    },
     descriptor,
     policy::{compiler, Concrete as ConcretePolicy, Liftable, Semantic as SemanticPolicy},
-    ScriptContext,
+    AbsLockTime, MiniscriptKey, ScriptContext,
 };
 
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
     convert::TryFrom,
     error, fmt,
+    sync::Arc,
 };
 
+// The x coordinate of the "H" point from BIP341, which was chosen such as nobody knows its
+// discrete logarithm. Used to create an unspendable internal key for Taproot descriptors.
+const UNSPENDABLE_KEY_X: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
 #[derive(Debug)]
 pub enum LianaPolicyError {
     MissingRecoveryPath,
@@ -23,6 +35,10 @@ pub enum LianaPolicyError {
     /// The spending policy is not a valid Miniscript policy: it may for instance be malleable, or
     /// overflow some limit.
     InvalidPolicy(compiler::CompilerError),
+    /// The Taproot tree could not be created from the spending paths.
+    InvalidTaprootTree(miniscript::Error),
+    /// Only the recovery paths may be behind a hashlock, and only behind a single one.
+    InvalidHashlock,
 }
 
 impl std::fmt::Display for LianaPolicyError {
@@ -49,6 +65,8 @@ impl std::fmt::Display for LianaPolicyError {
                 "Descriptor is not compatible with a Liana spending policy."
             ),
             Self::InvalidPolicy(e) => write!(f, "Invalid Miniscript policy: {}", e),
+            Self::InvalidTaprootTree(e) => write!(f, "Invalid Taproot tree: {}", e),
+            Self::InvalidHashlock => write!(f, "Only a recovery path may require a hash preimage, and it may only require a single one."),
         }
     }
 }
@@ -126,6 +144,50 @@ fn csv_check(csv_value: u32) -> Result<u16, LianaPolicyError> {
     }
 }
 
+// We require the absolute locktime to be positive and to fit in 31 bits, as Miniscript does. It
+// may either be a block height or a UNIX timestamp, which is signaled by its value.
+fn cltv_check(cltv_value: u32) -> Result<u32, LianaPolicyError> {
+    if cltv_value > 0 && cltv_value < 0x8000_0000 {
+        Ok(cltv_value)
+    } else {
+        Err(LianaPolicyError::InsaneTimelock(cltv_value))
+    }
+}
+
+// Get the timelock of a recovery path, if this policy node is a timelock.
+fn recovery_timelock<Pk: MiniscriptKey>(
+    policy: &SemanticPolicy<Pk>,
+) -> Option<Result<RecoveryTimelock, LianaPolicyError>> {
+    match policy {
+        SemanticPolicy::Older(val) => Some(csv_check(val.0).map(RecoveryTimelock::Relative)),
+        SemanticPolicy::After(val) => {
+            Some(cltv_check(val.to_consensus_u32()).map(RecoveryTimelock::Absolute))
+        }
+        _ => None,
+    }
+}
+
+// Get the timelock of the spending path described by this policy, if any. That is the first valid
+// timelock found in the policy tree.
+pub(super) fn path_timelock<Pk: MiniscriptKey>(
+    policy: &SemanticPolicy<Pk>,
+) -> Option<RecoveryTimelock> {
+    match policy {
+        SemanticPolicy::Threshold(_, subs) => subs.iter().find_map(path_timelock),
+        _ => recovery_timelock(policy).and_then(|tl| tl.ok()),
+    }
+}
+
+/// The timelock after which a recovery path is available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RecoveryTimelock {
+    /// A number of blocks after the coin confirmed (`older()`).
+    Relative(u16),
+    /// A block height or a UNIX timestamp (`after()`), as the nLockTime consensus value. Values
+    /// below 500 000 000 are block heights.
+    Absolute(u32),
+}
+
 // Get the fingerprint and the full derivation paths (path from the master fingerprint in the
 // origin, with the xpub derivation path appended) for a multipath xpub.
 fn key_origins(
@@ -151,11 +213,66 @@ fn key_origins(
     }
 }
 
+// Whether this key is an xpub for the unspendable "H" point, as used for the internal key of
+// Taproot descriptors whose primary path is not a single key.
+fn is_unspendable_key(key: &descriptor::DescriptorPublicKey) -> bool {
+    match key {
+        descriptor::DescriptorPublicKey::MultiXPub(ref xpub) => {
+            xpub.xkey.public_key.x_only_public_key().0.serialize() == UNSPENDABLE_KEY_X
+        }
+        _ => false,
+    }
+}
+
+// Get an unspendable internal key for a Taproot descriptor containing these keys. It's an xpub
+// for the "H" point, with a chain code derived from the other keys such as each derived internal
+// key is different and is not recognizable as unspendable by third parties.
+fn unspendable_internal_key<'a>(
+    keys: impl Iterator<Item = &'a descriptor::DescriptorPublicKey>,
+) -> descriptor::DescriptorPublicKey {
+    let mut network = None;
+    let mut engine = sha256::Hash::engine();
+    for key in keys {
+        if let descriptor::DescriptorPublicKey::MultiXPub(ref xpub) = key {
+            network.get_or_insert(xpub.xkey.network);
+            engine.input(&xpub.xkey.encode());
+        }
+    }
+    let chain_code = sha256::Hash::from_engine(engine).to_byte_array();
+
+    let mut key_bytes = [0x02; 33];
+    key_bytes[1..].copy_from_slice(&UNSPENDABLE_KEY_X);
+    let xkey = bip32::ExtendedPubKey {
+        network: network.expect("There is always at least one key"),
+        depth: 0,
+        parent_fingerprint: bip32::Fingerprint::default(),
+        child_number: bip32::ChildNumber::from_normal_idx(0).expect("Not hardened"),
+        public_key: secp256k1::PublicKey::from_slice(&key_bytes).expect("Valid point"),
+        chain_code: bip32::ChainCode::from(chain_code),
+    };
+    let derivation_paths = descriptor::DerivPaths::new(
+        [0, 1]
+            .iter()
+            .map(|i| vec![bip32::ChildNumber::from_normal_idx(*i).expect("Not hardened")].into())
+            .collect(),
+    )
+    .expect("Not empty");
+    descriptor::DescriptorPublicKey::MultiXPub(descriptor::DescriptorMultiXKey {
+        origin: None,
+        xkey,
+        derivation_paths,
+        wildcard: descriptor::Wildcard::Unhardened,
+    })
+}
+
 /// Information about a single spending path in the descriptor.
 #[derive(Debug, Eq, PartialEq, Clone, Ord, PartialOrd, Hash)]
 pub enum PathInfo {
     Single(descriptor::DescriptorPublicKey),
     Multi(usize, Vec<descriptor::DescriptorPublicKey>),
+    /// The keys of the inner path along with the preimage of this SHA256 hash. Only allowed in
+    /// recovery paths.
+    Hashlock(sha256::Hash, Box<PathInfo>),
 }
 
 impl PathInfo {
@@ -183,60 +300,69 @@ impl PathInfo {
 
     /// Get the information about the recovery spending path.
     /// Returns None if the policy does not describe the recovery spending path of a Liana
-    /// descriptor (that is, a set of keys after a timelock).
+    /// descriptor (that is, a set of keys after a timelock, possibly along with a hashlock).
     pub fn from_recovery_path(
         policy: SemanticPolicy<descriptor::DescriptorPublicKey>,
-    ) -> Result<(u16, PathInfo), LianaPolicyError> {
+    ) -> Result<(RecoveryTimelock, PathInfo), LianaPolicyError> {
         // The recovery spending path must always be a policy of type `thresh(2, older(x), thresh(n, key1,
         // key2, ..))`. In the special case n == 1, it is only `thresh(2, older(x), key)`. In the
         // special case n == len(keys) (i.e. it's an N-of-N multisig), it is normalized as
-        // `thresh(n+1, older(x), key1, key2, ...)`.
+        // `thresh(n+1, older(x), key1, key2, ...)`. The timelock may also be an absolute one,
+        // `after(x)`, instead of `older(x)`. If the path is also behind a hashlock, there is an
+        // additional `sha256(h)` sub and the threshold is incremented.
+        // In all cases all the subs are required, and all but the timelock and the hashlock are
+        // either a single (multi)key check or keys.
         let (k, subs) = match policy {
-            SemanticPolicy::Threshold(k, subs) => (k, subs),
+            SemanticPolicy::Threshold(k, subs) if k == subs.len() => (k, subs),
             _ => return Err(LianaPolicyError::IncompatibleDesc),
         };
-        if k == 2 && subs.len() == 2 {
-            // The general case (as well as the n == 1 case). The sub that is not the timelock is
-            // of the same form as a primary path.
-            let tl_value = subs
-                .iter()
-                .find_map(|s| match s {
-                    SemanticPolicy::Older(val) => Some(csv_check(val.0)),
-                    _ => None,
-                })
-                .ok_or(LianaPolicyError::IncompatibleDesc)??;
-            let keys_sub = subs
-                .into_iter()
-                .find(is_single_key_or_multisig)
-                .ok_or(LianaPolicyError::IncompatibleDesc)?;
-            PathInfo::from_primary_path(keys_sub).map(|info| (tl_value, info))
-        } else if k == subs.len() && subs.len() > 2 {
-            // The N-of-N case. All subs but the threshold must be keys (if one had been thresh()
-            // of keys it would have been normalized).
-            let mut tl_value = None;
-            let mut keys = Vec::with_capacity(subs.len());
-            for sub in subs {
-                match sub {
-                    SemanticPolicy::Key(key) => keys.push(key),
-                    SemanticPolicy::Older(val) => {
-                        if tl_value.is_some() {
-                            return Err(LianaPolicyError::IncompatibleDesc);
-                        }
-                        tl_value = Some(csv_check(val.0)?);
-                    }
-                    _ => return Err(LianaPolicyError::IncompatibleDesc),
+        let (mut tl_value, mut hashlock) = (None, None);
+        let mut keys_subs = Vec::with_capacity(k);
+        for sub in subs {
+            if let Some(timelock) = recovery_timelock(&sub) {
+                if tl_value.is_some() {
+                    return Err(LianaPolicyError::IncompatibleDesc);
                 }
+                tl_value = Some(timelock?);
+            } else if let SemanticPolicy::Sha256(hash) = sub {
+                if hashlock.is_some() {
+                    return Err(LianaPolicyError::IncompatibleDesc);
+                }
+                hashlock = Some(hash);
+            } else {
+                keys_subs.push(sub);
             }
-            assert!(keys.len() > 1); // At least 3 subs, only one of which may be older().
-            Ok((
-                tl_value.ok_or(LianaPolicyError::IncompatibleDesc)?,
-                PathInfo::Multi(k - 1, keys),
-            ))
-        } else {
-            // If there is less than 2 subs, there can't be both a timelock and keys. If the
-            // threshold is not equal to the number of subs, the timelock can't be mandatory.
-            Err(LianaPolicyError::IncompatibleDesc)
         }
+        let tl_value = tl_value.ok_or(LianaPolicyError::IncompatibleDesc)?;
+
+        // If there is a single keys sub it is of the same form as a primary path. Otherwise it's
+        // the N-of-N case: all of them must be keys (if one had been thresh() of keys it would have
+        // been normalized).
+        let path_info = if keys_subs.len() == 1 {
+            let keys_sub = keys_subs.pop().expect("Just checked the length");
+            if !is_single_key_or_multisig(&keys_sub) {
+                return Err(LianaPolicyError::IncompatibleDesc);
+            }
+            PathInfo::from_primary_path(keys_sub)?
+        } else if keys_subs.len() > 1 {
+            let keys = keys_subs
+                .into_iter()
+                .map(|sub| match sub {
+                    SemanticPolicy::Key(key) => Ok(key),
+                    _ => Err(LianaPolicyError::IncompatibleDesc),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            PathInfo::Multi(keys.len(), keys)
+        } else {
+            // There must be keys in a recovery path.
+            return Err(LianaPolicyError::IncompatibleDesc);
+        };
+
+        let path_info = match hashlock {
+            Some(hash) => PathInfo::Hashlock(hash, Box::new(path_info)),
+            None => path_info,
+        };
+        Ok((tl_value, path_info))
     }
 
     /// Add another available key to this `PathInfo`. Note this doesn't change the threshold.
@@ -247,6 +373,15 @@ impl PathInfo {
                 keys.push(key);
                 self
             }
+            Self::Hashlock(hash, path) => Self::Hashlock(hash, Box::new(path.with_added_key(key))),
+        }
+    }
+
+    /// The hash whose SHA256 preimage is required to spend through this path, if any.
+    pub fn hashlock(&self) -> Option<&sha256::Hash> {
+        match self {
+            Self::Hashlock(hash, _) => Some(hash),
+            _ => None,
         }
     }
 
@@ -281,11 +416,13 @@ impl PathInfo {
                 }
                 (*k, all_origins)
             }
+            PathInfo::Hashlock(_, path) => path.thresh_origins(),
         }
     }
 
     /// Get the spend information for this descriptor based from the list of all pubkeys that
-    /// signed the transaction.
+    /// signed the transaction. Whether the hash preimage was provided is not known here, it is
+    /// always assumed to be missing.
     pub fn spend_info<'a>(
         &self,
         all_pubkeys_signed: impl Iterator<Item = &'a (bip32::Fingerprint, bip32::DerivationPath)>,
@@ -326,6 +463,16 @@ impl PathInfo {
             threshold,
             sigs_count,
             signed_pubkeys,
+            hashlock: self.hashlock().map(|hash| (*hash, false)),
+        }
+    }
+
+    // All the keys in this path.
+    fn keys(&self) -> Vec<&descriptor::DescriptorPublicKey> {
+        match self {
+            PathInfo::Single(key) => vec![key],
+            PathInfo::Multi(_, keys) => keys.iter().collect(),
+            PathInfo::Hashlock(_, path) => path.keys(),
         }
     }
 
@@ -337,6 +484,9 @@ impl PathInfo {
                 thresh,
                 keys.into_iter().map(ConcretePolicy::Key).collect(),
             ),
+            PathInfo::Hashlock(hash, path) => {
+                ConcretePolicy::And(vec![path.into_ms_policy(), ConcretePolicy::Sha256(hash)])
+            }
         }
     }
 }
@@ -344,16 +494,20 @@ impl PathInfo {
 /// A Liana spending policy is one composed of at least two spending paths:
 ///     - A directly available path with any number of keys checks; or
 ///     - One or more recovery paths with any number of keys checks, behind increasing relative
-///     timelocks. No two recovery paths may have the same timelock.
+///     timelocks or absolute timelocks. No two recovery paths may have the same timelock.
 /// A Liana policy can be created from some settings (the primary and recovery keys, the
 /// timelock(s)) and be used to derive a descriptor. It can also be inferred from a descriptor and
 /// be used to retrieve the settings.
+/// The descriptor is either a P2WSH or a Taproot one. In the latter case the primary path is the
+/// key path spend if it is a single key, and each other spending path is a script leaf.
 /// Do note however that the descriptor generation process is not deterministic, therefore you
 /// **cannot roundtrip** a descriptor through a `LianaPolicy`.
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct LianaPolicy {
     pub(super) primary_path: PathInfo,
     pub(super) recovery_paths: BTreeMap<u16, PathInfo>,
+    pub(super) abs_recovery_paths: BTreeMap<u32, PathInfo>,
+    pub(super) is_taproot: bool,
 }
 
 impl LianaPolicy {
@@ -362,7 +516,38 @@ impl LianaPolicy {
         primary_path: PathInfo,
         recovery_paths: BTreeMap<u16, PathInfo>,
     ) -> Result<LianaPolicy, LianaPolicyError> {
-        if recovery_paths.is_empty() {
+        LianaPolicy::new_with_abs_timelocks(primary_path, recovery_paths, BTreeMap::new())
+    }
+
+    /// Create a new Liana policy from a given configuration, with recovery paths behind relative
+    /// timelocks (in blocks) and recovery paths behind absolute timelocks (block heights or UNIX
+    /// timestamps, as nLockTime values).
+    pub fn new_with_abs_timelocks(
+        primary_path: PathInfo,
+        recovery_paths: BTreeMap<u16, PathInfo>,
+        abs_recovery_paths: BTreeMap<u32, PathInfo>,
+    ) -> Result<LianaPolicy, LianaPolicyError> {
+        LianaPolicy::new_inner(primary_path, recovery_paths, abs_recovery_paths, false)
+    }
+
+    /// Create a new Liana policy from a given configuration, for use in a Taproot descriptor.
+    /// If the primary path is a single key it is used as the internal key. Otherwise the internal
+    /// key is unspendable and the primary path is a script leaf, like the recovery paths.
+    pub fn new_taproot(
+        primary_path: PathInfo,
+        recovery_paths: BTreeMap<u16, PathInfo>,
+        abs_recovery_paths: BTreeMap<u32, PathInfo>,
+    ) -> Result<LianaPolicy, LianaPolicyError> {
+        LianaPolicy::new_inner(primary_path, recovery_paths, abs_recovery_paths, true)
+    }
+
+    fn new_inner(
+        primary_path: PathInfo,
+        recovery_paths: BTreeMap<u16, PathInfo>,
+        abs_recovery_paths: BTreeMap<u32, PathInfo>,
+        is_taproot: bool,
+    ) -> Result<LianaPolicy, LianaPolicyError> {
+        if recovery_paths.is_empty() && abs_recovery_paths.is_empty() {
             return Err(LianaPolicyError::MissingRecoveryPath);
         }
 
@@ -376,23 +561,36 @@ impl LianaPolicy {
         if recovery_paths.contains_key(&0) {
             return Err(LianaPolicyError::InsaneTimelock(0));
         }
+        for abs_timelock in abs_recovery_paths.keys() {
+            cltv_check(*abs_timelock)?;
+        }
+
+        // Only the recovery paths may be behind a hashlock, and a single one.
+        let recov_paths = || recovery_paths.values().chain(abs_recovery_paths.values());
+        let nested_hashlock = |path: &PathInfo| match path {
+            PathInfo::Hashlock(_, path) => path.hashlock().is_some(),
+            _ => false,
+        };
+        if primary_path.hashlock().is_some() || recov_paths().any(nested_hashlock) {
+            return Err(LianaPolicyError::InvalidHashlock);
+        }
 
         // Check all keys are valid according to our standard (this checks all are multipath keys).
         // Note while the Miniscript compiler does check for duplicate, it does so at the
         // "descriptor key expression" level. We don't want duplicate xpubs at all so we do it
         // ourselves here.
-        let spending_paths = recovery_paths
-            .values()
-            .chain(std::iter::once(&primary_path));
+        // In a Taproot descriptor each spending path is a separate script, therefore the same
+        // keys may be reused across paths. This allows for instance for a multisig whose
+        // threshold decreases over time. In a P2WSH descriptor all the keys must be distinct,
+        // the same xpub may only be reused with different derivation paths.
+        let spending_paths = recov_paths().chain(std::iter::once(&primary_path));
         let mut key_checker = DescKeyChecker::new();
         for path in spending_paths {
-            match path {
-                PathInfo::Single(ref key) => key_checker.check(key)?,
-                PathInfo::Multi(_, ref keys) => {
-                    for key in keys {
-                        key_checker.check(key)?
-                    }
-                }
+            if is_taproot {
+                key_checker = DescKeyChecker::new();
+            }
+            for key in path.keys() {
+                key_checker.check(key)?;
             }
         }
 
@@ -400,32 +598,46 @@ impl LianaPolicy {
         let policy = LianaPolicy {
             primary_path,
             recovery_paths,
+            abs_recovery_paths,
+            is_taproot,
         };
-        policy.clone().into_miniscript()?;
+        policy.clone().into_descriptor()?;
         Ok(policy)
     }
 
     /// Create a Liana policy from a descriptor. This will check the descriptor is correctly formed
-    /// (P2WSH, multipath, ..) and has a valid Liana semantic.
+    /// (P2WSH or Taproot, multipath, ..) and has a valid Liana semantic.
     pub fn from_multipath_descriptor(
         desc: &descriptor::Descriptor<descriptor::DescriptorPublicKey>,
     ) -> Result<LianaPolicy, LianaPolicyError> {
-        // For now we only allow P2WSH descriptors.
-        let wsh_desc = match &desc {
-            descriptor::Descriptor::Wsh(desc) => desc,
-            _ => return Err(LianaPolicyError::IncompatibleDesc),
-        };
-
-        // Lift a semantic policy out of this Miniscript and normalize it to make sure we compare
+        // Lift a semantic policy out of this descriptor and normalize it to make sure we compare
         // apples to apples below.
-        let ms = match wsh_desc.as_inner() {
-            descriptor::WshInner::Ms(ms) => ms,
+        let (policy, is_taproot) = match &desc {
+            descriptor::Descriptor::Wsh(wsh_desc) => {
+                let ms = match wsh_desc.as_inner() {
+                    descriptor::WshInner::Ms(ms) => ms,
+                    _ => return Err(LianaPolicyError::IncompatibleDesc),
+                };
+                (
+                    ms.lift().expect("Lifting can't fail on a Miniscript"),
+                    false,
+                )
+            }
+            descriptor::Descriptor::Tr(tr_desc) => {
+                // The key path is the primary path, unless the internal key is unspendable. In
+                // this case the primary path must be in the tree.
+                let mut subs = Vec::with_capacity(2);
+                if !is_unspendable_key(tr_desc.internal_key()) {
+                    subs.push(SemanticPolicy::Key(tr_desc.internal_key().clone()));
+                }
+                if let Some(tree) = tr_desc.taptree() {
+                    subs.push(tree.lift().expect("Lifting can't fail on a Miniscript"));
+                }
+                (SemanticPolicy::Threshold(1, subs), true)
+            }
             _ => return Err(LianaPolicyError::IncompatibleDesc),
         };
-        let policy = ms
-            .lift()
-            .expect("Lifting can't fail on a Miniscript")
-            .normalized();
+        let policy = policy.normalized();
 
         // The policy must always be "1 of N spending paths" with at least an always-available
         // primary path with at least one key, and at least one timelocked recovery path with at
@@ -438,7 +650,8 @@ impl LianaPolicy {
 
         // Fetch the two spending paths' semantic policies. The primary path is identified as the
         // only one that isn't timelocked.
-        let (mut primary_path, mut recovery_paths) = (None::<PathInfo>, BTreeMap::new());
+        let (mut primary_path, mut recovery_paths, mut abs_recovery_paths) =
+            (None::<PathInfo>, BTreeMap::new(), BTreeMap::new());
         for sub in subs {
             // This is a (multi)key check. It must be the primary path.
             if is_single_key_or_multisig(&sub) {
@@ -458,31 +671,71 @@ impl LianaPolicy {
             } else {
                 // If it's not a simple (multi)key check, it must be the timelocked recovery path.
                 let (timelock, path_info) = PathInfo::from_recovery_path(sub)?;
-                if recovery_paths.contains_key(&timelock) {
+                let duplicate = match timelock {
+                    RecoveryTimelock::Relative(tl) => {
+                        recovery_paths.insert(tl, path_info).is_some()
+                    }
+                    RecoveryTimelock::Absolute(tl) => {
+                        abs_recovery_paths.insert(tl, path_info).is_some()
+                    }
+                };
+                if duplicate {
                     return Err(LianaPolicyError::IncompatibleDesc);
                 }
-                recovery_paths.insert(timelock, path_info);
             }
         }
 
         // Use the constructor for sanity checking the keys and the Miniscript policy. Note this
-        // makes sure the recovery paths mapping isn't empty, too.
+        // makes sure there is at least one recovery path, too.
         let prim_path = primary_path.ok_or(LianaPolicyError::IncompatibleDesc)?;
-        LianaPolicy::new(prim_path, recovery_paths)
+        LianaPolicy::new_inner(prim_path, recovery_paths, abs_recovery_paths, is_taproot)
     }
 
     pub fn primary_path(&self) -> &PathInfo {
         &self.primary_path
     }
 
-    /// Timelocks and path info of the recovery paths. Note we guarantee this mapping is never
-    /// empty, as there is always at least one recovery path.
+    /// Relative timelocks (in blocks) and path info of the recovery paths. Note this mapping may
+    /// be empty if all the recovery paths are behind absolute timelocks.
     pub fn recovery_paths(&self) -> &BTreeMap<u16, PathInfo> {
-        assert!(!self.recovery_paths.is_empty());
         &self.recovery_paths
     }
 
-    fn into_miniscript(
+    /// Absolute timelocks (as nLockTime values) and path info of the recovery paths.
+    pub fn abs_recovery_paths(&self) -> &BTreeMap<u32, PathInfo> {
+        &self.abs_recovery_paths
+    }
+
+    /// Whether this policy is used in a Taproot descriptor, as opposed to a P2WSH one.
+    pub fn is_taproot(&self) -> bool {
+        self.is_taproot
+    }
+
+    // The Miniscript policies of all the recovery paths, by increasing timelock. The relative
+    // timelocks come first.
+    fn recovery_policies(
+        recovery_paths: BTreeMap<u16, PathInfo>,
+        abs_recovery_paths: BTreeMap<u32, PathInfo>,
+    ) -> impl Iterator<Item = ConcretePolicy<descriptor::DescriptorPublicKey>> {
+        let rel_paths = recovery_paths.into_iter().map(|(timelock, path_info)| {
+            (
+                ConcretePolicy::Older(Sequence::from_height(timelock)),
+                path_info,
+            )
+        });
+        let abs_paths = abs_recovery_paths.into_iter().map(|(timelock, path_info)| {
+            (
+                ConcretePolicy::After(AbsLockTime::from_consensus(timelock)),
+                path_info,
+            )
+        });
+        rel_paths.chain(abs_paths).map(|(timelock, path_info)| {
+            let keys = path_info.into_ms_policy();
+            ConcretePolicy::And(vec![keys, timelock])
+        })
+    }
+
+    fn into_wsh_miniscript(
         self,
     ) -> Result<
         miniscript::Miniscript<descriptor::DescriptorPublicKey, miniscript::Segwitv0>,
@@ -491,29 +744,96 @@ impl LianaPolicy {
         let LianaPolicy {
             primary_path,
             recovery_paths,
+            abs_recovery_paths,
+            ..
         } = self;
 
         // Start with the primary spending path. We'll then or() all the recovery paths to it.
         let primary_keys = primary_path.into_ms_policy();
 
         // Incrementally create the top-level policy using all recovery paths.
-        assert!(!recovery_paths.is_empty());
-        let tl_policy =
-            recovery_paths
-                .into_iter()
-                .fold(primary_keys, |tl_policy, (timelock, path_info)| {
-                    let timelock = ConcretePolicy::Older(Sequence::from_height(timelock));
-                    let keys = path_info.into_ms_policy();
-                    let recovery_branch = ConcretePolicy::And(vec![keys, timelock]);
-                    // We assume the larger the timelock the less likely a branch would be used.
-                    ConcretePolicy::Or(vec![(99, tl_policy), (1, recovery_branch)])
-                });
+        assert!(!recovery_paths.is_empty() || !abs_recovery_paths.is_empty());
+        let tl_policy = LianaPolicy::recovery_policies(recovery_paths, abs_recovery_paths).fold(
+            primary_keys,
+            |tl_policy, recovery_branch| {
+                // We assume the larger the timelock the less likely a branch would be used.
+                ConcretePolicy::Or(vec![(99, tl_policy), (1, recovery_branch)])
+            },
+        );
 
         tl_policy
             .compile::<miniscript::Segwitv0>()
             .map_err(LianaPolicyError::InvalidPolicy)
     }
 
+    fn into_tr(self) -> Result<descriptor::Tr<descriptor::DescriptorPublicKey>, LianaPolicyError> {
+        let LianaPolicy {
+            primary_path,
+            recovery_paths,
+            abs_recovery_paths,
+            ..
+        } = self;
+
+        // A single key primary path is the key path spend. Otherwise use an unspendable internal
+        // key and make the primary path the first leaf.
+        let mut leaves_policies = Vec::with_capacity(recovery_paths.len() + 1);
+        let internal_key = match primary_path {
+            PathInfo::Single(key) => key,
+            PathInfo::Multi(..) | PathInfo::Hashlock(..) => {
+                let internal_key = unspendable_internal_key(
+                    std::iter::once(&primary_path)
+                        .chain(recovery_paths.values())
+                        .chain(abs_recovery_paths.values())
+                        .flat_map(|path| path.keys()),
+                );
+                leaves_policies.push(primary_path.into_ms_policy());
+                internal_key
+            }
+        };
+        assert!(!recovery_paths.is_empty() || !abs_recovery_paths.is_empty());
+        leaves_policies.extend(LianaPolicy::recovery_policies(
+            recovery_paths,
+            abs_recovery_paths,
+        ));
+
+        // Create the tree with the leaves by order of likelihood of being used, the first one
+        // being the shallowest.
+        let mut leaves = leaves_policies
+            .into_iter()
+            .map(|policy| {
+                policy
+                    .compile::<miniscript::Tap>()
+                    .map(|ms| descriptor::TapTree::Leaf(Arc::new(ms)))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(LianaPolicyError::InvalidPolicy)?;
+        let mut tree = leaves
+            .pop()
+            .expect("There is always at least one recovery path");
+        while let Some(leaf) = leaves.pop() {
+            tree = descriptor::TapTree::Tree(Arc::new(leaf), Arc::new(tree));
+        }
+
+        descriptor::Tr::new(internal_key, Some(tree)).map_err(LianaPolicyError::InvalidTaprootTree)
+    }
+
+    fn into_descriptor(
+        self,
+    ) -> Result<descriptor::Descriptor<descriptor::DescriptorPublicKey>, LianaPolicyError> {
+        if self.is_taproot {
+            let tr = self.into_tr()?;
+            tr.sanity_check()
+                .map_err(LianaPolicyError::InvalidTaprootTree)?;
+            Ok(descriptor::Descriptor::Tr(tr))
+        } else {
+            let ms = self.into_wsh_miniscript()?;
+            miniscript::Segwitv0::check_local_validity(&ms).expect("Miniscript must be sane");
+            Ok(descriptor::Descriptor::Wsh(
+                descriptor::Wsh::new(ms).expect("Must pass sanity checks"),
+            ))
+        }
+    }
+
     /// Create a descriptor from this spending policy with multipath key expressions. Note this
     /// involves a Miniscript policy compilation: this function is **not deterministic**. If you
     /// are inferring a `LianaPolicy` from a descriptor, generating a descriptor from this
@@ -521,11 +841,8 @@ impl LianaPolicy {
     pub fn into_multipath_descriptor(
         self,
     ) -> descriptor::Descriptor<descriptor::DescriptorPublicKey> {
-        let ms = self
-            .into_miniscript()
-            .expect("This is always checked when creating a LianaPolicy.");
-        miniscript::Segwitv0::check_local_validity(&ms).expect("Miniscript must be sane");
-        descriptor::Descriptor::Wsh(descriptor::Wsh::new(ms).expect("Must pass sanity checks"))
+        self.into_descriptor()
+            .expect("This is always checked when creating a LianaPolicy.")
     }
 }
 
@@ -539,6 +856,8 @@ pub struct PathSpendInfo {
     /// The keys for which a signature was provided and the number (always >=1) of
     /// signatures provided for this key.
     pub signed_pubkeys: HashMap<bip32::Fingerprint, usize>,
+    /// For a path behind a hashlock, the hash and whether its preimage was provided.
+    pub hashlock: Option<(sha256::Hash, bool)>,
 }
 
 /// Information about a partial spend of Liana coins
@@ -549,6 +868,9 @@ pub struct PartialSpendInfo {
     /// Number of signatures present for the recovery path, only present for the recovery paths
     /// that are available.
     pub(super) recovery_paths: BTreeMap<u16, PathSpendInfo>,
+    /// Number of signatures present for the recovery paths behind an absolute timelock, only
+    /// present for the recovery paths that are available.
+    pub(super) abs_recovery_paths: BTreeMap<u32, PathSpendInfo>,
 }
 
 impl PartialSpendInfo {
@@ -562,4 +884,10 @@ impl PartialSpendInfo {
     pub fn recovery_paths(&self) -> &BTreeMap<u16, PathSpendInfo> {
         &self.recovery_paths
     }
+
+    /// Get the number of signatures present for each recovery path behind an absolute timelock.
+    /// Only present for available paths.
+    pub fn abs_recovery_paths(&self) -> &BTreeMap<u32, PathSpendInfo> {
+        &self.abs_recovery_paths
+    }
 }
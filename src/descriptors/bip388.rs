@@ -0,0 +1,390 @@
+//! BIP388 wallet policies.
+//!
+//! Convert a Liana descriptor to and from a wallet policy: a descriptor template in which each key
+//! expression is replaced by a placeholder, along with the vector of keys the placeholders refer
+//! to. This is how descriptors are registered on signing devices and exchanged between
+//! coordinators. See https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki.
+
+use crate::descriptors::{LianaDescError, LianaDescriptor};
+
+use std::{error, fmt, str::FromStr};
+
+use miniscript::{
+    bitcoin::{
+        bip32,
+        hashes::{hash160, ripemd160, sha256},
+    },
+    descriptor, hash256, TranslatePk, Translator,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug)]
+pub enum WalletPolicyError {
+    /// This key can't be expressed as a placeholder, as it contains derivation steps other than
+    /// the multipath step.
+    UnsupportedKey(Box<descriptor::DescriptorPublicKey>),
+    /// A key information must be an xpub, along with its origin if any.
+    InvalidKeyInfo(Box<descriptor::DescriptorPublicKey>),
+    /// A key placeholder in the template is malformed.
+    InvalidPlaceholder(String),
+    /// A placeholder refers to a key which isn't part of the key information vector.
+    MissingKeyInfo(usize),
+    /// A key of the key information vector isn't used in the template.
+    UnusedKeyInfo(usize),
+    /// The placeholders don't appear in order in the template.
+    PlaceholdersOrder,
+    Descriptor(LianaDescError),
+}
+
+impl fmt::Display for WalletPolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnsupportedKey(key) => write!(
+                f,
+                "Key '{}' contains derivation steps other than the multipath step.",
+                key
+            ),
+            Self::InvalidKeyInfo(key) => write!(
+                f,
+                "Invalid key information '{}': must be an xpub without derivation steps.",
+                key
+            ),
+            Self::InvalidPlaceholder(placeholder) => {
+                write!(f, "Invalid key placeholder '{}'.", placeholder)
+            }
+            Self::MissingKeyInfo(index) => {
+                write!(f, "No key information for placeholder '@{}'.", index)
+            }
+            Self::UnusedKeyInfo(index) => {
+                write!(f, "Key information at index {} isn't used.", index)
+            }
+            Self::PlaceholdersOrder => write!(
+                f,
+                "Key placeholders must appear for the first time in increasing order."
+            ),
+            Self::Descriptor(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for WalletPolicyError {}
+
+impl From<LianaDescError> for WalletPolicyError {
+    fn from(e: LianaDescError) -> WalletPolicyError {
+        WalletPolicyError::Descriptor(e)
+    }
+}
+
+/// A BIP388 wallet policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletPolicy {
+    /// The descriptor, with each key expression replaced by a placeholder such as `@0/**`.
+    pub descriptor_template: String,
+    /// The key that each placeholder refers to, by index. These are xpubs along with their origin
+    /// and without any derivation step.
+    pub keys_info: Vec<descriptor::DescriptorPublicKey>,
+}
+
+// Replace the keys of a descriptor by placeholders, recording the key information as we go. Note
+// the keys aren't necessarily visited in the order they appear in the descriptor, so the indexes
+// of the placeholders need to be fixed up afterward.
+struct Templater {
+    keys_info: Vec<descriptor::DescriptorPublicKey>,
+}
+
+impl Translator<descriptor::DescriptorPublicKey, String, WalletPolicyError> for Templater {
+    fn pk(&mut self, pk: &descriptor::DescriptorPublicKey) -> Result<String, WalletPolicyError> {
+        let xpub = match pk {
+            descriptor::DescriptorPublicKey::MultiXPub(xpub)
+                if xpub.wildcard == descriptor::Wildcard::Unhardened =>
+            {
+                xpub
+            }
+            _ => return Err(WalletPolicyError::UnsupportedKey(pk.clone().into())),
+        };
+        let mut steps = Vec::with_capacity(xpub.derivation_paths.paths().len());
+        for der_path in xpub.derivation_paths.paths() {
+            match der_path.as_ref() {
+                [step] if step.is_normal() => steps.push(u32::from(*step).to_string()),
+                _ => return Err(WalletPolicyError::UnsupportedKey(pk.clone().into())),
+            }
+        }
+
+        let key_info = descriptor::DescriptorPublicKey::XPub(descriptor::DescriptorXKey {
+            origin: xpub.origin.clone(),
+            xkey: xpub.xkey,
+            derivation_path: bip32::DerivationPath::master(),
+            wildcard: descriptor::Wildcard::None,
+        });
+        let index = match self.keys_info.iter().position(|k| *k == key_info) {
+            Some(index) => index,
+            None => {
+                self.keys_info.push(key_info);
+                self.keys_info.len() - 1
+            }
+        };
+
+        Ok(format!("@{}/<{}>/*", index, steps.join(";")))
+    }
+
+    fn sha256(&mut self, hash: &sha256::Hash) -> Result<String, WalletPolicyError> {
+        Ok(hash.to_string())
+    }
+
+    fn hash256(&mut self, hash: &hash256::Hash) -> Result<String, WalletPolicyError> {
+        Ok(hash.to_string())
+    }
+
+    fn ripemd160(&mut self, hash: &ripemd160::Hash) -> Result<String, WalletPolicyError> {
+        Ok(hash.to_string())
+    }
+
+    fn hash160(&mut self, hash: &hash160::Hash) -> Result<String, WalletPolicyError> {
+        Ok(hash.to_string())
+    }
+}
+
+// Parse the key placeholder at the beginning of this string (past the '@'). Returns the index of
+// the key, the derivation steps to append to it and the length of the placeholder.
+fn parse_placeholder(s: &str) -> Result<(usize, String, usize), WalletPolicyError> {
+    let invalid = || {
+        let end = s.find(&[',', ')'][..]).unwrap_or(s.len());
+        WalletPolicyError::InvalidPlaceholder(format!("@{}", &s[..end]))
+    };
+
+    let index_len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let index = usize::from_str(&s[..index_len]).map_err(|_| invalid())?;
+    let rest = &s[index_len..];
+    if rest.starts_with("/**") {
+        return Ok((index, "/<0;1>/*".to_string(), index_len + 3));
+    }
+    if !rest.starts_with("/<") {
+        return Err(invalid());
+    }
+    let end = rest.find(">/*").ok_or_else(invalid)?;
+    let steps = rest[2..end]
+        .split(';')
+        .map(u32::from_str)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    if steps.len() != 2 || steps.iter().any(|s| *s >= 1 << 31) {
+        return Err(invalid());
+    }
+    Ok((index, rest[..end + 3].to_string(), index_len + end + 3))
+}
+
+// Replace each key placeholder of the template, by order of appearance, with the string returned
+// by the given closure for its key index and its derivation steps.
+fn replace_placeholders(
+    template: &str,
+    mut replace: impl FnMut(usize, &str) -> Result<String, WalletPolicyError>,
+) -> Result<String, WalletPolicyError> {
+    let mut res = String::with_capacity(template.len());
+    let mut cursor = 0;
+    while let Some(offset) = template[cursor..].find('@') {
+        let start = cursor + offset;
+        res.push_str(&template[cursor..start]);
+        let (index, steps, len) = parse_placeholder(&template[start + 1..])?;
+        res.push_str(&replace(index, &steps)?);
+        cursor = start + 1 + len;
+    }
+    res.push_str(&template[cursor..]);
+    Ok(res)
+}
+
+impl LianaDescriptor {
+    /// Get the BIP388 wallet policy for this descriptor.
+    pub fn to_wallet_policy(&self) -> Result<WalletPolicy, WalletPolicyError> {
+        let mut templater = Templater {
+            keys_info: Vec::new(),
+        };
+        let template_desc = self.multi_desc.translate_pk(&mut templater).map_err(|e| {
+            e.expect_translator_err("Only fails on keys we can't turn into placeholders.")
+        })?;
+        // Don't include the checksum, it's not part of the template.
+        let template = template_desc.to_string();
+        let template = template
+            .split('#')
+            .next()
+            .expect("There is always a first element");
+
+        // Number the keys by order of first appearance, and use the shorthand for the usual
+        // receive and change derivation steps.
+        let mut keys_info = Vec::with_capacity(templater.keys_info.len());
+        let mut new_indexes = vec![None; templater.keys_info.len()];
+        let descriptor_template = replace_placeholders(template, |index, steps| {
+            let new_index = *new_indexes[index].get_or_insert_with(|| {
+                keys_info.push(templater.keys_info[index].clone());
+                keys_info.len() - 1
+            });
+            if steps == "/<0;1>/*" {
+                Ok(format!("@{}/**", new_index))
+            } else {
+                Ok(format!("@{}{}", new_index, steps))
+            }
+        })?;
+
+        Ok(WalletPolicy {
+            descriptor_template,
+            keys_info,
+        })
+    }
+
+    /// Parse a Liana descriptor from a BIP388 wallet policy.
+    pub fn from_wallet_policy(policy: &WalletPolicy) -> Result<LianaDescriptor, WalletPolicyError> {
+        for key_info in &policy.keys_info {
+            match key_info {
+                descriptor::DescriptorPublicKey::XPub(xpub)
+                    if xpub.derivation_path.is_master()
+                        && xpub.wildcard == descriptor::Wildcard::None => {}
+                _ => return Err(WalletPolicyError::InvalidKeyInfo(key_info.clone().into())),
+            }
+        }
+
+        // Replace each placeholder with its key expression. Keys must be used in order.
+        let mut used = vec![false; policy.keys_info.len()];
+        let desc_str = replace_placeholders(&policy.descriptor_template, |index, steps| {
+            let key_info = policy
+                .keys_info
+                .get(index)
+                .ok_or(WalletPolicyError::MissingKeyInfo(index))?;
+            if !used[index] && used[..index].iter().any(|u| !u) {
+                return Err(WalletPolicyError::PlaceholdersOrder);
+            }
+            used[index] = true;
+            Ok(format!("{}{}", key_info, steps))
+        })?;
+        if let Some(unused) = used.iter().position(|u| !u) {
+            return Err(WalletPolicyError::UnusedKeyInfo(unused));
+        }
+
+        Ok(LianaDescriptor::from_str(&desc_str)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::descriptors::{LianaPolicy, PathInfo};
+
+    #[test]
+    fn wallet_policy_roundtrip() {
+        let desc = LianaDescriptor::from_str("wsh(or_d(multi(1,[573fb35b/48'/1'/0'/2']tpubDFKp9T7WAYDcENSjoifkrpq1gMDF47KGJcJrpxzX23Qor8wuGbrEVs9utNq1MDS8E2WXJSBk1qoPQLpwyokW7DiUNPwFuxQkL7owNkLAb9W/<0;1>/*,[573fb35b/48'/1'/1'/2']tpubDFGezyzuHJPhdP3jHGW7v7Hwes4Hihqv5W2yyCmRY9VZJCRchETvxrMC8uECeJZdxQ14V4iD4DecoArkUSDwj8ogYE9WEv4MNZr12thNHCs/<0;1>/*),and_v(v:multi(2,[573fb35b/48'/1'/2'/2']tpubDDwxQauiaU964vPzt5Vd7jnDHEUtp2Vc34PaWpEXg5TQ3bRccxnc1MKKh88Hi7xiMeZo9Tm6fBcq4UGXqnDtGUniJLjqAD8SjQ8Eci3aSR7/<0;1>/*,[573fb35b/48'/1'/3'/2']tpubDE37XAVB5CQ1x85md3BQ5uHCoMwT5fgT8X13zzCUQ3x5o2jskYxKjj7Qcxt1Jpj4QB8tqspn2dooPCekRuQDYrDHov7J1ueUNu2wcvgRDxr/<0;1>/*),older(1000))))#qjx6ycpc").unwrap();
+        let policy = desc.to_wallet_policy().unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "wsh(or_d(multi(1,@0/**,@1/**),and_v(v:multi(2,@2/**,@3/**),older(1000))))"
+        );
+        assert_eq!(policy.keys_info.len(), 4);
+        assert_eq!(policy.keys_info[0].to_string(), "[573fb35b/48'/1'/0'/2']tpubDFKp9T7WAYDcENSjoifkrpq1gMDF47KGJcJrpxzX23Qor8wuGbrEVs9utNq1MDS8E2WXJSBk1qoPQLpwyokW7DiUNPwFuxQkL7owNkLAb9W");
+        assert_eq!(LianaDescriptor::from_wallet_policy(&policy).unwrap(), desc);
+
+        // The same xpub may be used with different multipath steps. It's a single key information.
+        let desc = LianaDescriptor::from_str("wsh(or_d(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),and_v(v:pkh([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<2;3>/*),older(10))))").unwrap();
+        let policy = desc.to_wallet_policy().unwrap();
+        assert_eq!(
+            policy.descriptor_template,
+            "wsh(or_d(pk(@0/**),and_v(v:pkh(@0/<2;3>/*),older(10))))"
+        );
+        assert_eq!(policy.keys_info.len(), 1);
+        assert_eq!(LianaDescriptor::from_wallet_policy(&policy).unwrap(), desc);
+
+        // Taproot descriptors, including the unspendable internal key.
+        let key = |s: &str| descriptor::DescriptorPublicKey::from_str(s).unwrap();
+        let policy = LianaPolicy::new_taproot(
+            PathInfo::Multi(
+                2,
+                vec![
+                    key("[573fb35b/48'/1'/0'/2']tpubDFKp9T7WAYDcENSjoifkrpq1gMDF47KGJcJrpxzX23Qor8wuGbrEVs9utNq1MDS8E2WXJSBk1qoPQLpwyokW7DiUNPwFuxQkL7owNkLAb9W/<0;1>/*"),
+                    key("[573fb35b/48'/1'/1'/2']tpubDFGezyzuHJPhdP3jHGW7v7Hwes4Hihqv5W2yyCmRY9VZJCRchETvxrMC8uECeJZdxQ14V4iD4DecoArkUSDwj8ogYE9WEv4MNZr12thNHCs/<0;1>/*"),
+                ],
+            ),
+            [(
+                1000,
+                PathInfo::Single(key("[573fb35b/48'/1'/2'/2']tpubDDwxQauiaU964vPzt5Vd7jnDHEUtp2Vc34PaWpEXg5TQ3bRccxnc1MKKh88Hi7xiMeZo9Tm6fBcq4UGXqnDtGUniJLjqAD8SjQ8Eci3aSR7/<0;1>/*")),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            Default::default(),
+        )
+        .unwrap();
+        let desc = LianaDescriptor::new(policy);
+        let wallet_policy = desc.to_wallet_policy().unwrap();
+        assert_eq!(
+            wallet_policy.descriptor_template,
+            "tr(@0/**,{multi_a(2,@1/**,@2/**),and_v(v:pk(@3/**),older(1000))})"
+        );
+        assert_eq!(wallet_policy.keys_info.len(), 4);
+        assert_eq!(
+            LianaDescriptor::from_wallet_policy(&wallet_policy).unwrap(),
+            desc
+        );
+
+        // A wallet policy can be serialized as JSON, for instance to be shared with another
+        // coordinator.
+        let json = serde_json::to_string(&wallet_policy).unwrap();
+        assert_eq!(
+            serde_json::from_str::<WalletPolicy>(&json).unwrap(),
+            wallet_policy
+        );
+    }
+
+    #[test]
+    fn wallet_policy_invalid() {
+        let key_info = |s: &str| descriptor::DescriptorPublicKey::from_str(s).unwrap();
+        let keys_info = vec![
+            key_info("[abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr"),
+            key_info("[abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV"),
+        ];
+        let policy = |template: &str, keys_info: &[descriptor::DescriptorPublicKey]| {
+            LianaDescriptor::from_wallet_policy(&WalletPolicy {
+                descriptor_template: template.to_string(),
+                keys_info: keys_info.to_vec(),
+            })
+        };
+
+        policy("wsh(andor(pk(@0/**),older(10),pk(@1/<2;3>/*)))", &keys_info).unwrap();
+        assert!(matches!(
+            policy("wsh(andor(pk(@1/**),older(10),pk(@0/**)))", &keys_info),
+            Err(WalletPolicyError::PlaceholdersOrder)
+        ));
+        assert!(matches!(
+            policy("wsh(andor(pk(@0/**),older(10),pk(@2/**)))", &keys_info),
+            Err(WalletPolicyError::MissingKeyInfo(2))
+        ));
+        assert!(matches!(
+            policy("wsh(andor(pk(@0/**),older(10),pk(@0/<2;3>/*)))", &keys_info),
+            Err(WalletPolicyError::UnusedKeyInfo(1))
+        ));
+        for template in &[
+            "wsh(andor(pk(@0/**),older(10),pk(@1)))",
+            "wsh(andor(pk(@0/**),older(10),pk(@1/*)))",
+            "wsh(andor(pk(@0/**),older(10),pk(@1/<2;3;4>/*)))",
+            "wsh(andor(pk(@0/**),older(10),pk(@1/<2';3'>/*)))",
+            "wsh(andor(pk(@0/**),older(10),pk(@/**)))",
+        ] {
+            assert!(matches!(
+                policy(template, &keys_info),
+                Err(WalletPolicyError::InvalidPlaceholder(_))
+            ));
+        }
+        assert!(matches!(
+            policy(
+                "wsh(andor(pk(@0/**),older(10),pk(@1/**)))",
+                &[
+                    keys_info[0].clone(),
+                    key_info("[abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/0")
+                ]
+            ),
+            Err(WalletPolicyError::InvalidKeyInfo(_))
+        ));
+
+        // Keys with derivation steps besides the multipath one can't be turned into placeholders.
+        let desc = LianaDescriptor::from_str("wsh(andor(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/0/<0;1>/*),older(10),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))").unwrap();
+        assert!(matches!(
+            desc.to_wallet_policy(),
+            Err(WalletPolicyError::UnsupportedKey(_))
+        ));
+    }
+}
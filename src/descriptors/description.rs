@@ -0,0 +1,262 @@
+//! Human-readable description of a Liana policy.
+//!
+//! Lay out every spending path of a policy (who needs to sign, how many signatures are required,
+//! when it becomes available) for people who can't read a Miniscript descriptor. This is available
+//! as a structured description for the JSON RPC interface, and as plain text for backups.
+
+use crate::descriptors::{LianaPolicy, PathInfo, RecoveryTimelock};
+
+use std::{collections::HashMap, fmt};
+
+use miniscript::{
+    bitcoin::{bip32, hashes::sha256},
+    descriptor,
+};
+
+use serde::{Deserialize, Serialize};
+
+// The number of blocks mined per day on average, to approximate relative timelocks in days.
+const BLOCKS_PER_DAY: u32 = 144;
+
+// Get the UTC date and time of this UNIX timestamp, as year, month, day, hour and minute. Uses
+// Howard Hinnant's algorithm to convert a number of days since the epoch to a civil date.
+fn utc_date_time(timestamp: u32) -> (u32, u32, u32, u32, u32) {
+    let (days, secs) = (timestamp / 86_400, timestamp % 86_400);
+    // Count days from 0000-03-01, so the leap day is the last day of a year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era * 400 + year_of_era + u32::from(month <= 2);
+    (year, month, day, secs / 3_600, secs % 3_600 / 60)
+}
+
+/// A key which may sign for a spending path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyDescription {
+    /// Fingerprint of the master extended key.
+    pub fingerprint: bip32::Fingerprint,
+    /// Derivation path from the master extended key to the xpub.
+    pub derivation_path: bip32::DerivationPath,
+    pub xpub: bip32::ExtendedPubKey,
+    /// A name given to the signer owning this master extended key, if any.
+    pub alias: Option<String>,
+}
+
+impl KeyDescription {
+    fn new(
+        key: &descriptor::DescriptorPublicKey,
+        aliases: &HashMap<bip32::Fingerprint, String>,
+    ) -> KeyDescription {
+        match key {
+            descriptor::DescriptorPublicKey::MultiXPub(xpub) => {
+                let (fingerprint, derivation_path) = xpub
+                    .origin
+                    .clone()
+                    .expect("Must be a multixpub with an origin.");
+                KeyDescription {
+                    fingerprint,
+                    derivation_path,
+                    xpub: xpub.xkey,
+                    alias: aliases.get(&fingerprint).cloned(),
+                }
+            }
+            _ => unreachable!("All keys in a Liana policy are multixpubs."),
+        }
+    }
+}
+
+impl fmt::Display for KeyDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(alias) = &self.alias {
+            write!(f, "{} ", alias)?;
+        }
+        write!(f, "[{}", self.fingerprint)?;
+        for child in self.derivation_path.into_iter() {
+            write!(f, "/{}", child)?;
+        }
+        write!(f, "]")
+    }
+}
+
+/// When a recovery path becomes available.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TimelockDescription {
+    /// A number of blocks after the coin confirmed, along with the approximate number of days
+    /// this represents.
+    Relative { blocks: u16, approx_days: u32 },
+    /// Once the chain reached this block height.
+    AbsoluteHeight { height: u32 },
+    /// Once the chain reached this UNIX timestamp (as measured by the median time past).
+    AbsoluteTime { timestamp: u32 },
+}
+
+impl From<RecoveryTimelock> for TimelockDescription {
+    fn from(timelock: RecoveryTimelock) -> TimelockDescription {
+        match timelock {
+            RecoveryTimelock::Relative(blocks) => TimelockDescription::Relative {
+                blocks,
+                approx_days: (u32::from(blocks) + BLOCKS_PER_DAY / 2) / BLOCKS_PER_DAY,
+            },
+            RecoveryTimelock::Absolute(height) if height < 500_000_000 => {
+                TimelockDescription::AbsoluteHeight { height }
+            }
+            RecoveryTimelock::Absolute(timestamp) => {
+                TimelockDescription::AbsoluteTime { timestamp }
+            }
+        }
+    }
+}
+
+impl fmt::Display for TimelockDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Relative {
+                blocks,
+                approx_days,
+            } => write!(
+                f,
+                "{} blocks (about {} day{}) after a coin is received",
+                blocks,
+                approx_days,
+                if *approx_days == 1 { "" } else { "s" }
+            ),
+            Self::AbsoluteHeight { height } => write!(f, "block height {}", height),
+            Self::AbsoluteTime { timestamp } => {
+                let (year, month, day, hour, minute) = utc_date_time(*timestamp);
+                write!(
+                    f,
+                    "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+                    year, month, day, hour, minute
+                )
+            }
+        }
+    }
+}
+
+/// A spending path of a Liana policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathDescription {
+    /// The number of signatures required to spend through this path.
+    pub threshold: usize,
+    pub keys: Vec<KeyDescription>,
+    /// When this path becomes available. None for the primary path.
+    pub timelock: Option<TimelockDescription>,
+    /// The hash whose SHA256 preimage must also be provided to spend through this path, if any.
+    pub hashlock: Option<sha256::Hash>,
+}
+
+impl PathDescription {
+    fn new(
+        path_info: &PathInfo,
+        timelock: Option<RecoveryTimelock>,
+        aliases: &HashMap<bip32::Fingerprint, String>,
+    ) -> PathDescription {
+        let (threshold, keys) = match path_info {
+            PathInfo::Hashlock(_, path) => {
+                let desc = PathDescription::new(path, timelock, aliases);
+                (desc.threshold, desc.keys)
+            }
+            PathInfo::Single(key) => (1, vec![KeyDescription::new(key, aliases)]),
+            PathInfo::Multi(k, keys) => (
+                *k,
+                keys.iter()
+                    .map(|key| KeyDescription::new(key, aliases))
+                    .collect(),
+            ),
+        };
+        PathDescription {
+            threshold,
+            keys,
+            timelock: timelock.map(TimelockDescription::from),
+            hashlock: path_info.hashlock().copied(),
+        }
+    }
+}
+
+impl fmt::Display for PathDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.timelock {
+            Some(timelock) => write!(f, "Recovery path, available from {}", timelock)?,
+            None => write!(f, "Primary path, always available")?,
+        }
+        if self.keys.len() == 1 {
+            writeln!(f, ". Requires a signature from:")?;
+        } else {
+            writeln!(
+                f,
+                ". Requires {} signatures out of {} from:",
+                self.threshold,
+                self.keys.len()
+            )?;
+        }
+        for key in &self.keys {
+            writeln!(f, "  - {}", key)?;
+        }
+        if let Some(hash) = &self.hashlock {
+            writeln!(f, "  And the preimage of the SHA256 hash {}.", hash)?;
+        }
+        Ok(())
+    }
+}
+
+/// All the spending paths of a Liana policy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyDescription {
+    pub is_taproot: bool,
+    pub primary_path: PathDescription,
+    /// The recovery paths, those behind relative timelocks first. Each set is ordered by
+    /// increasing timelock.
+    pub recovery_paths: Vec<PathDescription>,
+}
+
+impl fmt::Display for PolicyDescription {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Coins in this {} wallet can be spent through any of the following paths.",
+            if self.is_taproot { "Taproot" } else { "P2WSH" }
+        )?;
+        writeln!(f)?;
+        write!(f, "{}", self.primary_path)?;
+        for path in &self.recovery_paths {
+            writeln!(f)?;
+            write!(f, "{}", path)?;
+        }
+        Ok(())
+    }
+}
+
+impl LianaPolicy {
+    /// Describe the spending paths of this policy. The keys are named after the given aliases for
+    /// their master extended key fingerprint, if any.
+    pub fn description(&self, aliases: &HashMap<bip32::Fingerprint, String>) -> PolicyDescription {
+        let recovery_paths = self
+            .recovery_paths()
+            .iter()
+            .map(|(tl, path)| (RecoveryTimelock::Relative(*tl), path))
+            .chain(
+                self.abs_recovery_paths()
+                    .iter()
+                    .map(|(tl, path)| (RecoveryTimelock::Absolute(*tl), path)),
+            )
+            .map(|(tl, path)| PathDescription::new(path, Some(tl), aliases))
+            .collect();
+
+        PolicyDescription {
+            is_taproot: self.is_taproot(),
+            primary_path: PathDescription::new(self.primary_path(), None, aliases),
+            recovery_paths,
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use miniscript::{
     bitcoin::{
         self, bip32,
-        psbt::{Input as PsbtIn, Psbt},
-        secp256k1,
+        psbt::{Input as PsbtIn, Output as PsbtOut, Psbt},
+        secp256k1, taproot,
     },
-    descriptor, translate_hash_clone, ForEachKey, TranslatePk, Translator,
+    descriptor,
+    policy::Liftable,
+    translate_hash_clone, ForEachKey, TranslatePk, Translator,
 };
 
 use std::{collections::BTreeMap, error, fmt, str};
@@ -17,6 +19,12 @@ pub use keys::*;
 pub mod analysis;
 pub use analysis::*;
 
+pub mod description;
+pub use description::*;
+
+pub mod bip388;
+pub use bip388::*;
+
 const WITNESS_FACTOR: usize = 4;
 
 #[derive(Debug)]
@@ -169,21 +177,43 @@ impl LianaDescriptor {
             .expect("We never create a Liana descriptor with an invalid Liana policy.")
     }
 
-    /// Get the value (in blocks) of the smallest relative timelock of the recovery paths.
-    pub fn first_timelock_value(&self) -> u16 {
-        *self
-            .policy()
+    /// Whether this is a Taproot descriptor, as opposed to a P2WSH one.
+    pub fn is_taproot(&self) -> bool {
+        matches!(self.multi_desc, descriptor::Descriptor::Tr(..))
+    }
+
+    /// Get the value (in blocks) of the smallest relative timelock of the recovery paths. None if
+    /// all the recovery paths are behind absolute timelocks.
+    pub fn first_timelock_value(&self) -> Option<u16> {
+        self.policy().recovery_paths.keys().next().copied()
+    }
+
+    /// Get the timelock of the first recovery path available. That is, the one with the smallest
+    /// relative timelock or, if all recovery paths are behind absolute timelocks, the one with
+    /// the smallest absolute timelock.
+    pub fn first_recovery_timelock(&self) -> RecoveryTimelock {
+        let policy = self.policy();
+        policy
             .recovery_paths
-            .iter()
+            .keys()
             .next()
+            .map(|tl| RecoveryTimelock::Relative(*tl))
+            .or_else(|| {
+                policy
+                    .abs_recovery_paths
+                    .keys()
+                    .next()
+                    .map(|tl| RecoveryTimelock::Absolute(*tl))
+            })
             .expect("There is always at least one recovery path")
-            .0
     }
 
     /// Get the maximum size difference of a transaction input spending a Script derived from this
     /// descriptor before and after satisfaction. The returned value is in weight units.
     /// Callers are expected to account for the Segwit marker (2 WU). This takes into account the
     /// size of the witness stack length varint.
+    /// For a Taproot descriptor this is the size of the largest script path spend, including the
+    /// control block, which is always larger than the key path spend.
     pub fn max_sat_weight(&self) -> usize {
         // We add one to account for the witness stack size, as the `max_weight_to_satisfy` method
         // computes the difference in size for a satisfied input that was *already* in a
@@ -217,7 +247,8 @@ impl LianaDescriptor {
         32 + 4 + 4 + 1 + self.max_sat_vbytes()
     }
 
-    /// Get some information about a PSBT input spending Liana coins.
+    /// Get some information about a PSBT input spending Liana coins. The `lock_time` is the
+    /// nLockTime of the spending transaction.
     /// This analysis assumes that:
     /// - The PSBT input actually spend a Liana coin for this descriptor. Otherwise the analysis will be off.
     /// - The signatures contained in the PSBT input are valid for this script.
@@ -225,24 +256,75 @@ impl LianaDescriptor {
         &self,
         psbt_in: &PsbtIn,
         txin: &bitcoin::TxIn,
+        lock_time: bitcoin::absolute::LockTime,
     ) -> PartialSpendInfo {
-        // Get the identifier of all the keys that signed this transaction.
-        let pubkeys_signed = psbt_in
+        // Get the identifier of all the keys that signed this transaction. The signatures for a
+        // P2WSH input or for the key path spend of a Taproot input may be for any path. But the
+        // same keys may be used in several Taproot leaves, so the signatures for a leaf are only
+        // accounted for the spending path of this leaf, identified by its timelock.
+        let tap_key_signed = psbt_in
+            .tap_key_sig
+            .and(psbt_in.tap_internal_key)
+            .and_then(|pk| psbt_in.tap_key_origins.get(&pk))
+            .map(|(_, origin)| origin);
+        let pubkeys_signed: Vec<_> = psbt_in
             .partial_sigs
             .iter()
-            .filter_map(|(pk, _)| psbt_in.bip32_derivation.get(&pk.inner));
+            .filter_map(|(pk, _)| psbt_in.bip32_derivation.get(&pk.inner))
+            .chain(tap_key_signed)
+            .collect();
+        let leaf_pubkeys_signed: Vec<_> = psbt_in
+            .tap_script_sigs
+            .iter()
+            .filter_map(|((pk, leaf_hash), _)| {
+                let path_timelock = tap_leaf_timelock(psbt_in, leaf_hash)?;
+                let (_, origin) = psbt_in.tap_key_origins.get(pk)?;
+                Some((path_timelock, origin))
+            })
+            .collect();
+        let spend_info = |timelock: Option<RecoveryTimelock>, path_info: &PathInfo| {
+            let signed = pubkeys_signed.iter().copied().chain(
+                leaf_pubkeys_signed
+                    .iter()
+                    .filter(|(tl, _)| *tl == timelock)
+                    .map(|(_, origin)| *origin),
+            );
+            let mut info = path_info.spend_info(signed);
+            if let Some((ref hash, ref mut has_preimage)) = info.hashlock {
+                *has_preimage = psbt_in.sha256_preimages.contains_key(hash);
+            }
+            info
+        };
 
         // Determine the structure of the descriptor. Then compute the spend info for the primary
         // and recovery paths. Only provide the spend info for the recovery path if it is available
         // (ie if the nSequence is >= to the chosen CSV value).
         let desc_info = self.policy();
-        let primary_path = desc_info.primary_path.spend_info(pubkeys_signed.clone());
+        let primary_path = spend_info(None, &desc_info.primary_path);
         let recovery_paths = desc_info
             .recovery_paths
             .iter()
             .filter_map(|(timelock, path_info)| {
                 if txin.sequence.is_height_locked() && txin.sequence.0 >= *timelock as u32 {
-                    Some((*timelock, path_info.spend_info(pubkeys_signed.clone())))
+                    let tl = RecoveryTimelock::Relative(*timelock);
+                    Some((*timelock, spend_info(Some(tl), path_info)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // Same for the recovery paths behind an absolute timelock, which are available if the
+        // nLockTime is enabled and of the same unit and >= to the CLTV value.
+        let abs_recovery_paths = desc_info
+            .abs_recovery_paths
+            .iter()
+            .filter_map(|(timelock, path_info)| {
+                let timelock_lt = bitcoin::absolute::LockTime::from_consensus(*timelock);
+                if txin.sequence.enables_absolute_lock_time()
+                    && timelock_lt.is_implied_by(lock_time)
+                {
+                    let tl = RecoveryTimelock::Absolute(*timelock);
+                    Some((*timelock, spend_info(Some(tl), path_info)))
                 } else {
                     None
                 }
@@ -252,6 +334,7 @@ impl LianaDescriptor {
         PartialSpendInfo {
             primary_path,
             recovery_paths,
+            abs_recovery_paths,
         }
     }
 
@@ -286,12 +369,13 @@ impl LianaDescriptor {
                 .expect("We checked at least one is present."),
             txins.next().expect("We checked at least one is present."),
         );
-        let spend_info = self.partial_spend_info_txin(first_psbt_in, first_txin);
+        let lock_time = psbt.unsigned_tx.lock_time;
+        let spend_info = self.partial_spend_info_txin(first_psbt_in, first_txin, lock_time);
         for (psbt_in, txin) in psbt_ins.zip(txins) {
             // TODO: maybe it's better to not error if one of the input has more, or different
             // signatures? Instead of erroring we could ignore the superfluous data?
             if txin.sequence != first_txin.sequence
-                || spend_info != self.partial_spend_info_txin(psbt_in, txin)
+                || spend_info != self.partial_spend_info_txin(psbt_in, txin, lock_time)
             {
                 return Err(LianaDescError::InconsistentPsbt);
             }
@@ -301,6 +385,21 @@ impl LianaDescriptor {
     }
 }
 
+// Get the timelock of the spending path a Taproot leaf of this PSBT input is for, or None for the
+// primary path. Returns None if the leaf can't be found or isn't a valid Miniscript.
+fn tap_leaf_timelock(
+    psbt_in: &PsbtIn,
+    leaf_hash: &taproot::TapLeafHash,
+) -> Option<Option<RecoveryTimelock>> {
+    let (script, _) = psbt_in
+        .tap_scripts
+        .values()
+        .find(|(script, ver)| taproot::TapLeafHash::from_script(script, *ver) == *leaf_hash)?;
+    let ms = miniscript::Miniscript::<_, miniscript::Tap>::parse_insane(script).ok()?;
+    let policy = ms.lift().ok()?;
+    Some(analysis::path_timelock(&policy))
+}
+
 impl SinglePathLianaDesc {
     /// Derive this descriptor at a given index for a receiving address.
     ///
@@ -363,22 +462,71 @@ impl SinglePathLianaDesc {
 /// Map of a raw public key to the xpub used to derive it and its derivation path
 pub type Bip32Deriv = BTreeMap<secp256k1::PublicKey, (bip32::Fingerprint, bip32::DerivationPath)>;
 
+/// Map of an x-only public key to the leaves it appears in, the xpub used to derive it and its
+/// derivation path
+pub type TapKeyOrigins = BTreeMap<
+    secp256k1::XOnlyPublicKey,
+    (
+        Vec<taproot::TapLeafHash>,
+        (bip32::Fingerprint, bip32::DerivationPath),
+    ),
+>;
+
 impl DerivedSinglePathLianaDesc {
     pub fn address(&self, network: bitcoin::Network) -> bitcoin::Address {
         self.0
             .address(network)
-            .expect("A P2WSH always has an address")
+            .expect("A P2WSH or a Taproot descriptor always has an address")
     }
 
     pub fn script_pubkey(&self) -> bitcoin::ScriptBuf {
         self.0.script_pubkey()
     }
 
-    pub fn witness_script(&self) -> bitcoin::ScriptBuf {
-        self.0.explicit_script().expect("Not a Taproot descriptor")
+    /// Set the information needed by signers to sign a PSBT input spending this descriptor. That
+    /// is the witness script and the BIP32 derivations of all keys for a P2WSH descriptor, or the
+    /// internal key, the script leaves and the BIP32 derivations of all keys for a Taproot one.
+    pub fn update_psbt_in(&self, psbt_in: &mut PsbtIn) {
+        match self.0 {
+            descriptor::Descriptor::Wsh(..) => {
+                psbt_in.witness_script =
+                    Some(self.0.explicit_script().expect("Not a Taproot descriptor"));
+                psbt_in.bip32_derivation = self.bip32_derivations();
+            }
+            descriptor::Descriptor::Tr(ref tr) => {
+                let spend_info = tr.spend_info();
+                psbt_in.tap_internal_key = Some(spend_info.internal_key());
+                psbt_in.tap_merkle_root = spend_info.merkle_root();
+                for (_, ms) in tr.iter_scripts() {
+                    let leaf = (ms.encode(), taproot::LeafVersion::TapScript);
+                    let control_block = spend_info
+                        .control_block(&leaf)
+                        .expect("There is a control block for every leaf");
+                    psbt_in.tap_scripts.insert(control_block, leaf);
+                }
+                psbt_in.tap_key_origins = self.tap_key_origins();
+            }
+            _ => unreachable!("All our descriptors are either P2WSH or Taproot"),
+        }
     }
 
-    pub fn bip32_derivations(&self) -> Bip32Deriv {
+    /// Set the information needed by signers to recognize a PSBT output paying to this descriptor.
+    /// That is the BIP32 derivations of all keys and, for a Taproot descriptor, the internal key.
+    pub fn update_psbt_out(&self, psbt_out: &mut PsbtOut) {
+        match self.0 {
+            descriptor::Descriptor::Wsh(..) => {
+                psbt_out.bip32_derivation = self.bip32_derivations();
+            }
+            descriptor::Descriptor::Tr(ref tr) => {
+                psbt_out.tap_internal_key = Some(tr.spend_info().internal_key());
+                psbt_out.tap_key_origins = self.tap_key_origins();
+            }
+            _ => unreachable!("All our descriptors are either P2WSH or Taproot"),
+        }
+    }
+
+    // The BIP32 derivations of all the keys in a P2WSH descriptor.
+    fn bip32_derivations(&self) -> Bip32Deriv {
         let ms = match self.0 {
             descriptor::Descriptor::Wsh(ref wsh) => match wsh.as_inner() {
                 descriptor::WshInner::Ms(ms) => ms,
@@ -386,7 +534,7 @@ impl DerivedSinglePathLianaDesc {
                     unreachable!("None of our descriptors is a sorted multi")
                 }
             },
-            _ => unreachable!("All our descriptors are always P2WSH"),
+            _ => unreachable!("Only called on P2WSH descriptors"),
         };
 
         // For DerivedPublicKey, Pk::Hash == Self.
@@ -394,15 +542,47 @@ impl DerivedSinglePathLianaDesc {
             .map(|k| (k.key.inner, (k.origin.0, k.origin.1)))
             .collect()
     }
+
+    // The BIP32 derivations of all the keys in a Taproot descriptor, along with the hashes of
+    // the leaves they appear in.
+    fn tap_key_origins(&self) -> TapKeyOrigins {
+        let tr = match self.0 {
+            descriptor::Descriptor::Tr(ref tr) => tr,
+            _ => unreachable!("Only called on Taproot descriptors"),
+        };
+
+        let internal_key = tr.internal_key();
+        let mut origins = TapKeyOrigins::new();
+        origins.insert(
+            internal_key.key.inner.x_only_public_key().0,
+            (Vec::new(), internal_key.origin.clone()),
+        );
+        for (_, ms) in tr.iter_scripts() {
+            let leaf_hash =
+                taproot::TapLeafHash::from_script(&ms.encode(), taproot::LeafVersion::TapScript);
+            for key in ms.iter_pk() {
+                origins
+                    .entry(key.key.inner.x_only_public_key().0)
+                    .or_insert_with(|| (Vec::new(), key.origin.clone()))
+                    .0
+                    .push(leaf_hash);
+            }
+        }
+        origins
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use bitcoin::Sequence;
+    use bitcoin::{
+        absolute,
+        hashes::{sha256, Hash},
+        Sequence,
+    };
 
-    use std::str::FromStr;
+    use std::{collections::HashMap, str::FromStr};
 
     use crate::signer::HotSigner;
 
@@ -629,8 +809,10 @@ mod tests {
 
         // Sanity check we can call the methods on the derived desc
         der_desc.script_pubkey();
-        der_desc.witness_script();
-        assert!(!der_desc.bip32_derivations().is_empty());
+        let mut psbt_in = PsbtIn::default();
+        der_desc.update_psbt_in(&mut psbt_in);
+        assert!(psbt_in.witness_script.is_some());
+        assert!(!psbt_in.bip32_derivation.is_empty());
     }
 
     #[test]
@@ -639,13 +821,190 @@ mod tests {
         LianaDescriptor::from_str("wsh(or_i(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))").unwrap_err();
 
         let desc = LianaDescriptor::from_str("wsh(andor(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(1),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))").unwrap();
-        assert_eq!(desc.first_timelock_value(), 1);
+        assert_eq!(desc.first_timelock_value(), Some(1));
 
         let desc = LianaDescriptor::from_str("wsh(andor(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(42000),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))").unwrap();
-        assert_eq!(desc.first_timelock_value(), 42000);
+        assert_eq!(desc.first_timelock_value(), Some(42000));
 
         let desc = LianaDescriptor::from_str("wsh(andor(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),older(65535),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))").unwrap();
-        assert_eq!(desc.first_timelock_value(), 0xffff);
+        assert_eq!(desc.first_timelock_value(), Some(0xffff));
+    }
+
+    #[test]
+    fn abs_timelock_recovery_paths() {
+        let secp = secp256k1::Secp256k1::signing_only();
+
+        // A recovery path behind an absolute timelock, at a block height.
+        let desc = LianaDescriptor::from_str("wsh(andor(pk([abcdef01]tpubDEN9WSToTyy9ZQfaYqSKfmVqmq1VVLNtYfj3Vkqh67et57eJ5sTKZQBkHqSwPUsoSskJeaYnPttHe2VrkCsKA27kUaN9SDc5zhqeLzKa1rr/<0;1>/*),after(800000),pk([abcdef01]tpubD8LYfn6njiA2inCoxwM7EuN3cuLVcaHAwLYeups13dpevd3nHLRdK9NdQksWXrhLQVxcUZRpnp5CkJ1FhE61WRAsHxDNAkvGkoQkAeWDYjV/<0;1>/*)))").unwrap();
+        let policy = desc.policy();
+        assert!(policy.recovery_paths().is_empty());
+        assert_eq!(
+            policy.abs_recovery_paths().keys().collect::<Vec<_>>(),
+            vec![&800_000]
+        );
+        assert_eq!(desc.first_timelock_value(), None);
+        assert_eq!(
+            desc.first_recovery_timelock(),
+            RecoveryTimelock::Absolute(800_000)
+        );
+
+        // Relative and absolute timelocks can be mixed, the relative ones come first. Here with a
+        // UNIX timestamp.
+        let prim_path = PathInfo::Single(random_desc_key(&secp));
+        let rel_path = PathInfo::Single(random_desc_key(&secp));
+        let abs_path = PathInfo::Multi(2, (0..3).map(|_| random_desc_key(&secp)).collect());
+        let policy = LianaPolicy::new_with_abs_timelocks(
+            prim_path.clone(),
+            [(52_560, rel_path.clone())].iter().cloned().collect(),
+            [(1_893_456_000, abs_path.clone())]
+                .iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+        let desc = LianaDescriptor::new(policy.clone());
+        assert_eq!(desc.policy(), policy);
+        assert_eq!(
+            desc.first_recovery_timelock(),
+            RecoveryTimelock::Relative(52_560)
+        );
+        assert_eq!(
+            LianaDescriptor::from_str(&desc.to_string())
+                .unwrap()
+                .policy(),
+            policy
+        );
+
+        // Only absolute timelocks.
+        let policy = LianaPolicy::new_with_abs_timelocks(
+            prim_path.clone(),
+            BTreeMap::new(),
+            [(1_000, rel_path.clone()), (2_000, abs_path.clone())]
+                .iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(LianaDescriptor::new(policy.clone()).policy(), policy);
+
+        // But there must be at least one recovery path, and the timelocks must be valid.
+        LianaPolicy::new_with_abs_timelocks(prim_path.clone(), BTreeMap::new(), BTreeMap::new())
+            .unwrap_err();
+        for invalid_tl in &[0, 0x8000_0000, u32::MAX] {
+            LianaPolicy::new_with_abs_timelocks(
+                prim_path.clone(),
+                BTreeMap::new(),
+                [(*invalid_tl, rel_path.clone())].iter().cloned().collect(),
+            )
+            .unwrap_err();
+        }
+    }
+
+    #[test]
+    fn taproot_descriptors() {
+        let secp = secp256k1::Secp256k1::new();
+        let prim_key = random_desc_key(&secp);
+        let recov_path = PathInfo::Multi(2, (0..3).map(|_| random_desc_key(&secp)).collect());
+
+        // A single key primary path is the internal key. The recovery path is a script leaf.
+        let policy = LianaPolicy::new_taproot(
+            PathInfo::Single(prim_key.clone()),
+            [(52_560, recov_path.clone())].iter().cloned().collect(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+        assert!(policy.is_taproot());
+        let desc = LianaDescriptor::new(policy.clone());
+        assert!(desc.is_taproot());
+        assert!(desc.to_string().starts_with(&format!("tr({},", prim_key)));
+        assert_eq!(desc.policy(), policy);
+        assert_eq!(
+            LianaDescriptor::from_str(&desc.to_string())
+                .unwrap()
+                .policy(),
+            policy
+        );
+        let der_desc = desc.receive_descriptor().derive(42.into(), &secp);
+        assert!(der_desc
+            .address(bitcoin::Network::Bitcoin)
+            .to_string()
+            .starts_with("bc1p"));
+        let mut psbt_in = PsbtIn::default();
+        der_desc.update_psbt_in(&mut psbt_in);
+        assert!(psbt_in.witness_script.is_none() && psbt_in.bip32_derivation.is_empty());
+        assert!(psbt_in.tap_internal_key.is_some() && psbt_in.tap_merkle_root.is_some());
+        assert_eq!(psbt_in.tap_scripts.len(), 1);
+        assert_eq!(psbt_in.tap_key_origins.len(), 4);
+        let mut psbt_out = PsbtOut::default();
+        der_desc.update_psbt_out(&mut psbt_out);
+        assert_eq!(psbt_out.tap_internal_key, psbt_in.tap_internal_key);
+        assert_eq!(psbt_out.tap_key_origins, psbt_in.tap_key_origins);
+
+        // Spending it is cheaper than spending the equivalent P2WSH descriptor.
+        let wsh_desc = LianaDescriptor::new(
+            LianaPolicy::new(
+                PathInfo::Single(prim_key),
+                [(52_560, recov_path.clone())].iter().cloned().collect(),
+            )
+            .unwrap(),
+        );
+        assert!(desc.max_sat_weight() < wsh_desc.max_sat_weight());
+
+        // A multisig primary path is a script leaf too. The internal key is unspendable, and is
+        // not mistaken for a primary path when parsing the descriptor.
+        let prim_path = PathInfo::Multi(2, (0..2).map(|_| random_desc_key(&secp)).collect());
+        let policy = LianaPolicy::new_taproot(
+            prim_path.clone(),
+            [(52_560, recov_path.clone())].iter().cloned().collect(),
+            [(1_893_456_000, PathInfo::Single(random_desc_key(&secp)))]
+                .iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+        let desc = LianaDescriptor::new(policy.clone());
+        assert_eq!(desc.policy(), policy);
+        assert_eq!(
+            LianaDescriptor::from_str(&desc.to_string())
+                .unwrap()
+                .policy(),
+            policy
+        );
+        assert_eq!(
+            desc.first_recovery_timelock(),
+            RecoveryTimelock::Relative(52_560)
+        );
+        let mut psbt_in = PsbtIn::default();
+        desc.receive_descriptor()
+            .derive(42.into(), &secp)
+            .update_psbt_in(&mut psbt_in);
+        assert_eq!(psbt_in.tap_scripts.len(), 3);
+        assert_eq!(psbt_in.tap_key_origins.len(), 7);
+        let internal_key = psbt_in.tap_internal_key.unwrap();
+        assert!(psbt_in.tap_key_origins[&internal_key].0.is_empty());
+
+        // The unspendable internal key is different for each derived descriptor.
+        let mut other_psbt_in = PsbtIn::default();
+        desc.receive_descriptor()
+            .derive(43.into(), &secp)
+            .update_psbt_in(&mut other_psbt_in);
+        assert_ne!(other_psbt_in.tap_internal_key, Some(internal_key));
+
+        // Without the primary path leaf, it's not a Liana descriptor anymore.
+        let desc_str = desc.to_string();
+        let without_primary = desc_str
+            .split_once(",{")
+            .map(|(ik, tree)| {
+                let recov_leaves = &tree[tree.find('{').unwrap()..tree.rfind('#').unwrap() - 2];
+                format!("{},{})", ik, recov_leaves)
+            })
+            .unwrap();
+        assert!(without_primary.starts_with("tr("));
+        LianaDescriptor::from_str(&without_primary).unwrap_err();
+
+        // A Taproot descriptor without any recovery path is invalid too.
+        LianaDescriptor::from_str(&format!("tr({})", random_desc_key(&secp))).unwrap_err();
+        LianaPolicy::new_taproot(prim_path, BTreeMap::new(), BTreeMap::new()).unwrap_err();
     }
 
     #[test]
@@ -1115,5 +1474,450 @@ mod tests {
         }
     }
 
+    #[test]
+    fn partial_spend_info_abs_timelock() {
+        // A single key as primary path and a single key recovery path after height 800 000.
+        let desc = LianaDescriptor::from_str("wsh(or_d(pk([f5acc2fd]tpubD6NzVbkrYhZ4YgUx2ZLNt2rLYAMTdYysCRzKoLu2BeSHKvzqPaBDvf17GeBPnExUVPkuBpx4kniP964e2MxyzzazcXLptxLXModSVCVEV1T/<0;1>/*),and_v(v:pkh([8a64f2a9]tpubD6NzVbkrYhZ4WmzFjvQrp7sDa4ECUxTi9oby8K4FZkd3XCBtEdKwUiQyYJaxiJo5y42gyDWEczrFpozEjeLxMPxjf2WtkfcbpUdfvNnozWF/<0;1>/*),after(800000))))").unwrap();
+        let mut psbt = psbt_from_str("cHNidP8BAHECAAAAAUSHuliRtuCX1S6JxRuDRqDCKkWfKmWL5sV9ukZ/wzvfAAAAAAD9////AogTAAAAAAAAFgAUIxe7UY6LJ6y5mFBoWTOoVispDmdwFwAAAAAAABYAFKqO83TK+t/KdpAt21z2HGC7/Z2FAAAAAAABASsQJwAAAAAAACIAIIIySQjGCTeyx/rKUQx8qobjhJeNCiVCliBJPdyRX6XKAQVBIQI2cqWpc9UAW2gZt2WkKjvi8KoMCui00pRlL6wG32uKDKxzZHapFNYASzIYkEdH9bJz6nnqUG3uBB8kiK1asmgiBgI2cqWpc9UAW2gZt2WkKjvi8KoMCui00pRlL6wG32uKDAz1rML9AAAAAG8AAAAiBgMLcbOxsfLe6+3r1UcjQo77HY0As8OKE4l37yj0/qhIyQyKZPKpAAAAAG8AAAAAAAA=");
+
+        // The nLockTime isn't set, the recovery path isn't available.
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().threshold, 1);
+        assert!(info.abs_recovery_paths().is_empty());
+        assert!(info.recovery_paths().is_empty());
+
+        // Too low, or a timestamp instead of a height.
+        psbt.unsigned_tx.lock_time = absolute::LockTime::from_consensus(799_999);
+        assert!(desc
+            .partial_spend_info(&psbt)
+            .unwrap()
+            .abs_recovery_paths()
+            .is_empty());
+        psbt.unsigned_tx.lock_time = absolute::LockTime::from_consensus(1_893_456_000);
+        assert!(desc
+            .partial_spend_info(&psbt)
+            .unwrap()
+            .abs_recovery_paths()
+            .is_empty());
+
+        // At or above the timelock, it's available.
+        for lock_time in &[800_000, 800_042] {
+            psbt.unsigned_tx.lock_time = absolute::LockTime::from_consensus(*lock_time);
+            let info = desc.partial_spend_info(&psbt).unwrap();
+            let recov_info = info.abs_recovery_paths().get(&800_000).unwrap();
+            assert_eq!(recov_info.threshold, 1);
+            assert_eq!(recov_info.sigs_count, 0);
+        }
+
+        // Unless the nLockTime is disabled by the nSequence.
+        psbt.unsigned_tx.input[0].sequence = Sequence::MAX;
+        assert!(desc
+            .partial_spend_info(&psbt)
+            .unwrap()
+            .abs_recovery_paths()
+            .is_empty());
+    }
+
+    #[test]
+    fn partial_spend_info_taproot() {
+        let secp = secp256k1::Secp256k1::new();
+        let network = bitcoin::Network::Bitcoin;
+        let signers: Vec<HotSigner> = (0..3)
+            .map(|_| HotSigner::generate(network).unwrap())
+            .collect();
+        let desc_key = |signer: &HotSigner| {
+            descriptor::DescriptorPublicKey::from_str(&format!(
+                "[{}]{}/<0;1>/*",
+                signer.fingerprint(&secp),
+                signer.xpub_at(&bip32::DerivationPath::master(), &secp)
+            ))
+            .unwrap()
+        };
+
+        // A 2-of-2 primary path and a single key recovery path after 10 blocks.
+        let policy = LianaPolicy::new_taproot(
+            PathInfo::Multi(2, vec![desc_key(&signers[0]), desc_key(&signers[1])]),
+            [(10, PathInfo::Single(desc_key(&signers[2])))]
+                .iter()
+                .cloned()
+                .collect(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let desc = LianaDescriptor::new(policy);
+        let spent_coin_desc = desc.receive_descriptor().derive(0.into(), &secp);
+        let mut psbt_in = PsbtIn {
+            witness_utxo: Some(bitcoin::TxOut {
+                value: 19_000,
+                script_pubkey: spent_coin_desc.script_pubkey(),
+            }),
+            ..PsbtIn::default()
+        };
+        spent_coin_desc.update_psbt_in(&mut psbt_in);
+        let mut psbt = Psbt::from_unsigned_tx(bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![bitcoin::TxOut {
+                value: 18_000,
+                script_pubkey: spent_coin_desc.script_pubkey(),
+            }],
+        })
+        .unwrap();
+        psbt.inputs[0] = psbt_in;
+
+        // The signatures for the primary path leaf are accounted for.
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().threshold, 2);
+        assert_eq!(info.primary_path().sigs_count, 0);
+        assert!(info.recovery_paths().is_empty());
+        let psbt = signers[0].sign_psbt(psbt, &secp).unwrap();
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().sigs_count, 1);
+        assert!(info
+            .primary_path()
+            .signed_pubkeys
+            .contains_key(&signers[0].fingerprint(&secp)));
+        let psbt = signers[1].sign_psbt(psbt, &secp).unwrap();
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().sigs_count, 2);
+
+        // So are the signatures for the recovery path leaf, once it is available.
+        let mut psbt = signers[2].sign_psbt(psbt, &secp).unwrap();
+        assert!(desc
+            .partial_spend_info(&psbt)
+            .unwrap()
+            .recovery_paths()
+            .is_empty());
+        psbt.unsigned_tx.input[0].sequence = Sequence::from_height(10);
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.recovery_paths()[&10].sigs_count, 1);
+        assert_eq!(info.primary_path().sigs_count, 2);
+
+        // With a single key primary path, the signature for the key path spend is accounted for.
+        let policy = LianaPolicy::new_taproot(
+            PathInfo::Single(desc_key(&signers[0])),
+            [(10, PathInfo::Single(desc_key(&signers[2])))]
+                .iter()
+                .cloned()
+                .collect(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let desc = LianaDescriptor::new(policy);
+        let spent_coin_desc = desc.receive_descriptor().derive(0.into(), &secp);
+        psbt.unsigned_tx.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        psbt.inputs[0] = PsbtIn {
+            witness_utxo: Some(bitcoin::TxOut {
+                value: 19_000,
+                script_pubkey: spent_coin_desc.script_pubkey(),
+            }),
+            ..PsbtIn::default()
+        };
+        spent_coin_desc.update_psbt_in(&mut psbt.inputs[0]);
+        assert_eq!(
+            desc.partial_spend_info(&psbt)
+                .unwrap()
+                .primary_path()
+                .sigs_count,
+            0
+        );
+        let psbt = signers[0].sign_psbt(psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].tap_key_sig.is_some());
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().threshold, 1);
+        assert_eq!(info.primary_path().sigs_count, 1);
+    }
+
+    #[test]
+    fn decaying_multisig() {
+        let secp = secp256k1::Secp256k1::new();
+        let network = bitcoin::Network::Bitcoin;
+        let signers: Vec<HotSigner> = (0..3)
+            .map(|_| HotSigner::generate(network).unwrap())
+            .collect();
+        let keys: Vec<_> = signers
+            .iter()
+            .map(|signer| {
+                descriptor::DescriptorPublicKey::from_str(&format!(
+                    "[{}]{}/<0;1>/*",
+                    signer.fingerprint(&secp),
+                    signer.xpub_at(&bip32::DerivationPath::master(), &secp)
+                ))
+                .unwrap()
+            })
+            .collect();
+
+        // A 3-of-3 which becomes a 2-of-3 after 10 blocks, and a 1-of-3 after 20 blocks. In a
+        // Taproot descriptor the same keys can be used in all the paths.
+        let recovery_paths: BTreeMap<_, _> = [
+            (10, PathInfo::Multi(2, keys.clone())),
+            (20, PathInfo::Multi(1, keys.clone())),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let policy = LianaPolicy::new_taproot(
+            PathInfo::Multi(3, keys.clone()),
+            recovery_paths.clone(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let desc = LianaDescriptor::new(policy.clone());
+        assert_eq!(
+            LianaDescriptor::from_str(&desc.to_string())
+                .unwrap()
+                .policy(),
+            policy
+        );
+
+        // But not in a P2WSH descriptor, nor twice in the same path.
+        assert!(matches!(
+            LianaPolicy::new(PathInfo::Multi(3, keys.clone()), recovery_paths),
+            Err(LianaPolicyError::DuplicateKey(..))
+        ));
+        LianaPolicy::new_taproot(
+            PathInfo::Multi(3, keys.clone()),
+            [(
+                10,
+                PathInfo::Multi(2, vec![keys[0].clone(), keys[0].clone()]),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+            BTreeMap::new(),
+        )
+        .unwrap_err();
+
+        // The signatures are only accounted for the path of the leaf they are for.
+        let spent_coin_desc = desc.receive_descriptor().derive(0.into(), &secp);
+        let mut psbt = Psbt::from_unsigned_tx(bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                sequence: Sequence::from_height(20),
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![bitcoin::TxOut {
+                value: 18_000,
+                script_pubkey: spent_coin_desc.script_pubkey(),
+            }],
+        })
+        .unwrap();
+        psbt.inputs[0].witness_utxo = Some(bitcoin::TxOut {
+            value: 19_000,
+            script_pubkey: spent_coin_desc.script_pubkey(),
+        });
+        spent_coin_desc.update_psbt_in(&mut psbt.inputs[0]);
+        let mut psbt = signers[0].sign_psbt(psbt, &secp).unwrap();
+        assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 3);
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().sigs_count, 1);
+        assert_eq!(info.recovery_paths()[&10].sigs_count, 1);
+        assert_eq!(info.recovery_paths()[&20].sigs_count, 1);
+        let last_leaf_sig = psbt.inputs[0]
+            .tap_script_sigs
+            .iter()
+            .find(|((_, leaf_hash), _)| {
+                tap_leaf_timelock(&psbt.inputs[0], leaf_hash)
+                    == Some(Some(RecoveryTimelock::Relative(20)))
+            })
+            .map(|(k, v)| (*k, *v))
+            .unwrap();
+        psbt.inputs[0].tap_script_sigs = [last_leaf_sig].iter().cloned().collect();
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().sigs_count, 0);
+        assert_eq!(info.recovery_paths()[&10].sigs_count, 0);
+        assert_eq!(info.recovery_paths()[&20].sigs_count, 1);
+        assert_eq!(info.recovery_paths()[&20].threshold, 1);
+    }
+
+    #[test]
+    fn hashlock_recovery_path() {
+        let secp = secp256k1::Secp256k1::new();
+        let preimage = [42; 32];
+        let hash = sha256::Hash::hash(&preimage);
+        let prim_path = PathInfo::Single(random_desc_key(&secp));
+        let recov_path = PathInfo::Hashlock(
+            hash,
+            Box::new(PathInfo::Multi(
+                2,
+                vec![random_desc_key(&secp), random_desc_key(&secp)],
+            )),
+        );
+        let other_recov_path = PathInfo::Single(random_desc_key(&secp));
+
+        // A recovery path may require the preimage of a hash, in both P2WSH and Taproot
+        // descriptors.
+        let recovery_paths: BTreeMap<_, _> =
+            [(10, recov_path.clone()), (20, other_recov_path.clone())]
+                .iter()
+                .cloned()
+                .collect();
+        let wsh_policy = LianaPolicy::new(prim_path.clone(), recovery_paths.clone()).unwrap();
+        let tr_policy =
+            LianaPolicy::new_taproot(prim_path.clone(), recovery_paths, BTreeMap::new()).unwrap();
+        for policy in &[wsh_policy, tr_policy] {
+            let desc = LianaDescriptor::new(policy.clone());
+            assert!(desc.to_string().contains(&format!("sha256({})", hash)));
+            assert_eq!(
+                LianaDescriptor::from_str(&desc.to_string())
+                    .unwrap()
+                    .policy(),
+                *policy
+            );
+        }
+        // A path behind a hashlock with a single key.
+        let single_key_recov_path =
+            PathInfo::Hashlock(hash, Box::new(PathInfo::Single(random_desc_key(&secp))));
+        let policy = LianaPolicy::new(
+            prim_path.clone(),
+            [(10, single_key_recov_path.clone())]
+                .iter()
+                .cloned()
+                .collect(),
+        )
+        .unwrap();
+        assert_eq!(LianaDescriptor::new(policy.clone()).policy(), policy);
+
+        // But the primary path can't require a preimage, and a recovery path only a single one.
+        assert!(matches!(
+            LianaPolicy::new(
+                single_key_recov_path.clone(),
+                [(10, other_recov_path.clone())].iter().cloned().collect(),
+            ),
+            Err(LianaPolicyError::InvalidHashlock)
+        ));
+        assert!(matches!(
+            LianaPolicy::new(
+                prim_path.clone(),
+                [(
+                    10,
+                    PathInfo::Hashlock(
+                        sha256::Hash::hash(&[43; 32]),
+                        Box::new(single_key_recov_path)
+                    )
+                )]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            Err(LianaPolicyError::InvalidHashlock)
+        ));
+
+        // The spend info tells whether the preimage was provided.
+        let desc = LianaDescriptor::new(
+            LianaPolicy::new(prim_path, [(10, recov_path)].iter().cloned().collect()).unwrap(),
+        );
+        let spent_coin_desc = desc.receive_descriptor().derive(0.into(), &secp);
+        let mut psbt = Psbt::from_unsigned_tx(bitcoin::Transaction {
+            version: 2,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![bitcoin::TxIn {
+                sequence: Sequence::from_height(10),
+                ..bitcoin::TxIn::default()
+            }],
+            output: vec![bitcoin::TxOut {
+                value: 18_000,
+                script_pubkey: spent_coin_desc.script_pubkey(),
+            }],
+        })
+        .unwrap();
+        spent_coin_desc.update_psbt_in(&mut psbt.inputs[0]);
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.primary_path().hashlock, None);
+        assert_eq!(info.recovery_paths()[&10].threshold, 2);
+        assert_eq!(info.recovery_paths()[&10].hashlock, Some((hash, false)));
+        psbt.inputs[0]
+            .sha256_preimages
+            .insert(hash, preimage.to_vec());
+        let info = desc.partial_spend_info(&psbt).unwrap();
+        assert_eq!(info.recovery_paths()[&10].hashlock, Some((hash, true)));
+    }
+
+    #[test]
+    fn policy_description() {
+        let desc = LianaDescriptor::from_str("wsh(or_d(multi(2,[f5acc2fd/48'/1'/0'/2']tpubD6NzVbkrYhZ4YgUx2ZLNt2rLYAMTdYysCRzKoLu2BeSHKvzqPaBDvf17GeBPnExUVPkuBpx4kniP964e2MxyzzazcXLptxLXModSVCVEV1T/<0;1>/*,[00112233]xpub6FC8vmQGGfSuQGfKG5L73fZ7WjXit8TzfJYDKwTtHkhrbAhU5Kma41oenVq6aMnpgULJRXpQuxnVysyfdpRhVgD6vYe7XLbFDhmvYmDrAVq/<0;1>/*),and_v(v:pkh([8a64f2a9]tpubD6NzVbkrYhZ4WmzFjvQrp7sDa4ECUxTi9oby8K4FZkd3XCBtEdKwUiQyYJaxiJo5y42gyDWEczrFpozEjeLxMPxjf2WtkfcbpUdfvNnozWF/<0;1>/*),older(26352))))").unwrap();
+        let aliases: HashMap<_, _> = [(
+            bip32::Fingerprint::from_str("f5acc2fd").unwrap(),
+            "Alice".to_string(),
+        )]
+        .iter()
+        .cloned()
+        .collect();
+        let description = desc.policy().description(&aliases);
+        assert!(!description.is_taproot);
+        assert_eq!(description.primary_path.threshold, 2);
+        assert_eq!(description.primary_path.timelock, None);
+        assert_eq!(
+            description.primary_path.keys[0].alias,
+            Some("Alice".to_string())
+        );
+        assert_eq!(
+            description.primary_path.keys[0].derivation_path,
+            bip32::DerivationPath::from_str("m/48'/1'/0'/2'").unwrap()
+        );
+        assert_eq!(description.primary_path.keys[1].alias, None);
+        assert_eq!(description.recovery_paths.len(), 1);
+        assert_eq!(
+            description.recovery_paths[0].timelock,
+            Some(TimelockDescription::Relative {
+                blocks: 26352,
+                approx_days: 183
+            })
+        );
+        assert_eq!(
+            description.to_string(),
+            "Coins in this P2WSH wallet can be spent through any of the following paths.
+
+Primary path, always available. Requires 2 signatures out of 2 from:
+  - Alice [f5acc2fd/48'/1'/0'/2']
+  - [00112233]
+
+Recovery path, available from 26352 blocks (about 183 days) after a coin is received. Requires a signature from:
+  - [8a64f2a9]
+"
+        );
+        let json = serde_json::to_value(&description.recovery_paths[0]).unwrap();
+        assert_eq!(
+            json["timelock"],
+            serde_json::json!({"type": "relative", "blocks": 26352, "approx_days": 183})
+        );
+        assert_eq!(json["hashlock"], serde_json::Value::Null);
+
+        // Absolute timelocks are either a block height or a UNIX timestamp.
+        assert_eq!(
+            TimelockDescription::from(RecoveryTimelock::Absolute(800_000)),
+            TimelockDescription::AbsoluteHeight { height: 800_000 }
+        );
+        assert_eq!(
+            TimelockDescription::from(RecoveryTimelock::Absolute(1_893_456_000)),
+            TimelockDescription::AbsoluteTime {
+                timestamp: 1_893_456_000
+            }
+        );
+        assert_eq!(
+            TimelockDescription::from(RecoveryTimelock::Absolute(800_000)).to_string(),
+            "block height 800000"
+        );
+        for (timestamp, date) in &[
+            (1_893_456_000, "2030-01-01 00:00 UTC"),
+            (1_709_210_096, "2024-02-29 12:34 UTC"),
+            (2_000_000_000, "2033-05-18 03:33 UTC"),
+            (u32::MAX, "2106-02-07 06:28 UTC"),
+        ] {
+            assert_eq!(
+                TimelockDescription::AbsoluteTime {
+                    timestamp: *timestamp
+                }
+                .to_string(),
+                *date
+            );
+        }
+    }
+
     // TODO: test error conditions of deserialization.
 }
@@ -1,16 +1,52 @@
 use crate::{
+    commands::FeeTarget,
+    database::LabelItem,
+    descriptors::RecoveryTimelock,
     jsonrpc::{Error, Params, Request, Response},
     DaemonControl,
 };
 
-use std::{collections::HashMap, convert::TryInto, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    str::FromStr,
+};
 
-use miniscript::bitcoin::{self, psbt::PartiallySignedTransaction as Psbt};
+use miniscript::bitcoin::{self, bip32, psbt::PartiallySignedTransaction as Psbt};
 
-fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
-    let destinations = params
-        .get(0, "destinations")
-        .ok_or_else(|| Error::invalid_params("Missing 'destinations' parameter."))?
+// The fee target of a transaction, given either as a feerate or as a confirmation target.
+fn fee_target_param(
+    params: &Params,
+    feerate_index: usize,
+    conf_target_index: usize,
+) -> Result<FeeTarget, Error> {
+    let feerate = params
+        .get(feerate_index, "feerate")
+        .filter(|f| !f.is_null());
+    let conf_target = params
+        .get(conf_target_index, "conf_target")
+        .filter(|t| !t.is_null());
+    match (feerate, conf_target) {
+        (Some(feerate), None) => feerate
+            .as_u64()
+            .map(FeeTarget::Feerate)
+            .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter.")),
+        (None, Some(conf_target)) => conf_target
+            .as_u64()
+            .and_then(|t| t.try_into().ok())
+            .map(FeeTarget::Blocks)
+            .ok_or_else(|| Error::invalid_params("Invalid 'conf_target' parameter.")),
+        (Some(_), Some(_)) => Err(Error::invalid_params(
+            "Only one of 'feerate' and 'conf_target' may be given.",
+        )),
+        (None, None) => Err(Error::invalid_params("Missing 'feerate' parameter.")),
+    }
+}
+
+fn destinations_from_json(
+    value: &serde_json::Value,
+) -> Result<HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>, Error> {
+    value
         .as_object()
         .and_then(|obj| {
             obj.into_iter()
@@ -19,12 +55,13 @@ fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::V
                     let amount: u64 = v.as_i64()?.try_into().ok()?;
                     Some((addr, amount))
                 })
-                .collect::<Option<HashMap<bitcoin::Address<bitcoin::address::NetworkUnchecked>, u64>>>()
+                .collect()
         })
-        .ok_or_else(|| Error::invalid_params("Invalid 'destinations' parameter."))?;
-    let outpoints = params
-        .get(1, "outpoints")
-        .ok_or_else(|| Error::invalid_params("Missing 'outpoints' parameter."))?
+        .ok_or_else(|| Error::invalid_params("Invalid 'destinations' parameter."))
+}
+
+fn outpoints_from_json(value: &serde_json::Value) -> Result<Vec<bitcoin::OutPoint>, Error> {
+    value
         .as_array()
         .and_then(|arr| {
             arr.iter()
@@ -33,19 +70,73 @@ fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::V
                         .as_str()
                         .and_then(|e| bitcoin::OutPoint::from_str(e).ok())
                 })
-                .collect::<Option<Vec<bitcoin::OutPoint>>>()
+                .collect()
         })
-        .ok_or_else(|| Error::invalid_params("Invalid 'outpoints' parameter."))?;
-    let feerate: u64 = params
-        .get(2, "feerate")
-        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
-        .as_u64()
-        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+        .ok_or_else(|| Error::invalid_params("Invalid 'outpoints' parameter."))
+}
+
+fn create_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let destinations = params
+        .get(0, "destinations")
+        .ok_or_else(|| Error::invalid_params("Missing 'destinations' parameter."))
+        .and_then(destinations_from_json)?;
+    // If no outpoint is given, coins will be selected automatically.
+    let outpoints = params
+        .get(1, "outpoints")
+        .map(outpoints_from_json)
+        .transpose()?
+        .unwrap_or_default();
+    let fee_target = fee_target_param(&params, 2, 5)?;
+    let allow_frozen = params
+        .get(3, "allow_frozen")
+        .map(|a| {
+            a.as_bool()
+                .ok_or_else(|| Error::invalid_params("Invalid 'allow_frozen' parameter."))
+        })
+        .transpose()?
+        .unwrap_or(false);
+    // All the value left after paying the destinations may be sent to this address.
+    let send_max = params
+        .get(4, "send_max")
+        .filter(|a| !a.is_null())
+        .map(|a| {
+            a.as_str()
+                .and_then(|s| bitcoin::Address::from_str(s).ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'send_max' parameter."))
+        })
+        .transpose()?;
 
-    let res = control.create_spend(&destinations, &outpoints, feerate)?;
+    let res = control.create_spend(
+        &destinations,
+        &outpoints,
+        fee_target,
+        allow_frozen,
+        send_max,
+    )?;
     Ok(serde_json::json!(&res))
 }
 
+fn outpoints_param(params: &Params) -> Result<Vec<bitcoin::OutPoint>, Error> {
+    params
+        .get(0, "outpoints")
+        .ok_or_else(|| Error::invalid_params("Missing 'outpoints' parameter."))
+        .and_then(outpoints_from_json)
+}
+
+fn freeze_coins(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let outpoints = outpoints_param(&params)?;
+    control.freeze_coins(&outpoints)?;
+
+    Ok(serde_json::json!({}))
+}
+
+fn unfreeze_coins(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let outpoints = outpoints_param(&params)?;
+    control.unfreeze_coins(&outpoints)?;
+
+    Ok(serde_json::json!({}))
+}
+
 fn update_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
     let psbt: Psbt = params
         .get(0, "psbt")
@@ -82,6 +173,112 @@ fn broadcast_spend(control: &DaemonControl, params: Params) -> Result<serde_json
     Ok(serde_json::json!({}))
 }
 
+fn sign_spend(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let passphrase = params
+        .get(1, "passphrase")
+        .map(|p| {
+            p.as_str()
+                .ok_or_else(|| Error::invalid_params("Invalid 'passphrase' parameter."))
+        })
+        .transpose()?;
+    let res = control.sign_spend(&txid, passphrase)?;
+
+    Ok(serde_json::json!(&res))
+}
+
+fn rbf_psbt(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txid = params
+        .get(0, "txid")
+        .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?
+        .as_str()
+        .and_then(|s| bitcoin::Txid::from_str(s).ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'txid' parameter."))?;
+    let feerate: u64 = params
+        .get(1, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+
+    let res = control.rbf_psbt(&txid, feerate)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn create_cpfp(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let txids: Vec<bitcoin::Txid> = params
+        .get(0, "txids")
+        .ok_or_else(|| Error::invalid_params("Missing 'txids' parameter."))?
+        .as_array()
+        .and_then(|arr| {
+            arr.iter()
+                .map(|entry| entry.as_str().and_then(|e| bitcoin::Txid::from_str(e).ok()))
+                .collect()
+        })
+        .ok_or_else(|| Error::invalid_params("Invalid 'txids' parameter."))?;
+    let feerate: u64 = params
+        .get(1, "feerate")
+        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .as_u64()
+        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+
+    let res = control.create_cpfp(&txids, feerate)?;
+    Ok(serde_json::json!(&res))
+}
+
+// Get the optional expiry threshold, in blocks, given as first parameter.
+fn threshold_param(params: Option<&Params>) -> Result<Option<u32>, Error> {
+    params
+        .and_then(|p| p.get(0, "threshold"))
+        .filter(|t| !t.is_null())
+        .map(|t| {
+            t.as_u64()
+                .and_then(|t| t.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'threshold' parameter."))
+        })
+        .transpose()
+}
+
+fn list_expiring_coins(
+    control: &DaemonControl,
+    params: Option<Params>,
+) -> Result<serde_json::Value, Error> {
+    let threshold = threshold_param(params.as_ref())?;
+    Ok(serde_json::json!(&control.list_expiring_coins(threshold)))
+}
+
+fn get_info(control: &DaemonControl, params: Option<Params>) -> Result<serde_json::Value, Error> {
+    let threshold = threshold_param(params.as_ref())?;
+    Ok(serde_json::json!(&control.get_info(threshold)))
+}
+
+fn get_policy(control: &DaemonControl, params: Option<Params>) -> Result<serde_json::Value, Error> {
+    let aliases: HashMap<bip32::Fingerprint, String> = params
+        .as_ref()
+        .and_then(|p| p.get(0, "aliases"))
+        .filter(|a| !a.is_null())
+        .map(|a| {
+            a.as_object()
+                .and_then(|obj| {
+                    obj.iter()
+                        .map(|(fg, alias)| {
+                            let fg = bip32::Fingerprint::from_str(fg).ok()?;
+                            Some((fg, alias.as_str()?.to_string()))
+                        })
+                        .collect()
+                })
+                .ok_or_else(|| Error::invalid_params("Invalid 'aliases' parameter."))
+        })
+        .transpose()?
+        .unwrap_or_default();
+
+    Ok(serde_json::json!(&control.get_policy(&aliases)))
+}
+
 fn list_confirmed(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
     let start: u32 = params
         .get(0, "start")
@@ -136,19 +333,80 @@ fn start_rescan(control: &DaemonControl, params: Params) -> Result<serde_json::V
 }
 
 fn create_recovery(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    // Without an address to sweep to, the value left goes to a change output.
     let address = params
         .get(0, "address")
-        .ok_or_else(|| Error::invalid_params("Missing 'address' parameter."))?
-        .as_str()
-        .and_then(|s| bitcoin::Address::from_str(s).ok())
-        .ok_or_else(|| Error::invalid_params("Invalid 'address' parameter."))?;
-    let feerate: u64 = params
-        .get(1, "feerate")
-        .ok_or_else(|| Error::invalid_params("Missing 'feerate' parameter."))?
+        .filter(|a| !a.is_null())
+        .map(|a| {
+            a.as_str()
+                .and_then(|s| bitcoin::Address::from_str(s).ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'address' parameter."))
+        })
+        .transpose()?;
+    let fee_target = fee_target_param(&params, 1, 3)?;
+    let timelock: Option<u16> = params
+        .get(2, "timelock")
+        .filter(|tl| !tl.is_null())
+        .map(|tl| {
+            tl.as_u64()
+                .and_then(|tl| tl.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'timelock' parameter."))
+        })
+        .transpose()?;
+    let destinations = params
+        .get(4, "destinations")
+        .filter(|d| !d.is_null())
+        .map(destinations_from_json)
+        .transpose()?
+        .unwrap_or_default();
+    if address.is_none() && destinations.is_empty() {
+        return Err(Error::invalid_params(
+            "Missing 'address' or 'destinations' parameter.",
+        ));
+    }
+    // If no outpoint is given, all coins available through this recovery path are spent.
+    let outpoints = params
+        .get(5, "outpoints")
+        .filter(|o| !o.is_null())
+        .map(outpoints_from_json)
+        .transpose()?
+        .unwrap_or_default();
+    // A recovery path behind an absolute timelock may be chosen instead.
+    let abs_timelock: Option<u32> = params
+        .get(6, "abs_timelock")
+        .filter(|tl| !tl.is_null())
+        .map(|tl| {
+            tl.as_u64()
+                .and_then(|tl| tl.try_into().ok())
+                .ok_or_else(|| Error::invalid_params("Invalid 'abs_timelock' parameter."))
+        })
+        .transpose()?;
+    let timelock = match (timelock, abs_timelock) {
+        (Some(_), Some(_)) => {
+            return Err(Error::invalid_params(
+                "Only one of 'timelock' and 'abs_timelock' may be given.",
+            ))
+        }
+        (Some(tl), None) => Some(RecoveryTimelock::Relative(tl)),
+        (None, Some(tl)) => Some(RecoveryTimelock::Absolute(tl)),
+        (None, None) => None,
+    };
+
+    let res = control.create_recovery(address, &destinations, &outpoints, fee_target, timelock)?;
+    Ok(serde_json::json!(&res))
+}
+
+fn create_refresh(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let threshold: u32 = params
+        .get(0, "threshold")
+        .ok_or_else(|| Error::invalid_params("Missing 'threshold' parameter."))?
         .as_u64()
-        .ok_or_else(|| Error::invalid_params("Invalid 'feerate' parameter."))?;
+        .and_then(|t| t.try_into().ok())
+        .ok_or_else(|| Error::invalid_params("Invalid 'threshold' parameter."))?;
+    let fee_target = fee_target_param(&params, 1, 3)?;
     let timelock: Option<u16> = params
         .get(2, "timelock")
+        .filter(|tl| !tl.is_null())
         .map(|tl| {
             tl.as_u64()
                 .and_then(|tl| tl.try_into().ok())
@@ -156,10 +414,62 @@ fn create_recovery(control: &DaemonControl, params: Params) -> Result<serde_json
         })
         .transpose()?;
 
-    let res = control.create_recovery(address, feerate, timelock)?;
+    let res = control.create_refresh(threshold, fee_target, timelock)?;
     Ok(serde_json::json!(&res))
 }
 
+fn update_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let network = control.config.bitcoin_config.network;
+    let items = params
+        .get(0, "labels")
+        .ok_or_else(|| Error::invalid_params("Missing 'labels' parameter."))?
+        .as_object()
+        .and_then(|obj| {
+            obj.into_iter()
+                .map(|(k, v)| {
+                    let item = LabelItem::parse(k, network)?;
+                    let value = if v.is_null() {
+                        None
+                    } else {
+                        Some(v.as_str()?.to_string())
+                    };
+                    Some((item, value))
+                })
+                .collect::<Option<HashMap<LabelItem, Option<String>>>>()
+        })
+        .ok_or_else(|| Error::invalid_params("Invalid 'labels' parameter."))?;
+    control.update_labels(&items);
+
+    Ok(serde_json::json!({}))
+}
+
+fn get_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let network = control.config.bitcoin_config.network;
+    let items = params
+        .get(0, "items")
+        .ok_or_else(|| Error::invalid_params("Missing 'items' parameter."))?
+        .as_array()
+        .and_then(|arr| {
+            arr.iter()
+                .map(|entry| entry.as_str().and_then(|e| LabelItem::parse(e, network)))
+                .collect::<Option<HashSet<LabelItem>>>()
+        })
+        .ok_or_else(|| Error::invalid_params("Invalid 'items' parameter."))?;
+
+    Ok(serde_json::json!(&control.get_labels(&items)))
+}
+
+fn import_labels(control: &DaemonControl, params: Params) -> Result<serde_json::Value, Error> {
+    let labels = params
+        .get(0, "labels")
+        .ok_or_else(|| Error::invalid_params("Missing 'labels' parameter."))?
+        .as_str()
+        .ok_or_else(|| Error::invalid_params("Invalid 'labels' parameter."))?;
+    control.import_labels(labels)?;
+
+    Ok(serde_json::json!({}))
+}
+
 /// Handle an incoming JSONRPC2 request.
 pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response, Error> {
     let result = match req.method.as_str() {
@@ -169,12 +479,24 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
                 .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
             broadcast_spend(control, params)?
         }
+        "createcpfp" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'txids' and 'feerate' parameters.")
+            })?;
+            create_cpfp(control, params)?
+        }
         "createrecovery" => {
             let params = req.params.ok_or_else(|| {
                 Error::invalid_params("Missing 'address' and 'feerate' parameters.")
             })?;
             create_recovery(control, params)?
         }
+        "createrefresh" => {
+            let params = req.params.ok_or_else(|| {
+                Error::invalid_params("Missing 'threshold' and 'feerate' parameters.")
+            })?;
+            create_refresh(control, params)?
+        }
         "createspend" => {
             let params = req.params.ok_or_else(|| {
                 Error::invalid_params(
@@ -189,8 +511,29 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
                 .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
             delete_spend(control, params)?
         }
-        "getinfo" => serde_json::json!(&control.get_info()),
+        "exportlabels" => serde_json::json!(&control.export_labels()),
+        "freezecoins" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'outpoints' parameter."))?;
+            freeze_coins(control, params)?
+        }
+        "getfeeestimates" => serde_json::json!(&control.get_fee_estimates()?),
+        "getinfo" => get_info(control, req.params)?,
+        "getlabels" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'items' parameter."))?;
+            get_labels(control, params)?
+        }
         "getnewaddress" => serde_json::json!(&control.get_new_address()),
+        "getpolicy" => get_policy(control, req.params)?,
+        "importlabels" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'labels' parameter."))?;
+            import_labels(control, params)?
+        }
         "listcoins" => serde_json::json!(&control.list_coins()),
         "listconfirmed" => {
             let params = req.params.ok_or_else(|| {
@@ -200,6 +543,7 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
             })?;
             list_confirmed(control, params)?
         }
+        "listexpiringcoins" => list_expiring_coins(control, req.params)?,
         "listspendtxs" => serde_json::json!(&control.list_spend()),
         "listtransactions" => {
             let params = req.params.ok_or_else(|| {
@@ -209,6 +553,18 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
             })?;
             list_transactions(control, params)?
         }
+        "rbfpsbt" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' and 'feerate' parameters."))?;
+            rbf_psbt(control, params)?
+        }
+        "signspend" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'txid' parameter."))?;
+            sign_spend(control, params)?
+        }
         "startrescan" => {
             let params = req
                 .params
@@ -216,6 +572,18 @@ pub fn handle_request(control: &DaemonControl, req: Request) -> Result<Response,
             start_rescan(control, params)?
         }
         "stop" => serde_json::json!({}),
+        "unfreezecoins" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'outpoints' parameter."))?;
+            unfreeze_coins(control, params)?
+        }
+        "updatelabels" => {
+            let params = req
+                .params
+                .ok_or_else(|| Error::invalid_params("Missing 'labels' parameter."))?;
+            update_labels(control, params)?
+        }
         "updatespend" => {
             let params = req
                 .params
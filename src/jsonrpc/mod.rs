@@ -154,8 +154,12 @@ impl From<commands::CommandError> for Error {
             commands::CommandError::NoOutpoint
             | commands::CommandError::UnknownOutpoint(..)
             | commands::CommandError::InvalidFeerate(..)
+            | commands::CommandError::InvalidConfTarget(..)
+            | commands::CommandError::NoFeeEstimate(..)
             | commands::CommandError::AlreadySpent(..)
             | commands::CommandError::ImmatureCoinbase(..)
+            | commands::CommandError::FrozenCoin(..)
+            | commands::CommandError::ReservedCoin(..)
             | commands::CommandError::Address(..)
             | commands::CommandError::InvalidOutputValue(..)
             | commands::CommandError::InsufficientFunds(..)
@@ -164,12 +168,23 @@ impl From<commands::CommandError> for Error {
             | commands::CommandError::SpendFinalization(..)
             | commands::CommandError::InsaneRescanTimestamp(..)
             | commands::CommandError::AlreadyRescanning
-            | commands::CommandError::RecoveryNotAvailable => {
+            | commands::CommandError::RecoveryNotAvailable
+            | commands::CommandError::CoinNotRecoverable(..)
+            | commands::CommandError::NoCoinToRefresh(..)
+            | commands::CommandError::UnknownRecoveryTimelock(..)
+            | commands::CommandError::NoRelativeRecoveryPath
+            | commands::CommandError::RbfError(..)
+            | commands::CommandError::CpfpError(..)
+            | commands::CommandError::InvalidBip329(..)
+            | commands::CommandError::NoHotSigner
+            | commands::CommandError::HotSigner(..) => {
                 Error::new(ErrorCode::InvalidParams, e.to_string())
             }
             commands::CommandError::FetchingTransaction(..)
             | commands::CommandError::SanityCheckFailure(_)
-            | commands::CommandError::RescanTrigger(..) => {
+            | commands::CommandError::RescanTrigger(..)
+            | commands::CommandError::Randomness(..)
+            | commands::CommandError::BitcoinBackend(..) => {
                 Error::new(ErrorCode::InternalError, e.to_string())
             }
             commands::CommandError::TxBroadcast(_) => {
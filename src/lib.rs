@@ -15,12 +15,16 @@ mod testutils;
 pub use bip39;
 pub use miniscript;
 
-pub use crate::bitcoin::d::{BitcoinD, BitcoindError, WalletError};
+pub use crate::bitcoin::{
+    d::{BitcoinD, BitcoindError, WalletError},
+    electrum::{Electrum, ElectrumError},
+    esplora::{Esplora, EsploraError},
+};
 #[cfg(feature = "daemon")]
 use crate::jsonrpc::server::{rpcserver_loop, rpcserver_setup};
 use crate::{
     bitcoin::{poller, BitcoinInterface},
-    config::Config,
+    config::{Config, ElectrumConfig, EsploraConfig},
     database::{
         sqlite::{FreshDbOptions, SqliteDb, SqliteDbError},
         DatabaseInterface,
@@ -93,6 +97,8 @@ pub enum StartupError {
     WindowsBitcoindWatchonlyDeletion(path::PathBuf, io::Error),
     Database(SqliteDbError),
     Bitcoind(BitcoindError),
+    Electrum(ElectrumError),
+    Esplora(EsploraError),
     #[cfg(unix)]
     Daemonization(&'static str),
 }
@@ -124,6 +130,8 @@ impl fmt::Display for StartupError {
             ),
             Self::Database(e) => write!(f, "Error initializing database: '{}'.", e),
             Self::Bitcoind(e) => write!(f, "Error setting up bitcoind interface: '{}'.", e),
+            Self::Electrum(e) => write!(f, "Error setting up Electrum interface: '{}'.", e),
+            Self::Esplora(e) => write!(f, "Error setting up Esplora interface: '{}'.", e),
             #[cfg(unix)]
             Self::Daemonization(e) => write!(f, "Error when daemonizing: '{}'.", e),
         }
@@ -150,6 +158,18 @@ impl From<BitcoindError> for StartupError {
     }
 }
 
+impl From<ElectrumError> for StartupError {
+    fn from(e: ElectrumError) -> Self {
+        Self::Electrum(e)
+    }
+}
+
+impl From<EsploraError> for StartupError {
+    fn from(e: EsploraError) -> Self {
+        Self::Esplora(e)
+    }
+}
+
 fn create_datadir(datadir_path: &path::Path) -> Result<(), StartupError> {
     #[cfg(unix)]
     return {
@@ -191,7 +211,7 @@ fn setup_sqlite(
     } else {
         None
     };
-    let sqlite = SqliteDb::new(db_path, options, secp)?;
+    let sqlite = SqliteDb::new(db_path, options, config.bitcoin_config.gap_limit, secp)?;
     sqlite.sanity_check(config.bitcoin_config.network, &config.main_descriptor)?;
     log::info!("Database initialized and checked.");
 
@@ -302,6 +322,33 @@ fn setup_bitcoind(
     Ok(bitcoind)
 }
 
+// Connect to the Electrum server and check it's on our network.
+fn setup_electrum(
+    config: &Config,
+    electrum_config: &ElectrumConfig,
+) -> Result<Electrum, StartupError> {
+    let electrum = Electrum::new(
+        electrum_config,
+        config.bitcoin_config.network,
+        config.bitcoin_config.gap_limit,
+    )?;
+    log::info!("Connection to the Electrum server established and checked.");
+
+    Ok(electrum)
+}
+
+// Query the Esplora server and check it's on our network.
+fn setup_esplora(config: &Config, esplora_config: &EsploraConfig) -> Result<Esplora, StartupError> {
+    let esplora = Esplora::new(
+        esplora_config,
+        config.bitcoin_config.network,
+        config.bitcoin_config.gap_limit,
+    )?;
+    log::info!("Connection to the Esplora server established and checked.");
+
+    Ok(esplora)
+}
+
 #[derive(Clone)]
 pub struct DaemonControl {
     config: Config,
@@ -342,7 +389,9 @@ impl DaemonHandle {
     /// This starts the Liana daemon. Call `shutdown` to shut it down.
     ///
     /// You may specify a custom Bitcoin interface through the `bitcoin` parameter. If `None`, the
-    /// default Bitcoin interface (`bitcoind` JSONRPC) will be used.
+    /// Bitcoin interface configured will be used: an Electrum server if there is an
+    /// `electrum_config` entry, an Esplora server if there is an `esplora_config` entry, `bitcoind`
+    /// JSONRPC otherwise.
     /// You may specify a custom Database interface through the `db` parameter. If `None`, the
     /// default Database interface (SQLite) will be used.
     ///
@@ -381,9 +430,17 @@ impl DaemonHandle {
         };
 
         // Now, set up the Bitcoin interface.
-        let bit = match bitcoin {
-            Some(bit) => sync::Arc::from(sync::Mutex::from(bit)),
-            None => sync::Arc::from(sync::Mutex::from(setup_bitcoind(
+        let bit = match (bitcoin, &config.electrum_config, &config.esplora_config) {
+            (Some(bit), _, _) => sync::Arc::from(sync::Mutex::from(bit)),
+            (None, Some(electrum_config), _) => {
+                sync::Arc::from(sync::Mutex::from(setup_electrum(&config, electrum_config)?))
+                    as sync::Arc<sync::Mutex<dyn BitcoinInterface>>
+            }
+            (None, None, Some(esplora_config)) => {
+                sync::Arc::from(sync::Mutex::from(setup_esplora(&config, esplora_config)?))
+                    as sync::Arc<sync::Mutex<dyn BitcoinInterface>>
+            }
+            (None, None, None) => sync::Arc::from(sync::Mutex::from(setup_bitcoind(
                 &config,
                 &data_dir,
                 fresh_data_dir,
@@ -410,6 +467,7 @@ impl DaemonHandle {
             db.clone(),
             config.bitcoin_config.poll_interval_secs,
             config.main_descriptor.clone(),
+            config.bitcoin_config.gap_limit,
         );
 
         // Finally, set up the API.
@@ -421,8 +479,8 @@ impl DaemonHandle {
         })
     }
 
-    /// Start the Liana daemon with the default Bitcoin and database interfaces (`bitcoind` RPC
-    /// and SQLite).
+    /// Start the Liana daemon with the default Bitcoin and database interfaces (`bitcoind` RPC, or
+    /// an Electrum or Esplora server if configured, and SQLite).
     pub fn start_default(config: Config) -> Result<DaemonHandle, StartupError> {
         DaemonHandle::start(config, Option::<BitcoinD>::None, Option::<SqliteDb>::None)
     }
@@ -487,7 +545,7 @@ impl DaemonHandle {
 mod tests {
     use super::*;
     use crate::{
-        config::{BitcoinConfig, BitcoindConfig},
+        config::{BitcoinConfig, BitcoindConfig, DEFAULT_GAP_LIMIT},
         descriptors::LianaDescriptor,
         testutils::*,
     };
@@ -710,6 +768,7 @@ mod tests {
         let bitcoin_config = BitcoinConfig {
             network,
             poll_interval_secs: time::Duration::from_secs(2),
+            gap_limit: DEFAULT_GAP_LIMIT,
         };
         let bitcoind_config = BitcoindConfig {
             addr,
@@ -724,6 +783,8 @@ mod tests {
         let config = Config {
             bitcoin_config,
             bitcoind_config: Some(bitcoind_config),
+            electrum_config: None,
+            esplora_config: None,
             data_dir: Some(data_dir),
             #[cfg(unix)]
             daemon: false,
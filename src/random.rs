@@ -89,6 +89,16 @@ pub fn random_bytes() -> Result<[u8; 32], RandomnessError> {
     Ok(sha256::Hash::from_engine(engine).to_byte_array())
 }
 
+/// Get a random integer in the `[0, upper)` range. The modulo bias is negligible for the small
+/// ranges we use it for.
+pub fn random_below(upper: u64) -> Result<u64, RandomnessError> {
+    assert!(upper > 0);
+    let bytes = random_bytes()?;
+    let mut int_bytes = [0; 8];
+    int_bytes.copy_from_slice(&bytes[..8]);
+    Ok(u64::from_le_bytes(int_bytes) % upper)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,6 +117,14 @@ mod tests {
         }
     }
 
+    #[test]
+    fn random_below_range() {
+        for upper in 1..100 {
+            assert!(random_below(upper).unwrap() < upper);
+        }
+        assert_eq!(random_below(1).unwrap(), 0);
+    }
+
     // I used this to perform statistical tests of the random generation function using ENT
     // (https://fourmilab.ch/random/).
     //#[test]
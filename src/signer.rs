@@ -3,7 +3,10 @@
 //! Some helpers to facilitate the usage of a signer in client of the Liana daemon. For now
 //! only contains a hot signer.
 
-use crate::random;
+use crate::{
+    descriptors::{DerivedSinglePathLianaDesc, LianaDescriptor},
+    random,
+};
 
 use std::{
     convert::TryInto,
@@ -17,10 +20,12 @@ use miniscript::bitcoin::{
     self,
     bip32::{self, Error as Bip32Error},
     ecdsa,
-    hashes::Hash,
-    psbt::Psbt,
-    secp256k1, sighash,
+    hashes::{hex::FromHex, Hash},
+    key::TapTweak,
+    psbt::{Input as PsbtIn, Psbt},
+    secp256k1, sighash, taproot,
 };
+use serde::{Deserialize, Serialize};
 
 /// An error related to using a signer.
 #[derive(Debug)]
@@ -29,6 +34,22 @@ pub enum SignerError {
     Mnemonic(bip39::Error),
     Bip32(Bip32Error),
     MnemonicStorage(io::Error),
+    /// The content of a mnemonic file couldn't be parsed.
+    MnemonicFile(String),
+    /// The mnemonic is encrypted but no passphrase was provided.
+    MissingPassphrase,
+    /// The mnemonic couldn't be decrypted with this passphrase.
+    Decryption,
+    /// A BIP39 passphrase may only be stored along with the mnemonic if it's encrypted.
+    UnencryptedBip39Passphrase,
+    /// Only 12, 18 and 24 words mnemonics may be generated.
+    WordsCount(usize),
+    /// The input at this index doesn't spend a coin from our descriptor, or its spending
+    /// information doesn't match the coin.
+    InputMismatch(usize),
+    /// The output at this index has the derivation information of a change output, but doesn't
+    /// pay to our descriptor.
+    ChangeMismatch(usize),
     InsanePsbt,
     IncompletePsbt,
 }
@@ -40,6 +61,34 @@ impl fmt::Display for SignerError {
             Self::Mnemonic(s) => write!(f, "Error when working with mnemonics: {}", s),
             Self::Bip32(e) => write!(f, "BIP32 error: {}", e),
             Self::MnemonicStorage(e) => write!(f, "BIP39 mnemonic storage error: {}", e),
+            Self::MnemonicFile(e) => write!(f, "Invalid BIP39 mnemonic file: {}", e),
+            Self::MissingPassphrase => write!(
+                f,
+                "The BIP39 mnemonic is encrypted and no passphrase was provided."
+            ),
+            Self::Decryption => write!(
+                f,
+                "Failed to decrypt the BIP39 mnemonic: wrong passphrase or corrupted file."
+            ),
+            Self::UnencryptedBip39Passphrase => write!(
+                f,
+                "Refusing to store a BIP39 passphrase in clear text along with the mnemonic."
+            ),
+            Self::WordsCount(c) => write!(
+                f,
+                "Invalid number of words for a BIP39 mnemonic: {}. Must be 12, 18 or 24.",
+                c
+            ),
+            Self::InputMismatch(i) => write!(
+                f,
+                "Input #{} does not spend a coin of our descriptor, or its information doesn't match it.",
+                i
+            ),
+            Self::ChangeMismatch(i) => write!(
+                f,
+                "Output #{} pretends to be change but does not pay to our descriptor.",
+                i
+            ),
             Self::InsanePsbt => write!(f, "Information contained in the PSBT is wrong."),
             Self::IncompletePsbt => write!(
                 f,
@@ -53,11 +102,28 @@ impl error::Error for SignerError {}
 
 pub const MNEMONICS_FOLDER_NAME: &str = "mnemonics";
 
-// TODO: zeroize, mlock, etc.. For now we don't even encrypt the seed on disk so that'd be
-// overkill.
+// The first line of a mnemonic file is this header, followed by the version of the format and
+// how the payload on the second line is stored. The (possibly encrypted) payload is a JSON object
+// with the mnemonic words and the BIP39 passphrase, if any. Files written before the format was
+// versioned only contain the mnemonic words in clear text.
+const MNEMONIC_FILE_HEADER: &str = "liana-mnemonic";
+const MNEMONIC_FILE_VERSION: u8 = 1;
+const MNEMONIC_CLEAR: &str = "clear";
+const MNEMONIC_ENCRYPTED: &str = "scrypt-chacha20poly1305";
+
+// The scrypt parameters used to derive the encryption key of a mnemonic from a passphrase. That's
+// 2^15 iterations with a block size of 8, using 32MiB of memory. When reading a file we refuse to
+// use more than 2^20 iterations, or any other block size and parallelization parameters.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_MAX_LOG_N: u8 = 20;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+// TODO: zeroize, mlock, etc..
 /// A signer that keeps the key on the laptop. Based on BIP39.
 pub struct HotSigner {
     mnemonic: bip39::Mnemonic,
+    bip39_passphrase: Option<String>,
     master_xpriv: bip32::ExtendedPrivKey,
 }
 
@@ -98,31 +164,348 @@ fn create_file(path: &path::Path) -> Result<fs::File, std::io::Error> {
     };
 }
 
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Derive the key to encrypt a mnemonic from the passphrase.
+fn mnemonic_encryption_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: &scrypt::Params,
+) -> chacha20poly1305::Key {
+    let mut key = chacha20poly1305::Key::default();
+    scrypt::scrypt(passphrase.as_bytes(), salt, params, &mut key)
+        .expect("The key is 32 bytes long");
+    key
+}
+
+// The payload of a mnemonic file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct StoredMnemonic {
+    mnemonic: String,
+    bip39_passphrase: Option<String>,
+}
+
+// Serialize the content of a mnemonic file, encrypting the mnemonic if a passphrase is given. The
+// key is derived from the passphrase using scrypt with a random salt, and the mnemonic encrypted
+// using ChaCha20Poly1305 with a random nonce. The header line is authenticated along with it.
+// The BIP39 passphrase is never stored in clear next to the mnemonic, as it would defeat its
+// purpose.
+fn mnemonic_file_content(
+    stored: &StoredMnemonic,
+    passphrase: Option<&str>,
+) -> Result<String, SignerError> {
+    let payload = serde_json::to_string(stored).expect("Serialization can't fail");
+    let passphrase = match passphrase {
+        Some(passphrase) => passphrase,
+        None => {
+            if stored.bip39_passphrase.is_some() {
+                return Err(SignerError::UnencryptedBip39Passphrase);
+            }
+            return Ok(format!(
+                "{} {} {}\n{}",
+                MNEMONIC_FILE_HEADER, MNEMONIC_FILE_VERSION, MNEMONIC_CLEAR, payload
+            ));
+        }
+    };
+    use chacha20poly1305::aead::{Aead, NewAead, Payload};
+
+    let random_bytes = random::random_bytes().map_err(SignerError::Randomness)?;
+    let salt = &random_bytes[..16];
+    let nonce: [u8; 12] = random_bytes[16..28].try_into().expect("12 bytes");
+    let params = scrypt::Params::new(SCRYPT_LOG_N, SCRYPT_R, SCRYPT_P).expect("Valid parameters");
+    let header = format!(
+        "{} {} {} {} {} {} {} {}",
+        MNEMONIC_FILE_HEADER,
+        MNEMONIC_FILE_VERSION,
+        MNEMONIC_ENCRYPTED,
+        SCRYPT_LOG_N,
+        SCRYPT_R,
+        SCRYPT_P,
+        to_hex(salt),
+        to_hex(&nonce)
+    );
+    let cipher = chacha20poly1305::ChaCha20Poly1305::new(&mnemonic_encryption_key(
+        passphrase, salt, &params,
+    ));
+    let ciphertext = cipher
+        .encrypt(
+            &chacha20poly1305::Nonce::from(nonce),
+            Payload {
+                msg: payload.as_bytes(),
+                aad: header.as_bytes(),
+            },
+        )
+        .expect("Encryption can't fail");
+
+    Ok(format!("{}\n{}", header, to_hex(&ciphertext)))
+}
+
+// Get the mnemonic from the content of a mnemonic file, decrypting it if necessary.
+fn mnemonic_from_file_content(
+    content: &str,
+    passphrase: Option<&str>,
+) -> Result<StoredMnemonic, SignerError> {
+    let mut lines = content.lines();
+    let header = lines.next().unwrap_or("");
+    let mut header_parts = header.split(' ');
+    if header_parts.next() != Some(MNEMONIC_FILE_HEADER) {
+        // A file written before the format was versioned.
+        return Ok(StoredMnemonic {
+            mnemonic: content.to_string(),
+            bip39_passphrase: None,
+        });
+    }
+    let invalid = |msg: &str| SignerError::MnemonicFile(msg.to_string());
+    if header_parts.next().and_then(|v| u8::from_str(v).ok()) != Some(MNEMONIC_FILE_VERSION) {
+        return Err(invalid("unknown format version"));
+    }
+    let payload = lines.next().ok_or_else(|| invalid("missing mnemonic"))?;
+
+    let payload = match header_parts.next() {
+        Some(MNEMONIC_CLEAR) => Ok(payload.to_string()),
+        Some(MNEMONIC_ENCRYPTED) => {
+            use chacha20poly1305::aead::{Aead, NewAead, Payload};
+
+            let passphrase = passphrase.ok_or(SignerError::MissingPassphrase)?;
+            let mut next_part = || {
+                header_parts
+                    .next()
+                    .ok_or_else(|| invalid("truncated header"))
+            };
+            let log_n = u8::from_str(next_part()?).map_err(|_| invalid("invalid scrypt log_n"))?;
+            let r = u32::from_str(next_part()?).map_err(|_| invalid("invalid scrypt r"))?;
+            let p = u32::from_str(next_part()?).map_err(|_| invalid("invalid scrypt p"))?;
+            let salt = Vec::<u8>::from_hex(next_part()?).map_err(|_| invalid("invalid salt"))?;
+            let nonce = <[u8; 12]>::from_hex(next_part()?).map_err(|_| invalid("invalid nonce"))?;
+            if log_n > SCRYPT_MAX_LOG_N || r != SCRYPT_R || p != SCRYPT_P {
+                return Err(invalid("unsupported scrypt parameters"));
+            }
+            let params = scrypt::Params::new(log_n, r, p)
+                .map_err(|_| invalid("invalid scrypt parameters"))?;
+            let ciphertext =
+                Vec::<u8>::from_hex(payload).map_err(|_| invalid("invalid ciphertext"))?;
+
+            let cipher = chacha20poly1305::ChaCha20Poly1305::new(&mnemonic_encryption_key(
+                passphrase, &salt, &params,
+            ));
+            let plaintext = cipher
+                .decrypt(
+                    &chacha20poly1305::Nonce::from(nonce),
+                    Payload {
+                        msg: &ciphertext,
+                        aad: header.as_bytes(),
+                    },
+                )
+                .map_err(|_| SignerError::Decryption)?;
+            String::from_utf8(plaintext).map_err(|_| SignerError::Decryption)
+        }
+        _ => Err(invalid("unknown storage method")),
+    }?;
+
+    serde_json::from_str(&payload).map_err(|e| invalid(&e.to_string()))
+}
+
+/// What a transaction does with the coins of the wallet, as checked against its descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendSummary {
+    /// The total value of the coins spent by this transaction.
+    pub spent: bitcoin::Amount,
+    /// The outputs of the transaction which don't pay back to the wallet.
+    pub outgoing: Vec<bitcoin::TxOut>,
+    /// The total value of the outputs paying back to the wallet.
+    pub change: bitcoin::Amount,
+    pub fee: bitcoin::Amount,
+}
+
+// The derivation index these BIP32 derivation paths all end with, if any.
+fn claimed_index<'a>(
+    mut der_paths: impl Iterator<Item = &'a bip32::DerivationPath>,
+) -> Option<bip32::ChildNumber> {
+    let index = *der_paths.next()?.into_iter().last()?;
+    if !index.is_normal() || der_paths.any(|path| path.into_iter().last() != Some(&index)) {
+        return None;
+    }
+    Some(index)
+}
+
+// Get our descriptor at the given derivation index, for receiving or change, as long as it pays
+// to this Script.
+fn wallet_desc_at(
+    desc: &LianaDescriptor,
+    index: bip32::ChildNumber,
+    script_pubkey: &bitcoin::Script,
+    secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+) -> Option<DerivedSinglePathLianaDesc> {
+    [desc.receive_descriptor(), desc.change_descriptor()]
+        .iter()
+        .map(|desc| desc.derive(index, secp))
+        .find(|desc| desc.script_pubkey().as_script() == script_pubkey)
+}
+
+/// Check the given PSBT only spends coins from our descriptor and the information for signing
+/// them is correct. Also check the outputs which claim to be change pay to our descriptor.
+/// Returns a summary of the value leaving the wallet and the fees paid by the transaction.
+pub fn check_psbt(
+    psbt: &Psbt,
+    desc: &LianaDescriptor,
+    secp: &secp256k1::Secp256k1<impl secp256k1::Verification>,
+) -> Result<SpendSummary, SignerError> {
+    if psbt.inputs.len() != psbt.unsigned_tx.input.len()
+        || psbt.outputs.len() != psbt.unsigned_tx.output.len()
+    {
+        return Err(SignerError::InsanePsbt);
+    }
+
+    let mut spent = bitcoin::Amount::from_sat(0);
+    for (i, psbt_in) in psbt.inputs.iter().enumerate() {
+        let txo = psbt_in
+            .witness_utxo
+            .as_ref()
+            .ok_or(SignerError::IncompletePsbt)?;
+        spent = spent
+            .checked_add(bitcoin::Amount::from_sat(txo.value))
+            .ok_or(SignerError::InsanePsbt)?;
+
+        // Find the coin we spend from the derivation paths of the keys, and check the spending
+        // information is the one we'd have set.
+        let der_paths = psbt_in
+            .bip32_derivation
+            .values()
+            .map(|(_, path)| path)
+            .chain(psbt_in.tap_key_origins.values().map(|(_, (_, path))| path));
+        let coin_desc = claimed_index(der_paths)
+            .and_then(|index| wallet_desc_at(desc, index, &txo.script_pubkey, secp))
+            .ok_or(SignerError::InputMismatch(i))?;
+        let mut expected = PsbtIn::default();
+        coin_desc.update_psbt_in(&mut expected);
+        if psbt_in.witness_script != expected.witness_script
+            || psbt_in.bip32_derivation != expected.bip32_derivation
+            || psbt_in.tap_internal_key != expected.tap_internal_key
+            || psbt_in.tap_merkle_root != expected.tap_merkle_root
+            || psbt_in.tap_scripts != expected.tap_scripts
+            || psbt_in.tap_key_origins != expected.tap_key_origins
+        {
+            return Err(SignerError::InputMismatch(i));
+        }
+
+        // Contrary to Taproot ones, P2WSH signatures don't commit to the value of all the coins
+        // spent. Make sure the value of the coin (and therefore the fee) isn't lied about by
+        // checking it against the transaction which created it.
+        if expected.tap_internal_key.is_none() {
+            let prevout = psbt.unsigned_tx.input[i].previous_output;
+            let prev_tx = psbt_in
+                .non_witness_utxo
+                .as_ref()
+                .ok_or(SignerError::IncompletePsbt)?;
+            if prev_tx.txid() != prevout.txid
+                || prev_tx.output.get(prevout.vout as usize) != Some(txo)
+            {
+                return Err(SignerError::InputMismatch(i));
+            }
+        }
+    }
+
+    let (mut outgoing, mut change) = (Vec::new(), bitcoin::Amount::from_sat(0));
+    let mut sent = bitcoin::Amount::from_sat(0);
+    for (i, (txo, psbt_out)) in psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .zip(psbt.outputs.iter())
+        .enumerate()
+    {
+        sent = sent
+            .checked_add(bitcoin::Amount::from_sat(txo.value))
+            .ok_or(SignerError::InsanePsbt)?;
+        let der_paths = psbt_out
+            .bip32_derivation
+            .values()
+            .map(|(_, path)| path)
+            .chain(psbt_out.tap_key_origins.values().map(|(_, (_, path))| path));
+        let mut der_paths = der_paths.peekable();
+        if der_paths.peek().is_none() {
+            outgoing.push(txo.clone());
+            continue;
+        }
+        claimed_index(der_paths)
+            .and_then(|index| wallet_desc_at(desc, index, &txo.script_pubkey, secp))
+            .ok_or(SignerError::ChangeMismatch(i))?;
+        change += bitcoin::Amount::from_sat(txo.value);
+    }
+
+    let fee = spent.checked_sub(sent).ok_or(SignerError::InsanePsbt)?;
+    Ok(SpendSummary {
+        spent,
+        outgoing,
+        change,
+        fee,
+    })
+}
+
+// Create a BIP340 signature, using fresh randomness as auxiliary data.
+fn sign_schnorr(
+    secp: &secp256k1::Secp256k1<impl secp256k1::Signing>,
+    msg: &secp256k1::Message,
+    keypair: &secp256k1::KeyPair,
+) -> Result<secp256k1::schnorr::Signature, SignerError> {
+    let aux_rand = random::random_bytes().map_err(SignerError::Randomness)?;
+    Ok(secp.sign_schnorr_with_aux_rand(msg, keypair, &aux_rand))
+}
+
 impl HotSigner {
     fn from_mnemonic(
         network: bitcoin::Network,
         mnemonic: bip39::Mnemonic,
+        bip39_passphrase: Option<&str>,
     ) -> Result<Self, SignerError> {
-        let master_xpriv = bip32::ExtendedPrivKey::new_master(network, &mnemonic.to_seed(""))
-            .map_err(SignerError::Bip32)?;
+        let seed = mnemonic.to_seed(bip39_passphrase.unwrap_or(""));
+        let master_xpriv =
+            bip32::ExtendedPrivKey::new_master(network, &seed).map_err(SignerError::Bip32)?;
         Ok(Self {
             mnemonic,
+            bip39_passphrase: bip39_passphrase.map(|p| p.to_string()),
             master_xpriv,
         })
     }
 
     /// Create a new hot signer from random bytes. Uses a 12-words mnemonics without a passphrase.
     pub fn generate(network: bitcoin::Network) -> Result<Self, SignerError> {
-        // We want a 12-words mnemonic so we only use 16 of the 32 bytes.
+        Self::generate_with(network, 12, None)
+    }
+
+    /// Create a new hot signer from random bytes, with a 12, 18 or 24 words mnemonic. If a BIP39
+    /// passphrase is given, it is used along with the mnemonic to derive the master key.
+    pub fn generate_with(
+        network: bitcoin::Network,
+        words_count: usize,
+        bip39_passphrase: Option<&str>,
+    ) -> Result<Self, SignerError> {
+        // Each word encodes 11 bits, of which 1 out of 33 is for the checksum.
+        let entropy_len = match words_count {
+            12 | 18 | 24 => words_count * 4 / 3,
+            _ => return Err(SignerError::WordsCount(words_count)),
+        };
         let random_32bytes = random::random_bytes().map_err(SignerError::Randomness)?;
-        let mnemonic =
-            bip39::Mnemonic::from_entropy(&random_32bytes[..16]).map_err(SignerError::Mnemonic)?;
-        Self::from_mnemonic(network, mnemonic)
+        let mnemonic = bip39::Mnemonic::from_entropy(&random_32bytes[..entropy_len])
+            .map_err(SignerError::Mnemonic)?;
+        Self::from_mnemonic(network, mnemonic, bip39_passphrase)
     }
 
     pub fn from_str(network: bitcoin::Network, s: &str) -> Result<Self, SignerError> {
+        Self::from_str_with_passphrase(network, s, None)
+    }
+
+    /// Import a hot signer from its BIP39 mnemonic and the BIP39 passphrase used along with it,
+    /// if any. Note a different passphrase results in a different master key.
+    pub fn from_str_with_passphrase(
+        network: bitcoin::Network,
+        s: &str,
+        bip39_passphrase: Option<&str>,
+    ) -> Result<Self, SignerError> {
         let mnemonic = bip39::Mnemonic::from_str(s).map_err(SignerError::Mnemonic)?;
-        Self::from_mnemonic(network, mnemonic)
+        Self::from_mnemonic(network, mnemonic, bip39_passphrase)
     }
 
     fn mnemonics_folder(datadir_root: &path::Path, network: bitcoin::Network) -> path::PathBuf {
@@ -135,34 +518,45 @@ impl HotSigner {
         .collect()
     }
 
-    /// Read all the mnemonics from the datadir for the given network.
+    /// Read all the mnemonics from the datadir for the given network. The passphrase is used to
+    /// decrypt the encrypted mnemonics, it is ignored for those stored in clear.
     pub fn from_datadir(
         datadir_root: &path::Path,
         network: bitcoin::Network,
+        passphrase: Option<&str>,
     ) -> Result<Vec<Self>, SignerError> {
         let mut signers = Vec::new();
 
         let mnemonic_paths = fs::read_dir(Self::mnemonics_folder(datadir_root, network))
             .map_err(SignerError::MnemonicStorage)?;
         for entry in mnemonic_paths {
-            let mnemonic = fs::read_to_string(entry.map_err(SignerError::MnemonicStorage)?.path())
+            let content = fs::read_to_string(entry.map_err(SignerError::MnemonicStorage)?.path())
                 .map_err(SignerError::MnemonicStorage)?;
-            signers.push(Self::from_str(network, &mnemonic)?);
+            let stored = mnemonic_from_file_content(&content, passphrase)?;
+            signers.push(Self::from_str_with_passphrase(
+                network,
+                &stored.mnemonic,
+                stored.bip39_passphrase.as_deref(),
+            )?);
         }
 
         Ok(signers)
     }
 
     /// The BIP39 mnemonics from which the master key of this signer is derived.
-    pub fn words(&self) -> [&'static str; 12] {
-        let words: Vec<&'static str> = self.mnemonic.word_iter().collect();
-        words.try_into().expect("Always 12 words")
+    pub fn words(&self) -> Vec<&'static str> {
+        self.mnemonic.word_iter().collect()
+    }
+
+    /// The BIP39 passphrase used along with the mnemonic to derive the master key, if any.
+    pub fn bip39_passphrase(&self) -> Option<&str> {
+        self.bip39_passphrase.as_deref()
     }
 
     /// The BIP39 mnemonic words as a string.
     pub fn mnemonic_str(&self) -> String {
-        let mut mnemonic_str = String::with_capacity(12 * 7);
         let words = self.words();
+        let mut mnemonic_str = String::with_capacity(words.len() * 7);
 
         for (i, word) in words.iter().enumerate() {
             mnemonic_str += word;
@@ -184,12 +578,14 @@ impl HotSigner {
 
     /// Store the mnemonic in a file within the given "data directory".
     /// The file is stored within a "mnemonics" folder, with the filename set to the fingerprint of
-    /// the master xpub corresponding to this mnemonic.
+    /// the master xpub corresponding to this mnemonic. If a passphrase is given the mnemonic is
+    /// encrypted with a key derived from it.
     pub fn store(
         &self,
         datadir_root: &path::Path,
         network: bitcoin::Network,
         secp: &secp256k1::Secp256k1<impl secp256k1::Signing>,
+        passphrase: Option<&str>,
     ) -> Result<(), SignerError> {
         let mut mnemonics_folder = Self::mnemonics_folder(datadir_root, network);
         if !mnemonics_folder.exists() {
@@ -197,12 +593,17 @@ impl HotSigner {
         }
 
         // This will fail if a file with this fingerprint exists already.
+        let stored = StoredMnemonic {
+            mnemonic: self.mnemonic_str(),
+            bip39_passphrase: self.bip39_passphrase.clone(),
+        };
+        let content = mnemonic_file_content(&stored, passphrase)?;
         mnemonics_folder.push(format!("mnemonic-{:x}.txt", self.fingerprint(secp)));
         let mnemonic_path = mnemonics_folder;
         let mut mnemonic_file =
             create_file(&mnemonic_path).map_err(SignerError::MnemonicStorage)?;
         mnemonic_file
-            .write_all(self.mnemonic_str().as_bytes())
+            .write_all(content.as_bytes())
             .map_err(SignerError::MnemonicStorage)?;
 
         Ok(())
@@ -228,21 +629,95 @@ impl HotSigner {
         bip32::ExtendedPubKey::from_priv(secp, &xpriv)
     }
 
+    /// Sign all inputs of the given PSBT, after checking it against our descriptor. See
+    /// [check_psbt] for the checks performed. Returns the signed PSBT along with a summary of what
+    /// the transaction does with our coins.
+    pub fn sign_psbt_checked(
+        &self,
+        psbt: Psbt,
+        desc: &LianaDescriptor,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Signing + secp256k1::Verification>,
+    ) -> Result<(Psbt, SpendSummary), SignerError> {
+        let summary = check_psbt(&psbt, desc, secp)?;
+        Ok((self.sign_psbt(psbt, secp)?, summary))
+    }
+
     /// Sign all inputs of the given PSBT.
     ///
-    /// **This does not perform any check. It will blindly sign anything that's passed.**
+    /// **This does not perform any check. It will blindly sign anything that's passed.** Use
+    /// [HotSigner::sign_psbt_checked] to sign only transactions spending from our descriptor.
     pub fn sign_psbt(
         &self,
         mut psbt: Psbt,
-        secp: &secp256k1::Secp256k1<impl secp256k1::Signing>,
+        secp: &secp256k1::Secp256k1<impl secp256k1::Signing + secp256k1::Verification>,
     ) -> Result<Psbt, SignerError> {
         let master_fingerprint = self.fingerprint(secp);
         let mut sighash_cache = sighash::SighashCache::new(&psbt.unsigned_tx);
 
+        // Taproot sighashes commit to all the coins spent by the transaction.
+        let prevouts: Option<Vec<bitcoin::TxOut>> = psbt
+            .inputs
+            .iter()
+            .map(|psbt_in| psbt_in.witness_utxo.clone())
+            .collect();
+
         // Sign each input in the PSBT.
         for i in 0..psbt.inputs.len() {
-            // First of all compute the sighash for this input. We assume P2WSH spend: the sighash
-            // script code is always the witness script.
+            // A Taproot input. Provide a signature for the key path if it is one of our keys and
+            // for all the script leaves our keys appear in.
+            if psbt.inputs[i].tap_internal_key.is_some() {
+                let prevouts = prevouts.as_ref().ok_or(SignerError::IncompletePsbt)?;
+                let prevouts = sighash::Prevouts::All(prevouts);
+                let sig_type = sighash::TapSighashType::Default;
+                for (curr_pubkey, (leaf_hashes, (fingerprint, der_path))) in
+                    psbt.inputs[i].tap_key_origins.clone()
+                {
+                    if fingerprint != master_fingerprint {
+                        continue;
+                    }
+                    let privkey = self.xpriv_at(&der_path, secp).to_priv();
+                    let keypair = secp256k1::KeyPair::from_secret_key(secp, &privkey.inner);
+                    if keypair.x_only_public_key().0 != curr_pubkey {
+                        return Err(SignerError::InsanePsbt);
+                    }
+
+                    if psbt.inputs[i].tap_internal_key == Some(curr_pubkey) {
+                        let sighash = sighash_cache
+                            .taproot_key_spend_signature_hash(i, &prevouts, sig_type)
+                            .map_err(|_| SignerError::InsanePsbt)?;
+                        let sighash = secp256k1::Message::from_slice(sighash.as_byte_array())
+                            .expect("Sighash is always 32 bytes.");
+                        let keypair = keypair
+                            .tap_tweak(secp, psbt.inputs[i].tap_merkle_root)
+                            .to_inner();
+                        let sig = sign_schnorr(secp, &sighash, &keypair)?;
+                        psbt.inputs[i].tap_key_sig = Some(taproot::Signature {
+                            sig,
+                            hash_ty: sig_type,
+                        });
+                    }
+
+                    for leaf_hash in leaf_hashes {
+                        let sighash = sighash_cache
+                            .taproot_script_spend_signature_hash(i, &prevouts, leaf_hash, sig_type)
+                            .map_err(|_| SignerError::InsanePsbt)?;
+                        let sighash = secp256k1::Message::from_slice(sighash.as_byte_array())
+                            .expect("Sighash is always 32 bytes.");
+                        let sig = sign_schnorr(secp, &sighash, &keypair)?;
+                        psbt.inputs[i].tap_script_sigs.insert(
+                            (curr_pubkey, leaf_hash),
+                            taproot::Signature {
+                                sig,
+                                hash_ty: sig_type,
+                            },
+                        );
+                    }
+                }
+                continue;
+            }
+
+            // Otherwise it's a P2WSH spend. First of all compute the sighash for this input. The
+            // sighash script code is always the witness script.
             let witscript = psbt.inputs[i]
                 .witness_script
                 .as_ref()
@@ -298,8 +773,12 @@ mod tests {
     use super::*;
     use crate::{descriptors, testutils::*};
     use miniscript::{
-        bitcoin::{locktime::absolute, psbt::Input as PsbtIn},
+        bitcoin::{
+            locktime::absolute,
+            psbt::{Input as PsbtIn, Output as PsbtOut},
+        },
         descriptor::{DerivPaths, DescriptorMultiXKey, DescriptorPublicKey, Wildcard},
+        psbt::PsbtExt,
     };
     use std::collections::{BTreeMap, HashSet};
 
@@ -333,6 +812,77 @@ mod tests {
         );
     }
 
+    #[test]
+    fn hot_signer_bip39_passphrase() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let network = bitcoin::Network::Bitcoin;
+
+        // 18 and 24 words mnemonics may be generated, too.
+        for words_count in &[12, 18, 24] {
+            let signer = HotSigner::generate_with(network, *words_count, None).unwrap();
+            assert_eq!(signer.words().len(), *words_count);
+        }
+        assert!(matches!(
+            HotSigner::generate_with(network, 15, None),
+            Err(SignerError::WordsCount(15))
+        ));
+
+        // The passphrase is used for deriving the master key. Test vector from BIP39.
+        let mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let signer =
+            HotSigner::from_str_with_passphrase(network, mnemonic, Some("TREZOR")).unwrap();
+        assert_eq!(signer.bip39_passphrase(), Some("TREZOR"));
+        assert_eq!(signer.master_xpriv.to_string(), "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF");
+        assert_ne!(
+            signer.fingerprint(&secp),
+            HotSigner::from_str(network, mnemonic)
+                .unwrap()
+                .fingerprint(&secp)
+        );
+
+        // The passphrase is stored along with the mnemonic, but only if it's encrypted.
+        let tmp_dir = tmp_dir();
+        fs::create_dir_all(&tmp_dir).unwrap();
+        let signers = [
+            HotSigner::generate_with(network, 24, Some("passphrase")).unwrap(),
+            HotSigner::generate_with(network, 18, Some("")).unwrap(),
+        ];
+        assert!(matches!(
+            signers[0].store(&tmp_dir, network, &secp, None),
+            Err(SignerError::UnencryptedBip39Passphrase)
+        ));
+        for signer in &signers {
+            signer
+                .store(&tmp_dir, network, &secp, Some("encryption"))
+                .unwrap();
+        }
+        let mut read_signers = HotSigner::from_datadir(&tmp_dir, network, Some("encryption"))
+            .unwrap()
+            .into_iter()
+            .map(|signer| {
+                (
+                    signer.mnemonic_str(),
+                    signer.bip39_passphrase().map(|p| p.to_string()),
+                    signer.fingerprint(&secp),
+                )
+            })
+            .collect::<Vec<_>>();
+        read_signers.sort_by_key(|(words, _, _)| words.split(' ').count());
+        assert_eq!(
+            read_signers,
+            signers
+                .iter()
+                .rev()
+                .map(|signer| (
+                    signer.mnemonic_str(),
+                    signer.bip39_passphrase().map(|p| p.to_string()),
+                    signer.fingerprint(&secp)
+                ))
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
     #[test]
     fn hot_signer_storage() {
         let secp = secp256k1::Secp256k1::signing_only();
@@ -343,11 +893,11 @@ mod tests {
         let words_set: HashSet<_> = (0..10)
             .map(|_| {
                 let signer = HotSigner::generate(network).unwrap();
-                signer.store(&tmp_dir, network, &secp).unwrap();
+                signer.store(&tmp_dir, network, &secp, None).unwrap();
                 signer.words()
             })
             .collect();
-        let words_read: HashSet<_> = HotSigner::from_datadir(&tmp_dir, network)
+        let words_read: HashSet<_> = HotSigner::from_datadir(&tmp_dir, network, None)
             .unwrap()
             .into_iter()
             .map(|signer| signer.words())
@@ -357,6 +907,91 @@ mod tests {
         fs::remove_dir_all(tmp_dir).unwrap();
     }
 
+    #[test]
+    fn hot_signer_encrypted_storage() {
+        let secp = secp256k1::Secp256k1::signing_only();
+        let tmp_dir = tmp_dir();
+        fs::create_dir_all(&tmp_dir).unwrap();
+        let network = bitcoin::Network::Bitcoin;
+        let mnemonics_folder = HotSigner::mnemonics_folder(&tmp_dir, network);
+
+        // Store an encrypted mnemonic, a mnemonic in clear and one in the format which predates
+        // versioning.
+        let encrypted_signer = HotSigner::generate(network).unwrap();
+        encrypted_signer
+            .store(&tmp_dir, network, &secp, Some("correct horse"))
+            .unwrap();
+        let encrypted_path = mnemonics_folder.join(format!(
+            "mnemonic-{:x}.txt",
+            encrypted_signer.fingerprint(&secp)
+        ));
+        let encrypted_content = fs::read_to_string(&encrypted_path).unwrap();
+        assert!(!encrypted_content.contains(&encrypted_signer.mnemonic_str()));
+        let clear_signer = HotSigner::generate(network).unwrap();
+        clear_signer.store(&tmp_dir, network, &secp, None).unwrap();
+        let legacy_signer = HotSigner::generate(network).unwrap();
+        fs::write(
+            mnemonics_folder.join(format!(
+                "mnemonic-{:x}.txt",
+                legacy_signer.fingerprint(&secp)
+            )),
+            legacy_signer.mnemonic_str(),
+        )
+        .unwrap();
+
+        // They can all be read with the passphrase.
+        let words_read: HashSet<_> =
+            HotSigner::from_datadir(&tmp_dir, network, Some("correct horse"))
+                .unwrap()
+                .into_iter()
+                .map(|signer| signer.words())
+                .collect();
+        let words_set: HashSet<_> = [&encrypted_signer, &clear_signer, &legacy_signer]
+            .iter()
+            .map(|signer| signer.words())
+            .collect();
+        assert_eq!(words_read, words_set);
+
+        // But not without it, or with a wrong one.
+        assert!(matches!(
+            HotSigner::from_datadir(&tmp_dir, network, None),
+            Err(SignerError::MissingPassphrase)
+        ));
+        assert!(matches!(
+            HotSigner::from_datadir(&tmp_dir, network, Some("battery staple")),
+            Err(SignerError::Decryption)
+        ));
+
+        // The header is authenticated along with the mnemonic.
+        let tampered_content = encrypted_content.replacen(" 15 8 1 ", " 14 8 1 ", 1);
+        assert_ne!(tampered_content, encrypted_content);
+        assert!(matches!(
+            mnemonic_from_file_content(&tampered_content, Some("correct horse")),
+            Err(SignerError::Decryption)
+        ));
+        assert!(matches!(
+            mnemonic_from_file_content(
+                &encrypted_content.replacen("liana-mnemonic 1", "liana-mnemonic 42", 1),
+                Some("correct horse")
+            ),
+            Err(SignerError::MnemonicFile(_))
+        ));
+
+        // We refuse to use scrypt parameters other than the ones we write, as they could make us
+        // use an unreasonable amount of memory or time.
+        for params in &[" 21 8 1 ", " 15 4096 1 ", " 15 8 4096 "] {
+            assert!(matches!(
+                mnemonic_from_file_content(
+                    &encrypted_content.replacen(" 15 8 1 ", params, 1),
+                    Some("correct horse")
+                ),
+                Err(SignerError::MnemonicFile(_))
+            ));
+        }
+
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
     #[test]
     fn hot_signer_sign() {
         let secp = secp256k1::Secp256k1::new();
@@ -455,8 +1090,6 @@ mod tests {
             proprietary: BTreeMap::new(),
             unknown: BTreeMap::new(),
             inputs: vec![PsbtIn {
-                witness_script: Some(spent_coin_desc.witness_script()),
-                bip32_derivation: spent_coin_desc.bip32_derivations(),
                 witness_utxo: Some(bitcoin::TxOut {
                     value: 19_000,
                     script_pubkey: spent_coin_desc.script_pubkey(),
@@ -465,6 +1098,7 @@ mod tests {
             }],
             outputs: Vec::new(),
         };
+        spent_coin_desc.update_psbt_in(&mut dummy_psbt.inputs[0]);
 
         // Sign the PSBT with the two primary signers. The second signer will sign for the two keys
         // that it manages.
@@ -497,15 +1131,15 @@ mod tests {
         // We can add another input to the PSBT. If we don't attach also another transaction input
         // it will fail.
         let other_spent_coin_desc = desc.receive_descriptor().derive(84.into(), &secp);
-        dummy_psbt.inputs.push(PsbtIn {
-            witness_script: Some(other_spent_coin_desc.witness_script()),
-            bip32_derivation: other_spent_coin_desc.bip32_derivations(),
+        let mut psbt_in = PsbtIn {
             witness_utxo: Some(bitcoin::TxOut {
                 value: 19_000,
                 script_pubkey: other_spent_coin_desc.script_pubkey(),
             }),
             ..PsbtIn::default()
-        });
+        };
+        other_spent_coin_desc.update_psbt_in(&mut psbt_in);
+        dummy_psbt.inputs.push(psbt_in);
         let psbt = dummy_psbt.clone();
         assert!(prim_signer_a
             .sign_psbt(psbt, &secp)
@@ -576,6 +1210,310 @@ mod tests {
         assert_eq!(psbt.inputs[1].partial_sigs.len(), 2);
     }
 
+    #[test]
+    fn hot_signer_sign_checked() {
+        let secp = secp256k1::Secp256k1::new();
+        let network = bitcoin::Network::Bitcoin;
+        let (prim_signer, recov_signer) = (
+            HotSigner::generate(network).unwrap(),
+            HotSigner::generate(network).unwrap(),
+        );
+        let desc_key = |signer: &HotSigner| {
+            let origin_der = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+            DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+                origin: Some((signer.fingerprint(&secp), origin_der.clone())),
+                xkey: signer.xpub_at(&origin_der, &secp),
+                derivation_paths: DerivPaths::new(vec![
+                    bip32::DerivationPath::from_str("m/0").unwrap(),
+                    bip32::DerivationPath::from_str("m/1").unwrap(),
+                ])
+                .unwrap(),
+                wildcard: Wildcard::Unhardened,
+            })
+        };
+        let prim_path = descriptors::PathInfo::Single(desc_key(&prim_signer));
+        let recov_paths: BTreeMap<_, _> =
+            [(42, descriptors::PathInfo::Single(desc_key(&recov_signer)))]
+                .iter()
+                .cloned()
+                .collect();
+        let wsh_desc = descriptors::LianaDescriptor::new(
+            descriptors::LianaPolicy::new(prim_path.clone(), recov_paths.clone()).unwrap(),
+        );
+        let tr_desc = descriptors::LianaDescriptor::new(
+            descriptors::LianaPolicy::new_taproot(prim_path, recov_paths, BTreeMap::new()).unwrap(),
+        );
+        let external_txo = bitcoin::TxOut {
+            value: 50_000,
+            script_pubkey: bitcoin::Address::from_str("bc1qvklensptw5lk7d470ds60pcpsr0psdpgyvwepv")
+                .unwrap()
+                .payload
+                .script_pubkey(),
+        };
+
+        for desc in &[wsh_desc, tr_desc] {
+            // A PSBT spending a coin at index 3 of our receive descriptor, sending to a third
+            // party and to our change descriptor at index 4.
+            let spent_coin_desc = desc.receive_descriptor().derive(3.into(), &secp);
+            let change_desc = desc.change_descriptor().derive(4.into(), &secp);
+            let spent_coin = bitcoin::TxOut {
+                value: 100_000,
+                script_pubkey: spent_coin_desc.script_pubkey(),
+            };
+            let prev_tx = bitcoin::Transaction {
+                version: 2,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn::default()],
+                output: vec![external_txo.clone(), spent_coin.clone()],
+            };
+            let mut psbt = Psbt::from_unsigned_tx(bitcoin::Transaction {
+                version: 2,
+                lock_time: absolute::LockTime::ZERO,
+                input: vec![bitcoin::TxIn {
+                    previous_output: bitcoin::OutPoint::new(prev_tx.txid(), 1),
+                    ..bitcoin::TxIn::default()
+                }],
+                output: vec![
+                    external_txo.clone(),
+                    bitcoin::TxOut {
+                        value: 49_000,
+                        script_pubkey: change_desc.script_pubkey(),
+                    },
+                ],
+            })
+            .unwrap();
+            psbt.inputs[0].witness_utxo = Some(spent_coin.clone());
+            psbt.inputs[0].non_witness_utxo = Some(prev_tx.clone());
+            spent_coin_desc.update_psbt_in(&mut psbt.inputs[0]);
+            change_desc.update_psbt_out(&mut psbt.outputs[1]);
+
+            let summary = check_psbt(&psbt, desc, &secp).unwrap();
+            assert_eq!(
+                summary,
+                SpendSummary {
+                    spent: bitcoin::Amount::from_sat(100_000),
+                    outgoing: vec![external_txo.clone()],
+                    change: bitcoin::Amount::from_sat(49_000),
+                    fee: bitcoin::Amount::from_sat(1_000),
+                }
+            );
+            let (signed_psbt, signed_summary) = prim_signer
+                .sign_psbt_checked(psbt.clone(), desc, &secp)
+                .unwrap();
+            assert_eq!(signed_summary, summary);
+            assert!(
+                !signed_psbt.inputs[0].partial_sigs.is_empty()
+                    || signed_psbt.inputs[0].tap_key_sig.is_some()
+            );
+
+            // The spending information of the input must be the one of our coin at this index.
+            let mut bad_psbt = psbt.clone();
+            bad_psbt.inputs[0] = PsbtIn {
+                witness_utxo: psbt.inputs[0].witness_utxo.clone(),
+                ..PsbtIn::default()
+            };
+            desc.receive_descriptor()
+                .derive(5.into(), &secp)
+                .update_psbt_in(&mut bad_psbt.inputs[0]);
+            assert!(matches!(
+                prim_signer.sign_psbt_checked(bad_psbt, desc, &secp),
+                Err(SignerError::InputMismatch(0))
+            ));
+            let mut bad_psbt = psbt.clone();
+            bad_psbt.inputs[0].witness_script = Some(external_txo.script_pubkey.clone());
+            bad_psbt.inputs[0].tap_scripts.clear();
+            assert!(matches!(
+                check_psbt(&bad_psbt, desc, &secp),
+                Err(SignerError::InputMismatch(0))
+            ));
+
+            // The change output must pay to our descriptor at the index it claims.
+            let mut bad_psbt = psbt.clone();
+            bad_psbt.outputs[1] = PsbtOut::default();
+            desc.change_descriptor()
+                .derive(5.into(), &secp)
+                .update_psbt_out(&mut bad_psbt.outputs[1]);
+            assert!(matches!(
+                check_psbt(&bad_psbt, desc, &secp),
+                Err(SignerError::ChangeMismatch(1))
+            ));
+
+            // Without the derivation information it's considered to be leaving the wallet.
+            let mut psbt_no_change = psbt.clone();
+            psbt_no_change.outputs[1] = PsbtOut::default();
+            let summary = check_psbt(&psbt_no_change, desc, &secp).unwrap();
+            assert_eq!(summary.outgoing.len(), 2);
+            assert_eq!(summary.change, bitcoin::Amount::from_sat(0));
+
+            // The outputs may not be worth more than the inputs.
+            let mut bad_psbt = psbt.clone();
+            bad_psbt.unsigned_tx.output[0].value = 60_000;
+            assert!(matches!(
+                check_psbt(&bad_psbt, desc, &secp),
+                Err(SignerError::InsanePsbt)
+            ));
+
+            // And their total value may not overflow.
+            let mut bad_psbt = psbt.clone();
+            bad_psbt.unsigned_tx.output[0].value = u64::MAX;
+            bad_psbt.unsigned_tx.output[1].value = u64::MAX;
+            assert!(matches!(
+                check_psbt(&bad_psbt, desc, &secp),
+                Err(SignerError::InsanePsbt)
+            ));
+
+            // For P2WSH coins, the value of the coin must match the one in the transaction which
+            // created it. This isn't necessary for Taproot coins since the signature commits to
+            // the value of all the coins spent.
+            let is_taproot = psbt.inputs[0].tap_internal_key.is_some();
+            let mut forged_psbt = psbt.clone();
+            forged_psbt.inputs[0].witness_utxo.as_mut().unwrap().value = 200_000;
+            if is_taproot {
+                assert_eq!(
+                    check_psbt(&forged_psbt, desc, &secp).unwrap().fee,
+                    bitcoin::Amount::from_sat(101_000)
+                );
+            } else {
+                assert!(matches!(
+                    check_psbt(&forged_psbt, desc, &secp),
+                    Err(SignerError::InputMismatch(0))
+                ));
+                let mut bad_psbt = psbt.clone();
+                bad_psbt.unsigned_tx.input[0].previous_output.vout = 0;
+                assert!(matches!(
+                    check_psbt(&bad_psbt, desc, &secp),
+                    Err(SignerError::InputMismatch(0))
+                ));
+                let mut bad_psbt = psbt.clone();
+                bad_psbt.inputs[0].non_witness_utxo = None;
+                assert!(matches!(
+                    check_psbt(&bad_psbt, desc, &secp),
+                    Err(SignerError::IncompletePsbt)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn signer_psbt_taproot() {
+        let secp = secp256k1::Secp256k1::new();
+        let network = bitcoin::Network::Bitcoin;
+        let (prim_signer_a, prim_signer_b, recov_signer) = (
+            HotSigner::generate(network).unwrap(),
+            HotSigner::generate(network).unwrap(),
+            HotSigner::generate(network).unwrap(),
+        );
+        let desc_key = |signer: &HotSigner| {
+            let origin_der = bip32::DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+            DescriptorPublicKey::MultiXPub(DescriptorMultiXKey {
+                origin: Some((signer.fingerprint(&secp), origin_der.clone())),
+                xkey: signer.xpub_at(&origin_der, &secp),
+                derivation_paths: DerivPaths::new(vec![
+                    bip32::DerivationPath::from_str("m/0").unwrap(),
+                    bip32::DerivationPath::from_str("m/1").unwrap(),
+                ])
+                .unwrap(),
+                wildcard: Wildcard::Unhardened,
+            })
+        };
+        let recov_keys = descriptors::PathInfo::Single(desc_key(&recov_signer));
+
+        // Create a PSBT spending a coin from the given descriptor, with the given nSequence.
+        let dummy_psbt = |desc: &descriptors::LianaDescriptor, sequence: bitcoin::Sequence| {
+            let spent_coin_desc = desc.receive_descriptor().derive(42.into(), &secp);
+            let mut psbt = Psbt {
+                unsigned_tx: bitcoin::Transaction {
+                    version: 2,
+                    lock_time: absolute::LockTime::Blocks(absolute::Height::ZERO),
+                    input: vec![bitcoin::TxIn {
+                        sequence,
+                        previous_output: bitcoin::OutPoint::from_str(
+                            "4613e078e4cdbb0fce1bc6e44b028f0e11621a134a1605efdc456c32d155c922:19",
+                        )
+                        .unwrap(),
+                        ..bitcoin::TxIn::default()
+                    }],
+                    output: vec![bitcoin::TxOut {
+                        value: 18_420,
+                        script_pubkey: bitcoin::Address::from_str(
+                            "bc1qvklensptw5lk7d470ds60pcpsr0psdpgyvwepv",
+                        )
+                        .unwrap()
+                        .payload
+                        .script_pubkey(),
+                    }],
+                },
+                version: 0,
+                xpub: BTreeMap::new(),
+                proprietary: BTreeMap::new(),
+                unknown: BTreeMap::new(),
+                inputs: vec![PsbtIn {
+                    witness_utxo: Some(bitcoin::TxOut {
+                        value: 19_000,
+                        script_pubkey: spent_coin_desc.script_pubkey(),
+                    }),
+                    ..PsbtIn::default()
+                }],
+                outputs: vec![PsbtOut::default()],
+            };
+            spent_coin_desc.update_psbt_in(&mut psbt.inputs[0]);
+            psbt
+        };
+
+        // A single key primary path is the key path spend. The primary signer provides a
+        // signature for the key path, and the PSBT can be finalized with it.
+        let policy = descriptors::LianaPolicy::new_taproot(
+            descriptors::PathInfo::Single(desc_key(&prim_signer_a)),
+            [(46, recov_keys.clone())].iter().cloned().collect(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let desc = descriptors::LianaDescriptor::new(policy);
+        let psbt = dummy_psbt(&desc, bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let psbt = prim_signer_a.sign_psbt(psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].tap_key_sig.is_some());
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+        let mut psbt = prim_signer_b.sign_psbt(psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].tap_script_sigs.is_empty());
+        psbt.finalize_mut(&secp).unwrap();
+
+        // The recovery signer provides a signature for the script leaf of the recovery path.
+        let psbt = dummy_psbt(&desc, bitcoin::Sequence::from_height(46));
+        let mut psbt = recov_signer.sign_psbt(psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].tap_key_sig.is_none());
+        assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 1);
+        psbt.finalize_mut(&secp).unwrap();
+
+        // A multisig primary path is a script leaf. Both primary signers need to sign for it.
+        let policy = descriptors::LianaPolicy::new_taproot(
+            descriptors::PathInfo::Multi(
+                2,
+                vec![desc_key(&prim_signer_a), desc_key(&prim_signer_b)],
+            ),
+            [(46, recov_keys)].iter().cloned().collect(),
+            BTreeMap::new(),
+        )
+        .unwrap();
+        let desc = descriptors::LianaDescriptor::new(policy);
+        let psbt = dummy_psbt(&desc, bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME);
+        let psbt = prim_signer_a.sign_psbt(psbt, &secp).unwrap();
+        assert!(psbt.inputs[0].tap_key_sig.is_none());
+        assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 1);
+        psbt.clone().finalize_mut(&secp).unwrap_err();
+        let mut psbt = prim_signer_b.sign_psbt(psbt, &secp).unwrap();
+        assert_eq!(psbt.inputs[0].tap_script_sigs.len(), 2);
+        psbt.finalize_mut(&secp).unwrap();
+
+        // The coins spent by the transaction are needed to compute the Taproot sighash.
+        let mut psbt = dummy_psbt(&desc, bitcoin::Sequence::ENABLE_RBF_NO_LOCKTIME);
+        psbt.inputs[0].witness_utxo = None;
+        assert!(prim_signer_a
+            .sign_psbt(psbt, &secp)
+            .unwrap_err()
+            .to_string()
+            .contains("The PSBT is missing some information necessary for signing."));
+    }
+
     #[test]
     fn signer_set_net() {
         let secp = secp256k1::Secp256k1::signing_only();
@@ -1,11 +1,16 @@
 use crate::{
-    bitcoin::{BitcoinInterface, Block, BlockChainTip, UTxO},
-    config::{BitcoinConfig, Config},
-    database::{BlockInfo, Coin, CoinType, DatabaseConnection, DatabaseInterface},
+    bitcoin::{BitcoinInterface, Block, BlockChainTip, MempoolEntry, UTxO},
+    config::{BitcoinConfig, Config, DEFAULT_GAP_LIMIT},
+    database::{BlockInfo, Coin, CoinType, DatabaseConnection, DatabaseInterface, LabelItem},
     descriptors, DaemonHandle,
 };
 
-use std::{collections::HashMap, env, fs, io, path, process, str::FromStr, sync, thread, time};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs, io, path, process,
+    str::FromStr,
+    sync, thread, time,
+};
 
 use miniscript::{
     bitcoin::{
@@ -16,6 +21,8 @@ use miniscript::{
 
 pub struct DummyBitcoind {
     pub txs: HashMap<Txid, (Transaction, Option<Block>)>,
+    pub mempool_entries: HashMap<Txid, MempoolEntry>,
+    pub fee_estimates: HashMap<u16, u64>,
 }
 
 impl DummyBitcoind {}
@@ -24,64 +31,77 @@ impl DummyBitcoind {
     pub fn new() -> Self {
         Self {
             txs: HashMap::new(),
+            mempool_entries: HashMap::new(),
+            fee_estimates: HashMap::new(),
         }
     }
 }
 
 impl BitcoinInterface for DummyBitcoind {
-    fn genesis_block(&self) -> BlockChainTip {
+    fn genesis_block(&self) -> Result<BlockChainTip, String> {
         let hash = bitcoin::BlockHash::from_str(
             "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
         )
         .unwrap();
-        BlockChainTip { hash, height: 0 }
+        Ok(BlockChainTip { hash, height: 0 })
     }
 
     fn sync_progress(&self) -> f64 {
         1.0
     }
 
-    fn chain_tip(&self) -> BlockChainTip {
+    fn chain_tip(&self) -> Result<BlockChainTip, String> {
         let hash = bitcoin::BlockHash::from_str(
             "000000007bc154e0fa7ea32218a72fe2c1bb9f86cf8c9ebf9a715ed27fdb229a",
         )
         .unwrap();
         let height = 100;
-        BlockChainTip { hash, height }
+        Ok(BlockChainTip { hash, height })
     }
 
-    fn is_in_chain(&self, _: &BlockChainTip) -> bool {
+    fn is_in_chain(&self, _: &BlockChainTip) -> Result<bool, String> {
         // No reorg
-        true
+        Ok(true)
     }
 
     fn received_coins(
         &self,
         _: &BlockChainTip,
         _: &[descriptors::SinglePathLianaDesc],
-    ) -> Vec<UTxO> {
-        Vec::new()
+    ) -> Result<Vec<UTxO>, String> {
+        Ok(Vec::new())
+    }
+
+    fn track_addresses(
+        &self,
+        _: &[descriptors::SinglePathLianaDesc],
+        _: u32,
+    ) -> Result<(), String> {
+        Ok(())
     }
 
     fn confirmed_coins(
         &self,
         _: &[bitcoin::OutPoint],
-    ) -> (Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>) {
-        (Vec::new(), Vec::new())
+    ) -> Result<(Vec<(bitcoin::OutPoint, i32, u32)>, Vec<bitcoin::OutPoint>), String> {
+        Ok((Vec::new(), Vec::new()))
     }
 
-    fn spending_coins(&self, _: &[bitcoin::OutPoint]) -> Vec<(bitcoin::OutPoint, bitcoin::Txid)> {
-        Vec::new()
+    fn spending_coins(
+        &self,
+        _: &[bitcoin::OutPoint],
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid)>, String> {
+        Ok(Vec::new())
     }
 
     fn spent_coins(
         &self,
         _: &[(bitcoin::OutPoint, bitcoin::Txid)],
-    ) -> Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)> {
-        Vec::new()
+    ) -> Result<Vec<(bitcoin::OutPoint, bitcoin::Txid, Block)>, String> {
+        Ok(Vec::new())
     }
 
-    fn common_ancestor(&self, _: &BlockChainTip) -> Option<BlockChainTip> {
+    fn common_ancestor(&self, _: &BlockChainTip) -> Result<Option<BlockChainTip>, String> {
         todo!()
     }
 
@@ -97,19 +117,35 @@ impl BitcoinInterface for DummyBitcoind {
         None
     }
 
-    fn block_before_date(&self, _: u32) -> Option<BlockChainTip> {
+    fn block_before_date(&self, _: u32) -> Result<Option<BlockChainTip>, String> {
         todo!()
     }
 
-    fn tip_time(&self) -> u32 {
+    fn tip_time(&self) -> Result<u32, String> {
         todo!()
     }
 
+    fn tip_median_time_past(&self) -> Result<u32, String> {
+        // The timestamp of mainnet's block 100.
+        Ok(1_231_660_825)
+    }
+
     fn wallet_transaction(
         &self,
         txid: &bitcoin::Txid,
-    ) -> Option<(bitcoin::Transaction, Option<Block>)> {
-        self.txs.get(txid).cloned()
+    ) -> Result<Option<(bitcoin::Transaction, Option<Block>)>, String> {
+        Ok(self.txs.get(txid).cloned())
+    }
+
+    fn mempool_entry(&self, txid: &bitcoin::Txid) -> Result<Option<MempoolEntry>, String> {
+        Ok(self.mempool_entries.get(txid).cloned())
+    }
+
+    fn fee_estimates(&self, conf_targets: &[u16]) -> Result<HashMap<u16, u64>, String> {
+        Ok(conf_targets
+            .iter()
+            .filter_map(|target| self.fee_estimates.get(target).map(|f| (*target, *f)))
+            .collect())
     }
 }
 
@@ -119,6 +155,8 @@ struct DummyDbState {
     curr_tip: Option<BlockChainTip>,
     coins: HashMap<bitcoin::OutPoint, Coin>,
     spend_txs: HashMap<bitcoin::Txid, (Psbt, Option<u32>)>,
+    txs: HashMap<bitcoin::Txid, bitcoin::Transaction>,
+    labels: Vec<(LabelItem, String)>,
 }
 
 pub struct DummyDatabase {
@@ -142,6 +180,8 @@ impl DummyDatabase {
                 curr_tip: None,
                 coins: HashMap::new(),
                 spend_txs: HashMap::new(),
+                txs: HashMap::new(),
+                labels: Vec::new(),
             })),
         }
     }
@@ -239,6 +279,14 @@ impl DatabaseConnection for DummyDatabase {
         }
     }
 
+    fn set_frozen_coins(&mut self, outpoints: &[bitcoin::OutPoint], frozen: bool) {
+        for op in outpoints {
+            if let Some(coin) = self.db.write().unwrap().coins.get_mut(op) {
+                coin.is_frozen = frozen;
+            }
+        }
+    }
+
     fn spend_coins<'a>(&mut self, outpoints: &[(bitcoin::OutPoint, bitcoin::Txid)]) {
         for (op, spend_txid) in outpoints {
             let mut db = self.db.write().unwrap();
@@ -362,6 +410,78 @@ impl DatabaseConnection for DummyDatabase {
         txids_and_time.truncate(limit as usize);
         txids_and_time.into_iter().map(|(txid, _)| txid).collect()
     }
+
+    fn new_txs(&mut self, txs: &[bitcoin::Transaction]) {
+        let db_txs = &mut self.db.write().unwrap().txs;
+        for tx in txs {
+            db_txs.entry(tx.txid()).or_insert_with(|| tx.clone());
+        }
+    }
+
+    fn list_missing_txids(&mut self) -> Vec<bitcoin::Txid> {
+        let db = self.db.read().unwrap();
+        let mut txids = Vec::new();
+        for coin in db.coins.values() {
+            for txid in Some(coin.outpoint.txid)
+                .iter()
+                .chain(coin.spend_txid.iter())
+            {
+                if !db.txs.contains_key(txid) && !txids.contains(txid) {
+                    txids.push(*txid);
+                }
+            }
+        }
+        txids
+    }
+
+    fn list_wallet_transactions(
+        &mut self,
+        txids: &[bitcoin::Txid],
+    ) -> Vec<(bitcoin::Transaction, Option<BlockInfo>)> {
+        let db = self.db.read().unwrap();
+        txids
+            .iter()
+            .filter_map(|txid| {
+                let tx = db.txs.get(txid)?.clone();
+                let block = db.coins.values().find_map(|coin| {
+                    if coin.outpoint.txid == *txid && coin.block_info.is_some() {
+                        coin.block_info
+                    } else if coin.spend_txid == Some(*txid) {
+                        coin.spend_block
+                    } else {
+                        None
+                    }
+                });
+                Some((tx, block))
+            })
+            .collect()
+    }
+
+    fn update_labels(&mut self, items: &HashMap<LabelItem, Option<String>>) {
+        let labels = &mut self.db.write().unwrap().labels;
+        for (item, value) in items {
+            match (labels.iter_mut().find(|(i, _)| i == item), value) {
+                (Some((_, v)), Some(value)) => *v = value.clone(),
+                (None, Some(value)) => labels.push((item.clone(), value.clone())),
+                (_, None) => labels.retain(|(i, _)| i != item),
+            }
+        }
+    }
+
+    fn labels(&mut self, items: &HashSet<LabelItem>) -> HashMap<LabelItem, String> {
+        self.db
+            .read()
+            .unwrap()
+            .labels
+            .iter()
+            .filter(|(item, _)| items.contains(item))
+            .cloned()
+            .collect()
+    }
+
+    fn list_labels(&mut self) -> Vec<(LabelItem, String)> {
+        self.db.read().unwrap().labels.clone()
+    }
 }
 
 pub struct DummyLiana {
@@ -392,6 +512,23 @@ impl DummyLiana {
     pub fn new(
         bitcoin_interface: impl BitcoinInterface + 'static,
         database: impl DatabaseInterface + 'static,
+    ) -> DummyLiana {
+        let owner_key = descriptors::PathInfo::Single(descriptor::DescriptorPublicKey::from_str("[aabbccdd]xpub68JJTXc1MWK8KLW4HGLXZBJknja7kDUJuFHnM424LbziEXsfkh1WQCiEjjHw4zLqSUm4rvhgyGkkuRowE9tCJSgt3TQB5J3SKAbZ2SdcKST/<0;1>/*").unwrap());
+        let heir_key = descriptors::PathInfo::Single(descriptor::DescriptorPublicKey::from_str("[aabbccdd]xpub68JJTXc1MWK8PEQozKsRatrUHXKFNkD1Cb1BuQU9Xr5moCv87anqGyXLyUd4KpnDyZgo3gz4aN1r3NiaoweFW8UutBsBbgKHzaD5HkTkifK/<0;1>/*").unwrap());
+        let policy = descriptors::LianaPolicy::new(
+            owner_key,
+            [(10_000, heir_key)].iter().cloned().collect(),
+        )
+        .unwrap();
+        let desc = descriptors::LianaDescriptor::new(policy);
+        DummyLiana::new_with_descriptor(bitcoin_interface, database, desc)
+    }
+
+    /// Creates a new DummyLiana interface for the given descriptor
+    pub fn new_with_descriptor(
+        bitcoin_interface: impl BitcoinInterface + 'static,
+        database: impl DatabaseInterface + 'static,
+        desc: descriptors::LianaDescriptor,
     ) -> DummyLiana {
         let tmp_dir = tmp_dir();
         fs::create_dir_all(&tmp_dir).unwrap();
@@ -402,19 +539,13 @@ impl DummyLiana {
         let bitcoin_config = BitcoinConfig {
             network,
             poll_interval_secs: time::Duration::from_secs(2),
+            gap_limit: DEFAULT_GAP_LIMIT,
         };
-
-        let owner_key = descriptors::PathInfo::Single(descriptor::DescriptorPublicKey::from_str("[aabbccdd]xpub68JJTXc1MWK8KLW4HGLXZBJknja7kDUJuFHnM424LbziEXsfkh1WQCiEjjHw4zLqSUm4rvhgyGkkuRowE9tCJSgt3TQB5J3SKAbZ2SdcKST/<0;1>/*").unwrap());
-        let heir_key = descriptors::PathInfo::Single(descriptor::DescriptorPublicKey::from_str("[aabbccdd]xpub68JJTXc1MWK8PEQozKsRatrUHXKFNkD1Cb1BuQU9Xr5moCv87anqGyXLyUd4KpnDyZgo3gz4aN1r3NiaoweFW8UutBsBbgKHzaD5HkTkifK/<0;1>/*").unwrap());
-        let policy = descriptors::LianaPolicy::new(
-            owner_key,
-            [(10_000, heir_key)].iter().cloned().collect(),
-        )
-        .unwrap();
-        let desc = descriptors::LianaDescriptor::new(policy);
         let config = Config {
             bitcoin_config,
             bitcoind_config: None,
+            electrum_config: None,
+            esplora_config: None,
             data_dir: Some(data_dir),
             #[cfg(unix)]
             daemon: false,
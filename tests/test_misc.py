@@ -233,6 +233,50 @@ def test_coinbase_deposit(lianad, bitcoind):
     )
 
 
+def test_gap_limit_discovery(lianad, bitcoind):
+    """Check we find coins paying to addresses past the gap limit when the addresses in between
+    get used too, like when the addresses were handed out by another wallet."""
+    desc = bitcoind.rpc.getdescriptorinfo(str(lianad.receive_desc))["descriptor"]
+    derive_addr = lambda i: bitcoind.rpc.deriveaddresses(desc, [i, i])[0]
+
+    # The coin at index 300 is past the default gap limit of 200, but we'll find it after finding
+    # the one at index 150. Both are received in the same block.
+    txids = [bitcoind.rpc.sendtoaddress(derive_addr(i), 0.01) for i in (150, 300)]
+    bitcoind.generate_block(1, wait_for_mempool=txids)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 2)
+
+    # A coin further than the gap limit from any used address won't be found.
+    txid = bitcoind.rpc.sendtoaddress(derive_addr(600), 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    assert len(lianad.rpc.listcoins()["coins"]) == 2
+
+
+def test_gap_limit_past_import_range(lianad, bitcoind):
+    """Check we keep finding coins as the derivation index grows past the range of addresses the
+    descriptors were initially imported with in the watchonly wallet."""
+    desc = bitcoind.rpc.getdescriptorinfo(str(lianad.receive_desc))["descriptor"]
+    derive_addr = lambda i: bitcoind.rpc.deriveaddresses(desc, [i, i])[0]
+
+    # Use addresses up to index 900, each within the gap limit of the previous one.
+    indexes = range(150, 901, 150)
+    txids = [bitcoind.rpc.sendtoaddress(derive_addr(i), 0.01) for i in indexes]
+    bitcoind.generate_block(1, wait_for_mempool=txids)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == len(indexes))
+
+    # Let the poller extend the tracked range past the highest index in use, then receive a coin
+    # past the initial range of 1000 addresses.
+    bitcoind.generate_block(1)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    txid = bitcoind.rpc.sendtoaddress(derive_addr(1050), 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == len(indexes) + 1)
+
+
 @pytest.mark.skipif(
     OLD_LIANAD_PATH is None, reason="Need the old lianad binary to create the datadir."
 )
@@ -259,6 +303,17 @@ def test_migration(lianad_multisig, bitcoind):
     lianad.cmd_line[0] = LIANAD_PATH
     lianad.restart_fresh(bitcoind)
 
+    # The transactions of the coins created by the older version, which didn't store them,
+    # are fetched from the Bitcoin backend.
+    coins = lianad.rpc.listcoins()["coins"]
+    txids = set(c["outpoint"][:-2] for c in coins) | set(
+        c["spend_info"]["txid"] for c in coins if c["spend_info"] is not None
+    )
+    wait_for(
+        lambda: len(lianad.rpc.listtransactions(list(txids))["transactions"])
+        == len(txids)
+    )
+
     # And we can go on to create more deposits and transactions. Make sure we now have
     # the "updated_at" field on tx drafts.
     receive_and_send(lianad, bitcoind)
@@ -36,6 +36,31 @@ def test_getaddress(lianad):
     assert res["address"] != lianad.rpc.getnewaddress()["address"]
 
 
+def test_getpolicy(lianad):
+    res = lianad.rpc.getpolicy()
+    policy = res["policy"]
+    assert not policy["is_taproot"]
+    assert policy["primary_path"]["threshold"] == 1
+    assert policy["primary_path"]["timelock"] is None
+    assert policy["primary_path"]["keys"][0]["fingerprint"] == "aabbccdd"
+    assert policy["primary_path"]["keys"][0]["alias"] is None
+    # We use a csv of 10 in the fixture.
+    assert len(policy["recovery_paths"]) == 1
+    assert policy["recovery_paths"][0]["timelock"] == {
+        "type": "relative",
+        "blocks": 10,
+        "approx_days": 0,
+    }
+    assert "Primary path, always available" in res["text"]
+
+    # The keys can be named.
+    res = lianad.rpc.getpolicy({"aabbccdd": "Alice"})
+    assert res["policy"]["primary_path"]["keys"][0]["alias"] == "Alice"
+    assert "Alice [aabbccdd]" in res["text"]
+    with pytest.raises(RpcError, match="Invalid 'aliases' parameter."):
+        lianad.rpc.getpolicy({"not a fingerprint": "Alice"})
+
+
 def test_listcoins(lianad, bitcoind):
     # Initially empty
     res = lianad.rpc.listcoins()
@@ -143,6 +168,183 @@ def test_create_spend(lianad, bitcoind):
         lianad.rpc.createspend(destinations, [imma_coin["outpoint"]], 1)
 
 
+def test_create_spend_coin_selection(lianad, bitcoind):
+    # Without any coin we can't create a transaction.
+    destinations = {bitcoind.rpc.getnewaddress(): 200_000}
+    with pytest.raises(RpcError, match=".*Cannot create a 2 sat/vb transaction.*"):
+        lianad.rpc.createspend(destinations, [], 2)
+
+    # Receive a few coins.
+    for amount in (0.001, 0.003, 0.01):
+        addr = lianad.rpc.getnewaddress()["address"]
+        txid = bitcoind.rpc.sendtoaddress(addr, amount)
+        bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 3)
+
+    # If we don't provide any outpoint, coins are selected for us.
+    res = lianad.rpc.createspend(destinations, [], 2)
+    spend_psbt = PSBT.from_base64(res["psbt"])
+    assert len(res["coins"]) == len(spend_psbt.tx.vin)
+    assert res["change_amount"] is not None
+    assert len(spend_psbt.tx.vout) == 2
+
+    # Once stored, the selected coins won't be selected for another transaction.
+    lianad.rpc.updatespend(res["psbt"])
+    res_b = lianad.rpc.createspend(destinations, [], 2)
+    assert not set(res["coins"]) & set(res_b["coins"])
+
+    # A send-to-self needs explicit coins.
+    with pytest.raises(RpcError, match=".*No provided outpoint.*"):
+        lianad.rpc.createspend({}, [], 2)
+
+
+def test_create_spend_send_max(lianad, bitcoind):
+    # Receive a few coins.
+    for amount in (0.001, 0.003, 0.01):
+        addr = lianad.rpc.getnewaddress()["address"]
+        txid = bitcoind.rpc.sendtoaddress(addr, amount)
+        bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 3)
+
+    # Pay a destination and send whatever is left to another one. There is no change.
+    destinations = {bitcoind.rpc.getnewaddress(): 200_000}
+    max_addr = bitcoind.rpc.getnewaddress()
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    res = lianad.rpc.createspend(destinations, outpoints[:2], 2, False, max_addr)
+    spend_psbt = PSBT.from_base64(res["psbt"])
+    assert res["change_amount"] is None
+    assert len(spend_psbt.tx.vout) == 2
+    assert sorted(res["coins"]) == sorted(outpoints[:2])
+
+    # Without any coin given, all our coins are swept to the address.
+    res = lianad.rpc.createspend({}, [], 2, False, max_addr)
+    spend_psbt = PSBT.from_base64(res["psbt"])
+    assert sorted(res["coins"]) == sorted(outpoints)
+    assert len(spend_psbt.tx.vout) == 1
+    txid = sign_and_broadcast(lianad, bitcoind, spend_psbt)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(
+        lambda: all(
+            c["spend_info"] is not None for c in lianad.rpc.listcoins()["coins"]
+        )
+    )
+    received = bitcoind.rpc.getreceivedbyaddress(max_addr)
+    assert 0.0139 < float(received) < 0.014
+
+
+def test_fee_estimates(lianad, bitcoind):
+    # Any estimate given is for one of the supported targets, in increasing order.
+    targets = [1, 2, 3, 6, 12, 24, 144, 504, 1008]
+    estimates = lianad.rpc.getfeeestimates()["estimates"]
+    assert [e["conf_target"] for e in estimates] == sorted(
+        e["conf_target"] for e in estimates
+    )
+    assert all(e["conf_target"] in targets and e["feerate"] >= 1 for e in estimates)
+
+    # A confirmation target can be given instead of a feerate.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    destinations = {bitcoind.rpc.getnewaddress(): 200_000}
+    estimated = {e["conf_target"]: e["feerate"] for e in estimates}
+    if 6 in estimated:
+        res = lianad.rpc.createspend(destinations, [], None, False, None, 6)
+        assert "psbt" in res
+    else:
+        with pytest.raises(RpcError, match=".*No fee estimate available.*"):
+            lianad.rpc.createspend(destinations, [], None, False, None, 6)
+    with pytest.raises(RpcError, match=".*Invalid confirmation target.*"):
+        lianad.rpc.createspend(destinations, [], None, False, None, 1009)
+    with pytest.raises(RpcError, match=".*Only one of 'feerate' and 'conf_target'.*"):
+        lianad.rpc.createspend(destinations, [], 2, False, None, 6)
+
+
+def test_draft_spends(lianad, bitcoind):
+    # Receive a coin.
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+    assert lianad.rpc.listcoins()["coins"][0]["draft_spends"] == []
+
+    # Once a Spend for it is stored, it's reserved by this draft.
+    destinations = {bitcoind.rpc.getnewaddress(): 100_000}
+    res = lianad.rpc.createspend(destinations, [outpoint], 2)
+    lianad.rpc.updatespend(res["psbt"])
+    spend_txid = PSBT.from_base64(res["psbt"]).tx.txid().hex()
+    assert lianad.rpc.listcoins()["coins"][0]["draft_spends"] == [spend_txid]
+    with pytest.raises(
+        RpcError,
+        match=f"Coin at '{outpoint}' is already spent by the stored Spend transaction '{spend_txid}'.",
+    ):
+        lianad.rpc.createspend(destinations, [outpoint], 2)
+
+    # Deleting the draft releases the coin.
+    lianad.rpc.delspendtx(spend_txid)
+    assert lianad.rpc.listcoins()["coins"][0]["draft_spends"] == []
+    res = lianad.rpc.createspend(destinations, [outpoint], 2)
+    lianad.rpc.updatespend(res["psbt"])
+    spend_txid = PSBT.from_base64(res["psbt"]).tx.txid().hex()
+
+    # Once broadcast, the Spend isn't a draft anymore.
+    signed_psbt = lianad.signer.sign_psbt(PSBT.from_base64(res["psbt"]))
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    lianad.rpc.broadcastspend(spend_txid)
+    wait_for(lambda: lianad.rpc.listcoins()["coins"][0]["spend_info"] is not None)
+    assert lianad.rpc.listcoins()["coins"][0]["draft_spends"] == []
+
+
+def test_freeze_coins(lianad, bitcoind):
+    # Receive two coins.
+    for amount in (0.01, 0.02):
+        addr = lianad.rpc.getnewaddress()["address"]
+        txid = bitcoind.rpc.sendtoaddress(addr, amount)
+        bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 2)
+    coins = lianad.rpc.listcoins()["coins"]
+    assert all(not c["is_frozen"] for c in coins)
+    frozen = next(c for c in coins if c["amount"] == 2_000_000)["outpoint"]
+    unfrozen = next(c for c in coins if c["amount"] == 1_000_000)["outpoint"]
+
+    # Freeze the largest one.
+    lianad.rpc.freezecoins([frozen])
+    coins = lianad.rpc.listcoins()["coins"]
+    assert next(c for c in coins if c["outpoint"] == frozen)["is_frozen"]
+    assert not next(c for c in coins if c["outpoint"] == unfrozen)["is_frozen"]
+
+    # It won't be spent unless explicitly requested.
+    destinations = {bitcoind.rpc.getnewaddress(): 1_500_000}
+    with pytest.raises(RpcError, match=".*Cannot create a 2 sat/vb transaction.*"):
+        lianad.rpc.createspend(destinations, [], 2)
+    with pytest.raises(RpcError, match=f"Coin at '{frozen}' is frozen."):
+        lianad.rpc.createspend(destinations, [frozen], 2)
+    res = lianad.rpc.createspend(destinations, [frozen], 2, True)
+    assert res["coins"] == [frozen]
+
+    # It won't be swept by a recovery transaction either.
+    bitcoind.generate_block(10)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    res = lianad.rpc.createrecovery(bitcoind.rpc.getnewaddress(), 2)
+    reco_psbt = PSBT.from_base64(res["psbt"])
+    assert len(reco_psbt.tx.vin) == 1
+    assert int(reco_psbt.tx.vout[0].nValue) < 1_000_000
+
+    # Once unfrozen it can be selected again.
+    lianad.rpc.unfreezecoins([frozen])
+    assert all(not c["is_frozen"] for c in lianad.rpc.listcoins()["coins"])
+    res = lianad.rpc.createspend(destinations, [], 2)
+    assert frozen in res["coins"]
+
+    # Unknown coins can't be frozen.
+    unknown = f"{'00' * 32}:0"
+    with pytest.raises(RpcError, match=f"Unknown outpoint '{unknown}'."):
+        lianad.rpc.freezecoins([unknown])
+
+
 def test_list_spend(lianad, bitcoind):
     # Start by creating two conflicting Spend PSBTs. The first one will have a change
     # output but not the second one.
@@ -283,6 +485,31 @@ def test_broadcast_spend(lianad, bitcoind):
     lianad.rpc.broadcastspend(txid)
 
 
+def test_sign_spend(lianad, bitcoind):
+    # Create a new coin and a spending tx for it.
+    addr = lianad.rpc.getnewaddress()["address"]
+    bitcoind.rpc.sendtoaddress(addr, 0.2567)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) > 0)
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    destinations = {
+        bitcoind.rpc.getnewaddress(): 200_000,
+    }
+    res = lianad.rpc.createspend(destinations, outpoints, 6)
+    psbt = PSBT.from_base64(res["psbt"])
+    txid = psbt.tx.txid().hex()
+
+    # We can't sign an unknown Spend
+    with pytest.raises(RpcError, match="Unknown spend transaction.*"):
+        lianad.rpc.signspend(txid)
+    lianad.rpc.updatespend(res["psbt"])
+
+    # There is no hot signer in the data directory of the functional tests.
+    with pytest.raises(RpcError, match="No hot signer in the data directory."):
+        lianad.rpc.signspend(txid)
+    with pytest.raises(RpcError, match="Invalid 'passphrase' parameter."):
+        lianad.rpc.signspend(txid, 42)
+
+
 def test_start_rescan(lianad, bitcoind):
     """Test we successfully retrieve all our transactions after losing state by rescanning."""
     initial_timestamp = int(time.time())
@@ -596,3 +823,164 @@ def test_create_recovery(lianad, bitcoind):
     assert len(reco_psbt.tx.vout) == 1
     assert int(0.39999 * COIN) < int(reco_psbt.tx.vout[0].nValue) < int(0.4 * COIN)
     sign_and_broadcast(lianad, bitcoind, reco_psbt, recovery=True)
+
+
+def test_create_recovery_partial(lianad, bitcoind):
+    """Test recovering only part of the coins available through the timelocked path."""
+    addrs = [lianad.rpc.getnewaddress()["address"] for _ in range(2)]
+    txid = bitcoind.rpc.sendmany("", {addrs[0]: 0.1, addrs[1]: 0.2})
+    bitcoind.generate_block(10, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    coins = lianad.rpc.listcoins()["coins"]
+    assert len(coins) == 2
+    big_coin = next(c for c in coins if c["amount"] == int(0.2 * COIN))
+
+    # Either an address or destinations must be given.
+    with pytest.raises(RpcError, match="Missing 'address' or 'destinations' parameter"):
+        lianad.rpc.createrecovery(None, 2)
+
+    # Pay part of a single coin to an external address, the rest goes back to the wallet.
+    dest = bitcoind.rpc.getnewaddress()
+    res = lianad.rpc.createrecovery(
+        None, 2, None, None, {dest: int(0.05 * COIN)}, [big_coin["outpoint"]]
+    )
+    reco_psbt = PSBT.from_base64(res["psbt"])
+    assert len(reco_psbt.tx.vin) == 1
+    assert len(reco_psbt.tx.vout) == 2
+    txid = sign_and_broadcast(lianad, bitcoind, reco_psbt, recovery=True)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+
+    # The change is back in the wallet, and the other coin was left untouched.
+    unspent = [c for c in lianad.rpc.listcoins()["coins"] if c["spend_info"] is None]
+    assert len(unspent) == 2
+    assert all(c["block_height"] is not None for c in unspent)
+    assert any(int(0.14 * COIN) < c["amount"] < int(0.15 * COIN) for c in unspent)
+
+
+def test_list_expiring_coins(lianad, bitcoind):
+    """Test we report when the timelocked path of our coins becomes available."""
+    assert lianad.rpc.listexpiringcoins()["coins"] == []
+    assert lianad.rpc.getinfo()["expiry"]["earliest_expiry"] is None
+
+    # Receive a coin. Its timelock starts once it's confirmed.
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.1)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    assert lianad.rpc.listexpiringcoins()["coins"] == []
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listexpiringcoins()["coins"]) == 1)
+    coin = lianad.rpc.listexpiringcoins()["coins"][0]
+    height = coin["block_height"]
+    # We use a csv of 10 in the fixture.
+    assert coin["expiries"] == [{"timelock": 10, "height": height + 10}]
+    expiry = lianad.rpc.getinfo()["expiry"]
+    assert expiry["earliest_expiry"] == height + 10
+    assert expiry["threshold"] == 1
+    assert expiry["value_at_risk"] == 0
+    assert lianad.rpc.listexpiringcoins(1)["coins"] == []
+
+    # Once the recovery path is available in the next block, it's at risk.
+    bitcoind.generate_block(9)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    assert lianad.rpc.getinfo()["expiry"]["value_at_risk"] == 10_000_000
+    assert len(lianad.rpc.listexpiringcoins(1)["coins"]) == 1
+
+
+def test_create_refresh(lianad, bitcoind):
+    """Test the refresh of coins whose timelocked path is about to be available."""
+    # Get two coins in a block and a third one in the next block.
+    destinations = {
+        lianad.rpc.getnewaddress()["address"]: 0.1,
+        lianad.rpc.getnewaddress()["address"]: 0.2,
+    }
+    txid = bitcoind.rpc.sendmany("", destinations)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    txid = bitcoind.rpc.sendtoaddress(lianad.rpc.getnewaddress()["address"], 0.3)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    first_coins = set(
+        c["outpoint"]
+        for c in lianad.rpc.listcoins()["coins"]
+        if c["amount"] < 25_000_000
+    )
+
+    # With a csv of 10 in the fixture, the first two coins' recovery path is available in 8
+    # blocks after the next one, and 9 blocks for the last one.
+    with pytest.raises(RpcError, match="No coin whose recovery path becomes available"):
+        lianad.rpc.createrefresh(8, 2)
+    res = lianad.rpc.createrefresh(9, 2)
+    assert set(res["coins"]) == first_coins
+    res = lianad.rpc.createrefresh(10, 2)
+    assert len(res["coins"]) == 3
+    refresh_psbt = PSBT.from_base64(res["psbt"])
+    assert len(refresh_psbt.tx.vout) == 1
+    assert res["change_amount"] is not None
+
+    # Once it's confirmed, the refreshed coins are spent and we got a fresh one.
+    txid = sign_and_broadcast(lianad, bitcoind, refresh_psbt)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(
+        lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount()
+    )
+    unspent = [c for c in lianad.rpc.listcoins()["coins"] if c["spend_info"] is None]
+    assert len(unspent) == 1
+    assert unspent[0]["outpoint"][:64] == txid
+    with pytest.raises(RpcError, match="No coin whose recovery path becomes available"):
+        lianad.rpc.createrefresh(9, 2)
+
+
+def test_labels(lianad, bitcoind):
+    """Test setting labels, and exporting and importing them in the BIP329 format."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    outpoint = lianad.rpc.listcoins()["coins"][0]["outpoint"]
+    items = [addr, txid, outpoint]
+    assert lianad.rpc.getlabels(items)["labels"] == {}
+
+    # Set a label on each item, and remove one of them.
+    lianad.rpc.updatelabels({addr: "deposit", txid: "payment", outpoint: "coin"})
+    assert lianad.rpc.getlabels(items)["labels"] == {
+        addr: "deposit",
+        txid: "payment",
+        outpoint: "coin",
+    }
+    lianad.rpc.updatelabels({outpoint: None, txid: "salary"})
+    assert lianad.rpc.getlabels(items)["labels"] == {addr: "deposit", txid: "salary"}
+
+    # Invalid items are rejected.
+    with pytest.raises(RpcError, match="Invalid 'labels' parameter."):
+        lianad.rpc.updatelabels({"not an item": "label"})
+    with pytest.raises(RpcError, match="Invalid 'items' parameter."):
+        lianad.rpc.getlabels(["not an item"])
+
+    # The labels are stored by the daemon, they survive a restart.
+    lianad.stop()
+    lianad.start()
+    assert lianad.rpc.getlabels(items)["labels"] == {addr: "deposit", txid: "salary"}
+
+    # They can be exported and imported in the BIP329 format.
+    exported = lianad.rpc.exportlabels()["labels"]
+    assert len(exported.splitlines()) == 2
+    lianad.rpc.updatelabels({addr: None, txid: None})
+    assert lianad.rpc.getlabels(items)["labels"] == {}
+    lianad.rpc.importlabels(
+        exported
+        + f'{{"type": "output", "ref": "{outpoint}", "label": "coin", "spendable": true}}\n'
+        + '{"type": "xpub", "ref": "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8", "label": "ignored"}\n'
+    )
+    assert lianad.rpc.getlabels(items)["labels"] == {
+        addr: "deposit",
+        txid: "salary",
+        outpoint: "coin",
+    }
+    with pytest.raises(RpcError, match="Invalid BIP329 labels at line 1"):
+        lianad.rpc.importlabels('{"type": "tx", "ref": "not a txid", "label": "a"}')
@@ -1,3 +1,5 @@
+import pytest
+
 from fixtures import *
 from test_framework.serializations import PSBT
 from test_framework.utils import wait_for, COIN, RpcError
@@ -219,3 +221,82 @@ def test_send_to_self(lianad, bitcoind):
         c for c in lianad.rpc.listcoins()["coins"] if c["spend_info"] is None
     )
     wait_for(lambda: len(list(unspent_coins())) == 1)
+
+
+def test_rbf_spend(lianad, bitcoind):
+    """We can bump the fee of an unconfirmed Spend transaction."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    txid = bitcoind.rpc.sendtoaddress(addr, 0.01)
+    bitcoind.generate_block(1, wait_for_mempool=txid)
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+
+    # Create, sign and broadcast a Spend transaction at 1sat/vb.
+    outpoints = [c["outpoint"] for c in lianad.rpc.listcoins()["coins"]]
+    dest_addr = bitcoind.rpc.getnewaddress()
+    res = lianad.rpc.createspend({dest_addr: 500_000}, outpoints, 1)
+    signed_psbt = lianad.signer.sign_psbt(PSBT.from_base64(res["psbt"]))
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    spend_txid = signed_psbt.tx.txid().hex()
+    lianad.rpc.broadcastspend(spend_txid)
+    wait_for(
+        lambda: lianad.rpc.listcoins()["coins"][0]["spend_info"] is not None
+    )
+
+    # The replacement must have a higher feerate.
+    with pytest.raises(RpcError, match=".*the feerate must be at least.*"):
+        lianad.rpc.rbfpsbt(spend_txid, 1)
+
+    # Bump it to 10sat/vb. It spends the same coin to the same destination, and
+    # the increased fee is taken from the change output.
+    res = lianad.rpc.rbfpsbt(spend_txid, 10)
+    assert res["coins"] == outpoints
+    rbf_psbt = PSBT.from_base64(res["psbt"])
+    assert len(rbf_psbt.tx.vout) == 2
+    assert 500_000 in [o.nValue for o in rbf_psbt.tx.vout]
+    rbf_txid = rbf_psbt.tx.txid().hex()
+    assert rbf_txid in [
+        PSBT.from_base64(s["psbt"]).tx.txid().hex()
+        for s in lianad.rpc.listspendtxs()["spend_txs"]
+    ]
+
+    # We can sign and broadcast it, it replaces the original transaction.
+    signed_psbt = lianad.signer.sign_psbt(rbf_psbt)
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    lianad.rpc.broadcastspend(rbf_txid)
+    bitcoind.generate_block(1, wait_for_mempool=rbf_txid)
+    wait_for(
+        lambda: lianad.rpc.listcoins()["coins"][0]["spend_info"]["txid"] == rbf_txid
+    )
+
+
+def test_cpfp(lianad, bitcoind):
+    """We can bump the feerate of an incoming transaction by spending its output."""
+    addr = lianad.rpc.getnewaddress()["address"]
+    # Pay us at 1sat/vb.
+    txid = bitcoind.rpc.sendtoaddress(
+        addr, 0.01, "", "", False, True, None, "unset", None, 1
+    )
+    wait_for(lambda: len(lianad.rpc.listcoins()["coins"]) == 1)
+    parent_entry = bitcoind.rpc.getmempoolentry(txid)
+
+    # Create a child to bring the package feerate to 20sat/vb.
+    res = lianad.rpc.createcpfp([txid], 20)
+    cpfp_psbt = PSBT.from_base64(res["psbt"])
+    assert len(cpfp_psbt.tx.vin) == 1
+    assert len(cpfp_psbt.tx.vout) == 1
+
+    # Sign and broadcast it, the package reaches the target feerate.
+    signed_psbt = lianad.signer.sign_psbt(cpfp_psbt)
+    lianad.rpc.updatespend(signed_psbt.to_base64())
+    cpfp_txid = signed_psbt.tx.txid().hex()
+    lianad.rpc.broadcastspend(cpfp_txid)
+    child_entry = bitcoind.rpc.getmempoolentry(cpfp_txid)
+    package_fee = (parent_entry["fees"]["base"] + child_entry["fees"]["base"]) * COIN
+    package_vsize = parent_entry["vsize"] + child_entry["vsize"]
+    assert package_fee / package_vsize >= 20
+
+    # It can't be done anymore once the parent is confirmed.
+    bitcoind.generate_block(1, wait_for_mempool=[txid, cpfp_txid])
+    wait_for(lambda: lianad.rpc.getinfo()["block_height"] == bitcoind.rpc.getblockcount())
+    with pytest.raises(RpcError, match=".*is already confirmed.*"):
+        lianad.rpc.createcpfp([txid], 20)